@@ -0,0 +1,109 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::attributes::AttrSource;
+use crate::convert::Notice;
+use crate::error::Result;
+use crate::pathspec::Pathspec;
+use crate::repo::Repository;
+use crate::status::{StatusCode, StatusKind, StatusOptions, UntrackedFiles};
+
+impl Repository {
+    // Stages what `pathspec` matches in the working tree, like `git add`:
+    // changed files, conflicts and untracked files that are not ignored
+    // are added, and tracked files that are gone are removed. Tells which
+    // paths changed in the index; what filters had to say goes to
+    // `notify`.
+    pub fn add_matching(
+        &self,
+        pathspec: &Pathspec,
+        mut notify: impl FnMut(&Notice),
+    ) -> Result<Vec<Vec<u8>>> {
+        let workdir = self.require_workdir()?;
+        let status = self.status(&StatusOptions {
+            untracked: UntrackedFiles::All,
+            renames: false,
+            pathspec: pathspec.clone(),
+            ..StatusOptions::new()
+        })?;
+        let mut index = self.index()?;
+        let converter = self.converter_for(AttrSource::CheckIn, &index)?;
+        let mut changed = Vec::new();
+        for entry in status.entries() {
+            let present = match entry.kind {
+                StatusKind::Changed if entry.worktree == StatusCode::Unmodified => continue,
+                StatusKind::Changed => entry.worktree != StatusCode::Deleted,
+                StatusKind::Unmerged => entry.worktree_mode != 0,
+                // Nested repositories show as directories; they are not
+                // added as submodules here.
+                StatusKind::Untracked if entry.path.ends_with(b"/") => continue,
+                StatusKind::Untracked => true,
+                StatusKind::Ignored => continue,
+            };
+            if present {
+                let path = OsStr::from_bytes(&entry.path);
+                index.add_path(workdir, path, self.odb(), &converter)?;
+            } else {
+                index.remove_entry(&entry.path);
+            }
+            for notice in converter.take_notices() {
+                notify(&notice);
+            }
+            changed.push(entry.path.clone());
+        }
+        if !changed.is_empty() {
+            self.write_index(&mut index)?;
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn add(repo: &Repository, specs: &[&str]) -> Vec<String> {
+        let pathspec = repo.pathspec(specs, b"").unwrap();
+        repo.add_matching(&pathspec, |_| {})
+            .unwrap()
+            .into_iter()
+            .map(|path| String::from_utf8(path).unwrap())
+            .collect()
+    }
+
+    fn staged(repo: &Repository) -> Vec<String> {
+        repo.index()
+            .unwrap()
+            .iter()
+            .map(|entry| String::from_utf8(entry.path.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stages_what_the_pathspec_matches() {
+        let tmp = TempDir::new();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let write = |path: &str, content: &str| {
+            let path = tmp.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(".gitignore", "*.log\n");
+        write("a", "a\n");
+        write("x.log", "x\n");
+        write("dir/b", "b\n");
+        write("dir/sub/c", "c\n");
+
+        assert_eq!(add(&repo, &["dir"]), ["dir/b", "dir/sub/c"]);
+        assert_eq!(add(&repo, &["."]), [".gitignore", "a"]);
+        assert_eq!(staged(&repo), [".gitignore", "a", "dir/b", "dir/sub/c"]);
+        assert!(add(&repo, &["."]).is_empty());
+
+        // Changes are staged and files that are gone are removed.
+        std::fs::remove_file(tmp.path().join("a")).unwrap();
+        write("dir/b", "changed\n");
+        assert_eq!(add(&repo, &["a", "dir/b"]), ["a", "dir/b"]);
+        assert_eq!(staged(&repo), [".gitignore", "dir/b", "dir/sub/c"]);
+    }
+}
@@ -0,0 +1,511 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::ignore::Pattern;
+use crate::index::Index;
+use crate::oid::Oid;
+use crate::repo::Repository;
+
+const GITATTRIBUTES: &str = ".gitattributes";
+const MACRO_PREFIX: &[u8] = b"[attr]";
+const BLANK: &[u8] = b" \t\r\n";
+const BUILTIN_MACROS: &[u8] = b"[attr]binary -diff -merge -text\n";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+// Where .gitattributes files inside the tree are read from. Adding a file
+// prefers the working tree, checking one out prefers the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttrSource {
+    CheckIn,
+    CheckOut,
+    IndexOnly,
+}
+
+#[derive(Debug, Clone)]
+struct AttrLine {
+    pattern: Pattern,
+    states: Vec<(String, AttrValue)>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AttrFile {
+    base: Vec<u8>,
+    lines: Vec<AttrLine>,
+    macros: Vec<(String, Vec<(String, AttrValue)>)>,
+    names: Vec<String>,
+}
+
+pub struct Attributes {
+    workdir: Option<PathBuf>,
+    source: AttrSource,
+    indexed: HashMap<Vec<u8>, Vec<u8>>,
+    // Files below and above those in the tree, in increasing precedence.
+    global: Vec<AttrFile>,
+    info: Vec<AttrFile>,
+    directories: RefCell<HashMap<Vec<u8>, AttrFile>>,
+    // Attribute names in the order they were first seen, which is the order
+    // `check_all` reports them in.
+    names: RefCell<Vec<String>>,
+}
+
+impl AttrValue {
+    pub fn is_set(&self) -> bool {
+        *self == AttrValue::Set
+    }
+
+    pub fn is_unset(&self) -> bool {
+        *self == AttrValue::Unset
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == AttrValue::Unspecified
+    }
+
+    pub fn as_value(&self) -> Option<&str> {
+        match self {
+            AttrValue::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttrValue::Set => write!(f, "set"),
+            AttrValue::Unset => write!(f, "unset"),
+            AttrValue::Value(value) => write!(f, "{}", value),
+            AttrValue::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name[0] != b'-'
+        && name
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+}
+
+fn skip_blank(s: &[u8]) -> &[u8] {
+    let start = s.iter().position(|b| !BLANK.contains(b)).unwrap_or(s.len());
+    &s[start..]
+}
+
+fn split_word(s: &[u8]) -> (&[u8], &[u8]) {
+    let end = s.iter().position(|b| BLANK.contains(b)).unwrap_or(s.len());
+    (&s[..end], &s[end..])
+}
+
+// Reverses git's C-style quoting of a pattern, returning the pattern and
+// what follows the closing quote.
+fn unquote(s: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut out = Vec::new();
+    let mut i = 1;
+    while i < s.len() {
+        match s[i] {
+            b'"' => return Some((out, &s[i + 1..])),
+            b'\\' => {
+                i += 1;
+                let c = *s.get(i)?;
+                match c {
+                    b'a' => out.push(0x07),
+                    b'b' => out.push(0x08),
+                    b'f' => out.push(0x0c),
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'v' => out.push(0x0b),
+                    b'"' | b'\\' => out.push(c),
+                    b'0'..=b'3' => {
+                        let digits = s.get(i..i + 3)?;
+                        if !digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+                            return None;
+                        }
+                        out.push(digits.iter().fold(0, |acc, d| acc * 8 + (d - b'0')));
+                        i += 2;
+                    }
+                    _ => return None,
+                }
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    None
+}
+
+fn parse_states(mut rest: &[u8]) -> Option<Vec<(String, AttrValue)>> {
+    let mut states = Vec::new();
+    rest = skip_blank(rest);
+    while !rest.is_empty() {
+        let (word, remainder) = split_word(rest);
+        let (name, value) = match word.iter().position(|&b| b == b'=') {
+            Some(equals) => (&word[..equals], Some(&word[equals + 1..])),
+            None => (word, None),
+        };
+        let (name, value) = match name.first() {
+            Some(b'-') => (&name[1..], AttrValue::Unset),
+            Some(b'!') => (&name[1..], AttrValue::Unspecified),
+            _ => match value {
+                Some(value) => (
+                    name,
+                    AttrValue::Value(String::from_utf8_lossy(value).into_owned()),
+                ),
+                None => (name, AttrValue::Set),
+            },
+        };
+        if !is_valid_name(name) {
+            return None;
+        }
+        states.push((String::from_utf8_lossy(name).into_owned(), value));
+        rest = skip_blank(remainder);
+    }
+    Some(states)
+}
+
+impl AttrFile {
+    // Malformed lines are skipped, as are macro definitions where they are
+    // not allowed and negative patterns, which git ignores with a warning.
+    fn parse(content: &[u8], base: &[u8], macros_allowed: bool) -> Self {
+        let mut file = AttrFile {
+            base: base.to_vec(),
+            ..AttrFile::default()
+        };
+        for line in content.split(|&b| b == b'\n') {
+            let line = skip_blank(line);
+            if line.is_empty() || line[0] == b'#' {
+                continue;
+            }
+            let (name, rest) = if line[0] == b'"' {
+                match unquote(line) {
+                    Some(unquoted) => unquoted,
+                    None => continue,
+                }
+            } else {
+                let (name, rest) = split_word(line);
+                (name.to_vec(), rest)
+            };
+            if name.len() > MACRO_PREFIX.len() && name.starts_with(MACRO_PREFIX) {
+                if !macros_allowed {
+                    continue;
+                }
+                let (macro_name, rest) = split_word(skip_blank(&line[MACRO_PREFIX.len()..]));
+                if !is_valid_name(macro_name) {
+                    continue;
+                }
+                if let Some(states) = parse_states(rest) {
+                    let macro_name = String::from_utf8_lossy(macro_name).into_owned();
+                    file.add_names(std::iter::once(&macro_name).chain(states.iter().map(|s| &s.0)));
+                    file.macros.push((macro_name, states));
+                }
+                continue;
+            }
+            if name.first() == Some(&b'!') {
+                continue;
+            }
+            if let (Some(pattern), Some(states)) = (Pattern::new(&name), parse_states(rest)) {
+                file.add_names(states.iter().map(|s| &s.0));
+                file.lines.push(AttrLine { pattern, states });
+            }
+        }
+        file
+    }
+
+    fn add_names<'a>(&mut self, names: impl Iterator<Item = &'a String>) {
+        for name in names {
+            if !self.names.contains(name) {
+                self.names.push(name.clone());
+            }
+        }
+    }
+}
+
+// Collects the values of attributes as the stack is walked from the most to
+// the least specific file; the first value seen for a name decides it.
+struct Collector<'a> {
+    macros: &'a HashMap<String, Vec<(String, AttrValue)>>,
+    wanted: Option<&'a [&'a str]>,
+    values: HashMap<String, AttrValue>,
+}
+
+impl<'a> Collector<'a> {
+    fn fill(&mut self, states: &[(String, AttrValue)]) {
+        for (name, value) in states.iter().rev() {
+            if self.values.contains_key(name) {
+                continue;
+            }
+            self.values.insert(name.clone(), value.clone());
+            if *value == AttrValue::Set {
+                if let Some(expansion) = self.macros.get(name) {
+                    self.fill(expansion);
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self.wanted {
+            Some(wanted) => wanted.iter().all(|name| self.values.contains_key(*name)),
+            None => false,
+        }
+    }
+}
+
+impl Attributes {
+    pub fn new(workdir: Option<&Path>, source: AttrSource) -> Self {
+        let attributes = Self {
+            workdir: workdir.map(Path::to_path_buf),
+            source,
+            indexed: HashMap::new(),
+            global: vec![AttrFile::parse(BUILTIN_MACROS, b"", true)],
+            info: Vec::new(),
+            directories: RefCell::new(HashMap::new()),
+            names: RefCell::new(Vec::new()),
+        };
+        attributes.register(&attributes.global[0]);
+        attributes
+    }
+
+    // Makes the .gitattributes files recorded in `index` available to
+    // sources that read from the index.
+    pub fn add_index(
+        &mut self,
+        index: &Index,
+        read_blob: impl Fn(&Oid) -> Result<Vec<u8>>,
+    ) -> Result<()> {
+        for entry in index.iter().filter(|entry| entry.stage() == 0) {
+            let dir = match entry.path.iter().rposition(|&b| b == b'/') {
+                Some(slash) if &entry.path[slash + 1..] == GITATTRIBUTES.as_bytes() => {
+                    &entry.path[..slash]
+                }
+                None if entry.path == GITATTRIBUTES.as_bytes() => &entry.path[..0],
+                _ => continue,
+            };
+            self.indexed.insert(dir.to_vec(), read_blob(&entry.id)?);
+        }
+        self.directories.borrow_mut().clear();
+        Ok(())
+    }
+
+    // core.attributesFile: below everything in the tree.
+    pub fn add_global_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        if let Some(content) = read_optional(path.as_ref())? {
+            let file = AttrFile::parse(&content, b"", true);
+            self.register(&file);
+            self.global.push(file);
+        }
+        Ok(())
+    }
+
+    // $GIT_DIR/info/attributes: above everything in the tree.
+    pub fn add_info_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        if let Some(content) = read_optional(path.as_ref())? {
+            let file = AttrFile::parse(&content, b"", true);
+            self.register(&file);
+            self.info.push(file);
+        }
+        Ok(())
+    }
+
+    pub fn workdir(&self) -> Option<&Path> {
+        self.workdir.as_deref()
+    }
+
+    fn register(&self, file: &AttrFile) {
+        let mut names = self.names.borrow_mut();
+        for name in &file.names {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+
+    fn read_tree_file(&self, dir: &[u8]) -> Result<Option<Vec<u8>>> {
+        let from_worktree = || -> Result<Option<Vec<u8>>> {
+            let workdir = match &self.workdir {
+                Some(workdir) => workdir,
+                None => return Ok(None),
+            };
+            let mut path = workdir.join(OsStr::from_bytes(dir));
+            path.push(GITATTRIBUTES);
+            // Like git, do not follow a symbolic link here.
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_file() => read_optional(&path),
+                _ => Ok(None),
+            }
+        };
+        let from_index = || self.indexed.get(dir).cloned();
+        Ok(match self.source {
+            AttrSource::CheckIn => from_worktree()?.or_else(from_index),
+            AttrSource::CheckOut => match from_index() {
+                Some(content) => Some(content),
+                None => from_worktree()?,
+            },
+            AttrSource::IndexOnly => from_index(),
+        })
+    }
+
+    fn load_directories(&self, dirs: &[&[u8]]) -> Result<()> {
+        for dir in dirs {
+            if self.directories.borrow().contains_key(*dir) {
+                continue;
+            }
+            let file = match self.read_tree_file(dir)? {
+                // Macros may only be defined at the top of the tree.
+                Some(content) => AttrFile::parse(&content, dir, dir.is_empty()),
+                None => AttrFile {
+                    base: dir.to_vec(),
+                    ..AttrFile::default()
+                },
+            };
+            self.register(&file);
+            self.directories.borrow_mut().insert(dir.to_vec(), file);
+        }
+        Ok(())
+    }
+
+    fn collect(&self, path: &[u8], wanted: Option<&[&str]>) -> Result<HashMap<String, AttrValue>> {
+        let is_dir = path.ends_with(b"/");
+        let path = path.strip_suffix(b"/").unwrap_or(path);
+        let mut dirs = vec![&path[..0]];
+        dirs.extend(
+            path.iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'/')
+                .map(|(i, _)| &path[..i]),
+        );
+        self.load_directories(&dirs)?;
+        let directories = self.directories.borrow();
+
+        // The stack from the most specific file to the least.
+        let mut stack: Vec<&AttrFile> = self.info.iter().rev().collect();
+        stack.extend(dirs.iter().rev().map(|dir| &directories[*dir]));
+        stack.extend(self.global.iter().rev());
+
+        let mut macros = HashMap::new();
+        for file in &stack {
+            for (name, states) in file.macros.iter().rev() {
+                macros.entry(name.clone()).or_insert_with(|| states.clone());
+            }
+        }
+        let mut collector = Collector {
+            macros: &macros,
+            wanted,
+            values: HashMap::new(),
+        };
+        'stack: for file in stack {
+            let relative = if file.base.is_empty() {
+                path
+            } else {
+                match path
+                    .strip_prefix(&file.base[..])
+                    .and_then(|rest| rest.strip_prefix(b"/"))
+                {
+                    Some(relative) => relative,
+                    None => continue,
+                }
+            };
+            for line in file.lines.iter().rev() {
+                if line.pattern.matches(relative, is_dir) {
+                    collector.fill(&line.states);
+                    if collector.is_done() {
+                        break 'stack;
+                    }
+                }
+            }
+        }
+        Ok(collector.values)
+    }
+
+    // Returns the value of each of `names` for `path`; a trailing slash
+    // marks `path` as a directory.
+    pub fn check(&self, path: &[u8], names: &[&str]) -> Result<Vec<(String, AttrValue)>> {
+        let values = self.collect(path, Some(names))?;
+        Ok(names
+            .iter()
+            .map(|name| {
+                let value = values.get(*name).cloned().unwrap_or(AttrValue::Unspecified);
+                (name.to_string(), value)
+            })
+            .collect())
+    }
+
+    pub fn get(&self, path: &[u8], name: &str) -> Result<AttrValue> {
+        Ok(self.check(path, &[name])?.remove(0).1)
+    }
+
+    // Every attribute that is not unspecified for `path`, like
+    // `git check-attr --all`.
+    pub fn check_all(&self, path: &[u8]) -> Result<Vec<(String, AttrValue)>> {
+        let mut values = self.collect(path, None)?;
+        Ok(self
+            .names
+            .borrow()
+            .iter()
+            .filter_map(|name| values.remove_entry(name))
+            .filter(|(_, value)| !value.is_unspecified())
+            .collect())
+    }
+}
+
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound || e.raw_os_error() == Some(20) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Where core.attributesFile points when it is not configured.
+pub fn default_attributes_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => Some(PathBuf::from(config).join("git/attributes")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/attributes")),
+    }
+}
+
+impl Repository {
+    pub fn attributes(&self, source: AttrSource) -> Result<Attributes> {
+        self.attributes_for(source, &self.index()?)
+    }
+
+    // Like `attributes`, reading the .gitattributes files of `index` rather
+    // than of the index on disk.
+    pub fn attributes_for(&self, source: AttrSource, index: &Index) -> Result<Attributes> {
+        let mut attributes = Attributes::new(self.workdir(), source);
+        let path = match self.config()?.get_path("core.attributesfile")? {
+            Some(path) => Some(path),
+            None => default_attributes_file(),
+        };
+        if let Some(path) = path {
+            attributes.add_global_file(path)?;
+        }
+        attributes.add_index(index, |oid| self.read_blob_content(oid))?;
+        // Read the top-level file first so that attribute names are seen in
+        // the same order as git sees them.
+        attributes.load_directories(&[b""])?;
+        attributes.add_info_file(self.commondir().join("info/attributes"))?;
+        Ok(attributes)
+    }
+
+    pub fn check_attr(&self, path: &[u8], names: &[&str]) -> Result<Vec<(String, AttrValue)>> {
+        self.attributes(AttrSource::CheckIn)?.check(path, names)
+    }
+}
@@ -1,5 +1,6 @@
 extern crate hit;
 
+use hit::object::Object;
 use hit::odb::Odb;
 use hit::odb::StandardOdb;
 use hit::oid::Oid;
@@ -10,25 +11,10 @@ fn main() {
     let odb = StandardOdb::from_path(".git/objects");
     let oid = Oid::from_hex(&args[1]);
     let object = odb.get(&oid).unwrap();
-    match object.as_blob() {
-        Some(blob) => {
-            println!("{}", blob.as_str());
-            return;
-        }
-        None => (),
-    }
-    match object.as_tree() {
-        Some(tree) => {
-            print!("{}", tree);
-            return;
-        }
-        None => (),
-    }
-    match object.as_commit() {
-        Some(commit) => {
-            print!("{}", commit);
-            return;
-        }
-        None => (),
+    match object {
+        Object::Blob(blob) => println!("{}", blob.as_str()),
+        Object::Tree(tree) => print!("{}", tree),
+        Object::Commit(commit) => print!("{}", String::from_utf8_lossy(&commit.to_bytes())),
+        Object::Tag(tag) => print!("{}", String::from_utf8_lossy(&tag.to_bytes())),
     }
 }
@@ -0,0 +1,963 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::diff::{diff, split_lines};
+use crate::error::{Error, Result};
+use crate::mailmap::Mailmap;
+use crate::odb::Odb;
+use crate::oid::Oid;
+use crate::refs::parse_oid;
+use crate::rename::{detect_renames, Candidate, DEFAULT_RENAME_SCORE};
+use crate::repo::Repository;
+use crate::status::quote_path;
+use crate::tree::{flatten_tree, TreeItem};
+
+// How many letters and digits a moved or copied chunk needs before blame
+// is passed to where it came from, like git's defaults.
+pub const DEFAULT_MOVE_SCORE: usize = 20;
+pub const DEFAULT_COPY_SCORE: usize = 40;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Clone)]
+pub struct BlameOptions {
+    // The lines of the blamed file to blame, all of them if empty.
+    pub ranges: Vec<Range<usize>>,
+    // Compares lines without their white space, like -w.
+    pub ignore_whitespace: bool,
+    // Finds lines moved within the file, like -M.
+    pub detect_moves: bool,
+    // How hard to look for lines copied from other files, like giving -C
+    // that many times: from files changed in the same commit, also from
+    // every file when the file is created, and from every file always.
+    pub copy_level: u32,
+    pub move_score: usize,
+    pub copy_score: usize,
+    // Commits whose changes are passed over, like --ignore-rev and
+    // --ignore-revs-file. Those of blame.ignoreRevsFile are added.
+    pub ignore_revs: Vec<Oid>,
+    pub ignore_revs_files: Vec<PathBuf>,
+    pub first_parent: bool,
+    // Blames root commits like any other, rather than as boundary.
+    pub show_root: bool,
+    // Lines that reach commits reachable from these stay with the last
+    // commit before them, marked as boundary.
+    pub hidden: Vec<Oid>,
+}
+
+// Consecutive lines of the blamed file that come from the same lines of
+// one commit. Line numbers start at 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameHunk {
+    pub commit: Oid,
+    // The path of the file in `commit`, which differs after a rename or
+    // copy.
+    pub path: Vec<u8>,
+    pub orig_start: usize,
+    pub final_start: usize,
+    pub lines: usize,
+    pub boundary: bool,
+    // Passed over an ignored commit to the line it most likely replaced.
+    pub ignored: bool,
+    // Changed by an ignored commit, with no line to pass it on to.
+    pub unblamable: bool,
+    // The commit before `commit` and the path the file had there.
+    pub previous: Option<(Oid, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Blame {
+    pub path: Vec<u8>,
+    lines: Vec<Vec<u8>>,
+    hunks: Vec<BlameHunk>,
+}
+
+// A commit and the path the blamed lines have in it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Origin {
+    commit: Oid,
+    path: Vec<u8>,
+}
+
+// Lines of the blamed file whose origin is suspected to be `origin`,
+// starting at line `start` there.
+#[derive(Debug, Clone)]
+struct Entry {
+    final_start: usize,
+    len: usize,
+    origin: Origin,
+    start: usize,
+    ignored: bool,
+    unblamable: bool,
+}
+
+struct CommitInfo {
+    tree: Oid,
+    time: i64,
+    parents: Vec<Oid>,
+}
+
+// The content of a file in some commit, as lines and as the keys they are
+// compared by.
+struct FileLines {
+    id: Oid,
+    lines: Vec<Vec<u8>>,
+    keys: Vec<Vec<u8>>,
+}
+
+struct Scoreboard<'a> {
+    repo: &'a Repository,
+    options: &'a BlameOptions,
+    ignored: HashSet<Oid>,
+    // The commits that may be blamed; None when nothing is hidden.
+    interesting: Option<HashSet<Oid>>,
+    commits: HashMap<Oid, CommitInfo>,
+    blobs: HashMap<Oid, Rc<FileLines>>,
+    files: HashMap<Oid, Rc<BTreeMap<Vec<u8>, TreeItem>>>,
+    // Entries not yet settled, by their suspect.
+    pending: BTreeMap<Origin, Vec<Entry>>,
+}
+
+impl BlameOptions {
+    pub fn new() -> Self {
+        Self {
+            ranges: Vec::new(),
+            ignore_whitespace: false,
+            detect_moves: false,
+            copy_level: 0,
+            move_score: DEFAULT_MOVE_SCORE,
+            copy_score: DEFAULT_COPY_SCORE,
+            ignore_revs: Vec::new(),
+            ignore_revs_files: Vec::new(),
+            first_parent: false,
+            show_root: false,
+            hidden: Vec::new(),
+        }
+    }
+}
+
+impl Default for BlameOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blame {
+    pub fn lines(&self) -> &[Vec<u8>] {
+        &self.lines
+    }
+
+    // The hunks in the order of the lines they cover.
+    pub fn hunks(&self) -> &[BlameHunk] {
+        &self.hunks
+    }
+
+    pub fn hunk_for_line(&self, line: usize) -> Option<&BlameHunk> {
+        let at = self
+            .hunks
+            .partition_point(|hunk| hunk.final_start + hunk.lines <= line);
+        self.hunks.get(at).filter(|hunk| hunk.final_start <= line)
+    }
+}
+
+impl Entry {
+    fn to_hunk(&self, boundary: bool, previous: Option<&Origin>) -> BlameHunk {
+        BlameHunk {
+            commit: self.origin.commit,
+            path: self.origin.path.clone(),
+            orig_start: self.start,
+            final_start: self.final_start,
+            lines: self.len,
+            boundary,
+            ignored: self.ignored,
+            unblamable: self.unblamable,
+            previous: previous.map(|origin| (origin.commit, origin.path.clone())),
+        }
+    }
+
+    // The `len` lines from `offset` on, blamed on `origin` from `start`.
+    fn part(&self, offset: usize, len: usize, origin: &Origin, start: usize) -> Entry {
+        Entry {
+            final_start: self.final_start + offset,
+            len,
+            origin: origin.clone(),
+            start,
+            ignored: self.ignored,
+            unblamable: self.unblamable,
+        }
+    }
+}
+
+// How much a chunk of lines says: the letters and digits in it.
+fn score(lines: &[Vec<u8>]) -> usize {
+    lines
+        .iter()
+        .map(|line| line.iter().filter(|c| c.is_ascii_alphanumeric()).count())
+        .sum()
+}
+
+// The pairs of adjacent bytes in a line, which tell how alike lines are.
+fn fingerprint(line: &[u8]) -> HashMap<[u8; 2], usize> {
+    let mut pairs = HashMap::new();
+    for pair in line.windows(2) {
+        *pairs.entry([pair[0], pair[1]]).or_insert(0) += 1;
+    }
+    pairs
+}
+
+// How alike two fingerprints are, from 0 to 100.
+fn likeness(a: &HashMap<[u8; 2], usize>, b: &HashMap<[u8; 2], usize>) -> usize {
+    let total: usize = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 0;
+    }
+    let common: usize = a
+        .iter()
+        .map(|(pair, count)| b.get(pair).map_or(0, |other| *count.min(other)))
+        .sum();
+    common * 200 / total
+}
+
+// Where each line of `new` comes from in `old`, if it is unchanged. With
+// `guess`, each changed line is taken to replace the most alike line that
+// was changed away, preferring those of its own change, if any is alike
+// enough.
+fn map_lines(old: &[Vec<u8>], new: &[Vec<u8>], guess: bool) -> Vec<Option<(usize, bool)>> {
+    const MIN_LIKENESS: usize = 50;
+    let mut map = vec![None; new.len()];
+    let hunks = diff(old, new);
+    let (mut x, mut y) = (0, 0);
+    for hunk in &hunks {
+        while y < hunk.new.start {
+            map[y] = Some((x, false));
+            x += 1;
+            y += 1;
+        }
+        x = hunk.old.end;
+        y = hunk.new.end;
+    }
+    while y < new.len() {
+        map[y] = Some((x, false));
+        x += 1;
+        y += 1;
+    }
+    if !guess {
+        return map;
+    }
+
+    let removed: Vec<(usize, HashMap<[u8; 2], usize>)> = hunks
+        .iter()
+        .flat_map(|hunk| hunk.old.clone())
+        .map(|line| (line, fingerprint(&old[line])))
+        .collect();
+    let mut used = HashSet::new();
+    for hunk in &hunks {
+        for line in hunk.new.clone() {
+            let print = fingerprint(&new[line]);
+            let best = removed
+                .iter()
+                .filter(|(old_line, _)| !used.contains(old_line))
+                .map(|(old_line, old_print)| {
+                    let own = hunk.old.contains(old_line);
+                    (
+                        likeness(&print, old_print),
+                        own,
+                        std::cmp::Reverse(*old_line),
+                    )
+                })
+                .filter(|&(likeness, _, _)| likeness >= MIN_LIKENESS)
+                .max();
+            if let Some((_, _, std::cmp::Reverse(old_line))) = best {
+                used.insert(old_line);
+                map[line] = Some((old_line, true));
+            }
+        }
+    }
+    map
+}
+
+// Reads the object ids listed in an ignore-revs file, one per line, with
+// `#` starting a comment.
+pub fn read_ignore_revs(content: &[u8]) -> Result<Vec<Oid>> {
+    let mut revs = Vec::new();
+    for line in content.split(|&c| c == b'\n') {
+        let line = match line.iter().position(|&c| c == b'#') {
+            Some(at) => &line[..at],
+            None => line,
+        };
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_oid(line) {
+            Some(oid) => revs.push(oid),
+            None => {
+                return Err(Error::InvalidBlame(format!(
+                    "invalid object name: {}",
+                    line
+                )))
+            }
+        }
+    }
+    Ok(revs)
+}
+
+impl<'a> Scoreboard<'a> {
+    fn commit(&mut self, oid: &Oid) -> Result<&CommitInfo> {
+        if !self.commits.contains_key(oid) {
+            let commit = self
+                .repo
+                .odb()
+                .get(oid)?
+                .into_commit()
+                .ok_or_else(|| Error::InvalidObject(format!("{} is not a commit", oid)))?;
+            let mut parents = commit.parents().to_vec();
+            if self.options.first_parent {
+                parents.truncate(1);
+            }
+            self.commits.insert(
+                *oid,
+                CommitInfo {
+                    tree: *commit.tree(),
+                    time: commit.committer().time().unwrap_or(0),
+                    parents,
+                },
+            );
+        }
+        Ok(&self.commits[oid])
+    }
+
+    fn files(&mut self, commit: &Oid) -> Result<Rc<BTreeMap<Vec<u8>, TreeItem>>> {
+        let tree = self.commit(commit)?.tree;
+        if let Some(files) = self.files.get(&tree) {
+            return Ok(Rc::clone(files));
+        }
+        let files = Rc::new(flatten_tree(self.repo.odb(), &tree)?);
+        self.files.insert(tree, Rc::clone(&files));
+        Ok(files)
+    }
+
+    // The regular file at `path` in `commit`.
+    fn file(&mut self, commit: &Oid, path: &[u8]) -> Result<Option<TreeItem>> {
+        Ok(self
+            .files(commit)?
+            .get(path)
+            .copied()
+            .filter(|item| item.mode & S_IFMT == S_IFREG))
+    }
+
+    fn blob(&mut self, id: &Oid) -> Result<Rc<FileLines>> {
+        if let Some(blob) = self.blobs.get(id) {
+            return Ok(Rc::clone(blob));
+        }
+        let content = self.repo.read_blob_content(id)?;
+        let lines: Vec<Vec<u8>> = split_lines(&content)
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect();
+        let keys = if self.options.ignore_whitespace {
+            lines
+                .iter()
+                .map(|line| {
+                    line.iter()
+                        .copied()
+                        .filter(|c| !c.is_ascii_whitespace())
+                        .collect()
+                })
+                .collect()
+        } else {
+            lines.clone()
+        };
+        let blob = Rc::new(FileLines {
+            id: *id,
+            lines,
+            keys,
+        });
+        self.blobs.insert(*id, Rc::clone(&blob));
+        Ok(blob)
+    }
+
+    fn origin_lines(&mut self, origin: &Origin) -> Result<Rc<FileLines>> {
+        let item = self.file(&origin.commit, &origin.path)?.ok_or_else(|| {
+            Error::InvalidBlame(format!(
+                "no such path {} in {}",
+                String::from_utf8_lossy(&origin.path),
+                origin.commit
+            ))
+        })?;
+        self.blob(&item.id)
+    }
+
+    // The file of `origin` in `parent`, if it is at the same path there.
+    fn same_path_origin(&mut self, origin: &Origin, parent: &Oid) -> Result<Option<Origin>> {
+        Ok(self.file(parent, &origin.path)?.map(|_| Origin {
+            commit: *parent,
+            path: origin.path.clone(),
+        }))
+    }
+
+    // The file of `parent` that `origin` was renamed from, going by
+    // content.
+    fn renamed_origin(&mut self, origin: &Origin, parent: &Oid) -> Result<Option<Origin>> {
+        let path = origin.path.clone();
+        let files = self.files(&origin.commit)?;
+        let parent_files = self.files(parent)?;
+        let sources: Vec<(&Vec<u8>, Oid, Vec<u8>)> = parent_files
+            .iter()
+            .filter(|(source, file)| file.mode & S_IFMT == S_IFREG && !files.contains_key(*source))
+            .map(|(source, file)| Ok((source, file.id, self.repo.read_blob_content(&file.id)?)))
+            .collect::<Result<_>>()?;
+        let item = files[&path];
+        let content = self.repo.read_blob_content(&item.id)?;
+        let candidates: Vec<Candidate<'_>> = sources
+            .iter()
+            .map(|(_, id, content)| Candidate { id: *id, content })
+            .collect();
+        let destination = Candidate {
+            id: item.id,
+            content: &content,
+        };
+        let renames = detect_renames(&candidates, &[destination], DEFAULT_RENAME_SCORE);
+        Ok(renames.first().map(|rename| Origin {
+            commit: *parent,
+            path: sources[rename.source].0.clone(),
+        }))
+    }
+
+    // Passes the lines of `entries` that `map` traces to `target` there,
+    // returning those that stay. When `guessing` for an ignored commit,
+    // those that stay are the ones it brought in with nothing to replace.
+    fn pass_mapped(
+        &mut self,
+        entries: Vec<Entry>,
+        map: &[Option<(usize, bool)>],
+        target: &Origin,
+        guessing: bool,
+    ) -> Vec<Entry> {
+        let mut remaining = Vec::new();
+        for entry in entries {
+            let mut offset = 0;
+            while offset < entry.len {
+                let line = entry.start + offset;
+                let mut len = 1;
+                while offset + len < entry.len
+                    && match (map[line], map[line + len]) {
+                        (None, None) => true,
+                        (Some((a, guessed)), Some((b, guessed_b))) => {
+                            a + len == b && guessed == guessed_b
+                        }
+                        _ => false,
+                    }
+                {
+                    len += 1;
+                }
+                match map[line] {
+                    Some((start, guessed)) => {
+                        let mut part = entry.part(offset, len, target, start);
+                        part.ignored |= guessed;
+                        self.pending.entry(target.clone()).or_default().push(part);
+                    }
+                    None => {
+                        let mut part = entry.part(offset, len, &entry.origin, line);
+                        part.unblamable |= guessing;
+                        remaining.push(part);
+                    }
+                }
+                offset += len;
+            }
+        }
+        remaining
+    }
+
+    // Passes the best chunk of each entry found in `target` there, if it
+    // says enough, and then looks again in what is left around it.
+    fn pass_copies(
+        &mut self,
+        entries: Vec<Entry>,
+        lines: &FileLines,
+        target: &Origin,
+        min_score: usize,
+    ) -> Result<Vec<Entry>> {
+        let target_lines = self.origin_lines(target)?;
+        let mut remaining = Vec::new();
+        let mut work = entries;
+        while let Some(entry) = work.pop() {
+            let keys = &lines.keys[entry.start..entry.start + entry.len];
+            let map = map_lines(&target_lines.keys, keys, false);
+            let mut best: Option<(usize, usize, usize, usize)> = None;
+            let mut offset = 0;
+            while offset < entry.len {
+                let start = match map[offset] {
+                    Some((start, _)) => start,
+                    None => {
+                        offset += 1;
+                        continue;
+                    }
+                };
+                let mut len = 1;
+                while offset + len < entry.len && map[offset + len] == Some((start + len, false)) {
+                    len += 1;
+                }
+                let chunk = &lines.lines[entry.start + offset..entry.start + offset + len];
+                let chunk_score = score(chunk);
+                if chunk_score > min_score && best.is_none_or(|(_, _, _, best)| chunk_score > best)
+                {
+                    best = Some((offset, len, start, chunk_score));
+                }
+                offset += len;
+            }
+            match best {
+                Some((offset, len, start, _)) => {
+                    let part = entry.part(offset, len, target, start);
+                    self.pending.entry(target.clone()).or_default().push(part);
+                    if offset > 0 {
+                        work.push(entry.part(0, offset, &entry.origin, entry.start));
+                    }
+                    let end = offset + len;
+                    if end < entry.len {
+                        work.push(entry.part(
+                            end,
+                            entry.len - end,
+                            &entry.origin,
+                            entry.start + end,
+                        ));
+                    }
+                }
+                None => remaining.push(entry),
+            }
+        }
+        Ok(remaining)
+    }
+
+    // The files of `parent` that copies may come from: those `commit`
+    // changes, or all of them when looking harder.
+    fn copy_sources(&mut self, commit: &Oid, parent: &Oid, created: bool) -> Result<Vec<Origin>> {
+        let files = self.files(commit)?;
+        let parent_files = self.files(parent)?;
+        let all = self.options.copy_level >= 3 || (self.options.copy_level >= 2 && created);
+        Ok(parent_files
+            .iter()
+            .filter(|(_, file)| file.mode & S_IFMT == S_IFREG)
+            .filter(|(path, file)| all || files.get(*path).is_some_and(|new| new.id != file.id))
+            .map(|(path, _)| Origin {
+                commit: *parent,
+                path: path.clone(),
+            })
+            .collect())
+    }
+
+    // Passes what it can of the entries suspecting `origin` on to its
+    // parents, returning the lines it is guilty of and where the file was
+    // before.
+    fn pass_blame(
+        &mut self,
+        origin: &Origin,
+        entries: Vec<Entry>,
+    ) -> Result<(Vec<Entry>, Option<Origin>)> {
+        let parents = self.commit(&origin.commit)?.parents.clone();
+        let ignored = self.ignored.contains(&origin.commit);
+        let lines = self.origin_lines(origin)?;
+        // Like git, every parent is searched for the same path before any
+        // is searched for a rename, and a parent with the very same file
+        // takes all of the blame as soon as it is found.
+        let mut found: Vec<Option<Origin>> = vec![None; parents.len()];
+        for renames in [false, true] {
+            for (i, parent) in parents.iter().enumerate() {
+                if found[i].is_some() {
+                    continue;
+                }
+                let parent_origin = if renames {
+                    self.renamed_origin(origin, parent)?
+                } else {
+                    self.same_path_origin(origin, parent)?
+                };
+                let parent_origin = match parent_origin {
+                    Some(parent_origin) => parent_origin,
+                    None => continue,
+                };
+                if self.origin_lines(&parent_origin)?.id == lines.id {
+                    for entry in entries {
+                        let part = entry.part(0, entry.len, &parent_origin, entry.start);
+                        self.pending
+                            .entry(parent_origin.clone())
+                            .or_default()
+                            .push(part);
+                    }
+                    return Ok((Vec::new(), Some(parent_origin)));
+                }
+                found[i] = Some(parent_origin);
+            }
+        }
+        let parent_origins: Vec<Origin> = found.into_iter().flatten().collect();
+
+        let mut remaining = entries;
+        let mut seen = Vec::new();
+        for parent_origin in &parent_origins {
+            if remaining.is_empty() {
+                break;
+            }
+            // A parent with the same file as an earlier one has nothing
+            // more to take.
+            let parent_lines = self.origin_lines(parent_origin)?;
+            if seen.contains(&parent_lines.id) {
+                continue;
+            }
+            seen.push(parent_lines.id);
+            let map = map_lines(&parent_lines.keys, &lines.keys, false);
+            remaining = self.pass_mapped(remaining, &map, parent_origin, false);
+        }
+
+        // Like git, looking for copies looks for moves as well.
+        if self.options.detect_moves || self.options.copy_level > 0 {
+            for parent_origin in &parent_origins {
+                if remaining.is_empty() {
+                    break;
+                }
+                remaining =
+                    self.pass_copies(remaining, &lines, parent_origin, self.options.move_score)?;
+            }
+        }
+        if self.options.copy_level > 0 {
+            let created = parent_origins.is_empty();
+            for parent in &parents {
+                for source in self.copy_sources(&origin.commit, parent, created)? {
+                    if remaining.is_empty() {
+                        break;
+                    }
+                    if parent_origins.contains(&source) {
+                        continue;
+                    }
+                    remaining =
+                        self.pass_copies(remaining, &lines, &source, self.options.copy_score)?;
+                }
+            }
+        }
+
+        // What an ignored commit changed goes to the line it replaced.
+        if ignored && !remaining.is_empty() {
+            if let Some(parent_origin) = parent_origins.first() {
+                let parent_lines = self.origin_lines(parent_origin)?;
+                let map = map_lines(&parent_lines.keys, &lines.keys, true);
+                remaining = self.pass_mapped(remaining, &map, parent_origin, true);
+            }
+        }
+        Ok((remaining, parent_origins.into_iter().next()))
+    }
+
+    // The pending commit with the newest date, which no other pending
+    // commit can descend from unless clocks are skewed.
+    fn next_origin(&mut self) -> Result<Option<Origin>> {
+        let mut best: Option<(i64, Origin)> = None;
+        let origins: Vec<Origin> = self.pending.keys().cloned().collect();
+        for origin in origins {
+            let time = self.commit(&origin.commit)?.time;
+            if best.as_ref().is_none_or(|(best, _)| time > *best) {
+                best = Some((time, origin));
+            }
+        }
+        Ok(best.map(|(_, origin)| origin))
+    }
+
+    fn run(&mut self, emit: &mut dyn FnMut(&BlameHunk) -> Result<()>) -> Result<()> {
+        while let Some(origin) = self.next_origin()? {
+            let entries = self.pending.remove(&origin).unwrap_or_default();
+            let hidden = self
+                .interesting
+                .as_ref()
+                .is_some_and(|interesting| !interesting.contains(&origin.commit));
+            let (mut guilty, previous) = if hidden {
+                (entries, None)
+            } else {
+                self.pass_blame(&origin, entries)?
+            };
+            let is_root = self.commit(&origin.commit)?.parents.is_empty();
+            let boundary = hidden || (is_root && !self.options.show_root);
+            guilty.sort_by_key(|entry| entry.final_start);
+            for entry in &guilty {
+                emit(&entry.to_hunk(boundary, previous.as_ref()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Joins hunks that continue each other.
+fn coalesce(mut hunks: Vec<BlameHunk>) -> Vec<BlameHunk> {
+    hunks.sort_by_key(|hunk| hunk.final_start);
+    let mut joined: Vec<BlameHunk> = Vec::with_capacity(hunks.len());
+    for hunk in hunks {
+        match joined.last_mut() {
+            Some(last)
+                if last.commit == hunk.commit
+                    && last.path == hunk.path
+                    && last.final_start + last.lines == hunk.final_start
+                    && last.orig_start + last.lines == hunk.orig_start
+                    && last.boundary == hunk.boundary
+                    && last.ignored == hunk.ignored
+                    && last.unblamable == hunk.unblamable
+                    && last.previous == hunk.previous =>
+            {
+                last.lines += hunk.lines;
+            }
+            _ => joined.push(hunk),
+        }
+    }
+    joined
+}
+
+// Writes hunks the way `git blame --incremental` does, giving the details
+// of each commit only the first time it shows up.
+pub struct IncrementalWriter<'a, W: Write> {
+    repo: &'a Repository,
+    writer: W,
+    mailmap: Mailmap,
+    seen: HashSet<Oid>,
+}
+
+impl<'a, W: Write> IncrementalWriter<'a, W> {
+    pub fn new(repo: &'a Repository, writer: W) -> Result<Self> {
+        Ok(Self {
+            repo,
+            writer,
+            mailmap: repo.mailmap()?,
+            seen: HashSet::new(),
+        })
+    }
+
+    pub fn write(&mut self, hunk: &BlameHunk) -> Result<()> {
+        writeln!(
+            self.writer,
+            "{} {} {} {}",
+            hunk.commit,
+            hunk.orig_start + 1,
+            hunk.final_start + 1,
+            hunk.lines
+        )?;
+        if self.seen.insert(hunk.commit) {
+            let commit = self
+                .repo
+                .odb()
+                .get(&hunk.commit)?
+                .into_commit()
+                .ok_or_else(|| Error::InvalidObject(format!("{} is not a commit", hunk.commit)))?;
+            for (role, identity) in [
+                ("author", commit.author()),
+                ("committer", commit.committer()),
+            ] {
+                let identity = self.mailmap.resolve(identity);
+                let tz = identity.tz().unwrap_or(0);
+                write!(self.writer, "{} ", role)?;
+                self.writer
+                    .write_all(&commit.decode(identity.name()).into_bytes())?;
+                write!(self.writer, "\n{}-mail <", role)?;
+                self.writer.write_all(identity.email())?;
+                writeln!(self.writer, ">")?;
+                writeln!(
+                    self.writer,
+                    "{}-time {}",
+                    role,
+                    identity.time().unwrap_or(0)
+                )?;
+                writeln!(
+                    self.writer,
+                    "{}-tz {}{:04}",
+                    role,
+                    if tz < 0 { '-' } else { '+' },
+                    tz.abs()
+                )?;
+            }
+            let message = commit.decoded_message();
+            let summary = message.lines().find(|line| !line.trim().is_empty());
+            writeln!(self.writer, "summary {}", summary.unwrap_or("").trim_end())?;
+            if hunk.boundary {
+                writeln!(self.writer, "boundary")?;
+            }
+        }
+        if let Some((commit, path)) = &hunk.previous {
+            write!(self.writer, "previous {} ", commit)?;
+            self.writer.write_all(&quote_path(path))?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(b"filename ")?;
+        self.writer.write_all(&quote_path(&hunk.path))?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl Repository {
+    // Which commit each line of the file at `path` in `commit` comes from.
+    pub fn blame(&self, path: &[u8], commit: &Oid, options: &BlameOptions) -> Result<Blame> {
+        let mut hunks = Vec::new();
+        let lines = self.blame_incremental(path, commit, options, |hunk| {
+            hunks.push(hunk.clone());
+            Ok(())
+        })?;
+        Ok(Blame {
+            path: path.to_vec(),
+            lines,
+            hunks: coalesce(hunks),
+        })
+    }
+
+    // Like `blame`, giving each hunk to `emit` as soon as it is known,
+    // in no particular order, and returning the lines of the file.
+    pub fn blame_incremental(
+        &self,
+        path: &[u8],
+        commit: &Oid,
+        options: &BlameOptions,
+        mut emit: impl FnMut(&BlameHunk) -> Result<()>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let mut ignored: HashSet<Oid> = options.ignore_revs.iter().copied().collect();
+        let mut files = options.ignore_revs_files.clone();
+        let config = self.config()?;
+        for file in config.get_all("blame.ignorerevsfile") {
+            files.push(match self.workdir() {
+                Some(workdir) => workdir.join(file),
+                None => PathBuf::from(file),
+            });
+        }
+        for file in files {
+            ignored.extend(read_ignore_revs(&fs::read(file)?)?);
+        }
+        let interesting = if options.hidden.is_empty() {
+            None
+        } else {
+            let walk = self.rev_walk(&[*commit], &options.hidden, &Default::default())?;
+            Some(walk.into_iter().map(|entry| entry.oid).collect())
+        };
+
+        let mut scoreboard = Scoreboard {
+            repo: self,
+            options,
+            ignored,
+            interesting,
+            commits: HashMap::new(),
+            blobs: HashMap::new(),
+            files: HashMap::new(),
+            pending: BTreeMap::new(),
+        };
+        let origin = Origin {
+            commit: *commit,
+            path: path.to_vec(),
+        };
+        let lines = scoreboard.origin_lines(&origin)?.lines.clone();
+        let mut ranges = options.ranges.clone();
+        if ranges.is_empty() {
+            ranges.push(0..lines.len());
+        }
+        ranges.sort_by_key(|range| range.start);
+        let mut entries: Vec<Entry> = Vec::new();
+        for range in ranges {
+            if range.start >= range.end || range.end > lines.len() {
+                if lines.is_empty() && range == (0..0) {
+                    continue;
+                }
+                return Err(Error::InvalidBlame(format!(
+                    "file {} has only {} lines",
+                    String::from_utf8_lossy(path),
+                    lines.len()
+                )));
+            }
+            match entries.last_mut() {
+                Some(last) if range.start <= last.final_start + last.len => {
+                    last.len = last.len.max(range.end - last.final_start);
+                }
+                _ => entries.push(Entry {
+                    final_start: range.start,
+                    len: range.len(),
+                    origin: origin.clone(),
+                    start: range.start,
+                    ignored: false,
+                    unblamable: false,
+                }),
+            }
+        }
+        if !entries.is_empty() {
+            scoreboard.pending.insert(origin, entries);
+        }
+        scoreboard.run(&mut emit)?;
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odb::{ObjectType, Odb};
+    use crate::testutil::{identity, TempDir};
+    use crate::tree::{Mode, Name, Tree, TreeEntry};
+
+    fn commit(repo: &Repository, files: &[(&str, &str)], parents: &[Oid]) -> Oid {
+        let mut tree = Tree::new();
+        for (path, content) in files {
+            let blob = repo
+                .odb()
+                .write(ObjectType::Blob, content.as_bytes())
+                .unwrap();
+            tree.append_entry(
+                Name(path.as_bytes().to_vec()),
+                TreeEntry::new(blob, Mode::from_u32(0o100644)),
+            );
+        }
+        let mut bytes = Vec::new();
+        tree.write(&mut bytes).unwrap();
+        let tree = repo.odb().write(ObjectType::Tree, &bytes).unwrap();
+        repo.commit(None, &identity(), &identity(), "commit\n", &tree, parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn prefers_same_path_in_any_parent_to_a_rename() {
+        let tmp = TempDir::new();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let base = commit(&repo, &[("f", "a\nb\nc\n")], &[]);
+        let side = commit(&repo, &[("f", "a\nb\nc\n"), ("g", "a\nb\nc\nd\n")], &[base]);
+        // g looks renamed from f of the first parent, but the second parent
+        // has it as it is.
+        let merge = commit(&repo, &[("g", "a\nb\nc\nd\n")], &[base, side]);
+
+        let blame = repo.blame(b"g", &merge, &BlameOptions::new()).unwrap();
+        assert!(!blame.hunks().is_empty());
+        for hunk in blame.hunks() {
+            assert_eq!(hunk.commit, side);
+            assert_eq!(hunk.path, b"g");
+        }
+    }
+
+    #[test]
+    fn follows_renames_when_no_parent_has_the_path() {
+        let tmp = TempDir::new();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let base = commit(&repo, &[("f", "a\nb\nc\n")], &[]);
+        let renamed = commit(&repo, &[("g", "a\nb\nc\nd\n")], &[base]);
+
+        let blame = repo.blame(b"g", &renamed, &BlameOptions::new()).unwrap();
+        let owners: Vec<(Oid, &[u8])> = (0..4)
+            .map(|line| {
+                let hunk = blame.hunk_for_line(line).unwrap();
+                (hunk.commit, &hunk.path[..])
+            })
+            .collect();
+        assert_eq!(
+            owners,
+            [
+                (base, &b"f"[..]),
+                (base, b"f"),
+                (base, b"f"),
+                (renamed, b"g")
+            ]
+        );
+    }
+}
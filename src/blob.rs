@@ -1,3 +1,7 @@
+use std::path::Path;
+
+use crate::error::Result;
+
 pub struct Blob {
     content: Vec<u8>,
 }
@@ -9,6 +13,15 @@ impl Blob {
         }
     }
 
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read(path)?;
+        Ok(Self { content })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.content
+    }
+
     pub fn as_str(&self) -> &str {
         std::str::from_utf8(&self.content).unwrap()
     }
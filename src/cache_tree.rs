@@ -0,0 +1,236 @@
+use std::cmp::Ordering;
+use std::io::BufRead;
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::index::IndexEntry;
+use crate::odb::{ObjectType, Odb};
+use crate::oid::Oid;
+use crate::tree::{Mode, Name, Tree, TreeEntry};
+
+const TREE_MODE: u32 = 0o040000;
+
+#[derive(Debug)]
+pub struct CacheTree {
+    pub name: Vec<u8>,
+    pub entry_count: i32,
+    pub id: Option<Oid>,
+    pub children: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    pub fn new() -> Self {
+        Self::with_name(Vec::new())
+    }
+
+    fn with_name(name: Vec<u8>) -> Self {
+        Self {
+            name,
+            entry_count: -1,
+            id: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.id.is_some()
+    }
+
+    pub fn find(&self, path: &[u8]) -> Option<&CacheTree> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        let (name, rest) = split_first_component(path);
+        self.child(name)?.find(rest.unwrap_or(&[]))
+    }
+
+    fn child(&self, name: &[u8]) -> Option<&CacheTree> {
+        self.child_position(name)
+            .ok()
+            .map(|position| &self.children[position])
+    }
+
+    // Subtrees are kept in git's order: shorter names first, then bytewise.
+    fn child_position(&self, name: &[u8]) -> std::result::Result<usize, usize> {
+        self.children
+            .binary_search_by(|child| compare_subtree_names(&child.name, name))
+    }
+
+    fn child_mut(&mut self, name: &[u8]) -> &mut CacheTree {
+        let position = match self.child_position(name) {
+            Ok(position) => position,
+            Err(position) => {
+                self.children
+                    .insert(position, CacheTree::with_name(name.to_vec()));
+                position
+            }
+        };
+        &mut self.children[position]
+    }
+
+    pub fn invalidate(&mut self, path: &[u8]) {
+        self.entry_count = -1;
+        match split_first_component(path) {
+            (name, None) => {
+                // A file replacing a directory makes the whole subtree stale.
+                if let Ok(position) = self.child_position(name) {
+                    self.children.remove(position);
+                }
+            }
+            (name, Some(rest)) => {
+                if let Ok(position) = self.child_position(name) {
+                    self.children[position].invalidate(rest);
+                }
+            }
+        }
+    }
+
+    pub fn update<O: Odb>(&mut self, entries: &[&IndexEntry], odb: &O) -> Result<Oid> {
+        if let Some(entry) = entries.iter().find(|entry| entry.stage() != 0) {
+            return Err(Error::UnmergedIndex(entry.path.clone()));
+        }
+        self.update_one(entries, 0, odb)?;
+        Ok(self.id.unwrap())
+    }
+
+    fn update_one<O: Odb>(
+        &mut self,
+        entries: &[&IndexEntry],
+        base_len: usize,
+        odb: &O,
+    ) -> Result<usize> {
+        if self.is_valid() {
+            return Ok(self.entry_count as usize);
+        }
+
+        let mut tree = Tree::new();
+        let mut visited = Vec::new();
+        let mut has_intent_to_add = false;
+        let mut i = 0;
+        while i < entries.len() {
+            let entry = entries[i];
+            let path = &entry.path[base_len..];
+            match split_first_component(path) {
+                (name, Some(_)) => {
+                    let mut prefix = entry.path[..base_len + name.len()].to_vec();
+                    prefix.push(b'/');
+                    let end = i + entries[i..]
+                        .iter()
+                        .take_while(|entry| entry.path.starts_with(&prefix))
+                        .count();
+                    visited.push(name.to_vec());
+                    let child = self.child_mut(name);
+                    child.update_one(&entries[i..end], prefix.len(), odb)?;
+                    has_intent_to_add |= child.entry_count < 0;
+                    if let Some(id) = child.id {
+                        tree.append_entry(
+                            Name(name.to_vec()),
+                            TreeEntry::new(id, Mode::from_u32(TREE_MODE)),
+                        );
+                    }
+                    i = end;
+                }
+                (name, None) => {
+                    if entry.intent_to_add() {
+                        has_intent_to_add = true;
+                    } else {
+                        tree.append_entry(
+                            Name(name.to_vec()),
+                            TreeEntry::new(entry.id, Mode::from_u32(entry.mode)),
+                        );
+                    }
+                    i += 1;
+                }
+            }
+        }
+
+        self.children.retain(|child| visited.contains(&child.name));
+
+        if tree.is_empty() && base_len != 0 {
+            self.id = None;
+            self.entry_count = -1;
+            return Ok(entries.len());
+        }
+        let mut buf = Vec::new();
+        tree.write(&mut buf)?;
+        self.id = Some(odb.write(ObjectType::Tree, &buf)?);
+        // Entries that are only intended to be added never reach the tree,
+        // so the node has to be rebuilt on the next write_tree.
+        self.entry_count = if has_intent_to_add {
+            -1
+        } else {
+            entries.len() as i32
+        };
+        Ok(entries.len())
+    }
+
+    pub fn from_reader<B: BufRead>(mut reader: B) -> Result<Self> {
+        Self::read_node(&mut reader)
+    }
+
+    fn read_node(reader: &mut dyn BufRead) -> Result<Self> {
+        let mut name = Vec::new();
+        reader.read_until(0x00, &mut name)?;
+        if name.pop() != Some(0x00) {
+            return Err(Error::InvalidIndex("corrupt cache tree".to_string()));
+        }
+        let mut entry_count = Vec::new();
+        reader.read_until(b' ', &mut entry_count)?;
+        entry_count.pop();
+        let entry_count: i32 = String::from_utf8(entry_count)?.parse()?;
+        let mut subtree_count = Vec::new();
+        reader.read_until(b'\n', &mut subtree_count)?;
+        subtree_count.pop();
+        let subtree_count: usize = String::from_utf8(subtree_count)?.parse()?;
+        let id = if entry_count >= 0 {
+            Some(Oid::from_reader(&mut *reader)?)
+        } else {
+            None
+        };
+        let mut children = Vec::with_capacity(subtree_count);
+        for _ in 0..subtree_count {
+            children.push(CacheTree::read_node(reader)?);
+        }
+        Ok(Self {
+            name,
+            entry_count,
+            id,
+            children,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        self.write_node(&mut writer)
+    }
+
+    fn write_node(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.name)?;
+        writeln!(writer, "\0{} {}", self.entry_count, self.children.len())?;
+        if self.entry_count >= 0 {
+            if let Some(id) = self.id {
+                writer.write_all(id.as_bytes())?;
+            }
+        }
+        for child in &self.children {
+            child.write_node(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for CacheTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn split_first_component(path: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match path.iter().position(|&b| b == b'/') {
+        Some(slash) => (&path[..slash], Some(&path[slash + 1..])),
+        None => (path, None),
+    }
+}
+
+fn compare_subtree_names(a: &[u8], b: &[u8]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::path::Path;
+
+use crate::attributes::AttrSource;
+use crate::convert::{Converter, Notice};
+use crate::error::{Error, Result};
+use crate::filter::{FilterMeta, Smudged};
+use crate::ignore::Excludes;
+use crate::index::{Index, IndexEntry};
+use crate::oid::Oid;
+use crate::repo::Repository;
+use crate::stat::StatOptions;
+use crate::status::{is_missing, StatusCode};
+use crate::tree::flatten_tree;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFGITLINK: u32 = 0o160000;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckoutOptions {
+    // Throw away local changes and untracked files in the way.
+    pub force: bool,
+    // When switching branches, merge local changes that are in the way
+    // instead of failing.
+    pub merge: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CheckoutProgress<'a> {
+    pub path: &'a [u8],
+    pub completed: usize,
+    pub total: usize,
+    // What filters had to say about `path`. Notices that come after a path
+    // is done report it again, with `completed` unchanged.
+    pub notices: &'a [Notice],
+}
+
+// A change to the working tree, made after the index has been updated.
+#[derive(Debug, Clone)]
+pub(crate) enum Update {
+    Remove(Vec<u8>),
+    Write(IndexEntry),
+}
+
+impl CheckoutOptions {
+    pub fn new() -> Self {
+        Self {
+            force: false,
+            merge: false,
+        }
+    }
+}
+
+impl Update {
+    fn path(&self) -> &[u8] {
+        match self {
+            Update::Remove(path) => path,
+            Update::Write(entry) => &entry.path,
+        }
+    }
+}
+
+fn workdir_path(workdir: &Path, path: &[u8]) -> std::path::PathBuf {
+    workdir.join(OsStr::from_bytes(path))
+}
+
+fn leading_dirs(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    path.iter()
+        .enumerate()
+        .filter(|&(_, &b)| b == b'/')
+        .map(move |(i, _)| &path[..i])
+}
+
+// Removes whatever is at `path`, a whole directory included.
+fn remove_path(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(ref e) if is_missing(e) => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+// Removes the directories leading to `path` that are left empty.
+fn remove_empty_parents(workdir: &Path, path: &[u8]) {
+    for dir in leading_dirs(path).collect::<Vec<_>>().into_iter().rev() {
+        if fs::remove_dir(workdir_path(workdir, dir)).is_err() {
+            break;
+        }
+    }
+}
+
+// Makes room for `path`: its leading directories exist and nothing is at
+// `path` itself, unless it is the directory of a gitlink.
+fn prepare_path(workdir: &Path, path: &[u8], keep_dir: bool) -> Result<()> {
+    for dir in leading_dirs(path) {
+        let full_path = workdir_path(workdir, dir);
+        match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => fs::remove_file(&full_path)?,
+            Err(ref e) if is_missing(e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        fs::create_dir(&full_path)?;
+    }
+    let full_path = workdir_path(workdir, path);
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() && keep_dir => Ok(()),
+        Ok(_) => remove_path(&full_path),
+        Err(ref e) if is_missing(e) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_content(workdir: &Path, entry: &IndexEntry, content: &[u8]) -> Result<()> {
+    let full_path = entry.workdir_path(workdir);
+    if entry.mode & S_IFMT == S_IFLNK {
+        symlink(OsStr::from_bytes(content), &full_path)?;
+        return Ok(());
+    }
+    let mode = if entry.mode & 0o100 != 0 {
+        0o777
+    } else {
+        0o666
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&full_path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+// Records the stat data of a freshly written file in the index.
+fn refresh_entry(workdir: &Path, index: &mut Index, path: &[u8]) -> Result<()> {
+    if let Some(entry) = index.get_mut(path, 0) {
+        let metadata = fs::symlink_metadata(entry.workdir_path(workdir))?;
+        entry.update_stat(&metadata);
+    }
+    Ok(())
+}
+
+impl Repository {
+    // Makes the index and the working tree match `tree`. Unless forced,
+    // only paths that differ from the index are touched, and local changes
+    // or untracked files that would be lost make it fail without changing
+    // anything.
+    pub fn checkout_tree(
+        &self,
+        tree: &Oid,
+        options: &CheckoutOptions,
+        mut progress: impl FnMut(&CheckoutProgress),
+    ) -> Result<()> {
+        let workdir = self.require_workdir()?;
+        let mut index = self.index()?;
+        let target = flatten_tree(self.odb(), tree)?;
+        if !options.force {
+            if let Some(conflict) = index.conflicts().first() {
+                return Err(Error::UnmergedIndex(conflict.path.to_vec()));
+            }
+        }
+
+        let converter = self.converter(AttrSource::CheckIn)?;
+        let stat_options = self.stat_options()?;
+        let mut updates = Vec::new();
+        let mut last_path: Option<&[u8]> = None;
+        for entry in index.iter() {
+            if last_path == Some(&entry.path[..]) {
+                continue;
+            }
+            last_path = Some(&entry.path);
+            if !target.contains_key(&entry.path) {
+                updates.push(Update::Remove(entry.path.clone()));
+            }
+        }
+        for (path, item) in &target {
+            let unchanged = match index.get(path, 0) {
+                Some(entry) if entry.mode == item.mode && entry.id == item.id => {
+                    !options.force
+                        || self
+                            .worktree_status(workdir, &index, entry, &stat_options, &converter)?
+                            .0
+                            == StatusCode::Unmodified
+                }
+                _ => false,
+            };
+            if !unchanged {
+                let entry = IndexEntry::with_id(path.clone(), item.mode, item.id, 0);
+                updates.push(Update::Write(entry));
+            }
+        }
+        if !options.force {
+            self.check_updates(workdir, &index, &updates, &stat_options, &converter)?;
+        }
+
+        let meta = FilterMeta {
+            ref_name: None,
+            treeish: Some(*tree),
+            blob: None,
+        };
+        self.apply_updates(workdir, &mut index, updates, &meta, &mut progress)?;
+        self.write_index(&mut index)
+    }
+
+    // Refuses updates that would lose local changes to tracked files or
+    // overwrite untracked ones. Ignored files are fair game.
+    pub(crate) fn check_updates(
+        &self,
+        workdir: &Path,
+        index: &Index,
+        updates: &[Update],
+        stat_options: &StatOptions,
+        converter: &Converter,
+    ) -> Result<()> {
+        let excludes = self.excludes()?;
+        let mut changed = Vec::new();
+        let mut untracked = Vec::new();
+        for update in updates {
+            let path = update.path();
+            match index.get(path, 0) {
+                Some(entry) => {
+                    let status = self
+                        .worktree_status(workdir, index, entry, stat_options, converter)?
+                        .0;
+                    if status == StatusCode::Modified || status == StatusCode::TypeChanged {
+                        changed.push(path.to_vec());
+                    } else if entry.mode & S_IFMT != S_IFGITLINK {
+                        // A directory where the file was may hold untracked
+                        // files.
+                        self.untracked_in_dir(workdir, index, &excludes, path, &mut untracked)?;
+                    }
+                }
+                None => {
+                    if let Update::Write(_) = update {
+                        self.check_absent(workdir, index, &excludes, path, &mut untracked)?;
+                    }
+                }
+            }
+        }
+        if !changed.is_empty() {
+            return Err(Error::LocalChanges(changed));
+        }
+        if !untracked.is_empty() {
+            untracked.sort();
+            untracked.dedup();
+            return Err(Error::UntrackedOverwritten(untracked));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_absent(
+        &self,
+        workdir: &Path,
+        index: &Index,
+        excludes: &Excludes,
+        path: &[u8],
+        untracked: &mut Vec<Vec<u8>>,
+    ) -> Result<()> {
+        for dir in leading_dirs(path) {
+            match fs::symlink_metadata(workdir_path(workdir, dir)) {
+                Ok(metadata) if metadata.is_dir() => continue,
+                Ok(_) => {
+                    // A tracked file in the way is checked on its own.
+                    if !index.contains(dir) && !excludes.is_ignored(dir, false)? {
+                        untracked.push(dir.to_vec());
+                    }
+                    return Ok(());
+                }
+                Err(ref e) if is_missing(e) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match fs::symlink_metadata(workdir_path(workdir, path)) {
+            Ok(metadata) if metadata.is_dir() => {
+                self.untracked_in_dir(workdir, index, excludes, path, untracked)
+            }
+            Ok(_) => {
+                if !excludes.is_ignored(path, false)? {
+                    untracked.push(path.to_vec());
+                }
+                Ok(())
+            }
+            Err(ref e) if is_missing(e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Collects the files below `dir` that are neither tracked nor ignored.
+    fn untracked_in_dir(
+        &self,
+        workdir: &Path,
+        index: &Index,
+        excludes: &Excludes,
+        dir: &[u8],
+        untracked: &mut Vec<Vec<u8>>,
+    ) -> Result<()> {
+        let entries = match fs::read_dir(workdir_path(workdir, dir)) {
+            Ok(entries) => entries,
+            Err(ref e) if is_missing(e) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let mut path = dir.to_vec();
+            path.push(b'/');
+            path.extend_from_slice(entry.file_name().as_bytes());
+            let is_dir = entry.file_type()?.is_dir();
+            if index.contains(&path) || excludes.is_ignored(&path, is_dir)? {
+                continue;
+            }
+            if is_dir && !path.ends_with(b"/.git") {
+                self.untracked_in_dir(workdir, index, excludes, &path, untracked)?;
+            } else {
+                untracked.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    // Applies `updates` to the index, then to the working tree: removals
+    // first, so that files can replace directories and the other way round.
+    pub(crate) fn apply_updates(
+        &self,
+        workdir: &Path,
+        index: &mut Index,
+        updates: Vec<Update>,
+        meta: &FilterMeta,
+        progress: &mut dyn FnMut(&CheckoutProgress),
+    ) -> Result<()> {
+        for update in &updates {
+            match update {
+                Update::Remove(path) => {
+                    index.remove_entry(path);
+                }
+                Update::Write(entry) => index.replace_entry(entry.clone()),
+            }
+        }
+        // The attributes of what is being checked out apply.
+        let converter = self.converter_for(AttrSource::CheckOut, index)?;
+
+        let total = updates.len();
+        let mut completed = 0;
+        let mut report = |path: &[u8], done: bool, notices: &[Notice]| {
+            if done {
+                completed += 1;
+            }
+            progress(&CheckoutProgress {
+                path,
+                completed,
+                total,
+                notices,
+            });
+        };
+        let (removals, writes): (Vec<Update>, Vec<Update>) = updates
+            .into_iter()
+            .partition(|update| matches!(update, Update::Remove(_)));
+        for update in removals {
+            let path = update.path();
+            let full_path = workdir_path(workdir, path);
+            match fs::symlink_metadata(&full_path) {
+                // Only an empty gitlink directory goes away.
+                Ok(metadata) if metadata.is_dir() => {
+                    let _ = fs::remove_dir(&full_path);
+                }
+                Ok(_) => fs::remove_file(&full_path)?,
+                Err(ref e) if is_missing(e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            remove_empty_parents(workdir, path);
+            report(path, true, &[]);
+        }
+
+        let mut delayed = HashMap::new();
+        for update in writes {
+            let entry = match update {
+                Update::Write(entry) => entry,
+                Update::Remove(_) => unreachable!(),
+            };
+            let is_gitlink = entry.mode & S_IFMT == S_IFGITLINK;
+            prepare_path(workdir, &entry.path, is_gitlink)?;
+            if is_gitlink {
+                fs::create_dir_all(entry.workdir_path(workdir))?;
+                report(&entry.path, true, &[]);
+                continue;
+            }
+            let content = self.read_blob_content(&entry.id)?;
+            let content = if entry.mode & S_IFMT == S_IFLNK {
+                Smudged::Ready(content)
+            } else {
+                let meta = FilterMeta {
+                    blob: Some(entry.id),
+                    ..meta.clone()
+                };
+                converter.smudge(&entry.path, content, Some(&meta), true)?
+            };
+            match content {
+                Smudged::Ready(content) => {
+                    write_content(workdir, &entry, &content)?;
+                    refresh_entry(workdir, index, &entry.path)?;
+                }
+                Smudged::Delayed => {
+                    delayed.insert(entry.path.clone(), entry.clone());
+                }
+            }
+            report(&entry.path, true, &converter.take_notices());
+        }
+
+        while converter.has_delayed() {
+            for path in converter.available_delayed()? {
+                let entry = match delayed.remove(&path) {
+                    Some(entry) => entry,
+                    None => {
+                        let warning = Notice::Warning(format!(
+                            "smudge: path '{}' not delayed",
+                            String::from_utf8_lossy(&path)
+                        ));
+                        report(&path, false, &[warning]);
+                        continue;
+                    }
+                };
+                let content = converter.finish_delayed(&path)?;
+                write_content(workdir, &entry, &content)?;
+                refresh_entry(workdir, index, &path)?;
+            }
+        }
+        let mut unfiltered = converter.take_unfiltered();
+        unfiltered.extend(delayed.into_keys());
+        if let Some(path) = unfiltered.first() {
+            // A filter that failed while asked for delayed paths left them
+            // unfiltered.
+            let notices = converter.take_notices();
+            if !notices.is_empty() {
+                report(path, false, &notices);
+            }
+            return Err(Error::Conversion(format!(
+                "'{}' was not filtered properly",
+                String::from_utf8_lossy(path)
+            )));
+        }
+        Ok(())
+    }
+}
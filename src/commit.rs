@@ -1,32 +1,266 @@
+use crate::date::{show_date, DateMode};
+use crate::encoding;
+use crate::error::{Error, Result};
+use crate::odb::{ObjectType, Odb};
 use crate::oid::Oid;
+use crate::repo::Repository;
 use chrono::offset::FixedOffset;
-use chrono::DateTime;
-use chrono::TimeZone;
+use chrono::{DateTime, TimeZone};
+use std::convert::TryFrom;
 use std::fmt;
+use std::ops::Range;
 
-#[derive(Debug)]
+// A name, email and date as git records them in commits, kept as the
+// bytes they were read from so that malformed lines survive a round trip.
+#[derive(Debug, Clone)]
 pub struct Identity {
-    name: Vec<u8>,
-    email: Vec<u8>,
-    datetime: DateTime<FixedOffset>,
+    raw: Vec<u8>,
+    name: Range<usize>,
+    email: Range<usize>,
+    date: Option<Range<usize>>,
+    tz: Option<Range<usize>>,
+}
+
+// What git strips from either end of names and emails.
+fn is_crud(c: u8) -> bool {
+    c <= b' ' || b".,:;<>\"\\'".contains(&c)
+}
+
+// `text` without crud at either end, nor the newlines and angle brackets
+// that would end its part of an identity line early.
+fn without_crud(text: &[u8]) -> Vec<u8> {
+    let start = text.iter().position(|&c| !is_crud(c)).unwrap_or(text.len());
+    let end = text
+        .iter()
+        .rposition(|&c| !is_crud(c))
+        .map_or(start, |i| i + 1);
+    text[start..end]
+        .iter()
+        .copied()
+        .filter(|c| !b"\n<>".contains(c))
+        .collect()
 }
 
 impl Identity {
+    // Names and emails are cleaned up the way git does, so that they cannot
+    // break the line they go on.
     pub fn new(name: Vec<u8>, email: Vec<u8>, datetime: DateTime<FixedOffset>) -> Self {
-        Self {
-            name,
-            email,
-            datetime,
+        let offset = datetime.offset().local_minus_utc() / 60;
+        let sign = if offset < 0 { '-' } else { '+' };
+        let mut raw = without_crud(&name);
+        raw.extend_from_slice(b" <");
+        raw.extend_from_slice(&without_crud(&email));
+        raw.extend_from_slice(
+            format!(
+                "> {} {}{:02}{:02}",
+                datetime.timestamp(),
+                sign,
+                offset.abs() / 60,
+                offset.abs() % 60
+            )
+            .as_bytes(),
+        );
+        Self::parse_lenient(&raw)
+    }
+
+    // Reads an identity the way git shows them: whatever comes before `<`
+    // is the name, up to `>` the email, and a date follows only if it looks
+    // like one. Lines without an email keep their bytes but have an empty
+    // name and email.
+    pub fn parse_lenient(line: &[u8]) -> Self {
+        let mut identity = Self {
+            raw: line.to_vec(),
+            name: 0..0,
+            email: 0..0,
+            date: None,
+            tz: None,
+        };
+        let mail_begin = match line.iter().position(|&c| c == b'<') {
+            Some(i) => i + 1,
+            None => return identity,
+        };
+        let mail_end = match line[mail_begin..].iter().position(|&c| c == b'>') {
+            Some(i) => mail_begin + i,
+            None => return identity,
+        };
+        let name_end = line[..mail_begin - 1]
+            .iter()
+            .rposition(|c| !c.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        identity.name = 0..name_end;
+        identity.email = mail_begin..mail_end;
+
+        // The date comes after the last `>`, which skips a stray `>` in
+        // the email.
+        let skip_space = |mut i: usize| {
+            while i < line.len() && line[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            i
+        };
+        let digits = |i: usize| line[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let last = line.iter().rposition(|&c| c == b'>').unwrap();
+        let date_begin = skip_space(last + 1);
+        let date_len = digits(date_begin);
+        if date_len == 0 {
+            return identity;
+        }
+        let tz_begin = skip_space(date_begin + date_len);
+        if !matches!(line.get(tz_begin), Some(b'+' | b'-')) {
+            return identity;
+        }
+        let tz_len = digits(tz_begin + 1);
+        if tz_len == 0 {
+            return identity;
+        }
+        identity.date = Some(date_begin..date_begin + date_len);
+        identity.tz = Some(tz_begin..tz_begin + 1 + tz_len);
+        identity
+    }
+
+    // Reads an identity, refusing the lines `git fsck` reports:
+    // `name <email> seconds +hhmm` with one space between each part.
+    pub fn parse(line: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Err(Error::InvalidObject(format!(
+                "invalid author/committer line - {}",
+                reason
+            )))
+        };
+        let find = |from: usize| {
+            from + line[from..]
+                .iter()
+                .take_while(|c| !b"<>\n".contains(c))
+                .count()
+        };
+        if line.first() == Some(&b'<') {
+            return invalid("missing space before email");
+        }
+        let mut p = find(0);
+        match line.get(p) {
+            Some(b'>') => return invalid("bad name"),
+            Some(b'<') => {}
+            _ => return invalid("missing email"),
+        }
+        if line[p - 1] != b' ' {
+            return invalid("missing space before email");
+        }
+        p = find(p + 1);
+        if line.get(p) != Some(&b'>') {
+            return invalid("bad email");
+        }
+        p += 1;
+        if line.get(p) != Some(&b' ') {
+            return invalid("missing space before date");
+        }
+        p += 1;
+        if line.get(p) == Some(&b'0') && line.get(p + 1) != Some(&b' ') {
+            return invalid("zero-padded date");
+        }
+        let date_len = line[p..].iter().take_while(|c| c.is_ascii_digit()).count();
+        if date_len > 0 && parse_digits(&line[p..p + date_len]).is_none() {
+            return invalid("date causes integer overflow");
+        }
+        if date_len == 0 || line.get(p + date_len) != Some(&b' ') {
+            return invalid("bad date");
+        }
+        p += date_len + 1;
+        let tz = &line[p.min(line.len())..];
+        if tz.len() != 5 || !b"+-".contains(&tz[0]) || !tz[1..].iter().all(u8::is_ascii_digit) {
+            return invalid("bad time zone");
+        }
+        Ok(Self::parse_lenient(line))
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.raw[self.name.clone()]
+    }
+
+    pub fn email(&self) -> &[u8] {
+        &self.raw[self.email.clone()]
+    }
+
+    // Whether the line has `<email>` at all, which git needs to make sense
+    // of the rest.
+    pub fn has_email(&self) -> bool {
+        self.email.end != 0
+    }
+
+    // The seconds since the epoch as written, even if they do not fit.
+    pub fn date_bytes(&self) -> Option<&[u8]> {
+        Some(&self.raw[self.date.clone()?])
+    }
+
+    // Seconds since the epoch, if the line has a date that fits.
+    pub fn time(&self) -> Option<i64> {
+        parse_digits(&self.raw[self.date.clone()?])
+    }
+
+    // The offset as git writes it, a number like -130 for -01:30.
+    pub fn tz(&self) -> Option<i64> {
+        let tz = &self.raw[self.tz.clone()?];
+        let hhmm = parse_digits(&tz[1..]).filter(|&hhmm| hhmm < i64::from(i32::MAX))?;
+        Some(if tz[0] == b'-' { -hhmm } else { hhmm })
+    }
+
+    // The date with its offset, if both are there and make sense.
+    pub fn datetime(&self) -> Option<DateTime<FixedOffset>> {
+        let tz = self.tz()?;
+        let (hours, minutes) = (tz.abs() / 100, tz.abs() % 100);
+        let seconds = i32::try_from((hours * 60 + minutes) * 60).ok()?;
+        let offset = if tz < 0 {
+            FixedOffset::west_opt(seconds)?
+        } else {
+            FixedOffset::east_opt(seconds)?
+        };
+        offset.timestamp_opt(self.time()?, 0).single()
+    }
+
+    // The date as `mode` shows it. Like git, a missing or overflowing date
+    // is shown as the epoch.
+    pub fn format_date(&self, mode: DateMode) -> String {
+        match self.time() {
+            Some(time) => show_date(time, self.tz().unwrap_or(0), mode),
+            None => show_date(0, 0, mode),
+        }
+    }
+
+    // The same identity and date under another name and email. Lines
+    // without an email are kept as they are.
+    pub fn with_name_and_email(&self, name: &[u8], email: &[u8]) -> Self {
+        if !self.has_email() {
+            return self.clone();
         }
+        let mut raw = name.to_vec();
+        raw.extend_from_slice(b" <");
+        raw.extend_from_slice(email);
+        raw.extend_from_slice(&self.raw[self.email.end..]);
+        Self::parse_lenient(&raw)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    // The identity as git writes it in commits and reflogs:
+    // `name <email> seconds +hhmm`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone()
     }
 }
 
+// The number `digits` make, unless it is too large for a timestamp.
+fn parse_digits(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
 #[derive(Debug)]
 pub struct Commit {
     tree: Oid,
     parents: Vec<Oid>,
     author: Identity,
     committer: Identity,
+    extra_headers: Vec<(Vec<u8>, Vec<u8>)>,
     message: Vec<u8>,
 }
 
@@ -39,12 +273,98 @@ impl Commit {
         message: Vec<u8>,
     ) -> Self {
         Self {
-            tree: tree,
-            parents: parents,
-            author: author,
-            committer: committer,
-            message: message,
+            tree,
+            parents,
+            author,
+            committer,
+            extra_headers: Vec::new(),
+            message,
+        }
+    }
+
+    pub fn tree(&self) -> &Oid {
+        &self.tree
+    }
+
+    pub fn parents(&self) -> &[Oid] {
+        &self.parents
+    }
+
+    pub fn author(&self) -> &Identity {
+        &self.author
+    }
+
+    pub fn committer(&self) -> &Identity {
+        &self.committer
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    // Headers after the committer, like `encoding` or `gpgsig`, in order.
+    // Values of more than one line are joined with newlines.
+    pub fn extra_headers(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.extra_headers
+    }
+
+    pub fn add_extra_header(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.extra_headers.push((key, value));
+    }
+
+    // The encoding the `encoding` header names for the message and the
+    // identities, which are UTF-8 without it.
+    pub fn encoding(&self) -> Option<&[u8]> {
+        self.extra_headers
+            .iter()
+            .find(|(key, _)| key == b"encoding")
+            .map(|(_, value)| value.as_slice())
+    }
+
+    // Text from this commit decoded from its encoding.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        encoding::decode(bytes, self.encoding())
+    }
+
+    pub fn decoded_message(&self) -> String {
+        self.decode(&self.message)
+    }
+
+    pub fn decoded_author_name(&self) -> String {
+        self.decode(self.author.name())
+    }
+
+    pub fn decoded_committer_name(&self) -> String {
+        self.decode(self.committer.name())
+    }
+
+    // The commit object as git stores it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("tree {}\n", self.tree).into_bytes();
+        for parent in &self.parents {
+            bytes.extend_from_slice(format!("parent {}\n", parent).as_bytes());
+        }
+        bytes.extend_from_slice(b"author ");
+        bytes.extend_from_slice(&self.author.to_bytes());
+        bytes.extend_from_slice(b"\ncommitter ");
+        bytes.extend_from_slice(&self.committer.to_bytes());
+        bytes.push(b'\n');
+        for (key, value) in &self.extra_headers {
+            bytes.extend_from_slice(key);
+            bytes.push(b' ');
+            // Lines after the first go on lines of their own starting with
+            // a space.
+            for (i, line) in value.split(|&c| c == b'\n').enumerate() {
+                if i > 0 {
+                    bytes.extend_from_slice(b"\n ");
+                }
+                bytes.extend_from_slice(line);
+            }
+            bytes.push(b'\n');
         }
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&self.message);
+        bytes
     }
 }
 
@@ -53,21 +373,59 @@ impl fmt::Display for Identity {
         write!(
             f,
             "{} <{}> {}",
-            std::str::from_utf8(&self.name).unwrap(),
-            std::str::from_utf8(&self.email).unwrap(),
-            self.datetime
+            String::from_utf8_lossy(self.name()),
+            String::from_utf8_lossy(self.email()),
+            self.format_date(DateMode::default())
         )
     }
 }
 
-impl fmt::Display for Commit {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "tree {}", self.tree)?;
-        for parent in &self.parents {
-            writeln!(f, "parent {}", parent)?;
+impl Repository {
+    // Writes a commit and, given `update_ref`, points that ref at it,
+    // following HEAD to the branch it is on. The ref must still be at the
+    // first parent, or not exist for a commit without parents, so that a
+    // concurrent update is never lost. The update is logged the way
+    // `git commit` logs it.
+    pub fn commit(
+        &self,
+        update_ref: Option<&str>,
+        author: &Identity,
+        committer: &Identity,
+        message: &str,
+        tree: &Oid,
+        parents: &[Oid],
+    ) -> Result<Oid> {
+        // Identities read from elsewhere may not be fit to write.
+        Identity::parse(author.as_bytes())?;
+        Identity::parse(committer.as_bytes())?;
+        let commit = Commit::new(
+            *tree,
+            parents.to_vec(),
+            author.clone(),
+            committer.clone(),
+            message.as_bytes().to_vec(),
+        );
+        let oid = self.odb().write(ObjectType::Commit, &commit.to_bytes())?;
+        let name = match update_ref {
+            Some(name) => self.refs().resolve_name(name)?,
+            None => return Ok(oid),
+        };
+        let old = parents.first();
+        self.refs().compare_and_swap(&name, old, &oid)?;
+
+        let kind = match parents.len() {
+            0 => "commit (initial)",
+            1 => "commit",
+            _ => "commit (merge)",
+        };
+        let reflog = format!("{}: {}", kind, message.lines().next().unwrap_or(""));
+        self.refs()
+            .append_reflog(&name, old, Some(&oid), committer, &reflog)?;
+        // HEAD logs the updates of the branch it is on too.
+        if name != "HEAD" && self.refs().resolve_name("HEAD")? == name {
+            self.refs()
+                .append_reflog("HEAD", old, Some(&oid), committer, &reflog)?;
         }
-        writeln!(f, "author {}", self.author)?;
-        writeln!(f, "comitter {}", self.committer)?;
-        write!(f, "{}", std::str::from_utf8(&self.message).unwrap())
+        Ok(oid)
     }
 }
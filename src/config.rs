@@ -0,0 +1,1525 @@
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use crate::date::approxidate_at;
+use crate::error::{Error, Result};
+use crate::repo::Repository;
+use crate::wildmatch::{wildmatch, CASEFOLD, PATHNAME};
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+// Attributes with the SGR codes that turn them on and off.
+const COLOR_ATTRIBUTES: [(&str, u32, u32); 7] = [
+    ("bold", 1, 22),
+    ("dim", 2, 22),
+    ("italic", 3, 23),
+    ("ul", 4, 24),
+    ("blink", 5, 25),
+    ("reverse", 7, 27),
+    ("strike", 9, 29),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+    Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    // Section and variable names are case-insensitive and kept lowercase;
+    // subsections are case-sensitive.
+    pub section: String,
+    pub subsection: Option<String>,
+    pub name: String,
+    // None for a variable without `=`, which booleans read as true.
+    pub value: Option<String>,
+    pub scope: ConfigScope,
+    pub origin: Option<PathBuf>,
+    pub line: usize,
+}
+
+// All the configuration that applies, in the order git reads it, so that
+// later entries override earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+    // What `includeIf` conditions are checked against.
+    gitdir: Option<PathBuf>,
+    branch: Option<String>,
+}
+
+// A single config file, kept as written so that edits leave its comments
+// and layout alone.
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    path: PathBuf,
+    content: Vec<u8>,
+    items: Vec<Item>,
+}
+
+// A value its variable cannot take, with where it was set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidValue {
+    // What the value should have been, like "boolean" or "numeric".
+    pub kind: &'static str,
+    pub key: String,
+    pub value: Option<String>,
+    pub scope: ConfigScope,
+    pub origin: Option<PathBuf>,
+    pub line: usize,
+    pub reason: Option<String>,
+}
+
+// How old entries must be to get pruned, as in gc.reflogExpire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    Never,
+    All,
+    // Entries from before this time, in seconds since the epoch.
+    Before(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Normal,
+    Default,
+    // An SGR foreground code, 30-37 or 90-97.
+    Ansi(u32),
+    Indexed(u32),
+    Rgb(u8, u8, u8),
+}
+
+// A parsed key: `section.subsection.name`, with the section and the name
+// as given.
+struct Key {
+    section: String,
+    subsection: Option<String>,
+    name: String,
+}
+
+// What a config file is made of, with where it is in the content. The span
+// of a variable takes its whole line when nothing else is on it.
+#[derive(Debug, Clone)]
+enum Item {
+    Section {
+        section: String,
+        subsection: Option<String>,
+        span: Range<usize>,
+    },
+    Variable {
+        section: String,
+        subsection: Option<String>,
+        name: String,
+        value: Option<String>,
+        line: usize,
+        span: Range<usize>,
+    },
+}
+
+struct Parser<'a> {
+    content: &'a [u8],
+    origin: &'a str,
+    position: usize,
+    line: usize,
+    eof: bool,
+}
+
+fn is_key_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-'
+}
+
+impl<'a> Parser<'a> {
+    fn new(content: &'a [u8], origin: &'a str) -> Self {
+        // A UTF-8 byte order mark is skipped.
+        let position = if content.starts_with(b"\xef\xbb\xbf") {
+            3
+        } else {
+            0
+        };
+        Self {
+            content,
+            origin,
+            position,
+            line: 1,
+            eof: false,
+        }
+    }
+
+    // The next character, with CRLF read as LF. The end of the content
+    // reads as a newline too.
+    fn next(&mut self) -> u8 {
+        let c = match self.content.get(self.position) {
+            Some(&c) => c,
+            None => {
+                self.eof = true;
+                return b'\n';
+            }
+        };
+        self.position += 1;
+        if c == b'\r' && self.content.get(self.position) == Some(&b'\n') {
+            self.position += 1;
+            self.line += 1;
+            return b'\n';
+        }
+        if c == b'\n' {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn error(&self) -> Error {
+        Error::InvalidConfig(format!(
+            "bad config line {} in {}",
+            // The line has been left when it ends early.
+            if self.content.get(self.position.wrapping_sub(1)) == Some(&b'\n') {
+                self.line - 1
+            } else {
+                self.line
+            },
+            self.origin
+        ))
+    }
+
+    fn parse(mut self) -> Result<Vec<Item>> {
+        let mut items = Vec::new();
+        let mut section: Option<(String, Option<String>)> = None;
+        let mut comment = false;
+        let mut line_start = self.position;
+        let mut header_end = 0;
+        loop {
+            let start = self.position;
+            let c = self.next();
+            if self.eof {
+                return Ok(items);
+            }
+            if c == b'\n' {
+                comment = false;
+                line_start = self.position;
+                continue;
+            }
+            if comment || c.is_ascii_whitespace() {
+                continue;
+            }
+            if c == b'#' || c == b';' {
+                comment = true;
+                continue;
+            }
+            if c == b'[' {
+                let (name, subsection) = self.section_header()?;
+                items.push(Item::Section {
+                    section: name.clone(),
+                    subsection: subsection.clone(),
+                    span: start..self.position,
+                });
+                section = Some((name, subsection));
+                header_end = self.position;
+                continue;
+            }
+            let (name, subsection) = match &section {
+                Some(section) if c.is_ascii_alphabetic() => section.clone(),
+                _ => return Err(self.error()),
+            };
+            let (key, value) = self.variable(c)?;
+            let line = if self.eof { self.line } else { self.line - 1 };
+            let blank_before = self.content[line_start..start]
+                .iter()
+                .all(u8::is_ascii_whitespace);
+            items.push(Item::Variable {
+                section: name,
+                subsection,
+                name: key,
+                value,
+                line,
+                span: if blank_before { line_start } else { header_end }..self.position,
+            });
+            line_start = self.position;
+        }
+    }
+
+    // `[section]`, `[section "subsection"]`, or the deprecated
+    // `[section.subsection]`, whose subsection is case-insensitive.
+    fn section_header(&mut self) -> Result<(String, Option<String>)> {
+        let mut name = String::new();
+        loop {
+            let c = self.next();
+            if self.eof {
+                return Err(self.error());
+            }
+            if c == b']' {
+                break;
+            }
+            if c.is_ascii_whitespace() {
+                let subsection = self.subsection()?;
+                return Ok((name, Some(subsection)));
+            }
+            if !is_key_char(c) && c != b'.' {
+                return Err(self.error());
+            }
+            name.push(c.to_ascii_lowercase() as char);
+        }
+        match name.split_once('.') {
+            Some((name, subsection)) => Ok((name.to_string(), Some(subsection.to_string()))),
+            None => Ok((name, None)),
+        }
+    }
+
+    fn subsection(&mut self) -> Result<String> {
+        let mut c = self.next();
+        while c.is_ascii_whitespace() && !self.eof {
+            c = self.next();
+        }
+        if c != b'"' {
+            return Err(self.error());
+        }
+        let mut subsection = Vec::new();
+        loop {
+            let mut c = self.next();
+            if c == b'\n' {
+                return Err(self.error());
+            }
+            if c == b'"' {
+                break;
+            }
+            if c == b'\\' {
+                c = self.next();
+                if c == b'\n' {
+                    return Err(self.error());
+                }
+            }
+            subsection.push(c);
+        }
+        if self.next() != b']' {
+            return Err(self.error());
+        }
+        Ok(String::from_utf8_lossy(&subsection).into_owned())
+    }
+
+    fn variable(&mut self, first: u8) -> Result<(String, Option<String>)> {
+        let mut name = String::new();
+        name.push(first.to_ascii_lowercase() as char);
+        let mut c = self.next();
+        while !self.eof && is_key_char(c) {
+            name.push(c.to_ascii_lowercase() as char);
+            c = self.next();
+        }
+        while c == b' ' || c == b'\t' {
+            c = self.next();
+        }
+        if c == b'\n' {
+            return Ok((name, None));
+        }
+        if c != b'=' {
+            return Err(self.error());
+        }
+        Ok((name, Some(self.value()?)))
+    }
+
+    // The rest of the line, with quotes and escapes resolved, comments
+    // dropped and runs of whitespace outside quotes turned into spaces,
+    // except at either end.
+    fn value(&mut self) -> Result<String> {
+        let mut value = Vec::new();
+        let (mut quoted, mut comment) = (false, false);
+        let mut spaces = 0;
+        loop {
+            let mut c = self.next();
+            if c == b'\n' {
+                if quoted {
+                    return Err(self.error());
+                }
+                return Ok(String::from_utf8_lossy(&value).into_owned());
+            }
+            if comment {
+                continue;
+            }
+            if c.is_ascii_whitespace() && !quoted {
+                if !value.is_empty() {
+                    spaces += 1;
+                }
+                continue;
+            }
+            if !quoted && (c == b';' || c == b'#') {
+                comment = true;
+                continue;
+            }
+            value.extend(std::iter::repeat_n(b' ', spaces));
+            spaces = 0;
+            match c {
+                b'\\' => {
+                    c = match self.next() {
+                        // An escaped newline continues the value on the
+                        // next line.
+                        b'\n' if !self.eof => continue,
+                        b't' => b'\t',
+                        b'b' => b'\x08',
+                        b'n' => b'\n',
+                        c @ (b'\\' | b'"') => c,
+                        _ => return Err(self.error()),
+                    };
+                    value.push(c);
+                }
+                b'"' => quoted = !quoted,
+                c => value.push(c),
+            }
+        }
+    }
+}
+
+fn parse(content: &[u8], origin: &str) -> Result<Vec<Item>> {
+    Parser::new(content, origin).parse()
+}
+
+fn describe(path: &Path) -> String {
+    format!("file {}", path.display())
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+// Expands a leading `~` or `~user` to a home directory.
+pub fn expand_home(path: &str) -> Result<PathBuf> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return Ok(PathBuf::from(path)),
+    };
+    let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let home = if user.is_empty() {
+        home_dir()
+    } else {
+        user_home(user)
+    };
+    match home {
+        Some(home) if rest.is_empty() => Ok(home),
+        Some(home) => Ok(home.join(rest)),
+        None => Err(Error::InvalidConfig(format!(
+            "failed to expand user dir in: '{}'",
+            path
+        ))),
+    }
+}
+
+// The home directory of `user` in the password database.
+fn user_home(user: &str) -> Option<PathBuf> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields[..] {
+            [name, _, _, _, _, home, ..] if name == user => Some(PathBuf::from(home)),
+            _ => None,
+        }
+    })
+}
+
+// Whether an environment variable holds a true boolean.
+pub(crate) fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(value) => !matches!(
+            value.to_ascii_lowercase().as_str(),
+            "" | "0" | "false" | "no" | "off"
+        ),
+        Err(_) => false,
+    }
+}
+
+pub fn system_config_file() -> Option<PathBuf> {
+    if env_flag("GIT_CONFIG_NOSYSTEM") {
+        return None;
+    }
+    match env::var_os("GIT_CONFIG_SYSTEM") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(PathBuf::from("/etc/gitconfig")),
+    }
+}
+
+// The global config files, read in this order: the XDG one, then
+// `~/.gitconfig`. GIT_CONFIG_GLOBAL replaces both.
+pub fn global_config_files() -> Vec<PathBuf> {
+    if let Some(path) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let mut files = Vec::new();
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => files.push(PathBuf::from(config).join("git/config")),
+        _ => files.extend(home_dir().map(|home| home.join(".config/git/config"))),
+    }
+    files.extend(home_dir().map(|home| home.join(".gitconfig")));
+    files
+}
+
+// true, yes and on, or false, no, off and the empty string, in any case.
+pub(crate) fn parse_bool_text(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+// A variable without a value is true, and so is any non-zero number.
+fn parse_bool(value: Option<&str>) -> Option<bool> {
+    match value {
+        None => Some(true),
+        Some(value) => parse_bool_text(value).or_else(|| {
+            parse_int(value)
+                .ok()
+                .filter(|&n| i32::try_from(n).is_ok())
+                .map(|n| n != 0)
+        }),
+    }
+}
+
+// An integer as C's strtoimax reads it with base 0, so `0x10` and `010`
+// are both 16, followed by an optional unit: k, m or g.
+fn parse_int(value: &str) -> std::result::Result<i64, &'static str> {
+    let trimmed = value.trim_start();
+    let (negative, unsigned) = match trimmed.as_bytes().first() {
+        Some(b'-') => (true, &trimmed[1..]),
+        Some(b'+') => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+    let hex = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+        .filter(|hex| hex.starts_with(|c: char| c.is_ascii_hexdigit()));
+    let (radix, digits) = match hex {
+        Some(hex) => (16, hex),
+        None if unsigned.starts_with('0') => (8, unsigned),
+        None => (10, unsigned),
+    };
+    let len = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    if len == 0 {
+        return Err("invalid unit");
+    }
+    let magnitude = u64::from_str_radix(&digits[..len], radix).map_err(|_| "out of range")?;
+    // Like git, this allows as much below zero as above.
+    let number = i64::try_from(magnitude).map_err(|_| "out of range")?;
+    let number = if negative { -number } else { number };
+    let factor = match &digits[len..] {
+        "" => 1,
+        "k" | "K" => 1 << 10,
+        "m" | "M" => 1 << 20,
+        "g" | "G" => 1 << 30,
+        _ => return Err("invalid unit"),
+    };
+    number.checked_mul(factor).ok_or("out of range")
+}
+
+impl Color {
+    fn parse(word: &str) -> Option<Self> {
+        if word.eq_ignore_ascii_case("normal") {
+            return Some(Color::Normal);
+        }
+        if word.eq_ignore_ascii_case("default") {
+            return Some(Color::Default);
+        }
+        if let Some(hex) = word.strip_prefix('#') {
+            if hex.len() == 6 && hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+                return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+            }
+            return None;
+        }
+        let lowercase = word.to_ascii_lowercase();
+        let (name, offset) = match lowercase.strip_prefix("bright") {
+            Some(name) => (name, 90),
+            None => (lowercase.as_str(), 30),
+        };
+        if let Some(i) = COLOR_NAMES.iter().position(|&color| color == name) {
+            return Some(Color::Ansi(offset + i as u32));
+        }
+        match word.parse::<i32>().ok()? {
+            -1 => Some(Color::Normal),
+            n @ 0..=7 => Some(Color::Ansi(30 + n as u32)),
+            n @ 8..=15 => Some(Color::Ansi(90 + n as u32 - 8)),
+            n @ 16..=255 => Some(Color::Indexed(n as u32)),
+            _ => None,
+        }
+    }
+
+    fn is_empty(color: Option<Self>) -> bool {
+        matches!(color, None | Some(Color::Normal))
+    }
+
+    fn write(self, out: &mut String, background: bool) {
+        let (offset, kind) = if background { (10, '4') } else { (0, '3') };
+        match self {
+            Color::Normal => {}
+            Color::Default => out.push_str(&(39 + offset).to_string()),
+            Color::Ansi(code) => out.push_str(&(code + offset).to_string()),
+            Color::Indexed(n) => out.push_str(&format!("{}8;5;{}", kind, n)),
+            Color::Rgb(r, g, b) => out.push_str(&format!("{}8;2;{};{};{}", kind, r, g, b)),
+        }
+    }
+}
+
+// Turns a color like `bold red blue` into the escape sequence that sets it:
+// words are `reset`, then up to two colors for the foreground and the
+// background, and attributes, which `no` or `no-` turn off.
+pub fn parse_color(value: &str) -> Option<String> {
+    let mut reset = false;
+    let (mut foreground, mut background) = (None, None);
+    let mut attributes: u32 = 0;
+    for word in value.split_ascii_whitespace() {
+        if word.eq_ignore_ascii_case("reset") {
+            reset = true;
+        } else if let Some(color) = Color::parse(word) {
+            if foreground.is_none() {
+                foreground = Some(color);
+            } else if background.is_none() {
+                background = Some(color);
+            } else {
+                return None;
+            }
+        } else {
+            let (negated, name) = match word.strip_prefix("no") {
+                Some(name) => (true, name.strip_prefix('-').unwrap_or(name)),
+                None => (false, word),
+            };
+            let &(_, on, off) = COLOR_ATTRIBUTES.iter().find(|(attr, _, _)| *attr == name)?;
+            attributes |= 1 << if negated { off } else { on };
+        }
+    }
+    let mut out = String::new();
+    if !reset && attributes == 0 && Color::is_empty(foreground) && Color::is_empty(background) {
+        return Some(out);
+    }
+    out.push_str("\x1b[");
+    // A reset is the empty code.
+    let mut codes = reset.then(String::new).into_iter().collect::<Vec<_>>();
+    codes.extend(
+        (0..32)
+            .filter(|i| attributes & (1 << i) != 0)
+            .map(|i| i.to_string()),
+    );
+    for (color, is_background) in [(foreground, false), (background, true)] {
+        if let Some(color) = color.filter(|&color| color != Color::Normal) {
+            let mut code = String::new();
+            color.write(&mut code, is_background);
+            codes.push(code);
+        }
+    }
+    out.push_str(&codes.join(";"));
+    out.push('m');
+    Some(out)
+}
+
+// Reads `never`, `all` and dates such as `2.weeks.ago`, as git's
+// gc.*Expire settings take them.
+pub fn parse_expiry(value: &str, now: i64) -> Option<Expiry> {
+    match value {
+        "never" | "false" => Some(Expiry::Never),
+        "all" | "now" => Some(Expiry::All),
+        _ => approxidate_at(value, now).map(Expiry::Before),
+    }
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bad {} config value '{}' for '{}'",
+            self.kind,
+            self.value.as_deref().unwrap_or(""),
+            self.key
+        )?;
+        match &self.origin {
+            Some(origin) => write!(f, " in file {} at line {}", origin.display(), self.line)?,
+            None if self.scope == ConfigScope::Command => write!(f, " in command line")?,
+            None => {}
+        }
+        match &self.reason {
+            Some(reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Key {
+    // Splits a key at its first and last dot. Section and variable names
+    // are checked; the subsection can hold anything.
+    fn parse(key: &str) -> Result<Self> {
+        let invalid = || Error::InvalidConfig(format!("invalid key: {}", key));
+        let (section, rest) = key.split_once('.').ok_or_else(invalid)?;
+        let (subsection, name) = match rest.rsplit_once('.') {
+            Some((subsection, name)) => (Some(subsection.to_string()), name),
+            None => (None, rest),
+        };
+        if section.is_empty() || !section.bytes().all(is_key_char) {
+            return Err(invalid());
+        }
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || !name.bytes().all(is_key_char) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            section: section.to_string(),
+            subsection,
+            name: name.to_string(),
+        })
+    }
+
+    fn matches_section(&self, section: &str, subsection: Option<&str>) -> bool {
+        self.section.eq_ignore_ascii_case(section) && self.subsection.as_deref() == subsection
+    }
+
+    fn matches(&self, section: &str, subsection: Option<&str>, name: &str) -> bool {
+        self.matches_section(section, subsection) && self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+impl ConfigEntry {
+    // The error for a value that is not a `kind`.
+    pub(crate) fn invalid(&self, kind: &'static str, reason: Option<&str>) -> Error {
+        Error::InvalidConfigValue(Box::new(InvalidValue {
+            kind,
+            key: self.key(),
+            value: self.value.clone(),
+            scope: self.scope,
+            origin: self.origin.clone(),
+            line: self.line,
+            reason: reason.map(str::to_string),
+        }))
+    }
+
+    pub fn key(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!("{}.{}.{}", self.section, subsection, self.name),
+            None => format!("{}.{}", self.section, self.name),
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A config whose `includeIf` conditions check the given git directory
+    // and branch, the short name of what HEAD points at.
+    pub fn for_repository(gitdir: Option<PathBuf>, branch: Option<String>) -> Self {
+        Self {
+            entries: Vec::new(),
+            gitdir,
+            branch,
+        }
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    // Reads a config file, if it exists.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P, scope: ConfigScope) -> Result<()> {
+        self.add_file_at_depth(path.as_ref(), scope, 0)
+    }
+
+    pub fn add_bytes(
+        &mut self,
+        content: &[u8],
+        origin: Option<&Path>,
+        scope: ConfigScope,
+    ) -> Result<()> {
+        self.add_bytes_at_depth(content, origin, scope, 0)
+    }
+
+    // Sets a variable on top of everything read so far, like `git -c`.
+    pub fn add_value(&mut self, key: &str, value: Option<&str>, scope: ConfigScope) -> Result<()> {
+        let key = Key::parse(key)?;
+        self.entries.push(ConfigEntry {
+            section: key.section.to_ascii_lowercase(),
+            subsection: key.subsection,
+            name: key.name.to_ascii_lowercase(),
+            value: value.map(str::to_string),
+            scope,
+            origin: None,
+            line: 0,
+        });
+        Ok(())
+    }
+
+    fn add_file_at_depth(&mut self, path: &Path, scope: ConfigScope, depth: usize) -> Result<()> {
+        let content = match fs::read(path) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.add_bytes_at_depth(&content, Some(path), scope, depth)
+    }
+
+    fn add_bytes_at_depth(
+        &mut self,
+        content: &[u8],
+        origin: Option<&Path>,
+        scope: ConfigScope,
+        depth: usize,
+    ) -> Result<()> {
+        let description = origin.map_or_else(|| "blob".to_string(), describe);
+        for item in parse(content, &description)? {
+            if let Item::Variable {
+                section,
+                subsection,
+                name,
+                value,
+                line,
+                ..
+            } = item
+            {
+                let include = match (section.as_str(), subsection.as_deref(), value.as_deref()) {
+                    ("include", None, Some(path)) if name == "path" => Some(path.to_string()),
+                    ("includeif", Some(condition), Some(path)) if name == "path" => {
+                        if self.include_condition(condition, origin)? {
+                            Some(path.to_string())
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+                self.entries.push(ConfigEntry {
+                    section,
+                    subsection,
+                    name,
+                    value,
+                    scope,
+                    origin: origin.map(Path::to_path_buf),
+                    line,
+                });
+                if let Some(path) = include {
+                    self.include(&path, origin, scope, depth)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads an included file, which is found relative to the including one.
+    fn include(
+        &mut self,
+        path: &str,
+        origin: Option<&Path>,
+        scope: ConfigScope,
+        depth: usize,
+    ) -> Result<()> {
+        let mut path = expand_home(path)?;
+        if path.is_relative() {
+            let dir = origin.and_then(Path::parent).ok_or_else(|| {
+                Error::InvalidConfig("relative config includes must come from files".to_string())
+            })?;
+            path = dir.join(path);
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(Error::InvalidConfig(format!(
+                "exceeded maximum include depth ({}) while including {}",
+                MAX_INCLUDE_DEPTH,
+                path.display()
+            )));
+        }
+        self.add_file_at_depth(&path, scope, depth + 1)
+    }
+
+    fn include_condition(&self, condition: &str, origin: Option<&Path>) -> Result<bool> {
+        if let Some(pattern) = condition.strip_prefix("gitdir:") {
+            return self.gitdir_matches(pattern, origin, 0);
+        }
+        if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+            return self.gitdir_matches(pattern, origin, CASEFOLD);
+        }
+        if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            let branch = match &self.branch {
+                Some(branch) => branch,
+                None => return Ok(false),
+            };
+            let mut pattern = pattern.to_string();
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            return Ok(wildmatch(pattern.as_bytes(), branch.as_bytes(), PATHNAME));
+        }
+        // Conditions git does not know are never true.
+        Ok(false)
+    }
+
+    // Patterns that are not absolute match at any depth, and a trailing
+    // slash matches everything below.
+    fn gitdir_matches(&self, pattern: &str, origin: Option<&Path>, flags: u32) -> Result<bool> {
+        let gitdir = match &self.gitdir {
+            Some(gitdir) => gitdir,
+            None => return Ok(false),
+        };
+        let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+            let dir = origin.and_then(Path::parent).ok_or_else(|| {
+                Error::InvalidConfig(
+                    "relative config include conditionals must come from files".to_string(),
+                )
+            })?;
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".").canonicalize()?
+            } else {
+                dir.canonicalize()?
+            };
+            format!("{}/{}", dir.display(), rest)
+        } else {
+            let expanded = expand_home(pattern)?.to_string_lossy().into_owned();
+            if expanded.starts_with('/') {
+                expanded
+            } else {
+                format!("**/{}", expanded)
+            }
+        };
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        let matches = |dir: &Path| {
+            wildmatch(
+                pattern.as_bytes(),
+                dir.to_string_lossy().as_bytes(),
+                PATHNAME | flags,
+            )
+        };
+        Ok(matches(gitdir) || gitdir.canonicalize().is_ok_and(|real| matches(&real)))
+    }
+
+    fn find<'a>(&'a self, key: &str) -> Result<impl Iterator<Item = &'a ConfigEntry>> {
+        let key = Key::parse(key)?;
+        Ok(self.entries.iter().filter(move |entry| {
+            key.matches(&entry.section, entry.subsection.as_deref(), &entry.name)
+        }))
+    }
+
+    // The entry that sets `key` last, which is the one that counts.
+    pub fn get_entry(&self, key: &str) -> Option<&ConfigEntry> {
+        self.find(key).ok()?.last()
+    }
+
+    pub fn get_entries(&self, key: &str) -> Vec<&ConfigEntry> {
+        self.find(key).map(Iterator::collect).unwrap_or_default()
+    }
+
+    // The value of `key`. A variable without a value reads as empty.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_entry(key)
+            .map(|entry| entry.value.as_deref().unwrap_or(""))
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.get_entries(key)
+            .into_iter()
+            .map(|entry| entry.value.as_deref().unwrap_or(""))
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let entry = match self.get_entry(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match parse_bool(entry.value.as_deref()) {
+            Some(value) => Ok(Some(value)),
+            None => Err(entry.invalid("boolean", None)),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        let entry = match self.get_entry(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match parse_int(entry.value.as_deref().unwrap_or("")) {
+            Ok(value) => Ok(Some(value)),
+            Err(reason) => Err(entry.invalid("numeric", Some(reason))),
+        }
+    }
+
+    // A path, with `~` expanded. A path marked `:(optional)` that does not
+    // exist counts as not set.
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>> {
+        let entry = match self.get_entry(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let value = entry
+            .value
+            .as_deref()
+            .ok_or_else(|| entry.invalid("path", Some("missing value")))?;
+        let (optional, value) = match value.strip_prefix(":(optional)") {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        let path = expand_home(value)
+            .map_err(|_| entry.invalid("path", Some("failed to expand user dir")))?;
+        if optional && fs::symlink_metadata(&path).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(path))
+    }
+
+    // The escape sequence for a color such as `bold red`.
+    pub fn get_color(&self, key: &str) -> Result<Option<String>> {
+        let entry = match self.get_entry(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match entry.value.as_deref().and_then(parse_color) {
+            Some(color) => Ok(Some(color)),
+            None => Err(entry.invalid("color", None)),
+        }
+    }
+
+    pub fn get_expiry(&self, key: &str) -> Result<Option<Expiry>> {
+        let entry = match self.get_entry(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match entry
+            .value
+            .as_deref()
+            .and_then(|value| parse_expiry(value, Utc::now().timestamp()))
+        {
+            Some(expiry) => Ok(Some(expiry)),
+            None => Err(entry.invalid("expiry date", None)),
+        }
+    }
+
+    // The subsections of `section` that have variables set, such as the
+    // names of remotes, in the order they first appear.
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let section = section.to_ascii_lowercase();
+        let mut subsections: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            if let Some(subsection) = &entry.subsection {
+                if entry.section == section && !subsections.contains(&subsection.as_str()) {
+                    subsections.push(subsection);
+                }
+            }
+        }
+        subsections
+    }
+}
+
+// Quotes a value as git writes it.
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains([';', '#']);
+    let mut quoted = String::with_capacity(value.len() + 2);
+    if needs_quotes {
+        quoted.push('"');
+    }
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c => quoted.push(c),
+        }
+    }
+    if needs_quotes {
+        quoted.push('"');
+    }
+    quoted
+}
+
+fn section_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => format!(
+            "[{} \"{}\"]\n",
+            section,
+            subsection.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => format!("[{}]\n", section),
+    }
+}
+
+impl ConfigFile {
+    // Reads a config file for editing. A missing file reads as empty and
+    // gets created when saved.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let items = parse(&content, &describe(&path))?;
+        Ok(Self {
+            path,
+            content,
+            items,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    fn replace(&mut self, span: Range<usize>, text: &[u8]) -> Result<()> {
+        self.content.splice(span, text.iter().copied());
+        self.items = parse(&self.content, &describe(&self.path))?;
+        Ok(())
+    }
+
+    // Replaces a variable. One that follows a section header on its line
+    // moves to a line of its own.
+    fn replace_variable(&mut self, span: Range<usize>, line: &[u8]) -> Result<()> {
+        let mut text = Vec::with_capacity(line.len() + 1);
+        if span.start > 0 && self.content[span.start - 1] != b'\n' {
+            text.push(b'\n');
+        }
+        text.extend_from_slice(line);
+        self.content.splice(span, text);
+        self.items = parse(&self.content, &describe(&self.path))?;
+        Ok(())
+    }
+
+    fn variable_spans(&self, key: &Key) -> Vec<Range<usize>> {
+        self.items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Variable {
+                    section,
+                    subsection,
+                    name,
+                    span,
+                    ..
+                } if key.matches(section, subsection.as_deref(), name) => Some(span.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Where a new variable of the section goes: after the last thing in
+    // the last section of that name, or None when there is none.
+    fn insertion_point(&self, key: &Key) -> Option<usize> {
+        let mut point = None;
+        let mut in_section = false;
+        for item in &self.items {
+            match item {
+                Item::Section {
+                    section,
+                    subsection,
+                    span,
+                } => {
+                    in_section = key.matches_section(section, subsection.as_deref());
+                    if in_section {
+                        point = Some(span.end);
+                    }
+                }
+                Item::Variable { span, .. } if in_section => point = Some(span.end),
+                Item::Variable { .. } => {}
+            }
+        }
+        // What follows on the line of a section header stays with it.
+        point.map(|point| self.line_end(point))
+    }
+
+    // Where the line `point` is on ends, past its newline, unless `point`
+    // is at the start of a line already.
+    fn line_end(&self, point: usize) -> usize {
+        if point > 0 && self.content[point - 1] == b'\n' {
+            point
+        } else {
+            self.content[point..]
+                .iter()
+                .position(|&c| c == b'\n')
+                .map_or(self.content.len(), |i| point + i + 1)
+        }
+    }
+
+    fn variable_line(key: &Key, value: &str) -> Vec<u8> {
+        format!("\t{} = {}\n", key.name, quote_value(value)).into_bytes()
+    }
+
+    // Sets `key` to `value`, replacing its line if it is set, and adding it
+    // to its section otherwise. A key with several values is left alone.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let parsed = Key::parse(key)?;
+        match &self.variable_spans(&parsed)[..] {
+            [] => self.add(key, value),
+            [span] => self.replace_variable(span.clone(), &Self::variable_line(&parsed, value)),
+            _ => Err(Error::InvalidConfig(format!(
+                "cannot overwrite multiple values of {} with a single value",
+                key
+            ))),
+        }
+    }
+
+    // Adds another value for `key`, as `git config --add` does.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        let parsed = Key::parse(key)?;
+        let line = Self::variable_line(&parsed, value);
+        match self.insertion_point(&parsed) {
+            // The last line of the section may be the last of the file
+            // and have no newline.
+            Some(point) if point > 0 && self.content[point - 1] != b'\n' => {
+                let mut text = vec![b'\n'];
+                text.extend_from_slice(&line);
+                self.replace(point..point, &text)
+            }
+            Some(point) => self.replace(point..point, &line),
+            None => {
+                let mut text = Vec::new();
+                if !self.content.is_empty() && !self.content.ends_with(b"\n") {
+                    text.push(b'\n');
+                }
+                text.extend_from_slice(
+                    section_header(&parsed.section, parsed.subsection.as_deref()).as_bytes(),
+                );
+                text.extend_from_slice(&line);
+                let end = self.content.len();
+                self.replace(end..end, &text)
+            }
+        }
+    }
+
+    fn variable_indices(&self, key: &Key) -> Vec<usize> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| match item {
+                Item::Variable {
+                    section,
+                    subsection,
+                    name,
+                    ..
+                } if key.matches(section, subsection.as_deref(), name) => Some(i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Removes the variable `items[index]`. Like git, a section it leaves
+    // without variables goes as well, unless comments are in it or right
+    // before it, as they may be about it. Sections of the same name right
+    // after one another count as one.
+    fn remove_variable(&mut self, index: usize) -> Result<()> {
+        let (section, subsection, span) = match &self.items[index] {
+            Item::Variable {
+                section,
+                subsection,
+                span,
+                ..
+            } => (section, subsection, span.clone()),
+            Item::Section { .. } => unreachable!(),
+        };
+        let same_section = |item: &Item| match item {
+            Item::Section {
+                section: item_section,
+                subsection: item_subsection,
+                ..
+            } => item_section == section && item_subsection == subsection,
+            Item::Variable { .. } => false,
+        };
+        let mut first = index;
+        while first > 0 && same_section(&self.items[first - 1]) {
+            first -= 1;
+        }
+        let mut last = index + 1;
+        while last < self.items.len() && same_section(&self.items[last]) {
+            last += 1;
+        }
+        let emptied = first < index && !matches!(self.items.get(last), Some(Item::Variable { .. }));
+        if !emptied {
+            return self.replace_variable(span, b"");
+        }
+        let start = match first.checked_sub(1).map(|i| &self.items[i]) {
+            Some(Item::Section { span, .. }) | Some(Item::Variable { span, .. }) => {
+                self.line_end(span.end)
+            }
+            None => 0,
+        };
+        let end = match self.items.get(last) {
+            Some(Item::Section { span, .. }) | Some(Item::Variable { span, .. }) => span.start,
+            None => self.content.len(),
+        };
+        // Anything between the headers and the variable is a comment.
+        let mut covered = vec![false; end - start];
+        for item in &self.items[first..last] {
+            let (Item::Section { span, .. } | Item::Variable { span, .. }) = item;
+            for i in span.start.max(start)..span.end.min(end) {
+                covered[i - start] = true;
+            }
+        }
+        let has_comment = self.content[start..end]
+            .iter()
+            .zip(&covered)
+            .any(|(&c, &covered)| !covered && (c == b'#' || c == b';'));
+        if has_comment {
+            return self.replace_variable(span, b"");
+        }
+        self.replace(start..end, b"")
+    }
+
+    // Removes `key`, and tells whether it was set. A key with several
+    // values is left alone.
+    pub fn unset(&mut self, key: &str) -> Result<bool> {
+        match &self.variable_indices(&Key::parse(key)?)[..] {
+            [] => Ok(false),
+            [index] => self.remove_variable(*index).map(|_| true),
+            _ => Err(Error::InvalidConfig(format!("{} has multiple values", key))),
+        }
+    }
+
+    // Removes every value of `key` and tells how many there were. Going
+    // from the last keeps the items before where they are.
+    pub fn unset_all(&mut self, key: &str) -> Result<usize> {
+        let indices = self.variable_indices(&Key::parse(key)?);
+        for &index in indices.iter().rev() {
+            self.remove_variable(index)?;
+        }
+        Ok(indices.len())
+    }
+
+    // The spans of every section named `name`, like `remote.origin`, from
+    // its header to where the next section starts.
+    fn section_spans(&self, name: &str) -> Vec<Range<usize>> {
+        let (section, subsection) = match name.split_once('.') {
+            Some((section, subsection)) => (section.to_ascii_lowercase(), Some(subsection)),
+            None => (name.to_ascii_lowercase(), None),
+        };
+        let mut spans = Vec::new();
+        let mut current: Option<usize> = None;
+        for item in &self.items {
+            if let Item::Section {
+                section: item_section,
+                subsection: item_subsection,
+                span,
+            } = item
+            {
+                let start = self.content[..span.start]
+                    .iter()
+                    .rposition(|&c| c == b'\n')
+                    .map_or(0, |i| i + 1);
+                if let Some(current) = current.take() {
+                    spans.push(current..start);
+                }
+                if *item_section == section && item_subsection.as_deref() == subsection {
+                    current = Some(start);
+                }
+            }
+        }
+        if let Some(current) = current {
+            spans.push(current..self.content.len());
+        }
+        spans
+    }
+
+    // Removes a section with all its variables, and tells whether there was
+    // one.
+    pub fn remove_section(&mut self, name: &str) -> Result<bool> {
+        let spans = self.section_spans(name);
+        for span in spans.iter().rev() {
+            self.content.drain(span.clone());
+        }
+        self.items = parse(&self.content, &describe(&self.path))?;
+        Ok(!spans.is_empty())
+    }
+
+    // Renames a section, rewriting its headers, and tells whether there was
+    // one.
+    pub fn rename_section(&mut self, old_name: &str, new_name: &str) -> Result<bool> {
+        let (section, subsection) = match new_name.split_once('.') {
+            Some((section, subsection)) => (section, Some(subsection)),
+            None => (new_name, None),
+        };
+        if section.is_empty() || !section.bytes().all(is_key_char) {
+            return Err(Error::InvalidConfig(format!(
+                "invalid section name: {}",
+                new_name
+            )));
+        }
+        let header = section_header(section, subsection);
+        let spans = self.section_spans(old_name);
+        for span in spans.iter().rev() {
+            // Only the header itself is replaced. A variable on the same
+            // line moves to a line of its own.
+            let end = self.items.iter().find_map(|item| match item {
+                Item::Section { span: header, .. } if header.start >= span.start => {
+                    Some(header.end)
+                }
+                _ => None,
+            });
+            let mut end = end.unwrap_or(span.start);
+            while matches!(self.content.get(end), Some(b' ' | b'\t')) {
+                end += 1;
+            }
+            let mut header = header.clone();
+            match self.content.get(end) {
+                Some(b'\n') => end += 1,
+                Some(_) => header.push('\t'),
+                None => {}
+            }
+            self.content.splice(span.start..end, header.bytes());
+        }
+        self.items = parse(&self.content, &describe(&self.path))?;
+        Ok(!spans.is_empty())
+    }
+
+    // Writes the file through `<path>.lock`.
+    pub fn save(&self) -> Result<()> {
+        let lock_path = PathBuf::from(format!("{}.lock", self.path.display()));
+        let mut lock = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)?;
+        let result = lock
+            .write_all(&self.content)
+            .and_then(|_| fs::rename(&lock_path, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&lock_path);
+        }
+        Ok(result?)
+    }
+}
+
+impl Repository {
+    // Reads the configuration of every scope: system, global, the
+    // repository's own and, with extensions.worktreeConfig, the worktree's,
+    // then GIT_CONFIG_COUNT variables from the environment.
+    pub fn config(&self) -> Result<Config> {
+        let branch = self.head_branch()?.map(|branch| {
+            branch
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&branch)
+                .to_string()
+        });
+        let mut config = Config::for_repository(Some(self.gitdir().to_path_buf()), branch);
+        if let Some(path) = system_config_file() {
+            config.add_file(path, ConfigScope::System)?;
+        }
+        for path in global_config_files() {
+            config.add_file(path, ConfigScope::Global)?;
+        }
+        config.add_file(self.commondir().join("config"), ConfigScope::Local)?;
+        let worktree_config = config
+            .get_entry("extensions.worktreeConfig")
+            .is_some_and(|entry| match &entry.value {
+                None => true,
+                Some(value) => matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "true" | "yes" | "on" | "1"
+                ),
+            });
+        if worktree_config {
+            config.add_file(self.gitdir().join("config.worktree"), ConfigScope::Worktree)?;
+        }
+
+        if let Ok(count) = env::var("GIT_CONFIG_COUNT") {
+            let count: usize = count.parse()?;
+            for i in 0..count {
+                let missing =
+                    |name: String| Error::InvalidConfig(format!("missing config key {}", name));
+                let key = env::var(format!("GIT_CONFIG_KEY_{}", i))
+                    .map_err(|_| missing(format!("GIT_CONFIG_KEY_{}", i)))?;
+                let value = env::var(format!("GIT_CONFIG_VALUE_{}", i))
+                    .map_err(|_| missing(format!("GIT_CONFIG_VALUE_{}", i)))?;
+                config.add_value(&key, Some(&value), ConfigScope::Command)?;
+            }
+        }
+        Ok(config)
+    }
+
+    // The file to edit for a scope. The global scope edits the XDG file
+    // only when it exists and `~/.gitconfig` does not, like git.
+    pub fn config_file(&self, scope: ConfigScope) -> Result<ConfigFile> {
+        let path = match scope {
+            ConfigScope::System => system_config_file(),
+            ConfigScope::Global => {
+                let files = global_config_files();
+                files
+                    .iter()
+                    .rev()
+                    .find(|path| path.exists())
+                    .or_else(|| files.last())
+                    .cloned()
+            }
+            ConfigScope::Local => Some(self.commondir().join("config")),
+            ConfigScope::Worktree => Some(self.gitdir().join("config.worktree")),
+            ConfigScope::Command => None,
+        };
+        let path = path.ok_or_else(|| {
+            Error::InvalidConfig(format!("no config file for the {:?} scope", scope))
+        })?;
+        ConfigFile::open(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    // Edits a file with `content` and returns what `edit` left in it.
+    fn edit(content: &str, edit: impl FnOnce(&mut ConfigFile) -> Result<()>) -> String {
+        let tmp = TempDir::new();
+        let path = tmp.path().join("config");
+        fs::write(&path, content).unwrap();
+        let mut file = ConfigFile::open(&path).unwrap();
+        edit(&mut file).unwrap();
+        String::from_utf8(file.content().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn edits_files_without_final_newline() {
+        let set = |content, key, value| edit(content, |file| file.set(key, value));
+        let add = |content, key, value| edit(content, |file| file.add(key, value));
+        let rename =
+            |content, old, new| edit(content, |file| file.rename_section(old, new).map(|_| ()));
+
+        assert_eq!(set("[a]\n\tx = 1", "a.y", "2"), "[a]\n\tx = 1\n\ty = 2\n");
+        assert_eq!(set("[a]\n\tx = 1", "a.x", "2"), "[a]\n\tx = 2\n");
+        assert_eq!(set("[a]", "a.y", "2"), "[a]\n\ty = 2\n");
+        assert_eq!(set("[a] x = 1", "a.y", "2"), "[a] x = 1\n\ty = 2\n");
+        assert_eq!(
+            set("[b]\n\ty = 1", "a.x", "2"),
+            "[b]\n\ty = 1\n[a]\n\tx = 2\n"
+        );
+        assert_eq!(add("[a]\n\tx = 1", "a.x", "2"), "[a]\n\tx = 1\n\tx = 2\n");
+        assert_eq!(
+            add("[a]\n\tx = 1 \\\n  2", "a.x", "3"),
+            "[a]\n\tx = 1 \\\n  2\n\tx = 3\n"
+        );
+        assert_eq!(rename("[a]\n\tx = 1", "a", "c"), "[c]\n\tx = 1");
+        assert_eq!(rename("[a]", "a", "c"), "[c]\n");
+        assert_eq!(rename("[a] x = 1", "a", "c"), "[c]\n\tx = 1");
+    }
+
+    #[test]
+    fn unsetting_removes_emptied_sections() {
+        let unset = |content, key| edit(content, |file| file.unset(key).map(|_| ()));
+        assert_eq!(
+            unset("[a]\n\tx = 1\n[b]\n\ty = 2\n", "a.x"),
+            "[b]\n\ty = 2\n"
+        );
+        assert_eq!(unset("[a]\n\tx = 1", "a.x"), "");
+        assert_eq!(unset("[a]\n\tx = 1\n\ty = 2\n", "a.x"), "[a]\n\ty = 2\n");
+        // Comments may be about the section, so it stays.
+        assert_eq!(
+            unset("# about a\n[a]\n\tx = 1\n", "a.x"),
+            "# about a\n[a]\n"
+        );
+    }
+}
@@ -0,0 +1,889 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::attributes::{AttrValue, Attributes};
+use crate::error::{Error, Result};
+use crate::filter::{
+    self, CommandOutput, FilterCommand, FilterDriver, FilterMeta, FilterProcess, Response, Smudged,
+};
+use crate::odb::{Odb, StandardOdb};
+use crate::oid::Oid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+// core.eol; `Native` is LF on the platforms this crate supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eol {
+    Native,
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafeCrlf {
+    False,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertOptions {
+    pub autocrlf: AutoCrlf,
+    pub eol: Eol,
+    pub safecrlf: SafeCrlf,
+}
+
+// What to do with line endings of a path, decided from its `text`, `eol`
+// and `crlf` attributes together with the options above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrlfAction {
+    Undefined,
+    Binary,
+    Text,
+    TextInput,
+    TextCrlf,
+    Auto,
+    AutoInput,
+    AutoCrlf,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextStat {
+    pub nul: usize,
+    pub lone_cr: usize,
+    pub lone_lf: usize,
+    pub crlf: usize,
+    pub printable: usize,
+    pub nonprintable: usize,
+}
+
+// Something git would say on stderr about a conversion that went ahead
+// anyway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    Warning(String),
+    Error(String),
+}
+
+pub struct Converter {
+    attributes: Option<Attributes>,
+    options: ConvertOptions,
+    odb: Option<StandardOdb>,
+    drivers: HashMap<String, FilterDriver>,
+    // Process filters are started on first use and kept until the converter
+    // is dropped.
+    processes: RefCell<HashMap<String, FilterProcess>>,
+    // Delayed paths whose filter gave up on them.
+    unfiltered: RefCell<Vec<Vec<u8>>>,
+    notices: RefCell<Vec<Notice>>,
+}
+
+impl ConvertOptions {
+    pub fn new() -> Self {
+        Self {
+            autocrlf: AutoCrlf::False,
+            eol: Eol::Native,
+            safecrlf: SafeCrlf::Warn,
+        }
+    }
+
+    fn text_eol_is_crlf(&self) -> bool {
+        match self.autocrlf {
+            AutoCrlf::True => true,
+            AutoCrlf::Input => false,
+            AutoCrlf::False => self.eol == Eol::Crlf,
+        }
+    }
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrlfAction {
+    fn from_attr(value: &AttrValue) -> Self {
+        match value {
+            AttrValue::Set => CrlfAction::Text,
+            AttrValue::Unset => CrlfAction::Binary,
+            AttrValue::Value(value) if value == "input" => CrlfAction::TextInput,
+            AttrValue::Value(value) if value == "auto" => CrlfAction::Auto,
+            _ => CrlfAction::Undefined,
+        }
+    }
+
+    fn is_auto(self) -> bool {
+        matches!(
+            self,
+            CrlfAction::Auto | CrlfAction::AutoInput | CrlfAction::AutoCrlf
+        )
+    }
+
+    fn output_is_crlf(self, options: &ConvertOptions) -> bool {
+        match self {
+            CrlfAction::Binary | CrlfAction::TextInput | CrlfAction::AutoInput => false,
+            CrlfAction::TextCrlf | CrlfAction::Undefined | CrlfAction::AutoCrlf => true,
+            CrlfAction::Text | CrlfAction::Auto => options.text_eol_is_crlf(),
+        }
+    }
+}
+
+impl TextStat {
+    pub fn gather(content: &[u8]) -> Self {
+        let mut stats = TextStat::default();
+        let mut i = 0;
+        while i < content.len() {
+            match content[i] {
+                b'\r' => {
+                    if content.get(i + 1) == Some(&b'\n') {
+                        stats.crlf += 1;
+                        i += 1;
+                    } else {
+                        stats.lone_cr += 1;
+                    }
+                }
+                b'\n' => stats.lone_lf += 1,
+                127 => stats.nonprintable += 1,
+                // BS, HT, ESC and FF are common enough in text files.
+                0x08 | b'\t' | 0x1b | 0x0c => stats.printable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.nonprintable += 1;
+                }
+                c if c < 32 => stats.nonprintable += 1,
+                _ => stats.printable += 1,
+            }
+            i += 1;
+        }
+        // A trailing DOS end-of-file marker does not make content binary.
+        if content.last() == Some(&0x1a) {
+            stats.nonprintable = stats.nonprintable.saturating_sub(1);
+        }
+        stats
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.nonprintable
+    }
+
+    // The line ending summary `git ls-files --eol` shows for content.
+    pub fn describe(&self) -> &'static str {
+        if self.is_binary() {
+            return "-text";
+        }
+        match (self.lone_lf > 0, self.crlf > 0) {
+            (true, false) => "lf",
+            (false, true) => "crlf",
+            (true, true) => "mixed",
+            (false, false) => "none",
+        }
+    }
+
+    fn will_convert_lf_to_crlf(&self, action: CrlfAction, options: &ConvertOptions) -> bool {
+        if !action.output_is_crlf(options) || self.lone_lf == 0 {
+            return false;
+        }
+        // Automatic conversion never touches content that already has CRs.
+        !(action.is_auto() && (self.lone_cr > 0 || self.crlf > 0 || self.is_binary()))
+    }
+}
+
+pub fn describe_eol(content: &[u8]) -> &'static str {
+    if content.is_empty() {
+        return "";
+    }
+    TextStat::gather(content).describe()
+}
+
+impl Converter {
+    // Converts nothing: the contents of the working tree and the object
+    // database are the same.
+    pub fn new() -> Self {
+        Self {
+            attributes: None,
+            options: ConvertOptions::new(),
+            odb: None,
+            drivers: HashMap::new(),
+            processes: RefCell::new(HashMap::new()),
+            unfiltered: RefCell::new(Vec::new()),
+            notices: RefCell::new(Vec::new()),
+        }
+    }
+
+    // `odb` is used to look at what the index already records for a path,
+    // as automatic conversion leaves files committed with CRLF alone.
+    pub fn with_attributes(
+        attributes: Attributes,
+        options: ConvertOptions,
+        odb: Option<StandardOdb>,
+    ) -> Self {
+        Self {
+            attributes: Some(attributes),
+            options,
+            odb,
+            drivers: HashMap::new(),
+            processes: RefCell::new(HashMap::new()),
+            unfiltered: RefCell::new(Vec::new()),
+            notices: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Makes `driver` available to paths whose `filter` attribute names it.
+    pub fn add_driver(&mut self, driver: FilterDriver) {
+        self.drivers.insert(driver.name.clone(), driver);
+    }
+
+    pub fn driver(&self, name: &str) -> Option<&FilterDriver> {
+        self.drivers.get(name)
+    }
+
+    pub fn options(&self) -> &ConvertOptions {
+        &self.options
+    }
+
+    pub fn attributes(&self) -> Option<&Attributes> {
+        self.attributes.as_ref()
+    }
+
+    pub fn crlf_action(&self, path: &[u8]) -> Result<CrlfAction> {
+        Ok(self.conversion(path)?.0)
+    }
+
+    // Looks up everything the attributes of `path` say about converting it.
+    fn conversion(&self, path: &[u8]) -> Result<(CrlfAction, Option<&FilterDriver>)> {
+        let (text, eol, crlf, filter) = match &self.attributes {
+            Some(attributes) => {
+                let mut values = attributes.check(path, &["text", "eol", "crlf", "filter"])?;
+                let filter = values.pop().unwrap().1;
+                let crlf = values.pop().unwrap().1;
+                let eol = values.pop().unwrap().1;
+                (values.pop().unwrap().1, eol, crlf, filter)
+            }
+            None => (
+                AttrValue::Unspecified,
+                AttrValue::Unspecified,
+                AttrValue::Unspecified,
+                AttrValue::Unspecified,
+            ),
+        };
+        let driver = filter.as_value().and_then(|name| self.drivers.get(name));
+        let mut action = CrlfAction::from_attr(&text);
+        if action == CrlfAction::Undefined {
+            action = CrlfAction::from_attr(&crlf);
+        }
+        if action != CrlfAction::Binary {
+            action = match (action, eol.as_value()) {
+                (CrlfAction::Auto, Some("lf")) => CrlfAction::AutoInput,
+                (CrlfAction::Auto, Some("crlf")) => CrlfAction::AutoCrlf,
+                (_, Some("lf")) => CrlfAction::TextInput,
+                (_, Some("crlf")) => CrlfAction::TextCrlf,
+                (action, _) => action,
+            };
+        }
+        let action = match action {
+            CrlfAction::Text if self.options.text_eol_is_crlf() => CrlfAction::TextCrlf,
+            CrlfAction::Text => CrlfAction::TextInput,
+            CrlfAction::Undefined => match self.options.autocrlf {
+                AutoCrlf::False => CrlfAction::Binary,
+                AutoCrlf::True => CrlfAction::AutoCrlf,
+                AutoCrlf::Input => CrlfAction::AutoInput,
+            },
+            action => action,
+        };
+        Ok((action, driver))
+    }
+
+    fn workdir(&self) -> &Path {
+        self.attributes
+            .as_ref()
+            .and_then(Attributes::workdir)
+            .unwrap_or_else(|| Path::new("."))
+    }
+
+    // Runs the filter `driver` configures for `command`. None means the
+    // content was not filtered, which is only an error for required drivers.
+    fn apply_filter(
+        &self,
+        driver: &FilterDriver,
+        command: FilterCommand,
+        path: &[u8],
+        content: &[u8],
+        meta: Option<&FilterMeta>,
+        can_delay: bool,
+    ) -> Result<Option<Smudged>> {
+        if let Some(process) = &driver.process {
+            return self
+                .apply_process_filter(process, driver, command, path, content, meta, can_delay);
+        }
+        let cmd = match driver.command(command) {
+            Some(cmd) => cmd,
+            None => return Ok(None),
+        };
+        let reason = match filter::run_command(cmd, path, self.workdir(), content)? {
+            CommandOutput::Success(filtered) => return Ok(Some(Smudged::Ready(filtered))),
+            CommandOutput::SpawnFailed => format!("cannot fork to run external filter '{}'", cmd),
+            CommandOutput::Exited(code) => format!("external filter '{}' failed {}", cmd, code),
+            CommandOutput::WriteFailed => {
+                format!("cannot feed the input to external filter '{}'", cmd)
+            }
+        };
+        self.error(reason);
+        self.error(format!("external filter '{}' failed", cmd));
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_process_filter(
+        &self,
+        cmd: &str,
+        driver: &FilterDriver,
+        command: FilterCommand,
+        path: &[u8],
+        content: &[u8],
+        meta: Option<&FilterMeta>,
+        can_delay: bool,
+    ) -> Result<Option<Smudged>> {
+        let mut processes = self.processes.borrow_mut();
+        if !processes.contains_key(cmd) {
+            match FilterProcess::start(cmd, self.workdir()) {
+                Ok(process) => processes.insert(cmd.to_string(), process),
+                Err(_) => {
+                    self.error(format!("initialization for subprocess '{}' failed", cmd));
+                    return Ok(None);
+                }
+            };
+        }
+        let process = processes.get_mut(cmd).unwrap();
+        if !process.supports(command.as_str()) {
+            return Ok(None);
+        }
+        let can_delay = can_delay && process.supports("delay");
+        match process.filter(command, path, content, meta, can_delay) {
+            Ok(Response::Success(content)) => Ok(Some(Smudged::Ready(content))),
+            Ok(Response::Delayed) if can_delay => {
+                process.delay(path, &driver.name);
+                Ok(Some(Smudged::Delayed))
+            }
+            Ok(Response::Error) | Ok(Response::Abort) => Ok(None),
+            Ok(Response::Delayed) | Err(_) => {
+                self.error(format!("external filter '{}' failed", cmd));
+                self.stop_process(&mut processes, cmd);
+                Ok(None)
+            }
+        }
+    }
+
+    fn error(&self, message: String) {
+        self.notices.borrow_mut().push(Notice::Error(message));
+    }
+
+    // Stops a filter that broke the protocol. Anything it had delayed is
+    // lost.
+    fn stop_process(&self, processes: &mut HashMap<String, FilterProcess>, cmd: &str) {
+        if let Some(mut process) = processes.remove(cmd) {
+            self.unfiltered
+                .borrow_mut()
+                .extend(process.abandon_delayed());
+            process.kill();
+        }
+    }
+
+    fn index_has_crlf(&self, index_id: Option<&Oid>) -> Result<bool> {
+        let (odb, id) = match (&self.odb, index_id) {
+            (Some(odb), Some(id)) => (odb, id),
+            _ => return Ok(false),
+        };
+        let content = match odb.get(id)?.into_blob() {
+            Some(blob) => blob.as_bytes().to_vec(),
+            None => return Ok(false),
+        };
+        if !content.contains(&b'\r') {
+            return Ok(false);
+        }
+        let stats = TextStat::gather(&content);
+        Ok(!stats.is_binary() && stats.crlf > 0)
+    }
+
+    // Turns working tree content into what gets hashed into a blob.
+    // `index_id` is the blob the index currently has for `path`, and
+    // `write_object` enables core.safecrlf, as it only applies to content
+    // that is actually being added.
+    pub fn to_git(
+        &self,
+        path: &[u8],
+        content: Vec<u8>,
+        index_id: Option<&Oid>,
+        write_object: bool,
+    ) -> Result<Vec<u8>> {
+        let (action, driver) = self.conversion(path)?;
+        // The clean filter runs first, so its output is what gets its line
+        // endings normalized.
+        let content = match driver {
+            Some(driver) => match self.apply_filter(
+                driver,
+                FilterCommand::Clean,
+                path,
+                &content,
+                None,
+                false,
+            )? {
+                Some(Smudged::Ready(filtered)) => filtered,
+                _ if driver.required => return Err(driver.failure(path, FilterCommand::Clean)),
+                _ => content,
+            },
+            None => content,
+        };
+        if action == CrlfAction::Binary || content.is_empty() {
+            return Ok(content);
+        }
+        let stats = TextStat::gather(&content);
+        let mut convert = stats.crlf > 0;
+        if action.is_auto() {
+            if stats.is_binary() {
+                return Ok(content);
+            }
+            if convert && self.index_has_crlf(index_id)? {
+                convert = false;
+            }
+        }
+        if write_object && self.options.safecrlf != SafeCrlf::False {
+            self.check_safe_crlf(path, &stats, convert, action)?;
+        }
+        if !convert {
+            return Ok(content);
+        }
+        let mut converted = Vec::with_capacity(content.len());
+        for (i, &c) in content.iter().enumerate() {
+            // Guessed text has no lone CR, so every CR can go.
+            let drop = c == b'\r' && (action.is_auto() || content.get(i + 1) == Some(&b'\n'));
+            if !drop {
+                converted.push(c);
+            }
+        }
+        Ok(converted)
+    }
+
+    // Warns through `take_notices`, or fails with core.safecrlf=true, when
+    // adding and checking out the content again would not give back the
+    // same line endings.
+    fn check_safe_crlf(
+        &self,
+        path: &[u8],
+        stats: &TextStat,
+        convert: bool,
+        action: CrlfAction,
+    ) -> Result<()> {
+        let mut after = *stats;
+        if convert {
+            after.lone_lf += after.crlf;
+            after.crlf = 0;
+        }
+        if after.will_convert_lf_to_crlf(action, &self.options) {
+            after.crlf += after.lone_lf;
+            after.lone_lf = 0;
+        }
+        let path = String::from_utf8_lossy(path);
+        let (from, to) = if stats.crlf > 0 && after.crlf == 0 {
+            ("CRLF", "LF")
+        } else if stats.lone_lf > 0 && after.lone_lf == 0 {
+            ("LF", "CRLF")
+        } else {
+            return Ok(());
+        };
+        if self.options.safecrlf == SafeCrlf::Fail {
+            return Err(Error::Conversion(format!(
+                "{} would be replaced by {} in {}",
+                from, to, path
+            )));
+        }
+        self.notices.borrow_mut().push(Notice::Warning(format!(
+            "in the working copy of '{}', {} will be replaced by {} the next time Git touches it",
+            path, from, to
+        )));
+        Ok(())
+    }
+
+    // Turns blob content into what gets written to the working tree.
+    pub fn to_worktree(&self, path: &[u8], content: Vec<u8>) -> Result<Vec<u8>> {
+        match self.smudge(path, content, None, false)? {
+            Smudged::Ready(content) => Ok(content),
+            Smudged::Delayed => unreachable!(),
+        }
+    }
+
+    // Like `to_worktree`, with what is known about the blob passed on to
+    // process filters. With `can_delay`, a process filter may hold on to
+    // the content; it is then fetched with `available_delayed` and
+    // `finish_delayed`.
+    pub fn smudge(
+        &self,
+        path: &[u8],
+        content: Vec<u8>,
+        meta: Option<&FilterMeta>,
+        can_delay: bool,
+    ) -> Result<Smudged> {
+        let (action, driver) = self.conversion(path)?;
+        let content = self.crlf_to_worktree(action, content);
+        let driver = match driver {
+            Some(driver) => driver,
+            None => return Ok(Smudged::Ready(content)),
+        };
+        match self.apply_filter(
+            driver,
+            FilterCommand::Smudge,
+            path,
+            &content,
+            meta,
+            can_delay,
+        )? {
+            Some(smudged) => Ok(smudged),
+            None if driver.required => Err(driver.failure(path, FilterCommand::Smudge)),
+            None => Ok(Smudged::Ready(content)),
+        }
+    }
+
+    fn crlf_to_worktree(&self, action: CrlfAction, content: Vec<u8>) -> Vec<u8> {
+        if content.is_empty() || !action.output_is_crlf(&self.options) {
+            return content;
+        }
+        let stats = TextStat::gather(&content);
+        if !stats.will_convert_lf_to_crlf(action, &self.options) {
+            return content;
+        }
+        let mut converted = Vec::with_capacity(content.len() + stats.lone_lf);
+        for (i, &c) in content.iter().enumerate() {
+            if c == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(c);
+        }
+        converted
+    }
+
+    pub fn has_delayed(&self) -> bool {
+        self.processes
+            .borrow()
+            .values()
+            .any(FilterProcess::has_delayed)
+    }
+
+    // Asks every filter still holding delayed paths which of them are
+    // ready. A filter with none ready has given up on the rest, which
+    // `take_unfiltered` then returns.
+    pub fn available_delayed(&self) -> Result<Vec<Vec<u8>>> {
+        let mut processes = self.processes.borrow_mut();
+        let waiting: Vec<String> = processes
+            .iter()
+            .filter(|(_, process)| process.has_delayed())
+            .map(|(cmd, _)| cmd.clone())
+            .collect();
+        let mut available = Vec::new();
+        for cmd in waiting {
+            let process = processes.get_mut(&cmd).unwrap();
+            match process.available_blobs() {
+                Ok(paths) if paths.is_empty() => {
+                    let abandoned = process.abandon_delayed();
+                    self.unfiltered.borrow_mut().extend(abandoned);
+                }
+                Ok(paths) => available.extend(paths),
+                Err(_) => {
+                    self.error(format!("external filter '{}' failed", cmd));
+                    self.stop_process(&mut processes, &cmd);
+                }
+            }
+        }
+        Ok(available)
+    }
+
+    // Fetches the content of a path a process filter delayed.
+    pub fn finish_delayed(&self, path: &[u8]) -> Result<Vec<u8>> {
+        let not_delayed = || {
+            Error::Conversion(format!(
+                "path '{}' was not delayed",
+                String::from_utf8_lossy(path)
+            ))
+        };
+        let mut processes = self.processes.borrow_mut();
+        let (cmd, name) = processes
+            .iter_mut()
+            .find_map(|(cmd, process)| Some((cmd.clone(), process.take_delayed(path)?)))
+            .ok_or_else(not_delayed)?;
+        let process = processes.get_mut(&cmd).unwrap();
+        match process.filter(FilterCommand::Smudge, path, b"", None, false) {
+            Ok(Response::Success(content)) => Ok(content),
+            Ok(_) => Err(Error::Conversion(format!(
+                "{}: smudge filter {} failed",
+                String::from_utf8_lossy(path),
+                name
+            ))),
+            Err(error) => {
+                self.error(format!("external filter '{}' failed", cmd));
+                self.stop_process(&mut processes, &cmd);
+                Err(error)
+            }
+        }
+    }
+
+    // Delayed paths no filter will deliver any more.
+    pub fn take_unfiltered(&self) -> Vec<Vec<u8>> {
+        self.unfiltered.borrow_mut().drain(..).collect()
+    }
+
+    // What the conversions so far had to say, for the caller to show.
+    pub fn take_notices(&self) -> Vec<Notice> {
+        self.notices.borrow_mut().drain(..).collect()
+    }
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notice::Warning(message) => write!(f, "warning: {}", message),
+            Notice::Error(message) => write!(f, "error: {}", message),
+        }
+    }
+}
+
+impl Default for Converter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::AttrSource;
+    use crate::odb::ObjectType;
+    use crate::testutil::{TempDir, FILTER_PROCESS};
+
+    fn converter(tmp: &TempDir, gitattributes: &str, options: ConvertOptions) -> Converter {
+        let path = tmp.path().join("attributes");
+        std::fs::write(&path, gitattributes).unwrap();
+        let mut attributes = Attributes::new(Some(tmp.path()), AttrSource::CheckIn);
+        attributes.add_info_file(path).unwrap();
+        let odb = StandardOdb::from_path(tmp.path().join("objects"));
+        Converter::with_attributes(attributes, options, Some(odb))
+    }
+
+    fn with_autocrlf(autocrlf: AutoCrlf) -> ConvertOptions {
+        ConvertOptions {
+            autocrlf,
+            ..ConvertOptions::new()
+        }
+    }
+
+    fn to_git(converter: &Converter, path: &str, content: &[u8]) -> Vec<u8> {
+        converter
+            .to_git(path.as_bytes(), content.to_vec(), None, false)
+            .unwrap()
+    }
+
+    fn to_worktree(converter: &Converter, path: &str, content: &[u8]) -> Vec<u8> {
+        converter
+            .to_worktree(path.as_bytes(), content.to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn converts_with_autocrlf() {
+        let tmp = TempDir::new();
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::True));
+        assert_eq!(to_git(&c, "a.txt", b"a\r\nb\r\n"), b"a\nb\n");
+        assert_eq!(to_worktree(&c, "a.txt", b"a\nb\n"), b"a\r\nb\r\n");
+        // Binary content is left alone both ways.
+        assert_eq!(to_git(&c, "bin", b"a\0\r\n"), b"a\0\r\n");
+        assert_eq!(to_worktree(&c, "bin", b"a\0\n"), b"a\0\n");
+
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::Input));
+        assert_eq!(to_git(&c, "a.txt", b"a\r\nb\r\n"), b"a\nb\n");
+        assert_eq!(to_worktree(&c, "a.txt", b"a\nb\n"), b"a\nb\n");
+
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::False));
+        assert_eq!(to_git(&c, "a.txt", b"a\r\nb\r\n"), b"a\r\nb\r\n");
+        assert_eq!(to_worktree(&c, "a.txt", b"a\nb\n"), b"a\nb\n");
+    }
+
+    #[test]
+    fn text_and_eol_attributes() {
+        let tmp = TempDir::new();
+        let c = converter(
+            &tmp,
+            "*.txt text eol=crlf\n*.sh text eol=lf\n*.bin -text\n",
+            with_autocrlf(AutoCrlf::True),
+        );
+        assert_eq!(c.crlf_action(b"a.txt").unwrap(), CrlfAction::TextCrlf);
+        assert_eq!(c.crlf_action(b"a.sh").unwrap(), CrlfAction::TextInput);
+        assert_eq!(c.crlf_action(b"a.bin").unwrap(), CrlfAction::Binary);
+        assert_eq!(c.crlf_action(b"other").unwrap(), CrlfAction::AutoCrlf);
+
+        // Declared text keeps lone CRs and is converted even with a NUL.
+        assert_eq!(to_git(&c, "a.txt", b"a\rb\r\n"), b"a\rb\n");
+        assert_eq!(to_git(&c, "a.txt", b"a\0\r\n"), b"a\0\n");
+        assert_eq!(to_worktree(&c, "a.txt", b"a\nb\r\n"), b"a\r\nb\r\n");
+        assert_eq!(to_worktree(&c, "a.sh", b"a\n"), b"a\n");
+        assert_eq!(to_git(&c, "a.bin", b"a\r\n"), b"a\r\n");
+        assert_eq!(to_worktree(&c, "a.bin", b"a\n"), b"a\n");
+    }
+
+    #[test]
+    fn auto_keeps_crlf_already_in_the_index() {
+        let tmp = TempDir::new();
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::True));
+        let odb = StandardOdb::from_path(tmp.path().join("objects"));
+        let crlf = odb.write(ObjectType::Blob, b"old\r\n").unwrap();
+        let lf = odb.write(ObjectType::Blob, b"old\n").unwrap();
+        let add = |id: &Oid| c.to_git(b"a.txt", b"new\r\n".to_vec(), Some(id), false);
+        assert_eq!(add(&crlf).unwrap(), b"new\r\n");
+        assert_eq!(add(&lf).unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn safecrlf() {
+        let tmp = TempDir::new();
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::True));
+        c.to_git(b"m.txt", b"a\r\nb\n".to_vec(), None, true)
+            .unwrap();
+        assert_eq!(
+            c.take_notices(),
+            [Notice::Warning(
+                "in the working copy of 'm.txt', LF will be replaced by CRLF the next time Git touches it".to_string()
+            )]
+        );
+        // Only content being written is checked.
+        c.to_git(b"m.txt", b"a\r\nb\n".to_vec(), None, false)
+            .unwrap();
+        assert!(c.take_notices().is_empty());
+
+        let c = converter(&tmp, "", with_autocrlf(AutoCrlf::Input));
+        assert_eq!(
+            c.to_git(b"c.txt", b"a\r\n".to_vec(), None, true).unwrap(),
+            b"a\n"
+        );
+        assert_eq!(
+            c.take_notices(),
+            [Notice::Warning(
+                "in the working copy of 'c.txt', CRLF will be replaced by LF the next time Git touches it".to_string()
+            )]
+        );
+
+        let c = converter(
+            &tmp,
+            "",
+            ConvertOptions {
+                safecrlf: SafeCrlf::Fail,
+                ..with_autocrlf(AutoCrlf::Input)
+            },
+        );
+        match c.to_git(b"c.txt", b"a\r\n".to_vec(), None, true) {
+            Err(Error::Conversion(message)) => {
+                assert_eq!(message, "CRLF would be replaced by LF in c.txt")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Nothing to say about content that survives a round trip.
+        c.to_git(b"l.txt", b"a\n".to_vec(), None, true).unwrap();
+        assert!(c.take_notices().is_empty());
+    }
+
+    fn driver(
+        name: &str,
+        clean: Option<&str>,
+        smudge: Option<&str>,
+        required: bool,
+    ) -> FilterDriver {
+        FilterDriver {
+            clean: clean.map(str::to_string),
+            smudge: smudge.map(str::to_string),
+            required,
+            ..FilterDriver::new(name)
+        }
+    }
+
+    #[test]
+    fn reports_failing_filter_commands() {
+        let tmp = TempDir::new();
+        let mut c = converter(
+            &tmp,
+            "*.up filter=upper\n*.req filter=strict\n",
+            ConvertOptions::new(),
+        );
+        c.add_driver(driver("upper", Some("exit 3"), Some("tr a-z A-Z"), false));
+        c.add_driver(driver("strict", Some("exit 3"), None, true));
+
+        assert_eq!(to_worktree(&c, "a.up", b"x\n"), b"X\n");
+        assert_eq!(to_git(&c, "a.up", b"x\n"), b"x\n");
+        assert_eq!(
+            c.take_notices(),
+            [
+                Notice::Error("external filter 'exit 3' failed 3".to_string()),
+                Notice::Error("external filter 'exit 3' failed".to_string()),
+            ]
+        );
+        match c.to_git(b"a.req", b"x\n".to_vec(), None, false) {
+            Err(Error::Conversion(message)) => {
+                assert_eq!(message, "a.req: clean filter 'strict' failed")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(c.take_notices().len(), 2);
+        // So is a required driver without the command.
+        assert!(c.to_worktree(b"a.req", b"x\n".to_vec()).is_err());
+        assert!(c.take_notices().is_empty());
+    }
+
+    #[test]
+    fn runs_process_filters() {
+        let tmp = TempDir::new();
+        let mut c = converter(&tmp, "* filter=proc\n", ConvertOptions::new());
+        c.add_driver(FilterDriver {
+            process: Some(FILTER_PROCESS.to_string()),
+            ..FilterDriver::new("proc")
+        });
+        assert_eq!(to_git(&c, "a", b"x\n"), b"X\n");
+        assert_eq!(to_worktree(&c, "error", b"x\n"), b"x\n");
+        assert!(c.take_notices().is_empty());
+
+        assert_eq!(
+            c.smudge(b"delay", b"x\n".to_vec(), None, true).unwrap(),
+            Smudged::Delayed
+        );
+        assert!(c.has_delayed());
+        assert_eq!(c.available_delayed().unwrap(), [b"delay".to_vec()]);
+        assert_eq!(c.finish_delayed(b"delay").unwrap(), b"X\n");
+        assert!(c.finish_delayed(b"delay").is_err());
+        assert!(!c.has_delayed());
+        assert!(c.take_unfiltered().is_empty());
+
+        // Once aborted, smudging goes on without the filter.
+        assert_eq!(to_worktree(&c, "abort", b"x\n"), b"x\n");
+        assert_eq!(to_worktree(&c, "b", b"x\n"), b"x\n");
+        assert_eq!(to_git(&c, "b", b"x\n"), b"X\n");
+        assert!(c.take_notices().is_empty());
+    }
+
+    #[test]
+    fn reports_process_filters_that_fail_to_start() {
+        let tmp = TempDir::new();
+        let mut c = converter(&tmp, "* filter=proc\n", ConvertOptions::new());
+        c.add_driver(FilterDriver {
+            process: Some("exit 0".to_string()),
+            ..FilterDriver::new("proc")
+        });
+        assert_eq!(to_git(&c, "a", b"x\n"), b"x\n");
+        assert_eq!(
+            c.take_notices(),
+            [Notice::Error(
+                "initialization for subprocess 'exit 0' failed".to_string()
+            )]
+        );
+    }
+}
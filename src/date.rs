@@ -0,0 +1,1107 @@
+use std::convert::TryFrom;
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, TimeZone, Timelike, Utc,
+};
+
+use crate::error::{Error, Result};
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Sundays",
+    "Mondays",
+    "Tuesdays",
+    "Wednesdays",
+    "Thursdays",
+    "Fridays",
+    "Saturdays",
+];
+
+// Time zone names with their offset in hours and whether they are summer
+// time, which adds an hour.
+const TIMEZONE_NAMES: [(&str, i64, i64); 44] = [
+    ("IDLW", -12, 0),
+    ("NT", -11, 0),
+    ("CAT", -10, 0),
+    ("HST", -10, 0),
+    ("HDT", -10, 1),
+    ("YST", -9, 0),
+    ("YDT", -9, 1),
+    ("PST", -8, 0),
+    ("PDT", -8, 1),
+    ("MST", -7, 0),
+    ("MDT", -7, 1),
+    ("CST", -6, 0),
+    ("CDT", -6, 1),
+    ("EST", -5, 0),
+    ("EDT", -5, 1),
+    ("AST", -3, 0),
+    ("ADT", -3, 1),
+    ("WAT", -1, 0),
+    ("GMT", 0, 0),
+    ("UTC", 0, 0),
+    ("Z", 0, 0),
+    ("WET", 0, 0),
+    ("BST", 0, 1),
+    ("CET", 1, 0),
+    ("MET", 1, 0),
+    ("MEWT", 1, 0),
+    ("MEST", 1, 1),
+    ("CEST", 1, 1),
+    ("MESZ", 1, 1),
+    ("FWT", 1, 0),
+    ("FST", 1, 1),
+    ("EET", 2, 0),
+    ("EEST", 2, 1),
+    ("WAST", 7, 0),
+    ("WADT", 7, 1),
+    ("CCT", 8, 0),
+    ("JST", 9, 0),
+    ("EAST", 10, 0),
+    ("EADT", 10, 1),
+    ("GST", 10, 0),
+    ("NZT", 12, 0),
+    ("NZST", 12, 0),
+    ("NZDT", 12, 1),
+    ("IDLE", 12, 0),
+];
+
+const NUMBER_NAMES: [&str; 11] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+];
+
+// Periods that can be counted back from now, in seconds.
+const PERIODS: [(&str, i64); 5] = [
+    ("seconds", 1),
+    ("minutes", 60),
+    ("hours", 60 * 60),
+    ("days", 24 * 60 * 60),
+    ("weeks", 7 * 24 * 60 * 60),
+];
+
+// The ways git shows dates, as --date names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    Default,
+    Relative,
+    Human,
+    Short,
+    Iso,
+    IsoStrict,
+    Rfc2822,
+    Raw,
+    Unix,
+}
+
+// A format, and whether to show dates in the local time zone rather than
+// the one they were recorded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateMode {
+    pub format: DateFormat,
+    pub local: bool,
+}
+
+// The parts of a date like C's `struct tm`, with -1 for those not known.
+// Years count from 1900 and months from 0.
+#[derive(Debug, Clone, Copy)]
+struct Tm {
+    year: i64,
+    mon: i64,
+    mday: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    wday: i64,
+    isdst: i64,
+}
+
+impl DateMode {
+    pub fn new(format: DateFormat) -> Self {
+        Self {
+            format,
+            local: false,
+        }
+    }
+
+    // Reads a --date name such as `iso-strict` or `short-local`. `local`
+    // alone is the default format in the local time zone.
+    pub fn parse(name: &str) -> Result<Self> {
+        let unknown = || Error::InvalidDate(format!("unknown date format {}", name));
+        let full = if name == "local" {
+            "default-local"
+        } else {
+            name
+        };
+        let (format, rest) = [
+            ("relative", DateFormat::Relative),
+            ("iso8601-strict", DateFormat::IsoStrict),
+            ("iso-strict", DateFormat::IsoStrict),
+            ("iso8601", DateFormat::Iso),
+            ("iso", DateFormat::Iso),
+            ("rfc2822", DateFormat::Rfc2822),
+            ("rfc", DateFormat::Rfc2822),
+            ("short", DateFormat::Short),
+            ("default", DateFormat::Default),
+            ("human", DateFormat::Human),
+            ("raw", DateFormat::Raw),
+            ("unix", DateFormat::Unix),
+        ]
+        .iter()
+        .find_map(|&(prefix, format)| full.strip_prefix(prefix).map(|rest| (format, rest)))
+        .ok_or_else(unknown)?;
+        match rest {
+            "" => Ok(Self::new(format)),
+            "-local" => Ok(Self {
+                format,
+                local: true,
+            }),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl Default for DateMode {
+    fn default() -> Self {
+        Self::new(DateFormat::Default)
+    }
+}
+
+impl Tm {
+    fn unset() -> Self {
+        Self {
+            year: -1,
+            mon: -1,
+            mday: -1,
+            hour: -1,
+            min: -1,
+            sec: -1,
+            wday: -1,
+            isdst: -1,
+        }
+    }
+
+    fn from_datetime<T: Datelike + Timelike>(datetime: &T) -> Self {
+        Self {
+            year: i64::from(datetime.year()) - 1900,
+            mon: i64::from(datetime.month0()),
+            mday: i64::from(datetime.day()),
+            hour: i64::from(datetime.hour()),
+            min: i64::from(datetime.minute()),
+            sec: i64::from(datetime.second()),
+            wday: i64::from(datetime.weekday().num_days_from_sunday()),
+            isdst: -1,
+        }
+    }
+
+    fn gmtime(time: i64) -> Option<Self> {
+        Utc.timestamp_opt(time, 0)
+            .single()
+            .map(|datetime| Self::from_datetime(&datetime))
+    }
+
+    fn localtime(time: i64) -> Self {
+        match Local.timestamp_opt(time, 0).earliest() {
+            Some(datetime) => {
+                let (standard, summer) = local_offsets(datetime.year());
+                Self {
+                    isdst: i64::from(standard != summer && local_offset(time) == summer),
+                    ..Self::from_datetime(&datetime)
+                }
+            }
+            None => Self::gmtime(0).unwrap(),
+        }
+    }
+
+    // Whether nothing of the date or time is known yet.
+    fn is_unset(&self) -> bool {
+        (self.year & self.mon & self.mday & self.hour & self.min & self.sec) < 0
+    }
+
+    // The time this is in the local time zone, with parts out of range
+    // carried over like mktime does.
+    fn mktime(&self) -> i64 {
+        let month = (self.year + 1900) * 12 + self.mon;
+        let date = i32::try_from(month.div_euclid(12))
+            .ok()
+            .and_then(|year| NaiveDate::from_ymd_opt(year, month.rem_euclid(12) as u32 + 1, 1));
+        let naive = match date.and_then(|date| date.and_hms_opt(0, 0, 0)) {
+            Some(naive) => {
+                naive
+                    + Duration::days(self.mday - 1)
+                    + Duration::seconds(self.hour * 60 * 60 + self.min * 60 + self.sec)
+            }
+            None => return -1,
+        };
+        let time = match Local.from_local_datetime(&naive).earliest() {
+            Some(datetime) => datetime.timestamp(),
+            // A time skipped by a change to summer time.
+            None => match Local
+                .from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+            {
+                Some(datetime) => datetime.timestamp() - 60 * 60,
+                None => return -1,
+            },
+        };
+        // Like mktime, a date said to be in summer time or not is read
+        // with that offset, whatever the offset is on that date.
+        let (standard, summer) = local_offsets(naive.year());
+        if self.isdst < 0
+            || standard == summer
+            || (local_offset(time) == summer) == (self.isdst > 0)
+        {
+            return time;
+        }
+        if self.isdst > 0 {
+            time - (summer - standard) * 60
+        } else {
+            time + (summer - standard) * 60
+        }
+    }
+
+    // The time this is in UTC, for the years 1970 to 2099 only.
+    fn utc_time(&self) -> i64 {
+        const MONTH_DAYS: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let year = self.year - 70;
+        let mut day = self.mday;
+        if !(0..=129).contains(&year) || !(0..=11).contains(&self.mon) {
+            return -1;
+        }
+        if self.mon < 2 || (year + 2) % 4 != 0 {
+            day -= 1;
+        }
+        if self.hour < 0 || self.min < 0 || self.sec < 0 {
+            return -1;
+        }
+        (year * 365 + (year + 1) / 4 + MONTH_DAYS[self.mon as usize] + day) * 24 * 60 * 60
+            + self.min * 60
+            + self.hour * 60 * 60
+            + self.sec
+    }
+
+    fn set_time(&mut self, hour: i64, min: i64, sec: i64) -> bool {
+        // A 61st second allows for leap seconds.
+        if (0..=24).contains(&hour) && (0..60).contains(&min) && (0..=60).contains(&sec) {
+            self.hour = hour;
+            self.min = min;
+            self.sec = sec;
+            return true;
+        }
+        false
+    }
+}
+
+fn is_digit_at(date: &[u8], i: usize) -> bool {
+    date.get(i).is_some_and(u8::is_ascii_digit)
+}
+
+// The number the digits at the start of `date` make, like strtoul, and
+// how many digits there are.
+fn parse_number(date: &[u8]) -> (i64, usize) {
+    let len = date.iter().take_while(|c| c.is_ascii_digit()).count();
+    let number = date[..len].iter().fold(0i64, |number, &c| {
+        number
+            .saturating_mul(10)
+            .saturating_add(i64::from(c - b'0'))
+    });
+    (number, len)
+}
+
+// How much of `date` matches the start of `name`, ignoring case, up to
+// where the word in `date` ends. A word that goes on matches nothing.
+fn match_string(date: &[u8], name: &str) -> usize {
+    let name = name.as_bytes();
+    let mut i = 0;
+    while i < date.len() {
+        let c = name.get(i).copied().unwrap_or(0);
+        if date[i].eq_ignore_ascii_case(&c) {
+            i += 1;
+            continue;
+        }
+        if !date[i].is_ascii_alphanumeric() {
+            break;
+        }
+        return 0;
+    }
+    i
+}
+
+// Sets the date to `year`-`month`-`day` if that makes sense. Given the
+// current time, the year may be left out, and dates more than ten days
+// ahead are refused.
+fn is_date(year: i64, month: i64, day: i64, now_tm: Option<&Tm>, now: i64, tm: &mut Tm) -> bool {
+    if !(1..13).contains(&month) || !(1..32).contains(&day) {
+        return false;
+    }
+    let mut check = *tm;
+    let r = if now_tm.is_some() {
+        &mut check
+    } else {
+        &mut *tm
+    };
+    r.mon = month - 1;
+    r.mday = day;
+    if year == -1 {
+        match now_tm {
+            Some(now_tm) => r.year = now_tm.year,
+            None => return true,
+        }
+    } else if (1970..2100).contains(&year) {
+        r.year = year - 1900;
+    } else if year > 70 && year < 100 {
+        r.year = year;
+    } else if year < 38 {
+        r.year = year + 100;
+    } else {
+        return false;
+    }
+    if now_tm.is_none() {
+        return true;
+    }
+    let specified = check.utc_time();
+    if specified != -1 && now + 10 * 24 * 60 * 60 < specified {
+        return false;
+    }
+    tm.mon = check.mon;
+    tm.mday = check.mday;
+    if year != -1 {
+        tm.year = check.year;
+    }
+    true
+}
+
+// Numbers separated by `c`, which make a time for `:` and otherwise a
+// date in one of several orders. Returns how much of `date` that takes,
+// or 0 if it is neither.
+fn match_multi_number(num: i64, c: u8, date: &[u8], end: usize, tm: &mut Tm, now: i64) -> usize {
+    let (num2, len) = parse_number(&date[end + 1..]);
+    let mut end = end + 1 + len;
+    let mut num3 = -1;
+    if date.get(end) == Some(&c) && is_digit_at(date, end + 1) {
+        let (number, len) = parse_number(&date[end + 1..]);
+        num3 = number;
+        end += 1 + len;
+    }
+    if c == b':' {
+        if num3 < 0 {
+            num3 = 0;
+        }
+        if num < 25 && (0..60).contains(&num2) && (0..=60).contains(&num3) {
+            tm.hour = num;
+            tm.min = num2;
+            tm.sec = num3;
+            return end;
+        }
+        return 0;
+    }
+    let now = if now == 0 {
+        Utc::now().timestamp()
+    } else {
+        now
+    };
+    let refuse_future = Tm::gmtime(now);
+    let refuse_future = refuse_future.as_ref();
+    // yyyy-mm-dd and yyyy-dd-mm, then mm/dd/yy unless the separator is a
+    // dot, as dd.mm.yy is the norm in Europe, then dd/mm/yy, then mm.dd.yy.
+    let matched = (num > 70
+        && (is_date(num, num2, num3, None, now, tm) || is_date(num, num3, num2, None, now, tm)))
+        || (c != b'.' && is_date(num3, num, num2, refuse_future, now, tm))
+        || is_date(num3, num2, num, refuse_future, now, tm)
+        || (c == b'.' && is_date(num3, num, num2, refuse_future, now, tm));
+    if matched {
+        end
+    } else {
+        0
+    }
+}
+
+// A month or weekday name, a time zone, AM or PM, or the `T` of ISO 8601.
+fn match_alpha(date: &[u8], tm: &mut Tm, offset: &mut i64) -> usize {
+    for (i, name) in MONTH_NAMES.iter().enumerate() {
+        let matched = match_string(date, name);
+        if matched >= 3 {
+            tm.mon = i as i64;
+            return matched;
+        }
+    }
+    for (i, name) in WEEKDAY_NAMES.iter().enumerate() {
+        let matched = match_string(date, name);
+        if matched >= 3 {
+            tm.wday = i as i64;
+            return matched;
+        }
+    }
+    for &(name, hours, dst) in &TIMEZONE_NAMES {
+        let matched = match_string(date, name);
+        if matched >= 3 || matched == name.len() {
+            // An offset given as a number wins over a name.
+            if *offset == -1 {
+                *offset = 60 * (hours + dst);
+            }
+            return matched;
+        }
+    }
+    if match_string(date, "PM") == 2 {
+        tm.hour = tm.hour % 12 + 12;
+        return 2;
+    }
+    if match_string(date, "AM") == 2 {
+        tm.hour %= 12;
+        return 2;
+    }
+    if date[0] == b'T' && is_digit_at(date, 1) && tm.hour == -1 {
+        tm.min = 0;
+        tm.sec = 0;
+        return 1;
+    }
+    1 + date[1..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .count()
+}
+
+// A number, which may be seconds since the epoch, a time, a date, a year
+// or an offset, depending on its length and what is known already.
+fn match_digit(date: &[u8], tm: &mut Tm, offset: &mut i64, tm_gmt: &mut bool) -> usize {
+    let (num, mut end) = parse_number(date);
+    // Fewer digits could be a date like 20070606.
+    if num >= 100_000_000 && tm.is_unset() {
+        if let Some(time) = Tm::gmtime(num) {
+            *tm = time;
+            *tm_gmt = true;
+            return end;
+        }
+    }
+    if let Some(&c @ (b':' | b'.' | b'/' | b'-')) = date.get(end) {
+        if is_digit_at(date, end + 1) {
+            let matched = match_multi_number(num, c, date, end, tm, 0);
+            if matched != 0 {
+                return matched;
+            }
+        }
+    }
+    let n = end;
+    // The compact ISO 8601 forms YYYYmmDD and HHMMSS.
+    if n == 8 || n == 6 {
+        let (num1, num2, num3) = (num / 10000, num % 10000 / 100, num % 100);
+        if n == 8 {
+            is_date(num1, num2, num3, None, 0, tm);
+        } else if tm.set_time(num1, num2, num3)
+            && date.get(end) == Some(&b'.')
+            && is_digit_at(date, end + 1)
+        {
+            end += 1 + parse_number(&date[end + 1..]).1;
+        }
+        return end;
+    }
+    if n == 4 {
+        if num <= 1400 && *offset == -1 {
+            *offset = num / 100 * 60 + num % 100;
+        } else if num > 1900 && num < 2100 {
+            tm.year = num - 1900;
+        }
+        return n;
+    }
+    if n > 2 {
+        return n;
+    }
+    // Numbers that could be a day of the month are one, so 01 Apr 05 is
+    // April 1st, 2005.
+    if num > 0 && num < 32 && tm.mday < 0 {
+        tm.mday = num;
+        return n;
+    }
+    if n == 2 && tm.year < 0 {
+        if num < 10 && tm.mday >= 0 {
+            tm.year = num + 100;
+            return n;
+        }
+        if num >= 70 {
+            tm.year = num;
+            return n;
+        }
+    }
+    if num > 0 && num < 13 && tm.mon < 0 {
+        tm.mon = num - 1;
+    }
+    n
+}
+
+// An offset like +0200, +02 or +02:00, in minutes.
+fn match_tz(date: &[u8], offset: &mut i64) -> usize {
+    let (mut hour, n) = parse_number(&date[1..]);
+    let mut end = 1 + n;
+    let mut min = 0;
+    if n == 4 {
+        min = hour % 100;
+        hour /= 100;
+    } else if n != 2 {
+        min = 99;
+    } else if date.get(end) == Some(&b':') {
+        let (minutes, len) = parse_number(&date[end + 1..]);
+        min = minutes;
+        end += 1 + len;
+        if end - 1 != 5 {
+            min = 99;
+        }
+    }
+    if min < 60 && hour < 24 {
+        let minutes = hour * 60 + min;
+        *offset = if date[0] == b'-' { -minutes } else { minutes };
+    }
+    end
+}
+
+// git's own `seconds +hhmm`, which is taken as is even for times near the
+// epoch that other forms would not allow.
+fn match_raw_date(date: &[u8]) -> Option<(i64, i64)> {
+    if !is_digit_at(date, 0) {
+        return None;
+    }
+    let (time, end) = parse_number(date);
+    if date.get(end) != Some(&b' ') || !matches!(date.get(end + 1), Some(b'+' | b'-')) {
+        return None;
+    }
+    let rest = &date[end + 2..];
+    let (hhmm, len) = parse_number(rest);
+    if len != 4 || !matches!(rest.get(len), None | Some(b'\n')) {
+        return None;
+    }
+    let offset = hhmm / 100 * 60 + hhmm % 100;
+    Some((
+        time,
+        if date[end + 1] == b'-' {
+            -offset
+        } else {
+            offset
+        },
+    ))
+}
+
+// The time and offset in minutes of a date in one of the forms git takes
+// strictly, like RFC 2822, ISO 8601 or its own. Dates without an offset
+// are in local time.
+fn parse_date_basic(date: &str) -> Option<(i64, i64)> {
+    let date = date.as_bytes();
+    if date.first() == Some(&b'@') {
+        if let Some(parsed) = match_raw_date(&date[1..]) {
+            return Some(parsed);
+        }
+    }
+    let mut tm = Tm::unset();
+    let mut offset = -1;
+    let mut tm_gmt = false;
+    let mut i = 0;
+    while i < date.len() && date[i] != b'\n' {
+        let rest = &date[i..];
+        let c = rest[0];
+        let matched = if c.is_ascii_alphabetic() {
+            match_alpha(rest, &mut tm, &mut offset)
+        } else if c.is_ascii_digit() {
+            match_digit(rest, &mut tm, &mut offset, &mut tm_gmt)
+        } else if (c == b'-' || c == b'+') && is_digit_at(rest, 1) {
+            match_tz(rest, &mut offset)
+        } else {
+            0
+        };
+        i += matched.max(1);
+    }
+    let mut time = tm.utc_time();
+    if time == -1 {
+        return None;
+    }
+    if offset == -1 {
+        offset = (time - tm.mktime()) / 60;
+    }
+    if !tm_gmt {
+        time -= offset * 60;
+    }
+    Some((time, offset))
+}
+
+// Reads a date as GIT_AUTHOR_DATE and GIT_COMMITTER_DATE take it: git's
+// raw `seconds +hhmm`, RFC 2822, ISO 8601 and similar forms.
+pub fn parse_date(date: &str) -> Result<DateTime<FixedOffset>> {
+    let invalid = || Error::InvalidDate(format!("invalid date format: {}", date));
+    let (time, offset) = parse_date_basic(date).ok_or_else(invalid)?;
+    FixedOffset::east_opt(i32::try_from(offset * 60).map_err(|_| invalid())?)
+        .and_then(|offset| offset.timestamp_opt(time, 0).single())
+        .ok_or_else(invalid)
+}
+
+// Fills in what the date does not say from `now`, then goes back `sec`
+// seconds.
+fn update_tm(tm: &mut Tm, now: &Tm, sec: i64) -> i64 {
+    if tm.mday < 0 {
+        tm.mday = now.mday;
+    }
+    if tm.mon < 0 {
+        tm.mon = now.mon;
+    }
+    if tm.year < 0 {
+        tm.year = now.year;
+        if tm.mon > now.mon {
+            tm.year -= 1;
+        }
+    }
+    let time = tm.mktime() - sec;
+    *tm = Tm::localtime(time);
+    time
+}
+
+// A number left over is taken as the day of the month, as in "Dec 6,
+// 1992", or else as the month or the year.
+fn pending_number(tm: &mut Tm, num: &mut i64) {
+    let number = std::mem::take(num);
+    if number == 0 {
+        return;
+    }
+    if tm.mday < 0 && number < 32 {
+        tm.mday = number;
+    } else if tm.mon < 0 && number < 13 {
+        tm.mon = number - 1;
+    } else if tm.year < 0 {
+        if number > 1969 && number < 2100 {
+            tm.year = number - 1900;
+        } else if number > 69 && number < 100 {
+            tm.year = number;
+        } else if number < 38 {
+            tm.year = 100 + number;
+        }
+    }
+}
+
+// Sets the time of day to `hour` o'clock, the day before if that is still
+// to come.
+fn date_time(tm: &mut Tm, now: &Tm, hour: i64) {
+    if tm.hour < hour {
+        update_tm(tm, now, 24 * 60 * 60);
+    }
+    tm.hour = hour;
+    tm.min = 0;
+    tm.sec = 0;
+}
+
+// Words like "yesterday", "noon" or "now". Returns false for other words.
+fn approxidate_special(word: &[u8], tm: &mut Tm, now: &Tm, num: &mut i64) -> bool {
+    let is = |name: &str| match_string(word, name) == name.len();
+    if is("yesterday") {
+        *num = 0;
+        update_tm(tm, now, 24 * 60 * 60);
+    } else if is("noon") || is("midnight") || is("tea") {
+        pending_number(tm, num);
+        let hour = if is("noon") {
+            12
+        } else if is("tea") {
+            17
+        } else {
+            0
+        };
+        date_time(tm, now, hour);
+    } else if is("PM") || is("AM") {
+        let mut hour = tm.hour;
+        let n = std::mem::take(num);
+        if n != 0 {
+            hour = n;
+            tm.min = 0;
+            tm.sec = 0;
+        }
+        tm.hour = hour % 12 + if is("PM") { 12 } else { 0 };
+    } else if is("never") {
+        *tm = Tm::localtime(0);
+        *num = 0;
+    } else if is("now") {
+        *num = 0;
+        update_tm(tm, now, 0);
+    } else {
+        return false;
+    }
+    true
+}
+
+// A word in a date like "3 weeks ago" or "last friday".
+fn approxidate_alpha(
+    date: &[u8],
+    tm: &mut Tm,
+    now: &Tm,
+    num: &mut i64,
+    touched: &mut bool,
+) -> usize {
+    let end = 1 + date[1..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .count();
+    if let Some(i) = MONTH_NAMES
+        .iter()
+        .position(|name| match_string(date, name) >= 3)
+    {
+        tm.mon = i as i64;
+        *touched = true;
+        return end;
+    }
+    if approxidate_special(date, tm, now, num) {
+        *touched = true;
+        return end;
+    }
+    if *num == 0 {
+        if let Some(i) = (1..NUMBER_NAMES.len())
+            .find(|&i| match_string(date, NUMBER_NAMES[i]) == NUMBER_NAMES[i].len())
+        {
+            *num = i as i64;
+            *touched = true;
+        } else if match_string(date, "last") == 4 {
+            *num = 1;
+            *touched = true;
+        }
+        return end;
+    }
+    // Singular names are fine too.
+    for &(name, seconds) in &PERIODS {
+        if match_string(date, name) >= name.len() - 1 {
+            update_tm(tm, now, seconds * *num);
+            *num = 0;
+            *touched = true;
+            return end;
+        }
+    }
+    if let Some(i) = WEEKDAY_NAMES
+        .iter()
+        .position(|name| match_string(date, name) >= 3)
+    {
+        let mut n = std::mem::take(num) - 1;
+        let mut diff = tm.wday - i as i64;
+        if diff <= 0 {
+            n += 1;
+        }
+        diff += 7 * n;
+        update_tm(tm, now, diff * 24 * 60 * 60);
+        *touched = true;
+        return end;
+    }
+    if match_string(date, "months") >= 5 {
+        update_tm(tm, now, 0);
+        let mut month = tm.mon - std::mem::take(num);
+        while month < 0 {
+            month += 12;
+            tm.year -= 1;
+        }
+        tm.mon = month;
+        *touched = true;
+        return end;
+    }
+    if match_string(date, "years") >= 4 {
+        update_tm(tm, now, 0);
+        tm.year -= std::mem::take(num);
+        *touched = true;
+    }
+    end
+}
+
+fn approxidate_digit(date: &[u8], tm: &mut Tm, num: &mut i64, now: i64) -> usize {
+    let (number, end) = parse_number(date);
+    if let Some(&c @ (b':' | b'.' | b'/' | b'-')) = date.get(end) {
+        if is_digit_at(date, end + 1) {
+            let matched = match_multi_number(number, c, date, end, tm, now);
+            if matched != 0 {
+                return matched;
+            }
+        }
+    }
+    // Zeros in front only make sense for small numbers, as in "Dec 02".
+    if date[0] != b'0' || end <= 2 {
+        *num = number;
+    }
+    end
+}
+
+// Dates in almost any form, like "yesterday", "3 weeks ago" or "last
+// friday noon", relative to `now`. Returns None when nothing in `date`
+// looks like a date at all.
+pub fn approxidate_at(date: &str, now: i64) -> Option<i64> {
+    if let Some((time, _)) = parse_date_basic(date) {
+        return Some(time);
+    }
+    let date = date.as_bytes();
+    let mut tm = Tm::localtime(now);
+    let now_tm = tm;
+    tm.year = -1;
+    tm.mon = -1;
+    tm.mday = -1;
+    let mut number = 0;
+    let mut touched = false;
+    let mut i = 0;
+    while i < date.len() {
+        let c = date[i];
+        if c.is_ascii_digit() {
+            pending_number(&mut tm, &mut number);
+            i += approxidate_digit(&date[i..], &mut tm, &mut number, now);
+            touched = true;
+        } else if c.is_ascii_alphabetic() {
+            i += approxidate_alpha(&date[i..], &mut tm, &now_tm, &mut number, &mut touched);
+        } else {
+            i += 1;
+        }
+    }
+    pending_number(&mut tm, &mut number);
+    let time = update_tm(&mut tm, &now_tm, 0);
+    if touched {
+        Some(time)
+    } else {
+        None
+    }
+}
+
+// Like `approxidate_at`, relative to the current time.
+pub fn approxidate(date: &str) -> Result<i64> {
+    approxidate_at(date, Utc::now().timestamp())
+        .ok_or_else(|| Error::InvalidDate(format!("invalid date format: {}", date)))
+}
+
+// An offset in minutes the way git writes it, as a number like -130 for
+// -01:30.
+fn tz_number(minutes: i64) -> i64 {
+    let tz = minutes.abs() / 60 * 100 + minutes.abs() % 60;
+    if minutes < 0 {
+        -tz
+    } else {
+        tz
+    }
+}
+
+// The local offsets in minutes for standard time and summer time in
+// `year`, which are the same where clocks do not change.
+fn local_offsets(year: i32) -> (i64, i64) {
+    let offset = |month| {
+        NaiveDate::from_ymd_opt(year, month, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+            .map_or(0, |datetime| {
+                i64::from(datetime.offset().local_minus_utc()) / 60
+            })
+    };
+    let (january, july) = (offset(1), offset(7));
+    (january.min(july), january.max(july))
+}
+
+fn local_offset(time: i64) -> i64 {
+    Local
+        .timestamp_opt(time, 0)
+        .earliest()
+        .map_or(0, |datetime| {
+            i64::from(datetime.offset().local_minus_utc()) / 60
+        })
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, unit)
+    } else {
+        format!("{} {}s", n, unit)
+    }
+}
+
+// How long ago `time` was, like "3 weeks ago".
+fn show_date_relative(time: i64, now: i64) -> String {
+    if now < time {
+        return "in the future".to_string();
+    }
+    let mut diff = now - time;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    // Days from here on.
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (months / 12, months % 12);
+        if months != 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+// git's default format, or with `human` set, one that leaves out what
+// goes without saying given the current time: the year, the date for
+// this week, and the time zone when it is the local one.
+fn show_date_normal(
+    time: i64,
+    tm: &Tm,
+    tz: i64,
+    human: Option<(&Tm, i64)>,
+    local: bool,
+    now: i64,
+) -> String {
+    let (human_tm, human_tz) = match human {
+        Some((human_tm, human_tz)) => (Some(human_tm), human_tz),
+        None => (None, -1),
+    };
+    let mut hide_tz = local || tz == human_tz;
+    let human_year = human_tm.map_or(0, |human_tm| human_tm.year);
+    let hide_year = tm.year == human_year;
+    let (mut hide_date, mut hide_wday, mut hide_time, mut hide_seconds) =
+        (false, false, false, false);
+    if let (true, Some(human_tm)) = (hide_year, human_tm) {
+        if tm.mon == human_tm.mon {
+            if tm.mday > human_tm.mday {
+                // A date in the future, from another time zone.
+            } else if tm.mday == human_tm.mday {
+                hide_date = true;
+                hide_wday = true;
+            } else if tm.mday + 5 > human_tm.mday {
+                hide_date = true;
+            }
+        }
+    }
+    // Times from today are shown as how long ago they were.
+    if hide_wday {
+        return show_date_relative(time, now);
+    }
+    if human_year != 0 {
+        hide_seconds = true;
+        hide_tz |= !hide_date;
+        hide_wday = !hide_year;
+        hide_time = !hide_year;
+    }
+    let mut out = String::new();
+    if !hide_wday {
+        out.push_str(&format!("{} ", &WEEKDAY_NAMES[tm.wday as usize][..3]));
+    }
+    if !hide_date {
+        out.push_str(&format!(
+            "{} {} ",
+            &MONTH_NAMES[tm.mon as usize][..3],
+            tm.mday
+        ));
+    }
+    if !hide_time {
+        out.push_str(&format!("{:02}:{:02}", tm.hour, tm.min));
+        if !hide_seconds {
+            out.push_str(&format!(":{:02}", tm.sec));
+        }
+    } else {
+        out.truncate(out.trim_end().len());
+    }
+    if !hide_year {
+        out.push_str(&format!(" {}", tm.year + 1900));
+    }
+    if !hide_tz {
+        out.push_str(&format!(" {:+05}", tz));
+    }
+    out
+}
+
+// Shows `time`, recorded with an offset of `tz` (like +0200), as `mode`
+// says, taking `now` as the current time.
+fn show_date_at(time: i64, tz: i64, mode: DateMode, now: i64) -> String {
+    let format = mode.format;
+    if format == DateFormat::Unix {
+        return time.to_string();
+    }
+    let human = if format == DateFormat::Human {
+        Some((Tm::localtime(now), tz_number(local_offset(now))))
+    } else {
+        None
+    };
+    let tz = if mode.local {
+        tz_number(local_offset(time))
+    } else {
+        tz
+    };
+    match format {
+        DateFormat::Raw => return format!("{} {:+05}", time, tz),
+        DateFormat::Relative => return show_date_relative(time, now),
+        _ => {}
+    }
+    let minutes = tz.abs() / 100 * 60 + tz.abs() % 100;
+    let shifted = if tz < 0 {
+        time - minutes * 60
+    } else {
+        time + minutes * 60
+    };
+    let (tm, tz) = if mode.local {
+        (Tm::localtime(time), tz)
+    } else {
+        match Tm::gmtime(shifted) {
+            Some(tm) => (tm, tz),
+            None => (Tm::gmtime(0).unwrap(), 0),
+        }
+    };
+    let date = format!("{:04}-{:02}-{:02}", tm.year + 1900, tm.mon + 1, tm.mday);
+    let clock = format!("{:02}:{:02}:{:02}", tm.hour, tm.min, tm.sec);
+    match format {
+        DateFormat::Short => date,
+        DateFormat::Iso => format!("{} {} {:+05}", date, clock, tz),
+        DateFormat::IsoStrict => format!(
+            "{}T{}{}{:02}:{:02}",
+            date,
+            clock,
+            if tz >= 0 { '+' } else { '-' },
+            tz.abs() / 100,
+            tz.abs() % 100
+        ),
+        DateFormat::Rfc2822 => format!(
+            "{}, {} {} {} {} {:+05}",
+            &WEEKDAY_NAMES[tm.wday as usize][..3],
+            tm.mday,
+            &MONTH_NAMES[tm.mon as usize][..3],
+            tm.year + 1900,
+            clock,
+            tz
+        ),
+        _ => show_date_normal(
+            time,
+            &tm,
+            tz,
+            human
+                .as_ref()
+                .map(|(human_tm, human_tz)| (human_tm, *human_tz)),
+            mode.local,
+            now,
+        ),
+    }
+}
+
+// Shows `time`, recorded with an offset of `tz` written as a number like
+// +0200, the way `git log --date=<mode>` does.
+pub fn show_date(time: i64, tz: i64, mode: DateMode) -> String {
+    show_date_at(time, tz, mode, Utc::now().timestamp())
+}
+
+// Like `show_date`, for a date with its offset.
+pub fn format_date(datetime: &DateTime<FixedOffset>, mode: DateMode) -> String {
+    let minutes = i64::from(datetime.offset().local_minus_utc()) / 60;
+    show_date(datetime.timestamp(), tz_number(minutes), mode)
+}
@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+
+// A run of lines that differs between two sequences: the `old` lines are
+// replaced by the `new` ones. One of the ranges may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+// One side of a diff: its lines as ids shared by both sides, and which of
+// them are changed, with a sentinel flag at either end.
+struct Side {
+    ids: Vec<usize>,
+    changed: Vec<bool>,
+}
+
+// The lines of one side that take part in the search for a shortest edit,
+// and their line numbers.
+struct Reduced {
+    ids: Vec<usize>,
+    lines: Vec<usize>,
+}
+
+// Where the search splits the sides, and whether each half has to be
+// compared exhaustively.
+struct Split {
+    old: usize,
+    new: usize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+// Myers' difference algorithm, searching from both ends at once like git's
+// xdiff, with the furthest reaching path of each diagonal in both
+// directions. `offset` makes the diagonals usable as indexes.
+struct Myers<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+}
+
+// A run of changed lines, possibly empty.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+// Splits content into lines, each keeping its newline. The last line lacks
+// one when the content does not end with a newline.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, &b) in content.iter().enumerate() {
+        if b == b'\n' {
+            lines.push(&content[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < content.len() {
+        lines.push(&content[start..]);
+    }
+    lines
+}
+
+impl Side {
+    fn new(ids: Vec<usize>) -> Self {
+        let changed = vec![false; ids.len() + 2];
+        Self { ids, changed }
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn is_changed(&self, line: usize) -> bool {
+        self.changed[line + 1]
+    }
+
+    // Whether the line before `line` is changed, false at the start.
+    fn is_changed_before(&self, line: usize) -> bool {
+        self.changed[line]
+    }
+
+    fn set_changed(&mut self, line: usize, changed: bool) {
+        self.changed[line + 1] = changed;
+    }
+
+    fn first_group(&self) -> Group {
+        let mut end = 0;
+        while self.is_changed(end) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = group.start;
+        while self.is_changed(group.end) {
+            group.end += 1;
+        }
+        true
+    }
+
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = group.end;
+        while self.is_changed_before(group.start) {
+            group.start -= 1;
+        }
+        true
+    }
+
+    // Moves a group down a line if the line after it equals its first one,
+    // joining the group that follows when they then touch.
+    fn slide_down(&mut self, group: &mut Group) -> bool {
+        if group.end == self.len() || self.ids[group.start] != self.ids[group.end] {
+            return false;
+        }
+        self.set_changed(group.start, false);
+        self.set_changed(group.end, true);
+        group.start += 1;
+        group.end += 1;
+        while self.is_changed(group.end) {
+            group.end += 1;
+        }
+        true
+    }
+
+    fn slide_up(&mut self, group: &mut Group) -> bool {
+        if group.start == 0 || self.ids[group.start - 1] != self.ids[group.end - 1] {
+            return false;
+        }
+        group.start -= 1;
+        group.end -= 1;
+        self.set_changed(group.start, true);
+        self.set_changed(group.end, false);
+        while self.is_changed_before(group.start) {
+            group.start -= 1;
+        }
+        true
+    }
+
+    // Slides each group of changes as far down as it goes, unless it can
+    // line up with a change on the other side. `other` walks along through
+    // the unchanged lines both sides share.
+    fn compact(&mut self, other: &Side) {
+        let mut group = self.first_group();
+        let mut other_group = other.first_group();
+        loop {
+            if group.end != group.start {
+                let mut end_matching_other;
+                let mut earliest_end;
+                loop {
+                    let size = group.end - group.start;
+                    end_matching_other = None;
+                    while self.slide_up(&mut group) {
+                        other.previous_group(&mut other_group);
+                    }
+                    earliest_end = group.end;
+                    if other_group.end != other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                    while self.slide_down(&mut group) {
+                        other.next_group(&mut other_group);
+                        if other_group.end != other_group.start {
+                            end_matching_other = Some(group.end);
+                        }
+                    }
+                    if size == group.end - group.start {
+                        break;
+                    }
+                }
+                if let Some(end) = end_matching_other {
+                    while group.end != earliest_end && group.end != end {
+                        self.slide_up(&mut group);
+                        other.previous_group(&mut other_group);
+                    }
+                }
+            }
+            if !self.next_group(&mut group) {
+                break;
+            }
+            other.next_group(&mut other_group);
+        }
+    }
+}
+
+pub fn diff<T: Hash + Eq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let mut ids = HashMap::new();
+    let old = intern(&mut ids, old);
+    let new = intern(&mut ids, new);
+    let mut counts = vec![[0, 0]; ids.len()];
+    for &id in &old {
+        counts[id][0] += 1;
+    }
+    for &id in &new {
+        counts[id][1] += 1;
+    }
+    let mut old = Side::new(old);
+    let mut new = Side::new(new);
+
+    let (old_len, new_len) = (old.len(), new.len());
+    let prefix = (0..old_len.min(new_len))
+        .take_while(|&i| old.ids[i] == new.ids[i])
+        .count();
+    let suffix = (0..old_len.min(new_len) - prefix)
+        .take_while(|&i| old.ids[old_len - 1 - i] == new.ids[new_len - 1 - i])
+        .count();
+    let old_reduced = old.reduce(prefix..old_len - suffix, |id| counts[id][1]);
+    let new_reduced = new.reduce(prefix..new_len - suffix, |id| counts[id][0]);
+
+    let mut old_changed = vec![false; old_reduced.ids.len()];
+    let mut new_changed = vec![false; new_reduced.ids.len()];
+    Myers::new(&old_reduced.ids, &new_reduced.ids).compare(
+        0..old_reduced.ids.len(),
+        0..new_reduced.ids.len(),
+        false,
+        &mut old_changed,
+        &mut new_changed,
+    );
+    old.mark(&old_reduced, &old_changed);
+    new.mark(&new_reduced, &new_changed);
+    old.compact(&new);
+    new.compact(&old);
+
+    // Unchanged lines pair up in order between the hunks.
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    while x < old_len || y < new_len {
+        let (old_start, new_start) = (x, y);
+        while x < old_len && old.is_changed(x) {
+            x += 1;
+        }
+        while y < new_len && new.is_changed(y) {
+            y += 1;
+        }
+        if x > old_start || y > new_start {
+            hunks.push(Hunk {
+                old: old_start..x,
+                new: new_start..y,
+            });
+        }
+        x += 1;
+        y += 1;
+    }
+    hunks
+}
+
+// Numbers lines so that equal lines get the same id.
+fn intern<'a, T: Hash + Eq>(ids: &mut HashMap<&'a T, usize>, lines: &'a [T]) -> Vec<usize> {
+    lines
+        .iter()
+        .map(|line| {
+            let next = ids.len();
+            *ids.entry(line).or_insert(next)
+        })
+        .collect()
+}
+
+// Git's integer approximation of a square root.
+fn rough_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+// Whether a line with many matches on the other side should be left out of
+// the search: it is when it sits among lines that are left out anyway, and
+// most of those have no match at all.
+fn is_discardable(kinds: &[u8], i: usize, range: &Range<usize>) -> bool {
+    const WINDOW: usize = 100;
+    let start = range.start.max(i.saturating_sub(WINDOW));
+    let end = (range.end - 1).min(i + WINDOW);
+    let run = |lines: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut multiple) = (0, 0);
+        for line in lines {
+            match kinds[line] {
+                0 => unmatched += 1,
+                2 => multiple += 1,
+                _ => break,
+            }
+        }
+        (unmatched, multiple)
+    };
+    let (unmatched_before, multiple_before) = run(&mut (start..i).rev());
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (unmatched_after, multiple_after) = run(&mut (i + 1..=end));
+    if unmatched_after == 0 {
+        return false;
+    }
+    let unmatched = unmatched_before + unmatched_after;
+    let multiple = multiple_before + multiple_after + 2;
+    multiple * 4 < multiple + unmatched
+}
+
+impl Side {
+    // Leaves the lines of `range` that cannot help the search out of it,
+    // marking them changed: lines without a match on the other side, and
+    // lines with many matches among those.
+    fn reduce(&mut self, range: Range<usize>, matches: impl Fn(usize) -> usize) -> Reduced {
+        let limit = rough_sqrt(self.len()).min(1024);
+        let mut kinds = vec![0; self.len()];
+        for i in range.clone() {
+            kinds[i] = match matches(self.ids[i]) {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            };
+        }
+        let mut reduced = Reduced {
+            ids: Vec::new(),
+            lines: Vec::new(),
+        };
+        for i in range.clone() {
+            if kinds[i] == 1 || (kinds[i] == 2 && !is_discardable(&kinds, i, &range)) {
+                reduced.ids.push(self.ids[i]);
+                reduced.lines.push(i);
+            } else {
+                self.set_changed(i, true);
+            }
+        }
+        reduced
+    }
+
+    fn mark(&mut self, reduced: &Reduced, changed: &[bool]) {
+        for (&line, &changed) in reduced.lines.iter().zip(changed) {
+            if changed {
+                self.set_changed(line, true);
+            }
+        }
+    }
+}
+
+impl<'a> Myers<'a> {
+    fn new(old: &'a [usize], new: &'a [usize]) -> Self {
+        let diagonals = old.len() + new.len() + 3;
+        Self {
+            old,
+            new,
+            forward: vec![0; diagonals],
+            backward: vec![0; diagonals],
+            offset: new.len() as isize + 1,
+            max_cost: rough_sqrt(diagonals).max(256) as isize,
+        }
+    }
+
+    fn forward(&mut self, diagonal: isize) -> &mut isize {
+        &mut self.forward[(diagonal + self.offset) as usize]
+    }
+
+    fn backward(&mut self, diagonal: isize) -> &mut isize {
+        &mut self.backward[(diagonal + self.offset) as usize]
+    }
+
+    fn matches(&self, x: isize, y: isize) -> bool {
+        self.old[x as usize] == self.new[y as usize]
+    }
+
+    // Marks the lines of the ranges that are not on a shortest edit path
+    // between them as changed, dividing the ranges where the forward and
+    // backward searches meet.
+    fn compare(
+        &mut self,
+        mut old: Range<usize>,
+        mut new: Range<usize>,
+        minimal: bool,
+        old_changed: &mut [bool],
+        new_changed: &mut [bool],
+    ) {
+        while !old.is_empty() && !new.is_empty() && self.old[old.start] == self.new[new.start] {
+            old.start += 1;
+            new.start += 1;
+        }
+        while !old.is_empty() && !new.is_empty() && self.old[old.end - 1] == self.new[new.end - 1] {
+            old.end -= 1;
+            new.end -= 1;
+        }
+        if old.is_empty() {
+            new_changed[new]
+                .iter_mut()
+                .for_each(|changed| *changed = true);
+        } else if new.is_empty() {
+            old_changed[old]
+                .iter_mut()
+                .for_each(|changed| *changed = true);
+        } else {
+            let split = self.split(old.clone(), new.clone(), minimal);
+            self.compare(
+                old.start..split.old,
+                new.start..split.new,
+                split.minimal_before,
+                old_changed,
+                new_changed,
+            );
+            self.compare(
+                split.old..old.end,
+                split.new..new.end,
+                split.minimal_after,
+                old_changed,
+                new_changed,
+            );
+        }
+    }
+
+    // Finds where a shortest edit path crosses the middle of the ranges.
+    // Unless `minimal` is set, the search gives up on expensive inputs and
+    // settles for a good enough split, as git does.
+    fn split(&mut self, old: Range<usize>, new: Range<usize>, minimal: bool) -> Split {
+        const SNAKE: isize = 20;
+        const HEURISTIC_MIN_COST: isize = 256;
+        let (off1, lim1) = (old.start as isize, old.end as isize);
+        let (off2, lim2) = (new.start as isize, new.end as isize);
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        *self.forward(fmid) = off1;
+        *self.backward(bmid) = lim1;
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                *self.forward(fmin - 1) = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                *self.forward(fmax + 1) = -1;
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut x = if *self.forward(d - 1) >= *self.forward(d + 1) {
+                    *self.forward(d - 1) + 1
+                } else {
+                    *self.forward(d + 1)
+                };
+                let start = x;
+                let mut y = x - d;
+                while x < lim1 && y < lim2 && self.matches(x, y) {
+                    x += 1;
+                    y += 1;
+                }
+                if x - start > SNAKE {
+                    got_snake = true;
+                }
+                *self.forward(d) = x;
+                if odd && bmin <= d && d <= bmax && *self.backward(d) <= x {
+                    return Split {
+                        old: x as usize,
+                        new: y as usize,
+                        minimal_before: true,
+                        minimal_after: true,
+                    };
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                *self.backward(bmin - 1) = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                *self.backward(bmax + 1) = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut x = if *self.backward(d - 1) < *self.backward(d + 1) {
+                    *self.backward(d - 1)
+                } else {
+                    *self.backward(d + 1) - 1
+                };
+                let start = x;
+                let mut y = x - d;
+                while x > off1 && y > off2 && self.matches(x - 1, y - 1) {
+                    x -= 1;
+                    y -= 1;
+                }
+                if start - x > SNAKE {
+                    got_snake = true;
+                }
+                *self.backward(d) = x;
+                if !odd && fmin <= d && d <= fmax && x <= *self.forward(d) {
+                    return Split {
+                        old: x as usize,
+                        new: y as usize,
+                        minimal_before: true,
+                        minimal_after: true,
+                    };
+                }
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // A long enough diagonal run after a costly search is taken as
+            // a good place to split.
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let distance = (d - fmid).abs();
+                    let x = *self.forward(d);
+                    let y = x - d;
+                    let value = (x - off1) + (y - off2) - distance;
+                    if value > 4 * cost
+                        && value > best
+                        && off1 + SNAKE <= x
+                        && x < lim1
+                        && off2 + SNAKE <= y
+                        && y < lim2
+                        && (1..=SNAKE).all(|k| self.matches(x - k, y - k))
+                    {
+                        best = value;
+                        split = Some((x, y));
+                    }
+                }
+                if let Some((x, y)) = split {
+                    return Split {
+                        old: x as usize,
+                        new: y as usize,
+                        minimal_before: true,
+                        minimal_after: false,
+                    };
+                }
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let distance = (d - bmid).abs();
+                    let x = *self.backward(d);
+                    let y = x - d;
+                    let value = (lim1 - x) + (lim2 - y) - distance;
+                    if value > 4 * cost
+                        && value > best
+                        && off1 < x
+                        && x <= lim1 - SNAKE
+                        && off2 < y
+                        && y <= lim2 - SNAKE
+                        && (0..SNAKE).all(|k| self.matches(x + k, y + k))
+                    {
+                        best = value;
+                        split = Some((x, y));
+                    }
+                }
+                if let Some((x, y)) = split {
+                    return Split {
+                        old: x as usize,
+                        new: y as usize,
+                        minimal_before: false,
+                        minimal_after: true,
+                    };
+                }
+            }
+
+            // Enough is enough: split at the furthest reaching path.
+            if cost >= self.max_cost {
+                let (mut forward_best, mut forward_x) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut x = (*self.forward(d)).min(lim1);
+                    let mut y = x - d;
+                    if lim2 < y {
+                        x = lim2 + d;
+                        y = lim2;
+                    }
+                    if forward_best < x + y {
+                        forward_best = x + y;
+                        forward_x = x;
+                    }
+                }
+                let (mut backward_best, mut backward_x) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut x = (*self.backward(d)).max(off1);
+                    let mut y = x - d;
+                    if y < off2 {
+                        x = off2 + d;
+                        y = off2;
+                    }
+                    if x + y < backward_best {
+                        backward_best = x + y;
+                        backward_x = x;
+                    }
+                }
+                return if (lim1 + lim2) - backward_best < forward_best - (off1 + off2) {
+                    Split {
+                        old: forward_x as usize,
+                        new: (forward_best - forward_x) as usize,
+                        minimal_before: true,
+                        minimal_after: false,
+                    }
+                } else {
+                    Split {
+                        old: backward_x as usize,
+                        new: (backward_best - backward_x) as usize,
+                        minimal_before: false,
+                        minimal_after: true,
+                    }
+                };
+            }
+            cost += 1;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Characters 0x80 to 0x9f in Windows-1252, where ISO-8859-1 has control
+// characters. Unused positions keep the control character.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    // The encoding a name like `ISO-8859-1` or `utf8` stands for, ignoring
+    // case. US-ASCII is read as UTF-8, which it is a part of.
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+        match name.as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" => Some(Encoding::Utf8),
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Some(Encoding::Latin1)
+            }
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    // Text in this encoding as a string. Bytes that are not valid UTF-8
+    // become replacement characters.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Encoding::Windows1252 => bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9f => WINDOWS_1252[usize::from(b - 0x80)],
+                    _ => char::from(b),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Text in the encoding `name` names, or UTF-8 without a name. git shows
+// text in encodings it cannot convert as it is, which is taken as UTF-8
+// here too.
+pub fn decode(bytes: &[u8], name: Option<&[u8]>) -> String {
+    name.and_then(Encoding::from_name)
+        .unwrap_or_default()
+        .decode(bytes)
+}
@@ -3,6 +3,27 @@ pub enum Error {
     IOError(std::io::Error),
     FromUtf8Error(std::string::FromUtf8Error),
     ParseIntError(std::num::ParseIntError),
+    NotAFile(std::path::PathBuf),
+    InvalidIndex(String),
+    UnmergedIndex(Vec<u8>),
+    PathConflict(Vec<u8>),
+    InvalidObject(String),
+    InvalidRef(String),
+    StaleRef(String),
+    InvalidDate(String),
+    InvalidFormat(String),
+    InvalidRevWalk(String),
+    InvalidBlame(String),
+    InvalidPathspec(String),
+    NotARepository(std::path::PathBuf),
+    Conversion(String),
+    InvalidPacket(String),
+    InvalidConfig(String),
+    InvalidConfigValue(Box<crate::config::InvalidValue>),
+    LocalChanges(Vec<Vec<u8>>),
+    UntrackedOverwritten(Vec<Vec<u8>>),
+    UntrackedRemoved(Vec<Vec<u8>>),
+    StagedChanges(Vec<Vec<u8>>),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,504 @@
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+use crate::error::{Error, Result};
+use crate::oid::Oid;
+use crate::pkt_line;
+
+// A `filter.<name>.*` driver selected by the `filter` attribute. When
+// `process` is set it is used for both directions and `clean` and `smudge`
+// are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterDriver {
+    pub name: String,
+    pub clean: Option<String>,
+    pub smudge: Option<String>,
+    pub process: Option<String>,
+    pub required: bool,
+}
+
+// What is known about the blob being checked out, passed on to process
+// filters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterMeta {
+    pub ref_name: Option<String>,
+    pub treeish: Option<Oid>,
+    pub blob: Option<Oid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Smudged {
+    Ready(Vec<u8>),
+    // The process filter will hand the content back later.
+    Delayed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterCommand {
+    Clean,
+    Smudge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Response {
+    Success(Vec<u8>),
+    Delayed,
+    // The filter could not handle this file.
+    Error,
+    // The filter does not want to be asked for this command again.
+    Abort,
+}
+
+// How a `clean` or `smudge` command ran. Failures leave the content
+// unfiltered, which is only an error for required drivers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandOutput {
+    Success(Vec<u8>),
+    SpawnFailed,
+    // The exit code, or 128 plus the signal that killed it, as git reports.
+    Exited(i32),
+    // The command exited cleanly without taking all of its input.
+    WriteFailed,
+}
+
+// A long-running `filter.<name>.process` command speaking version 2 of the
+// filter protocol over pkt-lines.
+pub struct FilterProcess {
+    command: String,
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    stdout: BufReader<ChildStdout>,
+    capabilities: Vec<String>,
+    // Paths waiting to be fetched, with the driver that delayed them.
+    delayed: Vec<(Vec<u8>, String)>,
+}
+
+const CAPABILITIES: [&str; 3] = ["clean", "smudge", "delay"];
+
+impl FilterDriver {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            clean: None,
+            smudge: None,
+            process: None,
+            required: false,
+        }
+    }
+
+    pub fn command(&self, command: FilterCommand) -> Option<&str> {
+        match command {
+            FilterCommand::Clean => self.clean.as_deref(),
+            FilterCommand::Smudge => self.smudge.as_deref(),
+        }
+    }
+
+    pub fn failure(&self, path: &[u8], command: FilterCommand) -> Error {
+        let path = String::from_utf8_lossy(path);
+        Error::Conversion(match command {
+            FilterCommand::Clean => format!("{}: clean filter '{}' failed", path, self.name),
+            FilterCommand::Smudge => format!("{}: smudge filter {} failed", path, self.name),
+        })
+    }
+}
+
+impl FilterCommand {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FilterCommand::Clean => "clean",
+            FilterCommand::Smudge => "smudge",
+        }
+    }
+}
+
+// Quotes `path` for the shell, as %f is replaced with it.
+fn shell_quote(path: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'\''];
+    for &b in path {
+        match b {
+            b'\'' | b'!' => {
+                quoted.extend_from_slice(b"'\\");
+                quoted.push(b);
+                quoted.push(b'\'');
+            }
+            _ => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+fn expand_command(command: &str, path: &[u8]) -> Vec<u8> {
+    let mut expanded = Vec::with_capacity(command.len());
+    let mut bytes = command.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            expanded.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'f') => expanded.extend_from_slice(&shell_quote(path)),
+            Some(b'%') => expanded.push(b'%'),
+            Some(other) => expanded.extend_from_slice(&[b'%', other]),
+            None => expanded.push(b'%'),
+        }
+    }
+    expanded
+}
+
+fn shell(command: &[u8], workdir: &Path) -> Command {
+    use std::os::unix::ffi::OsStrExt;
+    let mut shell = Command::new("sh");
+    shell
+        .arg("-c")
+        .arg(std::ffi::OsStr::from_bytes(command))
+        .current_dir(workdir);
+    shell
+}
+
+// Pipes `content` through a single-file `clean` or `smudge` command.
+// Errors are only for what went wrong on our side; how the command itself
+// failed is left for the caller to report.
+pub fn run_command(
+    command: &str,
+    path: &[u8],
+    workdir: &Path,
+    content: &[u8],
+) -> Result<CommandOutput> {
+    let child = shell(&expand_command(command, path), workdir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return Ok(CommandOutput::SpawnFailed),
+    };
+    // Write from another thread so a filter producing output before it has
+    // read all of its input cannot deadlock us.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = content.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // Like git, a filter is free to not read all of its input.
+    let written = match writer.join().unwrap_or(Ok(())) {
+        Err(ref e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        written => written,
+    };
+    if !output.status.success() {
+        let code = match output.status.code() {
+            Some(code) => code,
+            None => 128 + output.status.signal().unwrap_or(0),
+        };
+        return Ok(CommandOutput::Exited(code));
+    }
+    if written.is_err() {
+        return Ok(CommandOutput::WriteFailed);
+    }
+    Ok(CommandOutput::Success(output.stdout))
+}
+
+fn expect_line(lines: &[String], index: usize, expected: &str) -> Result<()> {
+    match lines.get(index) {
+        Some(line) if line == expected => Ok(()),
+        line => Err(Error::InvalidPacket(format!(
+            "expected '{}', got {:?}",
+            expected, line
+        ))),
+    }
+}
+
+// The last `status=` line of a list wins; no status keeps the previous one.
+fn read_status(lines: &[String], previous: &str) -> String {
+    lines
+        .iter()
+        .rev()
+        .find_map(|line| line.strip_prefix("status="))
+        .unwrap_or(previous)
+        .to_string()
+}
+
+impl FilterProcess {
+    pub fn start(command: &str, workdir: &Path) -> Result<Self> {
+        let mut child = shell(command.as_bytes(), workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut process = Self {
+            command: command.to_string(),
+            child,
+            stdin: Some(stdin),
+            stdout,
+            capabilities: Vec::new(),
+            delayed: Vec::new(),
+        };
+        process.handshake()?;
+        Ok(process)
+    }
+
+    fn stdin(&mut self) -> &mut BufWriter<ChildStdin> {
+        self.stdin.as_mut().unwrap()
+    }
+
+    fn handshake(&mut self) -> Result<()> {
+        let stdin = self.stdin();
+        pkt_line::write_text(stdin, "git-filter-client")?;
+        pkt_line::write_text(stdin, "version=2")?;
+        pkt_line::write_flush(stdin)?;
+        stdin.flush()?;
+        let welcome = pkt_line::read_text_list(&mut self.stdout)?;
+        expect_line(&welcome, 0, "git-filter-server")?;
+        expect_line(&welcome, 1, "version=2")?;
+
+        let stdin = self.stdin();
+        for capability in CAPABILITIES.iter() {
+            pkt_line::write_text(stdin, &format!("capability={}", capability))?;
+        }
+        pkt_line::write_flush(stdin)?;
+        stdin.flush()?;
+        for line in pkt_line::read_text_list(&mut self.stdout)? {
+            match line.strip_prefix("capability=") {
+                Some(capability) if CAPABILITIES.contains(&capability) => {
+                    self.capabilities.push(capability.to_string())
+                }
+                Some(_) => {}
+                None => {
+                    return Err(Error::InvalidPacket(format!(
+                        "unexpected capability line '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    pub fn has_delayed(&self) -> bool {
+        !self.delayed.is_empty()
+    }
+
+    pub(crate) fn filter(
+        &mut self,
+        command: FilterCommand,
+        path: &[u8],
+        content: &[u8],
+        meta: Option<&FilterMeta>,
+        can_delay: bool,
+    ) -> Result<Response> {
+        let stdin = self.stdin();
+        pkt_line::write_text(stdin, &format!("command={}", command.as_str()))?;
+        let mut pathname = b"pathname=".to_vec();
+        pathname.extend_from_slice(path);
+        pathname.push(b'\n');
+        pkt_line::write_packet(stdin, &pathname)?;
+        if let Some(meta) = meta {
+            if let Some(ref_name) = &meta.ref_name {
+                pkt_line::write_text(stdin, &format!("ref={}", ref_name))?;
+            }
+            if let Some(treeish) = &meta.treeish {
+                pkt_line::write_text(stdin, &format!("treeish={}", treeish))?;
+            }
+            if let Some(blob) = &meta.blob {
+                pkt_line::write_text(stdin, &format!("blob={}", blob))?;
+            }
+        }
+        if can_delay {
+            pkt_line::write_text(stdin, "can-delay=1")?;
+        }
+        pkt_line::write_flush(stdin)?;
+        pkt_line::write_stream(stdin, content)?;
+        stdin.flush()?;
+        self.read_response(command)
+    }
+
+    fn read_response(&mut self, command: FilterCommand) -> Result<Response> {
+        let status = read_status(&pkt_line::read_text_list(&mut self.stdout)?, "");
+        let response = match status.as_str() {
+            "success" => {
+                let content = pkt_line::read_stream(&mut self.stdout)?;
+                let status = read_status(&pkt_line::read_text_list(&mut self.stdout)?, &status);
+                match status.as_str() {
+                    "success" => Response::Success(content),
+                    "error" => Response::Error,
+                    "abort" => Response::Abort,
+                    _ => return Err(Error::InvalidPacket(format!("bad status '{}'", status))),
+                }
+            }
+            "delayed" => Response::Delayed,
+            "error" => Response::Error,
+            "abort" => Response::Abort,
+            _ => return Err(Error::InvalidPacket(format!("bad status '{}'", status))),
+        };
+        if response == Response::Abort {
+            self.capabilities.retain(|c| c != command.as_str());
+        }
+        Ok(response)
+    }
+
+    pub(crate) fn delay(&mut self, path: &[u8], driver: &str) {
+        self.delayed.push((path.to_vec(), driver.to_string()));
+    }
+
+    // Asks which delayed paths are ready. A filter with nothing ready is
+    // done with them.
+    pub(crate) fn available_blobs(&mut self) -> Result<Vec<Vec<u8>>> {
+        let stdin = self.stdin();
+        pkt_line::write_text(stdin, "command=list_available_blobs")?;
+        pkt_line::write_flush(stdin)?;
+        stdin.flush()?;
+        let mut available = Vec::new();
+        while let Some(mut line) = pkt_line::read_packet(&mut self.stdout)? {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if let Some(path) = line.strip_prefix(b"pathname=") {
+                available.push(path.to_vec());
+            }
+        }
+        let status = read_status(&pkt_line::read_text_list(&mut self.stdout)?, "");
+        if status != "success" {
+            return Err(Error::InvalidPacket(format!("bad status '{}'", status)));
+        }
+        Ok(available)
+    }
+
+    pub(crate) fn abandon_delayed(&mut self) -> Vec<Vec<u8>> {
+        self.delayed.drain(..).map(|(path, _)| path).collect()
+    }
+
+    // The driver that delayed `path`, which stops being delayed.
+    pub(crate) fn take_delayed(&mut self, path: &[u8]) -> Option<String> {
+        let position = self.delayed.iter().position(|(p, _)| p == path)?;
+        Some(self.delayed.remove(position).1)
+    }
+
+    pub(crate) fn kill(mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+impl Drop for FilterProcess {
+    // Closing its input tells the filter to exit.
+    fn drop(&mut self) {
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{TempDir, FILTER_PROCESS};
+
+    fn start(tmp: &TempDir) -> FilterProcess {
+        FilterProcess::start(FILTER_PROCESS, tmp.path()).unwrap()
+    }
+
+    fn smudge(process: &mut FilterProcess, path: &str, can_delay: bool) -> Response {
+        process
+            .filter(
+                FilterCommand::Smudge,
+                path.as_bytes(),
+                b"content\n",
+                None,
+                can_delay,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn runs_commands() {
+        let tmp = TempDir::new();
+        let run = |command: &str, path: &str| {
+            run_command(command, path.as_bytes(), tmp.path(), b"content\n").unwrap()
+        };
+        assert_eq!(
+            run("tr a-z A-Z", "a"),
+            CommandOutput::Success(b"CONTENT\n".to_vec())
+        );
+        assert_eq!(
+            run("cat >/dev/null; echo %f", "it's a b"),
+            CommandOutput::Success(b"it's a b\n".to_vec())
+        );
+        // Not reading the input is fine.
+        assert_eq!(run("true", "a"), CommandOutput::Success(Vec::new()));
+        assert_eq!(run("exit 3", "a"), CommandOutput::Exited(3));
+        assert_eq!(run("kill -9 $$", "a"), CommandOutput::Exited(137));
+        assert_eq!(
+            run_command("cat", b"a", &tmp.path().join("missing"), b"").unwrap(),
+            CommandOutput::SpawnFailed
+        );
+    }
+
+    #[test]
+    fn negotiates_capabilities() {
+        let tmp = TempDir::new();
+        let process = start(&tmp);
+        assert!(process.supports("clean"));
+        assert!(process.supports("smudge"));
+        assert!(process.supports("delay"));
+
+        assert!(FilterProcess::start("echo nope", tmp.path()).is_err());
+        assert!(FilterProcess::start("exit 0", tmp.path()).is_err());
+    }
+
+    #[test]
+    fn filters_and_reports_status() {
+        let tmp = TempDir::new();
+        let mut process = start(&tmp);
+        assert_eq!(
+            smudge(&mut process, "a", false),
+            Response::Success(b"CONTENT\n".to_vec())
+        );
+        assert_eq!(
+            process
+                .filter(FilterCommand::Clean, b"b", b"", None, false)
+                .unwrap(),
+            Response::Success(Vec::new())
+        );
+        assert_eq!(smudge(&mut process, "error", false), Response::Error);
+        assert_eq!(smudge(&mut process, "fail", false), Response::Error);
+        assert_eq!(
+            smudge(&mut process, "c", false),
+            Response::Success(b"CONTENT\n".to_vec())
+        );
+
+        // Aborting only gives up on that command.
+        assert_eq!(smudge(&mut process, "abort", false), Response::Abort);
+        assert!(!process.supports("smudge"));
+        assert!(process.supports("clean"));
+    }
+
+    #[test]
+    fn delays() {
+        let tmp = TempDir::new();
+        let mut process = start(&tmp);
+        assert_eq!(smudge(&mut process, "delay", true), Response::Delayed);
+        process.delay(b"delay", "upper");
+        assert!(process.has_delayed());
+
+        assert_eq!(process.available_blobs().unwrap(), [b"delay".to_vec()]);
+        assert_eq!(process.take_delayed(b"delay").as_deref(), Some("upper"));
+        assert_eq!(process.take_delayed(b"delay"), None);
+        assert_eq!(
+            process
+                .filter(FilterCommand::Smudge, b"delay", b"", None, false)
+                .unwrap(),
+            Response::Success(b"CONTENT\n".to_vec())
+        );
+        assert!(process.available_blobs().unwrap().is_empty());
+        assert!(!process.has_delayed());
+    }
+}
@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::repo::Repository;
+use crate::wildmatch::{wildmatch, PATHNAME};
+
+const GITIGNORE: &str = ".gitignore";
+
+// A gitignore-style path pattern, shared with .gitattributes.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    glob: Vec<u8>,
+    must_be_dir: bool,
+    basename_only: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExcludeRule {
+    pattern: Pattern,
+    original: Vec<u8>,
+    line: usize,
+    negated: bool,
+}
+
+// The patterns of one file. Those read from a .gitignore are relative to
+// its directory, `base`; the others are relative to the top of the tree.
+#[derive(Debug, Clone)]
+pub struct PatternList {
+    source: PathBuf,
+    base: Vec<u8>,
+    rules: Vec<ExcludeRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    pub source: PathBuf,
+    pub line: usize,
+    pub pattern: Vec<u8>,
+    pub negated: bool,
+}
+
+pub struct Excludes {
+    workdir: PathBuf,
+    // In increasing order of precedence.
+    global: Vec<PatternList>,
+    directories: RefCell<HashMap<Vec<u8>, Option<PatternList>>>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &[u8]) -> Option<Self> {
+        let mut glob = pattern;
+        let must_be_dir = glob.last() == Some(&b'/');
+        if must_be_dir {
+            glob = &glob[..glob.len() - 1];
+        }
+        let basename_only = !glob.contains(&b'/');
+        if glob.first() == Some(&b'/') {
+            glob = &glob[1..];
+        }
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Self {
+            glob: glob.to_vec(),
+            must_be_dir,
+            basename_only,
+        })
+    }
+
+    // `path` is relative to the directory the pattern was read from.
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.must_be_dir && !is_dir {
+            return false;
+        }
+        if self.basename_only {
+            let basename = match path.iter().rposition(|&b| b == b'/') {
+                Some(slash) => &path[slash + 1..],
+                None => path,
+            };
+            wildmatch(&self.glob, basename, 0)
+        } else {
+            wildmatch(&self.glob, path, PATHNAME)
+        }
+    }
+}
+
+impl ExcludeRule {
+    fn parse(line: &[u8], number: usize) -> Option<Self> {
+        let original = trim_trailing_spaces(line);
+        if original.is_empty() || original[0] == b'#' {
+            return None;
+        }
+        let negated = original[0] == b'!';
+        let pattern = Pattern::new(if negated { &original[1..] } else { original })?;
+        Some(Self {
+            pattern,
+            original: original.to_vec(),
+            line: number,
+            negated,
+        })
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+// Drops unescaped trailing spaces, as git does for every pattern line.
+fn trim_trailing_spaces(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    let mut last_space = None;
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b' ' => {
+                if last_space.is_none() {
+                    last_space = Some(i);
+                }
+            }
+            b'\\' => {
+                i += 1;
+                if i == line.len() {
+                    return line;
+                }
+                last_space = None;
+            }
+            _ => last_space = None,
+        }
+        i += 1;
+    }
+    if let Some(space) = last_space {
+        end = space;
+    }
+    &line[..end]
+}
+
+impl PatternList {
+    pub fn from_bytes<P: AsRef<Path>>(source: P, base: &[u8], content: &[u8]) -> Self {
+        let content = content.strip_prefix(b"\xef\xbb\xbf").unwrap_or(content);
+        let rules = content
+            .split(|&b| b == b'\n')
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                ExcludeRule::parse(line, i + 1)
+            })
+            .collect();
+        Self {
+            source: source.as_ref().to_path_buf(),
+            base: base.to_vec(),
+            rules,
+        }
+    }
+
+    // A missing file is the same as an empty one. Like git, a symbolic link
+    // is not followed for in-tree .gitignore files.
+    fn read_file(path: &Path, source: &Path, base: &[u8], follow: bool) -> Result<Option<Self>> {
+        if !follow {
+            match fs::symlink_metadata(path) {
+                Ok(metadata) if !metadata.is_file() => return Ok(None),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match fs::read(path) {
+            Ok(content) => Ok(Some(Self::from_bytes(source, base, &content))),
+            Err(ref e) if e.kind() == ErrorKind::NotFound || e.raw_os_error() == Some(20) => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn rules(&self) -> &[ExcludeRule] {
+        &self.rules
+    }
+
+    // Within a file the last matching pattern decides.
+    fn last_match(&self, path: &[u8], is_dir: bool) -> Option<&ExcludeRule> {
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            path.strip_prefix(&self.base[..])?.strip_prefix(b"/")?
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.pattern.matches(relative, is_dir))
+    }
+
+    fn to_match(&self, rule: &ExcludeRule) -> IgnoreMatch {
+        IgnoreMatch {
+            source: self.source.clone(),
+            line: rule.line,
+            pattern: rule.original.clone(),
+            negated: rule.negated,
+        }
+    }
+}
+
+impl IgnoreMatch {
+    pub fn is_ignored(&self) -> bool {
+        !self.negated
+    }
+}
+
+impl Excludes {
+    pub fn new<P: AsRef<Path>>(workdir: P) -> Self {
+        Self {
+            workdir: workdir.as_ref().to_path_buf(),
+            global: Vec::new(),
+            directories: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // Files added later take precedence over earlier ones, while any
+    // .gitignore in the tree takes precedence over all of them.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let source = path.strip_prefix(&self.workdir).unwrap_or(path);
+        if let Some(list) = PatternList::read_file(path, source, b"", true)? {
+            self.global.push(list);
+        }
+        Ok(())
+    }
+
+    pub fn add_patterns(&mut self, list: PatternList) {
+        self.global.push(list);
+    }
+
+    fn with_directory<T>(
+        &self,
+        dir: &[u8],
+        f: impl FnOnce(Option<&PatternList>) -> T,
+    ) -> Result<T> {
+        let mut directories = self.directories.borrow_mut();
+        if !directories.contains_key(dir) {
+            let mut source = dir.to_vec();
+            if !source.is_empty() {
+                source.push(b'/');
+            }
+            source.extend_from_slice(GITIGNORE.as_bytes());
+            let source = Path::new(OsStr::from_bytes(&source));
+            let list = PatternList::read_file(&self.workdir.join(source), source, dir, false)?;
+            directories.insert(dir.to_vec(), list);
+        }
+        Ok(f(directories[dir].as_ref()))
+    }
+
+    // Checks `path` against the patterns alone, without looking at whether
+    // one of its parent directories is excluded.
+    fn last_match(&self, path: &[u8], is_dir: bool) -> Result<Option<IgnoreMatch>> {
+        let mut dirs = vec![&path[..0]];
+        dirs.extend(
+            path.iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'/')
+                .map(|(i, _)| &path[..i]),
+        );
+        for dir in dirs.into_iter().rev() {
+            let found = self.with_directory(dir, |list| {
+                list.and_then(|list| list.last_match(path, is_dir).map(|p| list.to_match(p)))
+            })?;
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(self.global.iter().rev().find_map(|list| {
+            list.last_match(path, is_dir)
+                .map(|pattern| list.to_match(pattern))
+        }))
+    }
+
+    // Returns the pattern deciding whether `path` is ignored, if any. Files
+    // inside an excluded directory cannot be re-included by a negation.
+    pub fn check(&self, path: &[u8], is_dir: bool) -> Result<Option<IgnoreMatch>> {
+        let path = path.strip_suffix(b"/").unwrap_or(path);
+        for (i, _) in path.iter().enumerate().filter(|&(_, &b)| b == b'/') {
+            if let Some(found) = self.last_match(&path[..i], true)? {
+                if found.is_ignored() {
+                    return Ok(Some(found));
+                }
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&self, path: &[u8], is_dir: bool) -> Result<bool> {
+        Ok(self
+            .check(path, is_dir)?
+            .is_some_and(|found| found.is_ignored()))
+    }
+}
+
+// Where core.excludesFile points when it is not configured.
+pub fn default_excludes_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config) if !config.is_empty() => Some(PathBuf::from(config).join("git/ignore")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore")),
+    }
+}
+
+impl Repository {
+    pub fn excludes(&self) -> Result<Excludes> {
+        let workdir = self.require_workdir()?;
+        let mut excludes = Excludes::new(workdir);
+        let path = match self.config()?.get_path("core.excludesfile")? {
+            Some(path) => Some(path),
+            None => default_excludes_file(),
+        };
+        if let Some(path) = path {
+            excludes.add_file(path)?;
+        }
+        excludes.add_file(self.commondir().join("info/exclude"))?;
+        Ok(excludes)
+    }
+
+    // Like `git check-ignore -v`: tracked paths are never reported, and a
+    // negated match is returned so callers can tell why a path is kept.
+    pub fn check_ignore(&self, path: &[u8]) -> Result<Option<IgnoreMatch>> {
+        let workdir = self.require_workdir()?;
+        if self.index()?.contains(path) {
+            return Ok(None);
+        }
+        let is_dir = path.ends_with(b"/")
+            || fs::symlink_metadata(workdir.join(OsStr::from_bytes(path)))
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+        self.excludes()?.check(path, is_dir)
+    }
+}
@@ -5,12 +5,41 @@ extern crate hex;
 extern crate serde;
 extern crate sha1;
 
+pub mod add;
+pub mod attributes;
+pub mod blame;
 pub mod blob;
+pub mod cache_tree;
+pub mod checkout;
 pub mod commit;
+pub mod config;
+pub mod convert;
+pub mod date;
+pub mod diff;
+pub mod encoding;
+pub mod error;
+pub mod filter;
+pub mod ignore;
 pub mod index;
+pub mod mailmap;
+pub mod merge;
+pub mod object;
 pub mod odb;
 pub mod oid;
-//pub mod repo;
-pub mod error;
-pub mod object;
+pub mod pathspec;
+pub mod pkt_line;
+pub mod pretty;
+pub mod refs;
+pub mod rename;
+pub mod repo;
+pub mod resolve_undo;
+pub mod revwalk;
+pub mod stat;
+pub mod status;
+pub mod switch;
+pub mod tag;
+#[cfg(test)]
+mod testutil;
 pub mod tree;
+pub mod utf8;
+pub mod wildmatch;
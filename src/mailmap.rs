@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::commit::Identity;
+use crate::error::Result;
+use crate::object::Object;
+use crate::odb::Odb;
+use crate::repo::Repository;
+
+// The name and email to show instead, either of which may be left as is.
+#[derive(Debug, Clone, Default)]
+struct Replacement {
+    name: Option<Vec<u8>>,
+    email: Option<Vec<u8>>,
+}
+
+// What an email maps to, and what it maps to along with particular names.
+#[derive(Debug, Clone, Default)]
+struct MailmapEntry {
+    replacement: Replacement,
+    names: BTreeMap<Vec<u8>, Replacement>,
+}
+
+// Canonical names and emails for the people in a history, read from
+// .mailmap files. Emails and names match ignoring ASCII case.
+#[derive(Debug, Clone, Default)]
+pub struct Mailmap {
+    entries: BTreeMap<Vec<u8>, MailmapEntry>,
+}
+
+// A name, an email and the text after them.
+type NameAndEmail<'a> = (Option<&'a [u8]>, &'a [u8], &'a [u8]);
+
+// The name and email in `Name <email>` and what follows, or None without
+// an email. The name is None when it is empty.
+fn parse_name_and_email(buffer: &[u8], allow_empty_email: bool) -> Option<NameAndEmail<'_>> {
+    let left = buffer.iter().position(|&c| c == b'<')?;
+    let right = left + 1 + buffer[left + 1..].iter().position(|&c| c == b'>')?;
+    if !allow_empty_email && left + 1 == right {
+        return None;
+    }
+    let start = buffer[..left]
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(left);
+    let end = buffer[start..left]
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |i| start + i + 1);
+    let name = if start < end {
+        Some(&buffer[start..end])
+    } else {
+        None
+    };
+    Some((name, &buffer[left + 1..right], &buffer[right + 1..]))
+}
+
+impl Mailmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reads lines in the four forms git takes:
+    //
+    //   Proper Name <commit@email>
+    //   <proper@email> <commit@email>
+    //   Proper Name <proper@email> <commit@email>
+    //   Proper Name <proper@email> Commit Name <commit@email>
+    //
+    // Lines starting with `#` are comments. Later lines win over earlier
+    // ones for the same email and name.
+    pub fn add_bytes(&mut self, data: &[u8]) {
+        for line in data.split(|&c| c == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.first() == Some(&b'#') {
+                continue;
+            }
+            let (name1, email1, rest) = match parse_name_and_email(line, false) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            // Text after the first email without an email of its own is
+            // ignored.
+            let (name2, email2) = match parse_name_and_email(rest, true) {
+                Some((name2, email2, _)) => (name2, Some(email2)),
+                None => (None, None),
+            };
+            self.add(name1, email1, name2, email2);
+        }
+    }
+
+    fn add(
+        &mut self,
+        new_name: Option<&[u8]>,
+        new_email: &[u8],
+        old_name: Option<&[u8]>,
+        old_email: Option<&[u8]>,
+    ) {
+        let (new_email, old_email) = match old_email {
+            Some(old_email) => (Some(new_email), old_email),
+            None => (None, new_email),
+        };
+        let entry = self
+            .entries
+            .entry(old_email.to_ascii_lowercase())
+            .or_default();
+        match old_name {
+            None => {
+                if let Some(name) = new_name {
+                    entry.replacement.name = Some(name.to_vec());
+                }
+                if let Some(email) = new_email {
+                    entry.replacement.email = Some(email.to_vec());
+                }
+            }
+            Some(old_name) => {
+                entry.names.insert(
+                    old_name.to_ascii_lowercase(),
+                    Replacement {
+                        name: new_name.map(<[u8]>::to_vec),
+                        email: new_email.map(<[u8]>::to_vec),
+                    },
+                );
+            }
+        }
+    }
+
+    // Adds the entries in the file at `path`, if there is one.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        match fs::read(path) {
+            Ok(data) => {
+                self.add_bytes(&data);
+                Ok(())
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The canonical name and email for `name` and `email`, which are
+    // returned as they are without an entry for them.
+    pub fn map<'a>(&'a self, name: &'a [u8], email: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        let entry = match self.entries.get(&email.to_ascii_lowercase()) {
+            Some(entry) => entry,
+            None => return (name, email),
+        };
+        // An entry for the name as well wins over one for the email only.
+        let replacement = entry
+            .names
+            .get(&name.to_ascii_lowercase())
+            .unwrap_or(&entry.replacement);
+        (
+            replacement.name.as_deref().unwrap_or(name),
+            replacement.email.as_deref().unwrap_or(email),
+        )
+    }
+
+    // The identity under its canonical name and email, with its date.
+    pub fn resolve(&self, identity: &Identity) -> Identity {
+        let (name, email) = self.map(identity.name(), identity.email());
+        if name == identity.name() && email == identity.email() {
+            return identity.clone();
+        }
+        identity.with_name_and_email(name, email)
+    }
+}
+
+impl Repository {
+    // The mailmap git uses for this repository: .mailmap at the top of the
+    // work tree, then the blob mailmap.blob names, by default HEAD:.mailmap
+    // in bare repositories, then the file mailmap.file names.
+    pub fn mailmap(&self) -> Result<Mailmap> {
+        let mut mailmap = Mailmap::new();
+        if let Some(workdir) = self.workdir() {
+            // git does not follow a symlink here, as it may point outside
+            // the repository.
+            let path = workdir.join(".mailmap");
+            let is_symlink = fs::symlink_metadata(&path)
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false);
+            if !is_symlink {
+                mailmap.add_file(&path)?;
+            }
+        }
+        let config = self.config()?;
+        let blob = match config.get("mailmap.blob") {
+            Some(blob) => Some(blob.to_string()),
+            None if self.is_bare() => Some("HEAD:.mailmap".to_string()),
+            None => None,
+        };
+        if let Some(blob) = blob.filter(|blob| !blob.is_empty()) {
+            if let Some(data) = self.mailmap_blob(&blob)? {
+                mailmap.add_bytes(&data);
+            }
+        }
+        if let Some(path) = config.get_path("mailmap.file")? {
+            mailmap.add_file(path)?;
+        }
+        Ok(mailmap)
+    }
+
+    // The content of the blob `spec` names, either an object id or
+    // `<rev>:<path>`, or None if there is no such blob.
+    fn mailmap_blob(&self, spec: &str) -> Result<Option<Vec<u8>>> {
+        let oid = match spec.split_once(':') {
+            Some((rev, path)) => {
+                let tree = match self.resolve_revision(rev)? {
+                    Some(oid) => match self.odb().get(&oid)? {
+                        Object::Commit(commit) => *commit.tree(),
+                        Object::Tree(_) => oid,
+                        _ => return Ok(None),
+                    },
+                    None => return Ok(None),
+                };
+                match self.tree_entry(&tree, path.as_bytes())? {
+                    Some(entry) => *entry.oid(),
+                    None => return Ok(None),
+                }
+            }
+            None => match self.resolve_revision(spec)? {
+                Some(oid) => oid,
+                None => return Ok(None),
+            },
+        };
+        Ok(self
+            .odb()
+            .get(&oid)?
+            .into_blob()
+            .map(|blob| blob.as_bytes().to_vec()))
+    }
+}
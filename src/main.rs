@@ -1,25 +1,40 @@
 extern crate hit;
 
+use hit::attributes::AttrSource;
 use hit::index::*;
+use hit::odb::StandardOdb;
+use hit::repo::Repository;
+use hit::stat::StatOptions;
 use std::env;
-use std::io::BufReader;
-use std::io::Write;
 use std::path::PathBuf;
+use std::process;
 
 fn main() {
-    // let mut index = Index::new();
-    // let index_entry = IndexEntry::new("src/lib.rs");
-    // index.add_entry(index_entry);
     let args: Vec<String> = env::args().collect();
     let repo_root = &args[1];
     let mut git_index = PathBuf::from(repo_root);
     git_index.push(".git/index");
-    let mut f = std::fs::File::open(git_index).unwrap();
-    let mut reader = BufReader::new(f);
-    let mut index = Index::from_reader(reader).unwrap();
+    let mut index = Index::open(&git_index).unwrap();
     println!("{:?}", &index);
-    let entry = IndexEntry::new(&args[2]);
-    index.add_entry(entry);
-    let f = std::fs::File::create(".git/index").unwrap();
-    index.write(f).unwrap();
+    let repo = Repository::open(repo_root).unwrap();
+    if let Some(found) = repo.check_ignore(args[2].as_bytes()).unwrap() {
+        if found.is_ignored() {
+            eprintln!(
+                "The following path is ignored by one of your .gitignore files:\n{}",
+                &args[2]
+            );
+            process::exit(1);
+        }
+    }
+    let odb = StandardOdb::from_path(PathBuf::from(repo_root).join(".git/objects"));
+    let converter = repo.converter(AttrSource::CheckIn).unwrap();
+    index
+        .add_path(repo_root, &args[2], &odb, &converter)
+        .unwrap();
+    for notice in converter.take_notices() {
+        eprintln!("{}", notice);
+    }
+    index
+        .write_file(&git_index, repo_root, &StatOptions::new(), &converter)
+        .unwrap();
 }
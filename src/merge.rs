@@ -0,0 +1,505 @@
+use std::hash::Hash;
+use std::ops::Range;
+
+use crate::blob::Blob;
+use crate::diff::{diff, split_lines, Hunk};
+
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+// How conflicts are written: both sides, both sides with the base between
+// them, or that with the lines both sides share taken out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStyle {
+    Merge,
+    Diff3,
+    Zdiff3,
+}
+
+// How conflicts are resolved instead of being left with markers, like
+// -Xours, -Xtheirs and --union.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeFavor {
+    None,
+    Ours,
+    Theirs,
+    Union,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub ours_label: Option<String>,
+    pub base_label: Option<String>,
+    pub theirs_label: Option<String>,
+    pub marker_size: usize,
+    pub style: ConflictStyle,
+    pub favor: MergeFavor,
+    // Changes in white space alone are not changes, like the -X options
+    // of the same names.
+    pub ignore_all_space: bool,
+    pub ignore_space_change: bool,
+    pub ignore_space_at_eol: bool,
+    pub ignore_cr_at_eol: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    pub content: Vec<u8>,
+    pub conflicts: usize,
+}
+
+// How a region of the result is made up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolution {
+    Conflict,
+    Ours,
+    Theirs,
+    // Both sides turned out to make the same change.
+    Same,
+}
+
+// A region changed by at least one side, as lines of the base and of both
+// sides.
+struct Region {
+    resolution: Resolution,
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+}
+
+impl MergeOptions {
+    pub fn new() -> Self {
+        Self {
+            ours_label: None,
+            base_label: None,
+            theirs_label: None,
+            marker_size: DEFAULT_MARKER_SIZE,
+            style: ConflictStyle::Merge,
+            favor: MergeFavor::None,
+            ignore_all_space: false,
+            ignore_space_change: false,
+            ignore_space_at_eol: false,
+            ignore_cr_at_eol: false,
+        }
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeOptions {
+    fn ignores_whitespace(&self) -> bool {
+        self.ignore_all_space
+            || self.ignore_space_change
+            || self.ignore_space_at_eol
+            || self.ignore_cr_at_eol
+    }
+
+    // What a line is compared by: the line with the white space that does
+    // not count taken out.
+    fn key(&self, line: &[u8]) -> Vec<u8> {
+        let is_space = |c: &u8| matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c');
+        if self.ignore_all_space {
+            return line.iter().filter(|c| !is_space(c)).copied().collect();
+        }
+        if self.ignore_space_change || self.ignore_space_at_eol {
+            let end = line.iter().rposition(|c| !is_space(c)).map_or(0, |i| i + 1);
+            let line = &line[..end];
+            if !self.ignore_space_change {
+                return line.to_vec();
+            }
+            let mut key = Vec::with_capacity(line.len());
+            for &c in line {
+                if !is_space(&c) {
+                    key.push(c);
+                } else if key.last() != Some(&b' ') {
+                    key.push(b' ');
+                }
+            }
+            return key;
+        }
+        match line.strip_suffix(b"\r\n") {
+            Some(line) => [line, b"\n"].concat(),
+            None => line.strip_suffix(b"\r").unwrap_or(line).to_vec(),
+        }
+    }
+
+    fn keys(&self, lines: &[&[u8]]) -> Vec<Vec<u8>> {
+        lines.iter().map(|line| self.key(line)).collect()
+    }
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+// Adds a region to the list, joining it with the last one when they overlap
+// or touch on either side. Joined regions of different origin conflict.
+fn append_region(regions: &mut Vec<Region>, region: Region) {
+    match regions.last_mut() {
+        Some(last)
+            if region.ours.start <= last.ours.end || region.theirs.start <= last.theirs.end =>
+        {
+            if region.resolution != last.resolution {
+                last.resolution = Resolution::Conflict;
+            }
+            last.base.end = region.base.end;
+            last.ours.end = region.ours.end;
+            last.theirs.end = region.theirs.end;
+        }
+        _ => regions.push(region),
+    }
+}
+
+// Maps a range of the base to a side, given that base line `from` is line
+// `to` on that side, with nothing changed between. The side's own changes
+// can make the start fall before the first line, in which case the region
+// gets joined with the one before anyway.
+fn shifted(range: &Range<usize>, from: usize, to: usize, len: usize) -> Range<usize> {
+    let end = range.start + len + to - from;
+    end.saturating_sub(len)..end
+}
+
+// Pairs up the changes of both sides like git's xdiff. Changes of one side
+// that overlap or touch a change of the other conflict, unless both make
+// the same change.
+fn regions<T: Eq>(
+    our_hunks: &[Hunk],
+    their_hunks: &[Hunk],
+    our_lines: &[T],
+    their_lines: &[T],
+    base_len: usize,
+) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < our_hunks.len() && j < their_hunks.len() {
+        let (our, their) = (&our_hunks[i], &their_hunks[j]);
+        if our.old.end < their.old.start {
+            append_region(
+                &mut regions,
+                Region {
+                    resolution: Resolution::Ours,
+                    base: our.old.clone(),
+                    ours: our.new.clone(),
+                    theirs: shifted(&our.old, their.old.start, their.new.start, our.old.len()),
+                },
+            );
+            i += 1;
+            continue;
+        }
+        if their.old.end < our.old.start {
+            append_region(
+                &mut regions,
+                Region {
+                    resolution: Resolution::Theirs,
+                    base: their.old.clone(),
+                    ours: shifted(&their.old, our.old.start, our.new.start, their.old.len()),
+                    theirs: their.new.clone(),
+                },
+            );
+            j += 1;
+            continue;
+        }
+        if our.old != their.old || our_lines[our.new.clone()] != their_lines[their.new.clone()] {
+            // As with shifted ranges, a start before the first line means
+            // this joins the region before.
+            let start = our.old.start.min(their.old.start);
+            let end = our.old.end.max(their.old.end);
+            append_region(
+                &mut regions,
+                Region {
+                    resolution: Resolution::Conflict,
+                    base: start..end,
+                    ours: (our.new.start + start).saturating_sub(our.old.start)
+                        ..our.new.end + end - our.old.end,
+                    theirs: (their.new.start + start).saturating_sub(their.old.start)
+                        ..their.new.end + end - their.old.end,
+                },
+            );
+        }
+        let (our_end, their_end) = (our.old.end, their.old.end);
+        if our_end >= their_end {
+            j += 1;
+        }
+        if their_end >= our_end {
+            i += 1;
+        }
+    }
+    for our in &our_hunks[i..] {
+        append_region(
+            &mut regions,
+            Region {
+                resolution: Resolution::Ours,
+                base: our.old.clone(),
+                ours: our.new.clone(),
+                theirs: shifted(&our.old, base_len, their_lines.len(), our.old.len()),
+            },
+        );
+    }
+    for their in &their_hunks[j..] {
+        append_region(
+            &mut regions,
+            Region {
+                resolution: Resolution::Theirs,
+                base: their.old.clone(),
+                ours: shifted(&their.old, base_len, our_lines.len(), their.old.len()),
+                theirs: their.new.clone(),
+            },
+        );
+    }
+    regions
+}
+
+// Shrinks conflicts to the lines the sides really disagree on, like git's
+// zealous merge level: lines both sides have in common become part of the
+// result, and a conflict may split into several.
+fn refine_conflicts<T: Hash + Eq>(
+    regions: Vec<Region>,
+    our_lines: &[T],
+    their_lines: &[T],
+) -> Vec<Region> {
+    let mut refined = Vec::with_capacity(regions.len());
+    for region in regions {
+        if region.resolution != Resolution::Conflict
+            || region.ours.is_empty()
+            || region.theirs.is_empty()
+        {
+            refined.push(region);
+            continue;
+        }
+        let hunks = diff(
+            &our_lines[region.ours.clone()],
+            &their_lines[region.theirs.clone()],
+        );
+        if hunks.is_empty() {
+            refined.push(Region {
+                resolution: Resolution::Same,
+                ..region
+            });
+            continue;
+        }
+        for hunk in hunks {
+            refined.push(Region {
+                resolution: Resolution::Conflict,
+                base: region.base.clone(),
+                ours: region.ours.start + hunk.old.start..region.ours.start + hunk.old.end,
+                theirs: region.theirs.start + hunk.new.start..region.theirs.start + hunk.new.end,
+            });
+        }
+    }
+    refined
+}
+
+// Takes the lines both sides start and end a conflict with out of it, like
+// git's zdiff3. What is between is left whole, so that the base still
+// makes sense next to it.
+fn trim_conflicts<T: Eq>(regions: Vec<Region>, our_lines: &[T], their_lines: &[T]) -> Vec<Region> {
+    let mut trimmed = Vec::with_capacity(regions.len());
+    for region in regions {
+        if region.resolution != Resolution::Conflict {
+            trimmed.push(region);
+            continue;
+        }
+        let (ours, theirs) = (
+            &our_lines[region.ours.clone()],
+            &their_lines[region.theirs.clone()],
+        );
+        if ours == theirs {
+            trimmed.push(Region {
+                resolution: Resolution::Same,
+                ..region
+            });
+            continue;
+        }
+        let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+        let suffix = ours[prefix..]
+            .iter()
+            .rev()
+            .zip(theirs[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (ours, theirs) = (region.ours.clone(), region.theirs.clone());
+        if prefix > 0 {
+            trimmed.push(Region {
+                resolution: Resolution::Same,
+                base: region.base.start..region.base.start,
+                ours: ours.start..ours.start + prefix,
+                theirs: theirs.start..theirs.start + prefix,
+            });
+        }
+        trimmed.push(Region {
+            resolution: Resolution::Conflict,
+            base: region.base.clone(),
+            ours: ours.start + prefix..ours.end - suffix,
+            theirs: theirs.start + prefix..theirs.end - suffix,
+        });
+        if suffix > 0 {
+            trimmed.push(Region {
+                resolution: Resolution::Same,
+                base: region.base.end..region.base.end,
+                ours: ours.end - suffix..ours.end,
+                theirs: theirs.end - suffix..theirs.end,
+            });
+        }
+    }
+    trimmed
+}
+
+// Joins conflicts that only have a few lines between them, which reads
+// better than a run of small conflicts.
+fn join_close_conflicts(regions: Vec<Region>) -> Vec<Region> {
+    let mut joined: Vec<Region> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match joined.last_mut() {
+            Some(last)
+                if last.resolution == Resolution::Conflict
+                    && region.resolution == Resolution::Conflict
+                    && region.ours.start - last.ours.end <= 3 =>
+            {
+                last.base.end = region.base.end;
+                last.ours.end = region.ours.end;
+                last.theirs.end = region.theirs.end;
+            }
+            _ => joined.push(region),
+        }
+    }
+    joined
+}
+
+struct Output<'a> {
+    options: &'a MergeOptions,
+    content: Vec<u8>,
+    conflicts: usize,
+}
+
+impl<'a> Output<'a> {
+    fn lines(&mut self, lines: &[&[u8]]) {
+        for line in lines {
+            self.content.extend_from_slice(line);
+        }
+    }
+
+    fn marker(&mut self, c: u8, label: Option<&str>) {
+        self.content
+            .extend(std::iter::repeat_n(c, self.options.marker_size));
+        if let Some(label) = label {
+            self.content.push(b' ');
+            self.content.extend_from_slice(label.as_bytes());
+        }
+        self.content.push(b'\n');
+    }
+
+    // Conflicting lines always end with a newline, so that the markers
+    // after them start a line of their own.
+    fn conflict_side(&mut self, lines: &[&[u8]]) {
+        self.lines(lines);
+        if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+            self.content.push(b'\n');
+        }
+    }
+
+    // A conflict the favor option does not resolve is written with
+    // markers, and with the base as well in the diff3 styles.
+    fn conflict(&mut self, base: &[&[u8]], ours: &[&[u8]], theirs: &[&[u8]]) {
+        match self.options.favor {
+            MergeFavor::Ours => return self.lines(ours),
+            MergeFavor::Theirs => return self.lines(theirs),
+            MergeFavor::Union => {
+                self.conflict_side(ours);
+                return self.lines(theirs);
+            }
+            MergeFavor::None => {}
+        }
+        self.conflicts += 1;
+        self.marker(b'<', self.options.ours_label.as_deref());
+        self.conflict_side(ours);
+        if self.options.style != ConflictStyle::Merge {
+            self.marker(b'|', self.options.base_label.as_deref());
+            self.conflict_side(base);
+        }
+        self.marker(b'=', None);
+        self.conflict_side(theirs);
+        self.marker(b'>', self.options.theirs_label.as_deref());
+    }
+}
+
+// A line-based three-way merge of `ours` and `theirs`, which both derive
+// from `base`. Changes made by only one side are taken, and so are
+// identical changes; the rest are left between conflict markers.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> MergeResult {
+    let base_lines = split_lines(base);
+    let our_lines = split_lines(ours);
+    let their_lines = split_lines(theirs);
+    let regions = if options.ignores_whitespace() {
+        let keys = [&base_lines, &our_lines, &their_lines].map(|lines| options.keys(lines));
+        merge_regions(&keys[0], &keys[1], &keys[2], options.style)
+    } else {
+        merge_regions(&base_lines, &our_lines, &their_lines, options.style)
+    };
+
+    let mut output = Output {
+        options,
+        content: Vec::with_capacity(ours.len().max(theirs.len())),
+        conflicts: 0,
+    };
+    // Lines outside the regions are the same on both sides.
+    let mut position = 0;
+    for region in regions {
+        output.lines(&our_lines[position..region.ours.start]);
+        position = region.ours.end;
+        match region.resolution {
+            Resolution::Conflict => output.conflict(
+                &base_lines[region.base.clone()],
+                &our_lines[region.ours.clone()],
+                &their_lines[region.theirs.clone()],
+            ),
+            Resolution::Ours | Resolution::Same => {
+                output.lines(&our_lines[region.ours.clone()]);
+            }
+            Resolution::Theirs => output.lines(&their_lines[region.theirs.clone()]),
+        }
+    }
+    output.lines(&our_lines[position..]);
+    MergeResult {
+        content: output.content,
+        conflicts: output.conflicts,
+    }
+}
+
+// The regions the sides change, compared by whatever stands for their
+// lines. Like git, diff3 keeps conflicts as they are, for the base shown
+// with them to match.
+fn merge_regions<T: Hash + Eq>(
+    base: &[T],
+    ours: &[T],
+    theirs: &[T],
+    style: ConflictStyle,
+) -> Vec<Region> {
+    let our_hunks = diff(base, ours);
+    let their_hunks = diff(base, theirs);
+    // Like git, when ours changes nothing theirs is taken as it is, even
+    // where it only changes whitespace.
+    if our_hunks.is_empty() {
+        return vec![Region {
+            resolution: Resolution::Theirs,
+            base: 0..base.len(),
+            ours: 0..ours.len(),
+            theirs: 0..theirs.len(),
+        }];
+    }
+    let regions = regions(&our_hunks, &their_hunks, ours, theirs, base.len());
+    match style {
+        ConflictStyle::Merge => join_close_conflicts(refine_conflicts(regions, ours, theirs)),
+        ConflictStyle::Diff3 => regions,
+        ConflictStyle::Zdiff3 => trim_conflicts(regions, ours, theirs),
+    }
+}
+
+// Like `merge`, for blobs.
+pub fn merge_blobs(base: &Blob, ours: &Blob, theirs: &Blob, options: &MergeOptions) -> MergeResult {
+    merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), options)
+}
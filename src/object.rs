@@ -1,11 +1,13 @@
 use crate::blob::Blob;
 use crate::commit::Commit;
+use crate::tag::Tag;
 use crate::tree::Tree;
 
 pub enum Object {
-    Commit(Commit),
+    Commit(Box<Commit>),
     Tree(Tree),
     Blob(Blob),
+    Tag(Box<Tag>),
 }
 
 impl Object {
@@ -30,6 +32,13 @@ impl Object {
         }
     }
 
+    pub fn as_tag(&self) -> Option<&Tag> {
+        match self {
+            Object::Tag(ref tag) => Some(tag),
+            _ => None,
+        }
+    }
+
     pub fn into_blob(self) -> Option<Blob> {
         match self {
             Object::Blob(blob) => Some(blob),
@@ -46,7 +55,14 @@ impl Object {
 
     pub fn into_commit(self) -> Option<Commit> {
         match self {
-            Object::Commit(commit) => Some(commit),
+            Object::Commit(commit) => Some(*commit),
+            _ => None,
+        }
+    }
+
+    pub fn into_tag(self) -> Option<Tag> {
+        match self {
+            Object::Tag(tag) => Some(*tag),
             _ => None,
         }
     }
@@ -1,32 +1,40 @@
 use crate::blob::Blob;
 use crate::commit::{Commit, Identity};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::object::Object;
 use crate::oid::Oid;
+use crate::tag::Tag;
 use crate::tree::{Mode, Name, Tree, TreeEntry};
 
-use chrono::offset::FixedOffset;
-use chrono::TimeZone;
 use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
 pub trait Odb {
     fn get(&self, oid: &Oid) -> Result<Object>;
+    fn write(&self, object_type: ObjectType, data: &[u8]) -> Result<Oid>;
 }
 
+#[derive(Clone)]
 pub struct StandardOdb {
     objects: PathBuf,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum ObjectType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
     Blob,
     Commit,
     Tree,
+    Tag,
 }
 
 struct ObjectHeader {
@@ -35,32 +43,64 @@ struct ObjectHeader {
 }
 
 impl ObjectType {
-    fn new<T: AsRef<[u8]>>(object_type: T) -> Self {
+    fn new<T: AsRef<[u8]>>(object_type: T) -> Result<Self> {
         match object_type.as_ref() {
-            b"blob" => ObjectType::Blob,
-            b"commit" => ObjectType::Commit,
-            b"tree" => ObjectType::Tree,
-            _ => panic!(),
+            b"blob" => Ok(ObjectType::Blob),
+            b"commit" => Ok(ObjectType::Commit),
+            b"tree" => Ok(ObjectType::Tree),
+            b"tag" => Ok(ObjectType::Tag),
+            other => Err(Error::InvalidObject(format!(
+                "invalid object type \"{}\"",
+                String::from_utf8_lossy(other)
+            ))),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            ObjectType::Blob => b"blob",
+            ObjectType::Commit => b"commit",
+            ObjectType::Tree => b"tree",
+            ObjectType::Tag => b"tag",
         }
     }
 }
 
 impl ObjectHeader {
-    fn read<R: BufRead>(mut reader: R) -> Self {
+    fn read<R: BufRead>(mut reader: R) -> Result<Self> {
         let mut type_vec = Vec::new();
-        reader.read_until(b' ', &mut type_vec);
+        reader.read_until(b' ', &mut type_vec)?;
         type_vec.pop();
-        let object_type = ObjectType::new(&type_vec);
+        let object_type = ObjectType::new(&type_vec)?;
 
         let mut size_vec = Vec::new();
-        reader.read_until(0x00, &mut size_vec);
+        reader.read_until(0x00, &mut size_vec)?;
         size_vec.pop();
-        let object_size: usize = std::str::from_utf8(&size_vec).unwrap().parse().unwrap();
-        Self {
+        let object_size: usize = String::from_utf8(size_vec)?.parse()?;
+        Ok(Self {
             object_type,
             object_size,
-        }
+        })
     }
+
+    fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(self.object_type.as_bytes())?;
+        write!(writer, " {}\0", self.object_size)?;
+        Ok(())
+    }
+}
+
+pub fn hash_object(object_type: ObjectType, data: &[u8]) -> Oid {
+    let header = ObjectHeader {
+        object_type,
+        object_size: data.len(),
+    };
+    let mut buf = Vec::new();
+    header.write(&mut buf).unwrap();
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(&buf);
+    hasher.update(data);
+    Oid::new(hasher.digest().bytes())
 }
 
 impl StandardOdb {
@@ -80,118 +120,209 @@ impl StandardOdb {
         object_path
     }
 
-    fn decompress<R: BufRead>(reader: R) -> Vec<u8> {
+    // The ids of the loose objects whose hex form starts with `fanout`, the
+    // two digits that name their directory.
+    pub fn loose_objects(&self, fanout: &str) -> Result<Vec<String>> {
+        let entries = match std::fs::read_dir(self.objects.join(fanout)) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut ids = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(name) = name.to_str() {
+                if name.len() == 38 && name.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+                {
+                    ids.push(format!("{}{}", fanout, name));
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    fn decompress<R: BufRead>(reader: R) -> Result<Vec<u8>> {
         let mut decoder = ZlibDecoder::new(reader);
         let mut buf = Vec::new();
-        decoder.read_to_end(&mut buf).unwrap();
-        buf
+        decoder.read_to_end(&mut buf)?;
+        Ok(buf)
     }
 
-    fn read_object<R: BufRead>(mut reader: R) -> Object {
-        let header = ObjectHeader::read(&mut reader);
-        match header.object_type {
-            ObjectType::Blob => Object::Blob(Self::read_blob(&mut reader, header.object_size)),
-            ObjectType::Commit => Object::Commit(Self::read_commit(&mut reader)),
-            ObjectType::Tree => Object::Tree(Self::read_tree(&mut reader)),
-        }
+    fn compress(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn read_object<R: BufRead>(mut reader: R) -> Result<Object> {
+        let header = ObjectHeader::read(&mut reader)?;
+        let object = match header.object_type {
+            ObjectType::Blob => Object::Blob(Self::read_blob(&mut reader)?),
+            ObjectType::Commit => Object::Commit(Box::new(Self::read_commit(&mut reader)?)),
+            ObjectType::Tree => Object::Tree(Self::read_tree(&mut reader)?),
+            ObjectType::Tag => Object::Tag(Box::new(Self::read_tag(&mut reader)?)),
+        };
+        Ok(object)
     }
 
-    fn read_blob<R: Read>(mut reader: R, object_size: usize) -> Blob {
+    fn read_blob<R: Read>(mut reader: R) -> Result<Blob> {
         let mut buf = Vec::new();
-        let object_size = reader.read_to_end(&mut buf).unwrap();
-        Blob::new(&buf)
+        reader.read_to_end(&mut buf)?;
+        Ok(Blob::new(&buf))
     }
 
-    fn read_tree<R: BufRead>(mut reader: R) -> Tree {
+    fn read_tree<R: BufRead>(mut reader: R) -> Result<Tree> {
         let mut tree = Tree::new();
         loop {
             let mut mode = Vec::new();
             let mut name = Vec::new();
-            let mode_size = reader.read_until(b' ', &mut mode).unwrap();
+            let mode_size = reader.read_until(b' ', &mut mode)?;
             if mode_size == 0 {
                 break;
             }
-            reader.read_until(0x00, &mut name);
+            reader.read_until(0x00, &mut name)?;
             mode.pop();
             name.pop();
-            let oid = Oid::from_reader(&mut reader).unwrap();
+            let oid = Oid::from_reader(&mut reader)?;
             let entry = TreeEntry::new(oid, Mode(mode));
             tree.append_entry(Name(name), entry);
         }
-        tree
+        Ok(tree)
     }
 
-    fn read_commit<R: BufRead>(mut reader: R) -> Commit {
-        let mut buf = Vec::new();
-        reader.read_until(b' ', &mut buf);
-        let mut tree = Vec::new();
-        reader.read_until(b'\n', &mut tree);
-        tree.pop();
-        let tree = Oid::from_hex(&tree);
-        let mut parents = Vec::new();
-        loop {
-            buf.clear();
-            reader.read_until(b' ', &mut buf);
-            if &buf == b"author " {
-                break;
-            }
-            if &buf == b"parent " {
-                let mut parent = Vec::new();
-                reader.read_until(b'\n', &mut parent);
-                parent.pop();
-                parents.push(Oid::from_hex(&parent));
-            } else {
-                panic!("foo")
+    fn read_commit<R: BufRead>(mut reader: R) -> Result<Commit> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        // A blank line separates the message from the headers.
+        let (headers, message) = match data.windows(2).position(|w| w == b"\n\n") {
+            Some(i) => (&data[..i], &data[i + 2..]),
+            None => (data.strip_suffix(b"\n").unwrap_or(&data), &[][..]),
+        };
+        let mut fields: Vec<(&[u8], Vec<u8>)> = Vec::new();
+        for line in headers.split(|&c| c == b'\n') {
+            // Lines starting with a space go on with the header before.
+            if let (Some(rest), Some((_, value))) = (line.strip_prefix(b" "), fields.last_mut()) {
+                value.push(b'\n');
+                value.extend_from_slice(rest);
+                continue;
             }
+            let key_len = line.iter().position(|&c| c == b' ').unwrap_or(line.len());
+            let value = line.get(key_len + 1..).unwrap_or_default();
+            fields.push((&line[..key_len], value.to_vec()));
         }
-        let author = Self::read_identity(&mut reader);
-        buf.clear();
-        reader.read_until(b' ', &mut buf);
-        let committer = Self::read_identity(&mut reader);
-        let mut message = Vec::new();
-        reader.read_to_end(&mut message);
-        Commit::new(tree, parents, author, committer, message)
-    }
-
-    fn read_identity<R: BufRead>(mut reader: R) -> Identity {
-        let mut name = Vec::new();
-        reader.read_until(b'<', &mut name);
-        name.pop();
-        name.pop();
-        let mut email = Vec::new();
-        reader.read_until(b' ', &mut email);
-        email.pop();
-        email.pop();
-        let mut datetime = Vec::new();
-        reader.read_until(b' ', &mut datetime);
-        datetime.pop();
-        let datetime_secs = std::str::from_utf8(&datetime)
-            .unwrap()
-            .parse::<i64>()
-            .unwrap();
-        let mut offset = Vec::new();
-        reader.read_until(b'\n', &mut offset);
-        offset.pop();
-        let offset_secs = std::str::from_utf8(&offset[1..])
-            .unwrap()
-            .parse::<i32>()
-            .unwrap();
-        let offset = if offset[0] == b'+' {
-            FixedOffset::east(offset_secs)
-        } else {
-            FixedOffset::west(offset_secs)
+
+        let invalid = |reason: &str| Error::InvalidObject(format!("bad commit: {}", reason));
+        let mut fields = fields.into_iter().peekable();
+        let tree = match fields.next() {
+            Some((b"tree", value)) => Self::read_oid(&value).ok_or_else(|| invalid("bad tree"))?,
+            _ => return Err(invalid("missing tree")),
         };
-        let datetime = offset.timestamp(datetime_secs, 0);
-        Identity::new(name, email, datetime)
+        let mut parents = Vec::new();
+        while let Some((_, value)) = fields.next_if(|(key, _)| *key == b"parent") {
+            parents.push(Self::read_oid(&value).ok_or_else(|| invalid("bad parent"))?);
+        }
+        let author = match fields.next() {
+            Some((b"author", value)) => Identity::parse_lenient(&value),
+            _ => return Err(invalid("missing author")),
+        };
+        let committer = match fields.next() {
+            Some((b"committer", value)) => Identity::parse_lenient(&value),
+            _ => return Err(invalid("missing committer")),
+        };
+        let mut commit = Commit::new(tree, parents, author, committer, message.to_vec());
+        for (key, value) in fields {
+            commit.add_extra_header(key.to_vec(), value);
+        }
+        Ok(commit)
+    }
+
+    // Reads the headers a tag starts with, in the order git writes them.
+    // The tagger is optional, as very old tags have none.
+    fn read_tag<R: BufRead>(mut reader: R) -> Result<Tag> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let invalid = |reason: &str| Error::InvalidObject(format!("bad tag: {}", reason));
+        let mut rest = &data[..];
+        let mut header = |key: &[u8]| -> Option<Vec<u8>> {
+            let line_end = rest.iter().position(|&c| c == b'\n')?;
+            let value = rest[..line_end]
+                .strip_prefix(key)?
+                .strip_prefix(b" ")?
+                .to_vec();
+            rest = &rest[line_end + 1..];
+            Some(value)
+        };
+        let object = header(b"object")
+            .and_then(|value| Self::read_oid(&value))
+            .ok_or_else(|| invalid("bad object"))?;
+        let object_type = match header(b"type") {
+            Some(value) => ObjectType::new(&value).map_err(|_| invalid("bad type"))?,
+            None => return Err(invalid("missing type")),
+        };
+        let name = header(b"tag").ok_or_else(|| invalid("missing tag name"))?;
+        let tagger = header(b"tagger").map(|value| Identity::parse_lenient(&value));
+        // Whatever other headers there are end at a blank line.
+        let message = match rest.strip_prefix(b"\n") {
+            Some(message) => message,
+            None => match rest.windows(2).position(|w| w == b"\n\n") {
+                Some(i) => &rest[i + 2..],
+                None => &[][..],
+            },
+        };
+        Ok(Tag::new(
+            object,
+            object_type,
+            name,
+            tagger,
+            message.to_vec(),
+        ))
+    }
+
+    fn read_oid(hex: &[u8]) -> Option<Oid> {
+        if hex.len() == 40 && hex.iter().all(u8::is_ascii_hexdigit) {
+            Some(Oid::from_hex(hex))
+        } else {
+            None
+        }
     }
 }
 
 impl Odb for StandardOdb {
     fn get(&self, oid: &Oid) -> Result<Object> {
         let object_path = self.object_path(oid);
-        let f = std::fs::File::open(object_path)?;
+        let f = match std::fs::File::open(object_path) {
+            Ok(f) => f,
+            // git knows the empty tree without having it stored.
+            Err(error) if error.kind() == ErrorKind::NotFound && oid.hex() == EMPTY_TREE => {
+                return Ok(Object::Tree(Tree::new()))
+            }
+            Err(error) => return Err(error.into()),
+        };
         let reader = BufReader::new(f);
-        let decompressed: &[u8] = &Self::decompress(reader);
-        Ok(Self::read_object(decompressed))
+        let decompressed: &[u8] = &Self::decompress(reader)?;
+        Self::read_object(decompressed)
+    }
+
+    fn write(&self, object_type: ObjectType, data: &[u8]) -> Result<Oid> {
+        let oid = hash_object(object_type, data);
+        let object_path = self.object_path(&oid);
+        if object_path.exists() {
+            return Ok(oid);
+        }
+        let mut raw = Vec::with_capacity(data.len() + 32);
+        ObjectHeader {
+            object_type,
+            object_size: data.len(),
+        }
+        .write(&mut raw)?;
+        raw.extend_from_slice(data);
+        let compressed = Self::compress(&raw)?;
+
+        let dir = object_path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        let tmp_path = dir.join(format!("tmp_obj_{}", &oid.hex()[2..]));
+        std::fs::write(&tmp_path, &compressed)?;
+        std::fs::rename(&tmp_path, &object_path)?;
+        Ok(oid)
     }
 }
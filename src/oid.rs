@@ -6,7 +6,7 @@ use std::ops::Deref;
 
 use crate::error::Result;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Oid {
     id: [u8; GIT_OID_RAWSZ],
 }
@@ -0,0 +1,567 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+use std::rc::Rc;
+
+use crate::attributes::{AttrSource, AttrValue, Attributes};
+use crate::config::env_flag;
+use crate::error::{Error, Result};
+use crate::repo::Repository;
+use crate::wildmatch::{wildmatch, CASEFOLD, PATHNAME};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Magic(u32);
+
+impl Magic {
+    pub const NONE: Magic = Magic(0);
+    pub const TOP: Magic = Magic(0x0001);
+    pub const LITERAL: Magic = Magic(0x0002);
+    pub const GLOB: Magic = Magic(0x0004);
+    pub const ICASE: Magic = Magic(0x0008);
+    pub const EXCLUDE: Magic = Magic(0x0010);
+    pub const ATTR: Magic = Magic(0x0020);
+    // Only given by the environment: elements are literal unless they
+    // have `glob` magic.
+    const NOGLOB: Magic = Magic(0x0040);
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: Magic) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Magic {
+    type Output = Magic;
+    fn bitor(self, rhs: Magic) -> Magic {
+        Magic(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Magic {
+    fn bitor_assign(&mut self, rhs: Magic) {
+        self.0 |= rhs.0;
+    }
+}
+
+const LONG_MAGIC: &[(&str, Magic)] = &[
+    ("top", Magic::TOP),
+    ("literal", Magic::LITERAL),
+    ("glob", Magic::GLOB),
+    ("icase", Magic::ICASE),
+    ("exclude", Magic::EXCLUDE),
+];
+
+const SHORT_MAGIC: &[(u8, Magic)] = &[
+    (b'/', Magic::TOP),
+    (b'!', Magic::EXCLUDE),
+    (b'^', Magic::EXCLUDE),
+];
+
+// One element of a pathspec, its pattern made relative to the top of the
+// tree. Up to `nowildcard_len` the pattern is compared as it is, which is
+// what lets whole directories be skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathspecItem {
+    original: Vec<u8>,
+    pattern: Vec<u8>,
+    nowildcard_len: usize,
+    magic: Magic,
+    attrs: Vec<(String, AttrValue)>,
+}
+
+// The paths a command is limited to, matched as git matches pathspecs.
+// Without any element every path matches; with only `:(exclude)` ones,
+// every path they leave out does.
+#[derive(Clone, Default)]
+pub struct Pathspec {
+    items: Vec<PathspecItem>,
+    attributes: Option<Rc<Attributes>>,
+}
+
+// The characters that may mark short magic after a leading colon. Those
+// that have no meaning yet are reserved.
+fn is_magic_char(c: u8) -> bool {
+    c.is_ascii_punctuation() && !matches!(c, b'*' | b'?' | b'\\' | b'$' | b'_' | b':')
+}
+
+fn is_glob_special(c: u8) -> bool {
+    matches!(c, b'*' | b'?' | b'[' | b'\\')
+}
+
+fn eq(a: &[u8], b: &[u8], icase: bool) -> bool {
+    if icase {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidPathspec(message)
+}
+
+// Joins `path` to `prefix` and resolves "." and ".." in it, keeping a
+// trailing slash. Fails if it leads out of the tree.
+fn prefix_path(prefix: &[u8], path: &[u8], original: &[u8]) -> Result<Vec<u8>> {
+    let mut joined = prefix.to_vec();
+    if !joined.is_empty() && !joined.ends_with(b"/") {
+        joined.push(b'/');
+    }
+    joined.extend_from_slice(path);
+    let mut components: Vec<&[u8]> = Vec::new();
+    for component in joined.split(|&c| c == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => {
+                if components.pop().is_none() {
+                    return Err(invalid(format!(
+                        "{}: '{}' is outside repository",
+                        String::from_utf8_lossy(original),
+                        String::from_utf8_lossy(path)
+                    )));
+                }
+            }
+            _ => components.push(component),
+        }
+    }
+    let mut normalized = components.join(&b'/');
+    let is_dir = joined.ends_with(b"/") || joined.ends_with(b"/.") || joined.ends_with(b"/..");
+    if is_dir && !normalized.is_empty() {
+        normalized.push(b'/');
+    }
+    Ok(normalized)
+}
+
+fn is_attr_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.'))
+}
+
+// Parses the requirements of `attr:` magic: `name` for set, `-name` for
+// unset, `!name` for unspecified and `name=value`, separated by spaces.
+fn parse_attrs(spec: &[u8], original: &[u8]) -> Result<Vec<(String, AttrValue)>> {
+    let spec = String::from_utf8_lossy(spec);
+    let mut attrs = Vec::new();
+    for token in spec.split(' ').filter(|token| !token.is_empty()) {
+        let (name, value) = if let Some(name) = token.strip_prefix('-') {
+            (name, AttrValue::Unset)
+        } else if let Some(name) = token.strip_prefix('!') {
+            (name, AttrValue::Unspecified)
+        } else {
+            match token.find('=') {
+                Some(at) => {
+                    let mut value = String::new();
+                    let mut chars = token[at + 1..].chars();
+                    while let Some(c) = chars.next() {
+                        value.push(if c == '\\' {
+                            chars.next().unwrap_or(c)
+                        } else {
+                            c
+                        });
+                    }
+                    (&token[..at], AttrValue::Value(value))
+                }
+                None => (token, AttrValue::Set),
+            }
+        };
+        if !is_attr_name(name) {
+            return Err(invalid(format!(
+                "invalid attribute name {} in '{}'",
+                name,
+                String::from_utf8_lossy(original)
+            )));
+        }
+        attrs.push((name.to_string(), value));
+    }
+    if attrs.is_empty() {
+        return Err(invalid("attr spec must not be empty".to_string()));
+    }
+    Ok(attrs)
+}
+
+impl PathspecItem {
+    // Parses one element given in the directory `prefix`, with the magic
+    // that the environment turns on for every element.
+    fn parse(element: &[u8], prefix: &[u8], global: Magic) -> Result<Self> {
+        let lossy = || String::from_utf8_lossy(element);
+        let mut magic = Magic::NONE;
+        let mut attrs = Vec::new();
+        let mut path = element;
+        if global.contains(Magic::LITERAL) {
+            magic |= Magic::LITERAL;
+        } else if element.starts_with(b":(") {
+            let end = element.iter().position(|&c| c == b')').ok_or_else(|| {
+                invalid(format!(
+                    "Missing ')' at the end of pathspec magic in '{}'",
+                    lossy()
+                ))
+            })?;
+            for word in element[2..end].split(|&c| c == b',') {
+                if word.is_empty() {
+                    continue;
+                }
+                if let Some(spec) = word.strip_prefix(b"attr:") {
+                    if !attrs.is_empty() {
+                        return Err(invalid(
+                            "Only one 'attr:' specification is allowed.".to_string(),
+                        ));
+                    }
+                    attrs = parse_attrs(spec, element)?;
+                    magic |= Magic::ATTR;
+                    continue;
+                }
+                match LONG_MAGIC.iter().find(|(name, _)| name.as_bytes() == word) {
+                    Some(&(_, flag)) => magic |= flag,
+                    None => {
+                        return Err(invalid(format!(
+                            "Invalid pathspec magic '{}' in '{}'",
+                            String::from_utf8_lossy(word),
+                            lossy()
+                        )))
+                    }
+                }
+            }
+            path = &element[end + 1..];
+        } else if element.starts_with(b":") {
+            let mut at = 1;
+            while let Some(&c) = element.get(at) {
+                if c == b':' {
+                    at += 1;
+                    break;
+                }
+                if !is_magic_char(c) {
+                    break;
+                }
+                match SHORT_MAGIC.iter().find(|(mnemonic, _)| *mnemonic == c) {
+                    Some(&(_, flag)) => magic |= flag,
+                    None => {
+                        return Err(invalid(format!(
+                            "Unimplemented pathspec magic '{}' in '{}'",
+                            c as char,
+                            lossy()
+                        )))
+                    }
+                }
+                at += 1;
+            }
+            path = &element[at..];
+        }
+
+        if magic.contains(Magic::LITERAL) && magic.contains(Magic::GLOB) {
+            return Err(invalid(format!(
+                "{}: 'literal' and 'glob' are incompatible",
+                lossy()
+            )));
+        }
+        if global.contains(Magic::GLOB) && !magic.contains(Magic::LITERAL) {
+            magic |= Magic::GLOB;
+        }
+        if global.contains(Magic::NOGLOB) && !magic.contains(Magic::GLOB) {
+            magic |= Magic::LITERAL;
+        }
+        if global.contains(Magic::ICASE) {
+            magic |= Magic::ICASE;
+        }
+
+        let prefix = if magic.contains(Magic::TOP) {
+            &[][..]
+        } else {
+            prefix
+        };
+        let pattern = prefix_path(prefix, path, element)?;
+        // The part of the prefix left after resolving ".." is a directory
+        // name, never a pattern.
+        let prefix = prefix_path(prefix, b"", element)?;
+        let common = prefix
+            .iter()
+            .zip(&pattern)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let prefix_len = pattern[..common]
+            .iter()
+            .rposition(|&c| c == b'/')
+            .map_or(0, |at| at + 1);
+        let nowildcard_len = if magic.contains(Magic::LITERAL) {
+            pattern.len()
+        } else {
+            let simple = pattern
+                .iter()
+                .position(|&c| is_glob_special(c))
+                .unwrap_or(pattern.len());
+            std::cmp::max(simple, prefix_len)
+        };
+        Ok(Self {
+            original: element.to_vec(),
+            pattern,
+            nowildcard_len,
+            magic,
+            attrs,
+        })
+    }
+
+    pub fn original(&self) -> &[u8] {
+        &self.original
+    }
+
+    pub fn pattern(&self) -> &[u8] {
+        &self.pattern
+    }
+
+    pub fn magic(&self) -> Magic {
+        self.magic
+    }
+
+    pub fn attrs(&self) -> &[(String, AttrValue)] {
+        &self.attrs
+    }
+
+    pub fn has_wildcard(&self) -> bool {
+        self.nowildcard_len < self.pattern.len()
+    }
+
+    // The leading part of the pattern that holds no wildcard.
+    pub fn literal_prefix(&self) -> &[u8] {
+        &self.pattern[..self.nowildcard_len]
+    }
+
+    fn is_exclude(&self) -> bool {
+        self.magic.contains(Magic::EXCLUDE)
+    }
+
+    fn icase(&self) -> bool {
+        self.magic.contains(Magic::ICASE)
+    }
+
+    // Whether `path` is the pattern, lies inside it, or the pattern
+    // matches it as a wildcard, attributes aside.
+    fn matches_path(&self, path: &[u8], is_dir: bool) -> bool {
+        let pattern = &self.pattern;
+        if pattern.is_empty() {
+            return true;
+        }
+        let icase = self.icase();
+        let len = pattern.len();
+        if len <= path.len() && eq(pattern, &path[..len], icase) {
+            if len == path.len() || pattern[len - 1] == b'/' || path[len] == b'/' {
+                return true;
+            }
+        } else if is_dir
+            && pattern[len - 1] == b'/'
+            && path.len() == len - 1
+            && eq(&pattern[..len - 1], path, icase)
+        {
+            return true;
+        }
+        if !self.has_wildcard() {
+            return false;
+        }
+        let prefix = self.nowildcard_len;
+        if path.len() < prefix || !eq(&pattern[..prefix], &path[..prefix], icase) {
+            return false;
+        }
+        let mut flags = 0;
+        if self.magic.contains(Magic::GLOB) {
+            flags |= PATHNAME;
+        }
+        if icase {
+            flags |= CASEFOLD;
+        }
+        wildmatch(&pattern[prefix..], &path[prefix..], flags)
+    }
+
+    // Whether something inside the directory `dir` may match, judged by
+    // the literal prefix of the pattern alone.
+    fn may_match_inside(&self, dir: &[u8]) -> bool {
+        let literal = self.literal_prefix();
+        let mut dir = dir.to_vec();
+        dir.push(b'/');
+        let len = std::cmp::min(literal.len(), dir.len());
+        if !eq(&literal[..len], &dir[..len], self.icase()) {
+            return false;
+        }
+        // Either the pattern names something below `dir`, or `dir` is
+        // below its literal prefix, where a wildcard may match anything.
+        // An empty pattern, from `.` at the top, matches everything.
+        dir.len() <= literal.len()
+            || self.has_wildcard()
+            || literal.is_empty()
+            || literal.ends_with(b"/")
+            || dir[literal.len()] == b'/'
+    }
+
+    // Whether the element leaves out everything inside `dir`, which only
+    // one with a literal pattern can be sure of.
+    fn covers(&self, dir: &[u8]) -> bool {
+        self.attrs.is_empty() && !self.has_wildcard() && self.matches_path(dir, true)
+    }
+
+    fn matches(&self, path: &[u8], is_dir: bool, attributes: Option<&Attributes>) -> Result<bool> {
+        if !self.matches_path(path, is_dir) {
+            return Ok(false);
+        }
+        if self.attrs.is_empty() {
+            return Ok(true);
+        }
+        let attributes = attributes.ok_or_else(|| {
+            invalid(format!(
+                "{}: attr magic needs the attributes of the repository",
+                String::from_utf8_lossy(&self.original)
+            ))
+        })?;
+        let mut path = path.to_vec();
+        if is_dir {
+            path.push(b'/');
+        }
+        let names: Vec<&str> = self.attrs.iter().map(|(name, _)| name.as_str()).collect();
+        let values = attributes.check(&path, &names)?;
+        Ok(self
+            .attrs
+            .iter()
+            .zip(values)
+            .all(|((_, expected), (_, value))| *expected == value))
+    }
+}
+
+// The magic GIT_LITERAL_PATHSPECS and friends give every element.
+fn global_magic() -> Result<Magic> {
+    let mut magic = Magic::NONE;
+    if env_flag("GIT_LITERAL_PATHSPECS") {
+        magic |= Magic::LITERAL;
+    }
+    if env_flag("GIT_GLOB_PATHSPECS") {
+        magic |= Magic::GLOB;
+    }
+    if env_flag("GIT_NOGLOB_PATHSPECS") {
+        if magic.contains(Magic::GLOB) {
+            return Err(invalid(
+                "global 'glob' and 'noglob' pathspec settings are incompatible".to_string(),
+            ));
+        }
+        magic |= Magic::NOGLOB;
+    }
+    if env_flag("GIT_ICASE_PATHSPECS") {
+        magic |= Magic::ICASE;
+    }
+    Ok(magic)
+}
+
+impl Pathspec {
+    // Parses `specs` given in the directory `prefix` of the tree, "" at
+    // the top.
+    pub fn parse<S: AsRef<[u8]>>(specs: &[S], prefix: &[u8]) -> Result<Self> {
+        let global = global_magic()?;
+        let items = specs
+            .iter()
+            .map(|spec| {
+                let spec = spec.as_ref();
+                if spec.is_empty() {
+                    return Err(invalid("empty string is not a valid pathspec".to_string()));
+                }
+                PathspecItem::parse(spec, prefix, global)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            items,
+            attributes: None,
+        })
+    }
+
+    // A pathspec matching `path` and everything inside it, and nothing
+    // else.
+    pub fn literal(path: &[u8]) -> Self {
+        Self {
+            items: vec![PathspecItem {
+                original: path.to_vec(),
+                pattern: path.to_vec(),
+                nowildcard_len: path.len(),
+                magic: Magic::LITERAL,
+                attrs: Vec::new(),
+            }],
+            attributes: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[PathspecItem] {
+        &self.items
+    }
+
+    pub fn has_magic(&self, magic: Magic) -> bool {
+        self.items.iter().any(|item| item.magic.contains(magic))
+    }
+
+    // The attributes `attr:` magic is checked against.
+    pub fn set_attributes(&mut self, attributes: Rc<Attributes>) {
+        self.attributes = Some(attributes);
+    }
+
+    // Whether `path`, relative to the top of the tree, is selected: some
+    // element that is not an exclusion matches it, unless there are none,
+    // and no exclusion does.
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> Result<bool> {
+        let attributes = self.attributes.as_deref();
+        let mut positive = None;
+        for item in self.items.iter().filter(|item| !item.is_exclude()) {
+            if item.matches(path, is_dir, attributes)? {
+                positive = Some(true);
+                break;
+            }
+            positive = Some(false);
+        }
+        if positive == Some(false) {
+            return Ok(false);
+        }
+        for item in self.items.iter().filter(|item| item.is_exclude()) {
+            if item.matches(path, is_dir, attributes)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Whether anything inside the directory `dir` may be selected, so that
+    // walks can skip those that cannot hold a match without reading them.
+    pub fn may_match_dir(&self, dir: &[u8]) -> bool {
+        if self
+            .items
+            .iter()
+            .any(|item| item.is_exclude() && item.covers(dir))
+        {
+            return false;
+        }
+        let mut positive = self
+            .items
+            .iter()
+            .filter(|item| !item.is_exclude())
+            .peekable();
+        positive.peek().is_none() || positive.any(|item| item.may_match_inside(dir))
+    }
+}
+
+impl fmt::Debug for Pathspec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pathspec")
+            .field("items", &self.items)
+            .finish()
+    }
+}
+
+impl Repository {
+    // Parses `specs` given in the directory `prefix`, reading the
+    // attributes of the working tree if any element has `attr:` magic.
+    pub fn pathspec<S: AsRef<[u8]>>(&self, specs: &[S], prefix: &[u8]) -> Result<Pathspec> {
+        let mut pathspec = Pathspec::parse(specs, prefix)?;
+        if pathspec.has_magic(Magic::ATTR) {
+            pathspec.set_attributes(Rc::new(self.attributes(AttrSource::CheckIn)?));
+        }
+        Ok(pathspec)
+    }
+}
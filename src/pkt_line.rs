@@ -0,0 +1,78 @@
+use std::io::{Read, Write};
+
+use crate::error::{Error, Result};
+
+// The largest payload a single packet can carry.
+pub const MAX_PAYLOAD: usize = 65516;
+
+const LENGTH_SIZE: usize = 4;
+
+pub fn write_packet<W: Write>(writer: &mut W, payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::InvalidPacket(format!(
+            "payload of {} bytes is too large",
+            payload.len()
+        )));
+    }
+    write!(writer, "{:04x}", payload.len() + LENGTH_SIZE)?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+pub fn write_text<W: Write>(writer: &mut W, line: &str) -> Result<()> {
+    write_packet(writer, format!("{}\n", line).as_bytes())
+}
+
+pub fn write_flush<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(b"0000")?;
+    Ok(())
+}
+
+// Sends `data` split over as many packets as needed, then a flush.
+pub fn write_stream<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        write_packet(writer, chunk)?;
+    }
+    write_flush(writer)
+}
+
+// Returns None for a flush packet.
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; LENGTH_SIZE];
+    reader.read_exact(&mut length)?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| {
+            Error::InvalidPacket(format!("bad length {:?}", String::from_utf8_lossy(&length)))
+        })?;
+    match length {
+        0 => Ok(None),
+        1..=3 => Err(Error::InvalidPacket(format!("bad length {}", length))),
+        _ => {
+            let mut payload = vec![0; length - LENGTH_SIZE];
+            reader.read_exact(&mut payload)?;
+            Ok(Some(payload))
+        }
+    }
+}
+
+// Reads text packets up to the next flush, without their trailing newline.
+pub fn read_text_list<R: Read>(reader: &mut R) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Some(mut payload) = read_packet(reader)? {
+        if payload.last() == Some(&b'\n') {
+            payload.pop();
+        }
+        lines.push(String::from_utf8(payload)?);
+    }
+    Ok(lines)
+}
+
+pub fn read_stream<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    while let Some(payload) = read_packet(reader)? {
+        data.extend_from_slice(&payload);
+    }
+    Ok(data)
+}
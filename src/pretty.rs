@@ -0,0 +1,1540 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::env;
+
+use crate::commit::{Commit, Identity};
+use crate::config::parse_color;
+use crate::date::{DateFormat, DateMode};
+use crate::encoding::Encoding;
+use crate::error::{Error, Result};
+use crate::mailmap::Mailmap;
+use crate::odb::Odb;
+use crate::oid::Oid;
+use crate::refs::parse_oid;
+use crate::repo::Repository;
+use crate::utf8::{
+    add_wrapped_text, display_width, escape_len, is_space, next_char, replace_columns,
+};
+use crate::wildmatch::wildmatch;
+
+const COLOR_RESET: &str = "\x1b[m";
+const COLOR_COMMIT: &str = "\x1b[33m";
+
+// Colors `%Cred` and friends name without parentheses.
+const BASIC_COLORS: [(&str, &str); 4] = [
+    ("red", "\x1b[31m"),
+    ("green", "\x1b[32m"),
+    ("blue", "\x1b[34m"),
+    ("reset", COLOR_RESET),
+];
+
+// Where refs are decorated from unless configured otherwise.
+const DEFAULT_DECORATIONS: [&str; 6] = [
+    "HEAD",
+    "refs/heads/",
+    "refs/remotes/",
+    "refs/tags/",
+    "refs/stash",
+    "refs/replace/",
+];
+
+// Columns of a terminal whose width cannot be found out.
+const DEFAULT_COLUMNS: isize = 80;
+
+// The most columns `%w()` and `%<()` take, so that templates cannot make
+// for huge output.
+const FORMATTING_LIMIT: i64 = 16 * 1024;
+
+// How `git log` lays out each commit: one of the presets `--pretty` names,
+// or a template with placeholders like `%h %s`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PrettyFormat {
+    Oneline,
+    Short,
+    #[default]
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    Email,
+    // `terminated` templates end each entry with a newline, as `tformat:`
+    // does, instead of putting one between entries like `format:`.
+    Format {
+        template: String,
+        terminated: bool,
+    },
+}
+
+// The presets in the order git looks for them.
+const PRESETS: [(&str, PrettyFormat); 7] = [
+    ("raw", PrettyFormat::Raw),
+    ("medium", PrettyFormat::Medium),
+    ("short", PrettyFormat::Short),
+    ("email", PrettyFormat::Email),
+    ("fuller", PrettyFormat::Fuller),
+    ("full", PrettyFormat::Full),
+    ("oneline", PrettyFormat::Oneline),
+];
+
+impl PrettyFormat {
+    // Reads what `--pretty` takes: a preset name or the start of one,
+    // `format:<template>`, `tformat:<template>`, or a template with a `%`,
+    // which is taken as a tformat.
+    pub fn parse(arg: &str) -> Result<Self> {
+        Self::parse_with_aliases(arg, &[])
+    }
+
+    // Like parse(), with names for formats of one's own as the pretty.<name>
+    // settings give them.
+    fn parse_with_aliases(arg: &str, aliases: &[(String, String)]) -> Result<Self> {
+        if let Some(template) = arg.strip_prefix("format:") {
+            return Ok(PrettyFormat::Format {
+                template: template.to_string(),
+                terminated: false,
+            });
+        }
+        if arg.is_empty() || arg.starts_with("tformat:") || arg.contains('%') {
+            return Ok(PrettyFormat::Format {
+                template: arg.strip_prefix("tformat:").unwrap_or(arg).to_string(),
+                terminated: true,
+            });
+        }
+        let mut sought = arg.to_string();
+        for _ in 0..=aliases.len() {
+            // The shortest name starting with what is sought wins, the
+            // presets over aliases of the same length.
+            let mut found: Option<(&str, Option<&str>)> = None;
+            let names = PRESETS.iter().map(|(name, _)| (*name, None)).chain(
+                aliases
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), Some(value.as_str()))),
+            );
+            for (name, value) in names {
+                if name.starts_with(sought.as_str())
+                    && found.is_none_or(|(found, _)| found.len() > name.len())
+                {
+                    found = Some((name, value));
+                }
+            }
+            match found {
+                None => {
+                    return Err(Error::InvalidFormat(format!(
+                        "invalid --pretty format: {}",
+                        arg
+                    )))
+                }
+                Some((name, None)) => {
+                    let (_, format) = PRESETS.iter().find(|(preset, _)| *preset == name).unwrap();
+                    return Ok(format.clone());
+                }
+                Some((_, Some(value))) => {
+                    if value.starts_with("format:")
+                        || value.starts_with("tformat:")
+                        || value.contains('%')
+                    {
+                        return Self::parse(value);
+                    }
+                    sought = value.to_string();
+                }
+            }
+        }
+        Err(Error::InvalidFormat(format!(
+            "invalid --pretty format: '{}' references an alias which points to itself",
+            arg
+        )))
+    }
+
+    // Whether each entry ends with a newline rather than having one
+    // between it and the next.
+    fn is_terminated(&self) -> bool {
+        match self {
+            PrettyFormat::Oneline => true,
+            PrettyFormat::Format { terminated, .. } => *terminated,
+            _ => false,
+        }
+    }
+
+    // Whether tabs in messages are expanded to the columns they line up.
+    fn expands_tabs(&self) -> bool {
+        matches!(
+            self,
+            PrettyFormat::Medium | PrettyFormat::Full | PrettyFormat::Fuller
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecorationKind {
+    Ref,
+    Branch,
+    RemoteBranch,
+    Tag,
+    Stash,
+    Head,
+    Replaced,
+}
+
+impl DecorationKind {
+    fn of(refname: &str) -> Self {
+        if refname.starts_with("refs/heads/") {
+            DecorationKind::Branch
+        } else if refname.starts_with("refs/remotes/") {
+            DecorationKind::RemoteBranch
+        } else if refname.starts_with("refs/tags/") {
+            DecorationKind::Tag
+        } else if refname == "refs/stash" {
+            DecorationKind::Stash
+        } else if refname == "HEAD" {
+            DecorationKind::Head
+        } else {
+            DecorationKind::Ref
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            DecorationKind::Ref => COLOR_RESET,
+            DecorationKind::Branch => "\x1b[1;32m",
+            DecorationKind::RemoteBranch => "\x1b[1;31m",
+            DecorationKind::Tag => "\x1b[1;33m",
+            DecorationKind::Stash => "\x1b[1;35m",
+            DecorationKind::Head => "\x1b[1;36m",
+            DecorationKind::Replaced => "\x1b[1;34m",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoration {
+    pub name: String,
+    pub kind: DecorationKind,
+}
+
+// The names of refs that point at commits, to show next to them.
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    by_oid: HashMap<Oid, Vec<Decoration>>,
+    head_branch: Option<String>,
+}
+
+impl Decorations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds `name` to the names of `oid`, ahead of those it already has.
+    pub fn add(&mut self, oid: Oid, name: String, kind: DecorationKind) {
+        self.by_oid
+            .entry(oid)
+            .or_default()
+            .insert(0, Decoration { name, kind });
+    }
+
+    // The branch HEAD points at, which is shown as `HEAD -> <branch>`
+    // instead of on its own.
+    pub fn set_head_branch(&mut self, name: Option<String>) {
+        self.head_branch = name;
+    }
+
+    pub fn get(&self, oid: &Oid) -> &[Decoration] {
+        self.by_oid.get(oid).map_or(&[], Vec::as_slice)
+    }
+
+    // The names of `oid` between `prefix` and `suffix`, or nothing if it has
+    // none.
+    fn format(
+        &self,
+        oid: &Oid,
+        color: bool,
+        prefix: &str,
+        separator: &str,
+        suffix: &str,
+    ) -> String {
+        let decorations = self.get(oid);
+        if decorations.is_empty() {
+            return String::new();
+        }
+        let paint = |code: &'static str| if color { code } else { "" };
+        let current = match (
+            &self.head_branch,
+            decorations.iter().any(|d| d.kind == DecorationKind::Head),
+        ) {
+            (Some(branch), true) => decorations
+                .iter()
+                .position(|d| d.kind == DecorationKind::Branch && d.name == *branch),
+            _ => None,
+        };
+        let mut out = String::new();
+        let mut prefix = prefix;
+        for (i, decoration) in decorations.iter().enumerate() {
+            if Some(i) == current {
+                continue;
+            }
+            out.push_str(paint(COLOR_COMMIT));
+            out.push_str(prefix);
+            out.push_str(paint(COLOR_RESET));
+            out.push_str(paint(decoration.kind.color()));
+            if decoration.kind == DecorationKind::Tag {
+                out.push_str("tag: ");
+            }
+            out.push_str(&decoration.name);
+            if let (Some(current), DecorationKind::Head) = (current, decoration.kind) {
+                out.push_str(" -> ");
+                out.push_str(paint(COLOR_RESET));
+                out.push_str(paint(decorations[current].kind.color()));
+                out.push_str(&decorations[current].name);
+            }
+            out.push_str(paint(COLOR_RESET));
+            prefix = separator;
+        }
+        out.push_str(paint(COLOR_COMMIT));
+        out.push_str(suffix);
+        out.push_str(paint(COLOR_RESET));
+        out
+    }
+}
+
+// `refname` as decorations show it unless asked for full names.
+fn short_refname(refname: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| refname.strip_prefix(prefix))
+        .unwrap_or(refname)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PrettyOptions<'a> {
+    pub format: PrettyFormat,
+    // How `%ad`, `%cd` and the dates of presets are shown.
+    pub date_mode: DateMode,
+    // The digits abbreviated ids have at least, or None for core.abbrev.
+    pub abbrev: Option<usize>,
+    // Whether presets name commits by abbreviated ids, as --abbrev-commit
+    // and --oneline do.
+    pub abbrev_commit: bool,
+    pub color: bool,
+    // Whether presets show the refs pointing at commits, as --decorate
+    // does. `%d` and `%D` show them either way.
+    pub decorate: bool,
+    // The refs to decorate commits with, read from the repository when
+    // they are needed otherwise.
+    pub decorations: Option<&'a Decorations>,
+    // Whether presets show people under the names the mailmap gives them,
+    // as log.mailmap does. `%aN`, `%aE` and `%aL` always do.
+    pub use_mailmap: bool,
+    // The mailmap to use, read from the repository when it is needed
+    // otherwise.
+    pub mailmap: Option<&'a Mailmap>,
+}
+
+impl<'a> PrettyOptions<'a> {
+    pub fn new(format: PrettyFormat) -> Self {
+        Self {
+            format,
+            ..Self::default()
+        }
+    }
+}
+
+// Formats commits one after the other the way `git log` shows them.
+pub struct LogFormatter<'a> {
+    repo: &'a Repository,
+    options: PrettyOptions<'a>,
+    abbrev: usize,
+    decorations: OnceCell<Decorations>,
+    mailmap: OnceCell<Mailmap>,
+    shown_one: bool,
+}
+
+impl<'a> LogFormatter<'a> {
+    pub fn new(repo: &'a Repository, options: PrettyOptions<'a>) -> Result<Self> {
+        let abbrev = match options.abbrev {
+            Some(abbrev) => abbrev,
+            None => repo.abbrev_len()?,
+        };
+        Ok(Self {
+            repo,
+            options,
+            abbrev,
+            decorations: OnceCell::new(),
+            mailmap: OnceCell::new(),
+            shown_one: false,
+        })
+    }
+
+    // The next commit as `git log` shows it, with the newline that ends it
+    // or that separates it from the one before.
+    pub fn format(&mut self, oid: &Oid, commit: &Commit) -> Result<String> {
+        let mut out = String::new();
+        let terminated = self.options.format.is_terminated();
+        if self.shown_one && !terminated {
+            out.push('\n');
+        }
+        self.shown_one = true;
+        out.push_str(&self.format_entry(oid, commit)?);
+        let empty = matches!(&self.options.format, PrettyFormat::Format { template, .. } if template.is_empty());
+        if terminated && !empty {
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    // A commit as `git log` shows it, without anything between it and the
+    // others.
+    pub fn format_entry(&self, oid: &Oid, commit: &Commit) -> Result<String> {
+        let mut out = Vec::new();
+        match &self.options.format {
+            PrettyFormat::Format { template, .. } => {
+                return Expansion::new(self, oid, commit).expand(template);
+            }
+            PrettyFormat::Email => {
+                out.extend_from_slice(
+                    format!("From {} Mon Sep 17 00:00:00 2001\n", oid).as_bytes(),
+                );
+            }
+            format => {
+                let oneline = *format == PrettyFormat::Oneline;
+                out.extend_from_slice(self.paint(COLOR_COMMIT).as_bytes());
+                if !oneline {
+                    out.extend_from_slice(b"commit ");
+                }
+                let id = if self.options.abbrev_commit {
+                    self.repo.abbreviate(oid, self.abbrev)?
+                } else {
+                    oid.hex()
+                };
+                out.extend_from_slice(id.as_bytes());
+                out.extend_from_slice(self.paint(COLOR_RESET).as_bytes());
+                if self.options.decorate {
+                    let decorations =
+                        self.decorations()?
+                            .format(oid, self.options.color, " (", ", ", ")");
+                    out.extend_from_slice(decorations.as_bytes());
+                }
+                out.push(if oneline { b' ' } else { b'\n' });
+            }
+        }
+        self.pretty_print(commit, &mut out)?;
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.options.color {
+            code
+        } else {
+            ""
+        }
+    }
+
+    fn decorations(&self) -> Result<&Decorations> {
+        if let Some(decorations) = self.options.decorations {
+            return Ok(decorations);
+        }
+        if self.decorations.get().is_none() {
+            let _ = self.decorations.set(self.repo.decorations(false)?);
+        }
+        Ok(self.decorations.get().unwrap())
+    }
+
+    fn mailmap(&self) -> Result<&Mailmap> {
+        if let Some(mailmap) = self.options.mailmap {
+            return Ok(mailmap);
+        }
+        if self.mailmap.get().is_none() {
+            let _ = self.mailmap.set(self.repo.mailmap()?);
+        }
+        Ok(self.mailmap.get().unwrap())
+    }
+
+    // The part of a preset after the line naming the commit.
+    fn pretty_print(&self, commit: &Commit, out: &mut Vec<u8>) -> Result<()> {
+        let format = &self.options.format;
+        let oneline = *format == PrettyFormat::Oneline;
+        let mail = *format == PrettyFormat::Email;
+        let buffer = reencode(commit);
+        let start = out.len();
+        let mut rest = self.pp_header(commit, buffer.as_bytes(), out)?;
+        // Mails say when their text is not ASCII.
+        let need_8bit = mail && !rest.is_ascii();
+        if !oneline && !mail {
+            out.push(b'\n');
+        }
+        rest = &rest[skip_blank_lines(rest)..];
+        if oneline || mail {
+            rest = self.pp_title_line(rest, need_8bit, out);
+        }
+        let body_start = out.len();
+        if !oneline {
+            let indent = if mail { 0 } else { 4 };
+            let tab_width = if format.expands_tabs() { 8 } else { 0 };
+            pp_remainder(rest, indent, tab_width, *format == PrettyFormat::Short, out);
+        }
+        while out.len() > start && is_space(out[out.len() - 1]) {
+            out.pop();
+        }
+        if !oneline {
+            out.push(b'\n');
+        }
+        // Keep the blank line between the headers and the body of a mail.
+        if mail && out.len() <= body_start {
+            out.push(b'\n');
+        }
+        Ok(())
+    }
+
+    // Shows the headers of `buffer` as the preset does and returns the
+    // message after them.
+    fn pp_header<'b>(
+        &self,
+        commit: &Commit,
+        buffer: &'b [u8],
+        out: &mut Vec<u8>,
+    ) -> Result<&'b [u8]> {
+        let format = &self.options.format;
+        let mut rest = buffer;
+        let mut parents_shown = false;
+        loop {
+            let len = line_len(rest);
+            let line = &rest[..len];
+            rest = &rest[len..];
+            if len <= 1 {
+                return Ok(rest);
+            }
+            if *format == PrettyFormat::Raw {
+                out.extend_from_slice(line);
+                continue;
+            }
+            if line.starts_with(b"parent ") {
+                continue;
+            }
+            if !parents_shown {
+                self.add_merge_info(commit, out)?;
+                parents_shown = true;
+            }
+            if let Some(identity) = line.strip_prefix(b"author ") {
+                self.pp_user_info("Author", identity, out)?;
+            }
+            if let Some(identity) = line.strip_prefix(b"committer ") {
+                if matches!(format, PrettyFormat::Full | PrettyFormat::Fuller) {
+                    self.pp_user_info("Commit", identity, out)?;
+                }
+            }
+        }
+    }
+
+    fn add_merge_info(&self, commit: &Commit, out: &mut Vec<u8>) -> Result<()> {
+        let format = &self.options.format;
+        if matches!(format, PrettyFormat::Oneline | PrettyFormat::Email)
+            || commit.parents().len() < 2
+        {
+            return Ok(());
+        }
+        out.extend_from_slice(b"Merge:");
+        for parent in commit.parents() {
+            out.push(b' ');
+            out.extend_from_slice(self.repo.abbreviate(parent, self.abbrev)?.as_bytes());
+        }
+        out.push(b'\n');
+        Ok(())
+    }
+
+    fn pp_user_info(&self, what: &str, line: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let format = &self.options.format;
+        if *format == PrettyFormat::Oneline {
+            return Ok(());
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let identity = Identity::parse_lenient(line);
+        if !identity.has_email() {
+            return Ok(());
+        }
+        let (name, email) = if self.options.use_mailmap {
+            self.mailmap()?.map(identity.name(), identity.email())
+        } else {
+            (identity.name(), identity.email())
+        };
+        if *format == PrettyFormat::Email {
+            out.extend_from_slice(b"From: ");
+            let mut max_length = 78;
+            if needs_rfc2047_encoding(name) {
+                add_rfc2047(out, name, true);
+                max_length = 76;
+            } else if needs_rfc822_quoting(name) {
+                add_wrapped_text(out, &rfc822_quoted(name), -6, 1, max_length as isize);
+            } else {
+                add_wrapped_text(out, name, -6, 1, max_length as isize);
+            }
+            if max_length < last_line_length(out) + 2 + email.len() + 1 {
+                out.push(b'\n');
+            }
+            out.extend_from_slice(b" <");
+            out.extend_from_slice(email);
+            out.extend_from_slice(b">\n");
+        } else {
+            out.extend_from_slice(what.as_bytes());
+            out.extend_from_slice(b": ");
+            if *format == PrettyFormat::Fuller {
+                out.extend_from_slice(b"    ");
+            }
+            out.extend_from_slice(name);
+            out.extend_from_slice(b" <");
+            out.extend_from_slice(email);
+            out.extend_from_slice(b">\n");
+        }
+        let date = match format {
+            PrettyFormat::Medium => {
+                format!("Date:   {}\n", identity.format_date(self.options.date_mode))
+            }
+            PrettyFormat::Email => format!(
+                "Date: {}\n",
+                identity.format_date(DateMode::new(DateFormat::Rfc2822))
+            ),
+            PrettyFormat::Fuller => format!(
+                "{}Date: {}\n",
+                what,
+                identity.format_date(self.options.date_mode)
+            ),
+            _ => String::new(),
+        };
+        out.extend_from_slice(date.as_bytes());
+        Ok(())
+    }
+
+    // Shows the subject on a line of its own and returns what follows it.
+    fn pp_title_line<'b>(&self, message: &'b [u8], need_8bit: bool, out: &mut Vec<u8>) -> &'b [u8] {
+        let (title, len) = format_subject(message, b" ");
+        if self.options.format == PrettyFormat::Email {
+            out.extend_from_slice(b"Subject: [PATCH] ");
+            if needs_rfc2047_encoding(&title) {
+                add_rfc2047(out, &title, false);
+            } else {
+                let indent = -(last_line_length(out) as isize);
+                add_wrapped_text(out, &title, indent, 1, 78);
+            }
+        } else {
+            out.extend_from_slice(&title);
+        }
+        out.push(b'\n');
+        if need_8bit {
+            out.extend_from_slice(
+                b"MIME-Version: 1.0\n\
+                  Content-Type: text/plain; charset=UTF-8\n\
+                  Content-Transfer-Encoding: 8bit\n",
+            );
+        }
+        if self.options.format == PrettyFormat::Email {
+            out.push(b'\n');
+        }
+        &message[len..]
+    }
+}
+
+// The commit as text in UTF-8, which is what is shown. The `encoding`
+// header goes once the text has been converted from it.
+fn reencode(commit: &Commit) -> String {
+    let bytes = commit.to_bytes();
+    match commit.encoding().and_then(Encoding::from_name) {
+        Some(encoding) if encoding != Encoding::Utf8 => {
+            let text = encoding.decode(&bytes);
+            let headers_end = text.find("\n\n").map_or(text.len(), |i| i + 1);
+            match text[..headers_end].find("\nencoding ") {
+                Some(i) => {
+                    let start = i + 1;
+                    let end = start + line_len(&text.as_bytes()[start..]);
+                    format!("{}{}", &text[..start], &text[end..])
+                }
+                None => text,
+            }
+        }
+        _ => String::from_utf8_lossy(&bytes).into_owned(),
+    }
+}
+
+// The length of the first line of `text` with its newline.
+fn line_len(text: &[u8]) -> usize {
+    text.iter()
+        .position(|&c| c == b'\n')
+        .map_or(text.len(), |i| i + 1)
+}
+
+// `line` without the white space it ends with.
+fn trim_line(line: &[u8]) -> &[u8] {
+    let len = line
+        .iter()
+        .rposition(|&c| !is_space(c))
+        .map_or(0, |i| i + 1);
+    &line[..len]
+}
+
+// Where the first line of `text` that is not blank starts.
+fn skip_blank_lines(text: &[u8]) -> usize {
+    let mut pos = 0;
+    loop {
+        let len = line_len(&text[pos..]);
+        if len == 0 || !trim_line(&text[pos..pos + len]).is_empty() {
+            return pos;
+        }
+        pos += len;
+    }
+}
+
+// The lines of `message` up to the first blank one joined by `separator`,
+// and the length of what they took up with the blank line.
+fn format_subject(message: &[u8], separator: &[u8]) -> (Vec<u8>, usize) {
+    let mut subject = Vec::new();
+    let mut pos = 0;
+    loop {
+        let len = line_len(&message[pos..]);
+        let line = trim_line(&message[pos..pos + len]);
+        pos += len;
+        if len == 0 || line.is_empty() {
+            return (subject, pos);
+        }
+        if !subject.is_empty() {
+            subject.extend_from_slice(separator);
+        }
+        subject.extend_from_slice(line);
+    }
+}
+
+// The subject made fit for a file name: runs of other characters than
+// letters, digits, `.` and `_` become a dash.
+fn format_sanitized_subject(subject: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    let mut space = 2;
+    let mut i = 0;
+    while i < subject.len() {
+        let c = subject[i];
+        if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' {
+            if space == 1 {
+                out.push(b'-');
+            }
+            space = 0;
+            out.push(c);
+            if c == b'.' {
+                while subject.get(i + 1) == Some(&b'.') {
+                    i += 1;
+                }
+            }
+        } else {
+            space |= 1;
+        }
+        i += 1;
+    }
+    while out.len() > start && matches!(out.last(), Some(b'.' | b'-')) {
+        out.pop();
+    }
+}
+
+// The message from the first line that is not blank, indented and with
+// trailing white space dropped. The short preset stops at the first blank
+// line.
+fn pp_remainder(message: &[u8], indent: usize, tab_width: usize, short: bool, out: &mut Vec<u8>) {
+    let mut rest = message;
+    let mut first = true;
+    loop {
+        let len = line_len(rest);
+        if len == 0 {
+            return;
+        }
+        let line = trim_line(&rest[..len]);
+        rest = &rest[len..];
+        if line.is_empty() {
+            if first {
+                continue;
+            }
+            if short {
+                return;
+            }
+        }
+        first = false;
+        out.resize(out.len() + indent, b' ');
+        if tab_width > 0 {
+            add_tab_expanded(line, tab_width, out);
+        } else {
+            out.extend_from_slice(line);
+        }
+        out.push(b'\n');
+    }
+}
+
+// `line` with each tab turned into the spaces up to the next tab stop,
+// counting from the one before.
+fn add_tab_expanded(line: &[u8], tab_width: usize, out: &mut Vec<u8>) {
+    let mut rest = line;
+    while let Some(tab) = rest.iter().position(|&c| c == b'\t') {
+        let width = display_width(&rest[..tab]);
+        out.extend_from_slice(&rest[..tab]);
+        out.resize(out.len() + tab_width - width % tab_width, b' ');
+        rest = &rest[tab + 1..];
+    }
+    out.extend_from_slice(rest);
+}
+
+fn last_line_length(text: &[u8]) -> usize {
+    text.len() - text.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1)
+}
+
+fn needs_rfc2047_encoding(text: &[u8]) -> bool {
+    text.iter().any(|&c| !c.is_ascii() || c == b'\n') || text.windows(2).any(|pair| pair == b"=?")
+}
+
+// Whether `c` must be encoded in a header, where addresses allow fewer
+// characters as they are than subjects.
+fn is_rfc2047_special(c: u8, address: bool) -> bool {
+    if !c.is_ascii() || !(c.is_ascii_graphic() || c == b' ') {
+        return true;
+    }
+    if is_space(c) || c == b'=' || c == b'?' || c == b'_' {
+        return true;
+    }
+    address && !(c.is_ascii_alphanumeric() || b"!*+-/".contains(&c))
+}
+
+// Adds `text` as the encoded words of RFC 2047, on lines of at most 76
+// characters.
+fn add_rfc2047(out: &mut Vec<u8>, text: &[u8], address: bool) {
+    const MAX_ENCODED_LENGTH: usize = 76;
+    const START: &[u8] = b"=?UTF-8?q?";
+    let mut line_len = last_line_length(out) + START.len();
+    out.extend_from_slice(START);
+    let mut rest = text;
+    while !rest.is_empty() {
+        // Characters are not split across encoded words.
+        let len = next_char(rest).map_or(1, |(_, len)| len);
+        let special = len > 1 || is_rfc2047_special(rest[0], address);
+        let encoded_len = if special { 3 * len } else { 1 };
+        if line_len + encoded_len + 2 > MAX_ENCODED_LENGTH {
+            out.extend_from_slice(b"?=\n ");
+            out.extend_from_slice(START);
+            line_len = START.len() + 1;
+        }
+        for &c in &rest[..len] {
+            if special {
+                out.extend_from_slice(format!("={:02X}", c).as_bytes());
+            } else {
+                out.push(c);
+            }
+        }
+        line_len += encoded_len;
+        rest = &rest[len..];
+    }
+    out.extend_from_slice(b"?=");
+}
+
+fn needs_rfc822_quoting(text: &[u8]) -> bool {
+    text.iter().any(|c| b"()<>[]:;@,.\"\\".contains(c))
+}
+
+fn rfc822_quoted(text: &[u8]) -> Vec<u8> {
+    let mut quoted = vec![b'"'];
+    for &c in text {
+        if c == b'"' || c == b'\\' {
+            quoted.push(b'\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+// The number `text` starts with after any white space, and the rest, like
+// C's strtol(). Without digits the number is 0 and nothing is consumed.
+fn leading_number(text: &str) -> (i64, &str) {
+    let trimmed = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, unsigned) = match trimmed.as_bytes().first() {
+        Some(b'-') => (true, &trimmed[1..]),
+        Some(b'+') => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+    let digits = unsigned.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return (0, text);
+    }
+    let value = unsigned[..digits].parse::<i64>().unwrap_or(i64::MAX);
+    (if negative { -value } else { value }, &unsigned[digits..])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flush {
+    None,
+    Left,
+    Right,
+    Both,
+    LeftAndSteal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Truncate {
+    None,
+    Left,
+    Middle,
+    Right,
+}
+
+// What `%-x`, `%+x` and `% x` do around a placeholder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Magic {
+    None,
+    DeleteNewlineBeforeEmpty,
+    AddNewlineBeforeNonEmpty,
+    AddSpaceBeforeNonEmpty,
+}
+
+// The state of expanding a template for one commit.
+struct Expansion<'f, 'a> {
+    formatter: &'f LogFormatter<'a>,
+    oid: &'f Oid,
+    commit: &'f Commit,
+    buffer: String,
+    message_start: usize,
+    author: Option<Identity>,
+    committer: Option<Identity>,
+    // Whether placeholders color themselves, which `%C(auto)` turns on.
+    auto_color: bool,
+    // `%w()` rewraps what comes after it up to the next `%w()`.
+    wrap_start: usize,
+    width: usize,
+    indent1: usize,
+    indent2: usize,
+    // `%<()` and friends pad or truncate the placeholder after them.
+    padding: isize,
+    flush: Flush,
+    truncate: Truncate,
+}
+
+impl<'f, 'a> Expansion<'f, 'a> {
+    fn new(formatter: &'f LogFormatter<'a>, oid: &'f Oid, commit: &'f Commit) -> Self {
+        let buffer = reencode(commit);
+        let (mut author, mut committer) = (None, None);
+        let mut pos = 0;
+        loop {
+            let len = line_len(&buffer.as_bytes()[pos..]);
+            let line = buffer.as_bytes()[pos..pos + len].strip_suffix(b"\n");
+            match line {
+                None | Some(b"") => break,
+                Some(line) => {
+                    if let Some(identity) = line.strip_prefix(b"author ") {
+                        author = Some(Identity::parse_lenient(identity));
+                    } else if let Some(identity) = line.strip_prefix(b"committer ") {
+                        committer = Some(Identity::parse_lenient(identity));
+                    }
+                }
+            }
+            pos += len;
+        }
+        let message_start = (pos + 1).min(buffer.len());
+        Self {
+            formatter,
+            oid,
+            commit,
+            buffer,
+            message_start,
+            author,
+            committer,
+            auto_color: false,
+            wrap_start: 0,
+            width: 0,
+            indent1: 0,
+            indent2: 0,
+            padding: 0,
+            flush: Flush::None,
+            truncate: Truncate::None,
+        }
+    }
+
+    fn expand(mut self, template: &str) -> Result<String> {
+        let mut out = Vec::new();
+        let mut rest = template;
+        while let Some(percent) = rest.find('%') {
+            out.extend_from_slice(&rest.as_bytes()[..percent]);
+            rest = &rest[percent + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                out.push(b'%');
+                rest = after;
+                continue;
+            }
+            // Placeholders that are not known are shown as they are.
+            match self.item(&mut out, rest)? {
+                0 => out.push(b'%'),
+                consumed => rest = &rest[consumed..],
+            }
+        }
+        out.extend_from_slice(rest.as_bytes());
+        self.rewrap(&mut out, 0, 0, 0);
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn item(&mut self, out: &mut Vec<u8>, placeholder: &str) -> Result<usize> {
+        let magic = match placeholder.as_bytes().first() {
+            Some(b'-') => Magic::DeleteNewlineBeforeEmpty,
+            Some(b'+') => Magic::AddNewlineBeforeNonEmpty,
+            Some(b' ') => Magic::AddSpaceBeforeNonEmpty,
+            _ => Magic::None,
+        };
+        let placeholder = match magic {
+            Magic::None => placeholder,
+            // `%+w()` never expands to anything but changes what came
+            // before, so there is nothing sensible to do with it.
+            _ if placeholder[1..].starts_with('w') => return Ok(0),
+            _ => &placeholder[1..],
+        };
+        let orig_len = out.len();
+        let consumed = match self.flush {
+            Flush::None => self.one(out, placeholder)?,
+            _ => self.pad(out, placeholder)?,
+        };
+        match magic {
+            Magic::None => return Ok(consumed),
+            Magic::DeleteNewlineBeforeEmpty if out.len() == orig_len => {
+                while out.last() == Some(&b'\n') {
+                    out.pop();
+                }
+            }
+            Magic::AddNewlineBeforeNonEmpty if out.len() != orig_len => {
+                out.insert(orig_len, b'\n');
+            }
+            Magic::AddSpaceBeforeNonEmpty if out.len() != orig_len => {
+                out.insert(orig_len, b' ');
+            }
+            _ => {}
+        }
+        Ok(consumed + 1)
+    }
+
+    fn paint(&self, code: &'static str) -> &'static [u8] {
+        if self.auto_color {
+            code.as_bytes()
+        } else {
+            b""
+        }
+    }
+
+    // Expands the placeholder `placeholder` starts with and returns its
+    // length, or 0 if it is not one.
+    fn one(&mut self, out: &mut Vec<u8>, placeholder: &str) -> Result<usize> {
+        let bytes = placeholder.as_bytes();
+        let first = match bytes.first() {
+            Some(&first) => first,
+            None => return Ok(0),
+        };
+        let formatter = self.formatter;
+        let repo = formatter.repo;
+        match first {
+            b'n' => {
+                out.push(b'\n');
+                return Ok(1);
+            }
+            b'x' => {
+                let byte = placeholder
+                    .get(1..3)
+                    .filter(|hex| hex.bytes().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                return Ok(match byte {
+                    Some(byte) => {
+                        out.push(byte);
+                        3
+                    }
+                    None => 0,
+                });
+            }
+            b'C' => {
+                if placeholder[1..].starts_with("(auto)") {
+                    self.auto_color = formatter.options.color;
+                    if self.auto_color && !out.is_empty() {
+                        out.extend_from_slice(COLOR_RESET.as_bytes());
+                    }
+                    return Ok(7);
+                }
+                // An unknown color keeps an earlier %C(auto) going.
+                let consumed = self.color(out, placeholder)?;
+                if consumed > 0 {
+                    self.auto_color = false;
+                }
+                return Ok(consumed);
+            }
+            b'w' => return Ok(self.parse_wrap(out, placeholder)),
+            b'<' | b'>' => return Ok(self.parse_padding(placeholder)),
+            _ => {}
+        }
+
+        let commit = self.commit;
+        match first {
+            b'H' => {
+                out.extend_from_slice(self.paint(COLOR_COMMIT));
+                out.extend_from_slice(self.oid.hex().as_bytes());
+                out.extend_from_slice(self.paint(COLOR_RESET));
+                return Ok(1);
+            }
+            b'h' => {
+                out.extend_from_slice(self.paint(COLOR_COMMIT));
+                out.extend_from_slice(repo.abbreviate(self.oid, formatter.abbrev)?.as_bytes());
+                out.extend_from_slice(self.paint(COLOR_RESET));
+                return Ok(1);
+            }
+            b'T' => {
+                out.extend_from_slice(commit.tree().hex().as_bytes());
+                return Ok(1);
+            }
+            b't' => {
+                out.extend_from_slice(repo.abbreviate(commit.tree(), formatter.abbrev)?.as_bytes());
+                return Ok(1);
+            }
+            b'P' | b'p' => {
+                for (i, parent) in commit.parents().iter().enumerate() {
+                    if i > 0 {
+                        out.push(b' ');
+                    }
+                    let id = match first {
+                        b'P' => parent.hex(),
+                        _ => repo.abbreviate(parent, formatter.abbrev)?,
+                    };
+                    out.extend_from_slice(id.as_bytes());
+                }
+                return Ok(1);
+            }
+            // Without boundaries or symmetric ranges every commit is on the
+            // right.
+            b'm' => {
+                out.push(b'>');
+                return Ok(1);
+            }
+            b'd' | b'D' => {
+                let (prefix, suffix) = if first == b'd' { (" (", ")") } else { ("", "") };
+                let decorations = formatter.decorations()?.format(
+                    self.oid,
+                    self.auto_color,
+                    prefix,
+                    ", ",
+                    suffix,
+                );
+                out.extend_from_slice(decorations.as_bytes());
+                return Ok(1);
+            }
+            b'a' => return self.person(out, bytes.get(1).copied(), self.author.as_ref()),
+            b'c' => return self.person(out, bytes.get(1).copied(), self.committer.as_ref()),
+            b'e' => {
+                if let Some(encoding) = commit.encoding() {
+                    out.extend_from_slice(encoding);
+                }
+                return Ok(1);
+            }
+            _ => {}
+        }
+
+        let message = &self.buffer.as_bytes()[self.message_start..];
+        let subject = &message[skip_blank_lines(message)..];
+        match first {
+            b'B' => out.extend_from_slice(message),
+            b's' => out.extend_from_slice(&format_subject(subject, b" ").0),
+            b'f' => format_sanitized_subject(&subject[..line_len(subject)], out),
+            b'b' => {
+                let (_, len) = format_subject(subject, b" ");
+                let body = &subject[len..];
+                out.extend_from_slice(&body[skip_blank_lines(body)..]);
+            }
+            _ => return Ok(0),
+        }
+        Ok(1)
+    }
+
+    // `%an`, `%cd` and the like, given the letter after `a` or `c`.
+    fn person(
+        &self,
+        out: &mut Vec<u8>,
+        part: Option<u8>,
+        identity: Option<&Identity>,
+    ) -> Result<usize> {
+        let part = match part {
+            Some(part) => part,
+            None => return Ok(0),
+        };
+        // What is there to show of an identity that cannot be read.
+        let skip = || Ok(if b"netdDri".contains(&part) { 2 } else { 0 });
+        let identity = match identity.filter(|identity| identity.has_email()) {
+            Some(identity) => identity,
+            None => return skip(),
+        };
+        let (name, email) = match part {
+            b'N' | b'E' | b'L' => self
+                .formatter
+                .mailmap()?
+                .map(identity.name(), identity.email()),
+            _ => (identity.name(), identity.email()),
+        };
+        match part {
+            b'n' | b'N' => {
+                out.extend_from_slice(name);
+                return Ok(2);
+            }
+            b'e' | b'E' => {
+                out.extend_from_slice(email);
+                return Ok(2);
+            }
+            b'l' | b'L' => {
+                let local = email.split(|&c| c == b'@').next().unwrap_or(email);
+                out.extend_from_slice(local);
+                return Ok(2);
+            }
+            _ => {}
+        }
+        let date = match identity.date_bytes() {
+            Some(date) => date,
+            None => return skip(),
+        };
+        let mode = match part {
+            b't' => {
+                out.extend_from_slice(date);
+                return Ok(2);
+            }
+            b'd' => self.formatter.options.date_mode,
+            b'D' => DateMode::new(DateFormat::Rfc2822),
+            b'r' => DateMode::new(DateFormat::Relative),
+            b'i' => DateMode::new(DateFormat::Iso),
+            b'I' => DateMode::new(DateFormat::IsoStrict),
+            b'h' => DateMode::new(DateFormat::Human),
+            b's' => DateMode::new(DateFormat::Short),
+            _ => return skip(),
+        };
+        out.extend_from_slice(identity.format_date(mode).as_bytes());
+        Ok(2)
+    }
+
+    // `%C(<color>)`, with `auto,` to color only when coloring or `always,`
+    // to color regardless, and `%Cred`, `%Cgreen`, `%Cblue` and `%Creset`.
+    fn color(&self, out: &mut Vec<u8>, placeholder: &str) -> Result<usize> {
+        let color = self.formatter.options.color;
+        if let Some(spec) = placeholder[1..].strip_prefix('(') {
+            let end = match spec.find(')') {
+                Some(end) => end,
+                None => return Ok(0),
+            };
+            let consumed = end + 3;
+            let spec = &spec[..end];
+            let spec = if let Some(spec) = spec.strip_prefix("always,") {
+                spec
+            } else if !color {
+                return Ok(consumed);
+            } else {
+                spec.strip_prefix("auto,").unwrap_or(spec)
+            };
+            let code = parse_color(spec).ok_or_else(|| {
+                Error::InvalidFormat("unable to parse --pretty format".to_string())
+            })?;
+            out.extend_from_slice(code.as_bytes());
+            return Ok(consumed);
+        }
+        for (name, code) in BASIC_COLORS.iter() {
+            if placeholder[1..].starts_with(name) {
+                if color {
+                    out.extend_from_slice(code.as_bytes());
+                }
+                return Ok(1 + name.len());
+            }
+        }
+        Ok(0)
+    }
+
+    // `%w(<width>,<indent1>,<indent2>)`.
+    fn parse_wrap(&mut self, out: &mut Vec<u8>, placeholder: &str) -> usize {
+        let args = match placeholder[1..].strip_prefix('(') {
+            Some(args) => args,
+            None => return 0,
+        };
+        let end = match args.find(')') {
+            Some(end) => end,
+            None => return 0,
+        };
+        let mut numbers = [0; 3];
+        if end > 0 {
+            let mut rest = &args[..=end];
+            for (i, number) in numbers.iter_mut().enumerate() {
+                let (value, after) = leading_number(rest);
+                // Negative numbers wrap around to huge ones, as in git.
+                if !(0..=FORMATTING_LIMIT).contains(&value) {
+                    return 0;
+                }
+                *number = value as usize;
+                rest = after;
+                match rest.strip_prefix(',') {
+                    Some(after) if i < 2 => rest = after,
+                    _ => break,
+                }
+            }
+            if rest != ")" {
+                return 0;
+            }
+        }
+        self.rewrap(out, numbers[0], numbers[1], numbers[2]);
+        end + 3
+    }
+
+    // Wraps what came since the last `%w()` as that asked, and starts
+    // wrapping what follows with new settings.
+    fn rewrap(&mut self, out: &mut Vec<u8>, width: usize, indent1: usize, indent2: usize) {
+        if (self.width, self.indent1, self.indent2) == (width, indent1, indent2) {
+            return;
+        }
+        if self.wrap_start < out.len() {
+            let tail = out.split_off(self.wrap_start);
+            add_wrapped_text(
+                out,
+                &tail,
+                self.indent1 as isize,
+                self.indent2 as isize,
+                self.width as isize,
+            );
+        }
+        self.wrap_start = out.len();
+        self.width = width;
+        self.indent1 = indent1;
+        self.indent2 = indent2;
+    }
+
+    // `%<(N)`, `%>(N)`, `%><(N)` and `%>>(N)` pad the next placeholder to
+    // N columns on the right, left, both sides or left taking spaces from
+    // before it; `|` pads up to column N instead. A second argument of
+    // `trunc`, `ltrunc` or `mtrunc` cuts longer text.
+    fn parse_padding(&mut self, placeholder: &str) -> usize {
+        let mut rest = placeholder;
+        let flush = if let Some(after) = rest.strip_prefix('<') {
+            rest = after;
+            Flush::Right
+        } else if let Some(after) = rest.strip_prefix("><") {
+            rest = after;
+            Flush::Both
+        } else if let Some(after) = rest.strip_prefix(">>") {
+            rest = after;
+            Flush::LeftAndSteal
+        } else {
+            rest = &rest[1..];
+            Flush::Left
+        };
+        let to_column = match rest.strip_prefix('|') {
+            Some(after) => {
+                rest = after;
+                true
+            }
+            None => false,
+        };
+        let args = match rest.strip_prefix('(') {
+            Some(args) => args,
+            None => return 0,
+        };
+        let end = match args.find([',', ')']) {
+            Some(end) if end > 0 => end,
+            _ => return 0,
+        };
+        let (mut width, after) = leading_number(&args[..end]);
+        if after.len() == end || width == 0 || width > FORMATTING_LIMIT {
+            return 0;
+        }
+        if width < 0 {
+            if to_column {
+                width += terminal_columns() as i64;
+            }
+            if width < 0 {
+                return 0;
+            }
+        }
+        let width = width as isize;
+        let mut consumed = placeholder.len() - args.len() + end + 1;
+        let truncate = if args[end..].starts_with(',') {
+            let option = &args[end + 1..];
+            let truncate = if option.starts_with("trunc)") {
+                Truncate::Right
+            } else if option.starts_with("ltrunc)") {
+                Truncate::Left
+            } else if option.starts_with("mtrunc)") {
+                Truncate::Middle
+            } else {
+                return 0;
+            };
+            consumed += option.find(')').unwrap() + 1;
+            truncate
+        } else {
+            Truncate::None
+        };
+        self.padding = if to_column { -width } else { width };
+        self.flush = flush;
+        self.truncate = truncate;
+        consumed
+    }
+
+    // Expands the placeholder after a padding one, along with the colors
+    // right before it, and pads or truncates what it expands to.
+    fn pad(&mut self, out: &mut Vec<u8>, placeholder: &str) -> Result<usize> {
+        let mut padding = self.padding;
+        if padding < 0 {
+            let start = out.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+            padding = -padding - display_width(&out[start..]) as isize;
+        }
+        let mut local = Vec::new();
+        let mut total = 0;
+        let mut placeholder = placeholder;
+        loop {
+            let modifier = placeholder.starts_with('C');
+            let consumed = self.one(&mut local, placeholder)?;
+            total += consumed;
+            if !modifier {
+                break;
+            }
+            placeholder = &placeholder[consumed..];
+            match placeholder.strip_prefix('%') {
+                Some(after) => {
+                    placeholder = after;
+                    total += 1;
+                }
+                None => break,
+            }
+        }
+        let len = display_width(&local) as isize;
+
+        if self.flush == Flush::LeftAndSteal {
+            // Take back spaces that came before, keeping colors in place.
+            let mut ch = out.len() as isize - 1;
+            while len > padding && ch > 0 {
+                let c = out[ch as usize];
+                if c == b' ' {
+                    ch -= 1;
+                    padding += 1;
+                    continue;
+                }
+                if c != b'm' {
+                    break;
+                }
+                let mut p = ch - 1;
+                while p > 0 && ch - p < 10 && out[p as usize] != 0x1b {
+                    p -= 1;
+                }
+                if out[p as usize] != 0x1b
+                    || (ch + 1 - p) as usize != escape_len(&out[p as usize..])
+                {
+                    break;
+                }
+                local.splice(0..0, out[p as usize..=ch as usize].iter().copied());
+                ch = p - 1;
+            }
+            out.truncate((ch + 1) as usize);
+            self.flush = Flush::Left;
+        }
+
+        if len > padding {
+            let cut = len - (padding - 2);
+            match self.truncate {
+                Truncate::Left => replace_columns(&mut local, 0, cut, b".."),
+                Truncate::Middle => replace_columns(&mut local, padding / 2 - 1, cut, b".."),
+                Truncate::Right => replace_columns(&mut local, padding - 2, cut, b".."),
+                Truncate::None => {}
+            }
+            out.extend_from_slice(&local);
+        } else {
+            let offset = match self.flush {
+                Flush::Left => padding - len,
+                Flush::Both => (padding - len) / 2,
+                _ => 0,
+            } as usize;
+            let start = out.len();
+            out.resize(start + (padding - len) as usize + local.len(), b' ');
+            out[start + offset..start + offset + local.len()].copy_from_slice(&local);
+        }
+        self.flush = Flush::None;
+        Ok(total)
+    }
+}
+
+// The width of the terminal as $COLUMNS gives it.
+fn terminal_columns() -> isize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+impl Repository {
+    // The refs `git log` decorates commits with: HEAD, branches, remote
+    // branches, tags, the stash and replaced objects, or every ref once
+    // log.excludeDecoration or log.initialDecorationSet=all is set. Names
+    // are shortened unless `full` is set. Like git, a ref to an annotated
+    // tag decorates what the tag is peeled to as a tag, as well as the tag
+    // object.
+    pub fn decorations(&self, full: bool) -> Result<Decorations> {
+        let config = self.config()?;
+        let excluded = config.get_all("log.excludeDecoration");
+        let all = !excluded.is_empty() || config.get("log.initialDecorationSet") == Some("all");
+        let shown = |refname: &str| {
+            let default = DEFAULT_DECORATIONS.iter().any(|prefix| {
+                refname == *prefix || (prefix.ends_with('/') && refname.starts_with(prefix))
+            });
+            // Patterns without wildcards match whole components.
+            let is_excluded = excluded.iter().any(|pattern| {
+                if pattern.contains(|c| "*?[\\".contains(c)) {
+                    wildmatch(pattern.as_bytes(), refname.as_bytes(), 0)
+                } else {
+                    match refname.strip_prefix(pattern) {
+                        Some(rest) => {
+                            rest.is_empty() || rest.starts_with('/') || pattern.ends_with('/')
+                        }
+                        None => false,
+                    }
+                }
+            });
+            (all || default) && !is_excluded
+        };
+        let shorten = |refname: &str| {
+            if full {
+                refname.to_string()
+            } else {
+                short_refname(refname).to_string()
+            }
+        };
+
+        let mut decorations = Decorations::new();
+        let mut refs = self.refs().list("refs/")?;
+        if let Some(head) = self.head()? {
+            refs.push(("HEAD".to_string(), head));
+        }
+        for (refname, oid) in refs {
+            if !shown(&refname) {
+                continue;
+            }
+            if let Some(replaced) = refname.strip_prefix("refs/replace/") {
+                if let Some(replaced) = parse_oid(replaced) {
+                    decorations.add(replaced, "replaced".to_string(), DecorationKind::Replaced);
+                }
+                continue;
+            }
+            let name = shorten(&refname);
+            decorations.add(oid, name.clone(), DecorationKind::of(&refname));
+            let mut object = oid;
+            while let Some(tag) = self.odb().get(&object)?.into_tag() {
+                object = *tag.object();
+                decorations.add(object, name.clone(), DecorationKind::Tag);
+            }
+        }
+        decorations.set_head_branch(self.head_branch()?.map(|branch| shorten(&branch)));
+        Ok(decorations)
+    }
+
+    // Reads a `--pretty` argument, which may name a format of one's own
+    // set as pretty.<name>. Those cannot take the names of presets.
+    pub fn pretty_format(&self, arg: &str) -> Result<PrettyFormat> {
+        let config = self.config()?;
+        let mut aliases: Vec<(String, String)> = Vec::new();
+        for entry in config.entries() {
+            if entry.section != "pretty" || entry.subsection.is_some() {
+                continue;
+            }
+            if PRESETS.iter().any(|(name, _)| *name == entry.name) {
+                continue;
+            }
+            let value = entry.value.clone().unwrap_or_default();
+            match aliases.iter_mut().find(|(name, _)| *name == entry.name) {
+                Some(alias) => alias.1 = value,
+                None => aliases.push((entry.name.clone(), value)),
+            }
+        }
+        PrettyFormat::parse_with_aliases(arg, &aliases)
+    }
+}
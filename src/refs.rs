@@ -0,0 +1,283 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::commit::Identity;
+use crate::error::{Error, Result};
+use crate::oid::Oid;
+
+const MAX_SYMREF_DEPTH: usize = 5;
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    Direct(Oid),
+    Symbolic(String),
+}
+
+pub struct Refs {
+    gitdir: PathBuf,
+    commondir: PathBuf,
+}
+
+impl Reference {
+    fn parse(name: &str, content: &str) -> Result<Self> {
+        let content = content.trim_end();
+        if let Some(target) = content.strip_prefix("ref: ") {
+            return Ok(Reference::Symbolic(target.trim().to_string()));
+        }
+        parse_oid(content)
+            .map(Reference::Direct)
+            .ok_or_else(|| Error::InvalidRef(format!("{}: invalid content {:?}", name, content)))
+    }
+}
+
+impl Refs {
+    // `commondir` is where a linked worktree shares refs with the main
+    // one, and is `gitdir` itself everywhere else.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(gitdir: P, commondir: Q) -> Self {
+        Self {
+            gitdir: gitdir.as_ref().to_path_buf(),
+            commondir: commondir.as_ref().to_path_buf(),
+        }
+    }
+
+    // HEAD and the other pseudorefs, and a few namespaces, belong to each
+    // worktree; everything else lives in the common directory.
+    fn is_per_worktree(name: &str) -> bool {
+        !name.starts_with("refs/")
+            || name.starts_with("refs/bisect/")
+            || name.starts_with("refs/worktree/")
+            || name.starts_with("refs/rewritten/")
+    }
+
+    fn dir_for(&self, name: &str) -> &Path {
+        if Self::is_per_worktree(name) {
+            &self.gitdir
+        } else {
+            &self.commondir
+        }
+    }
+
+    fn loose_path(&self, name: &str) -> PathBuf {
+        self.dir_for(name).join(name)
+    }
+
+    pub fn read(&self, name: &str) -> Result<Option<Reference>> {
+        let path = self.loose_path(name);
+        // A directory of that name means the ref itself does not exist.
+        if !path.is_dir() {
+            match fs::read_to_string(&path) {
+                Ok(content) => return Reference::parse(name, &content).map(Some),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(self
+            .packed()?
+            .into_iter()
+            .find(|(packed_name, _)| packed_name == name)
+            .map(|(_, oid)| Reference::Direct(oid)))
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<Option<Oid>> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.read(&name)? {
+                Some(Reference::Direct(oid)) => return Ok(Some(oid)),
+                Some(Reference::Symbolic(target)) => name = target,
+                None => return Ok(None),
+            }
+        }
+        Err(Error::InvalidRef(format!("{}: symbolic ref loop", name)))
+    }
+
+    // Follows symbolic refs and returns the name of the final ref, even if
+    // it does not exist yet (an unborn branch).
+    pub fn resolve_name(&self, name: &str) -> Result<String> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.read(&name)? {
+                Some(Reference::Symbolic(target)) => name = target,
+                _ => return Ok(name),
+            }
+        }
+        Err(Error::InvalidRef(format!("{}: symbolic ref loop", name)))
+    }
+
+    // Writes a loose ref through `<name>.lock`, so that readers never see
+    // it half written and concurrent writers fail instead of racing.
+    pub fn write(&self, name: &str, reference: &Reference) -> Result<()> {
+        self.write_locked(name, reference, |_| Ok(()))
+    }
+
+    // Points `name` at `new` if it still points at `old`, where None means
+    // that it must not exist yet. The check is made while holding the lock,
+    // so a concurrent update makes this fail rather than get lost.
+    pub fn compare_and_swap(&self, name: &str, old: Option<&Oid>, new: &Oid) -> Result<()> {
+        self.write_locked(name, &Reference::Direct(*new), |refs| {
+            let current = match refs.read(name)? {
+                Some(Reference::Direct(oid)) => Some(oid),
+                Some(Reference::Symbolic(target)) => {
+                    return Err(Error::StaleRef(format!(
+                        "cannot lock ref '{}': it is a symbolic ref to '{}'",
+                        name, target
+                    )))
+                }
+                None => None,
+            };
+            match (current, old) {
+                (Some(current), Some(old)) if current == *old => Ok(()),
+                (None, None) => Ok(()),
+                (Some(current), Some(old)) => Err(Error::StaleRef(format!(
+                    "cannot lock ref '{}': is at {} but expected {}",
+                    name, current, old
+                ))),
+                (Some(_), None) => Err(Error::StaleRef(format!(
+                    "cannot lock ref '{}': reference already exists",
+                    name
+                ))),
+                (None, Some(_)) => Err(Error::StaleRef(format!(
+                    "cannot lock ref '{}': unable to resolve reference '{}'",
+                    name, name
+                ))),
+            }
+        })
+    }
+
+    // Takes the lock on `name`, runs `check` and writes the ref if that
+    // passes.
+    fn write_locked<F>(&self, name: &str, reference: &Reference, check: F) -> Result<()>
+    where
+        F: FnOnce(&Self) -> Result<()>,
+    {
+        let content = match reference {
+            Reference::Direct(oid) => format!("{}\n", oid),
+            Reference::Symbolic(target) => format!("ref: {}\n", target),
+        };
+        let path = self.loose_path(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lock_path = path.with_file_name(format!(
+            "{}.lock",
+            path.file_name().unwrap().to_string_lossy()
+        ));
+        let mut lock = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)?;
+        let result = check(self).and_then(|_| {
+            lock.write_all(content.as_bytes())
+                .and_then(|_| fs::rename(&lock_path, &path))
+                .map_err(Error::from)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&lock_path);
+        }
+        result
+    }
+
+    // Records a change of `name` from `old` to `new` in its reflog. A ref
+    // that did not or no longer exists is logged with the null id.
+    pub fn append_reflog(
+        &self,
+        name: &str,
+        old: Option<&Oid>,
+        new: Option<&Oid>,
+        committer: &Identity,
+        message: &str,
+    ) -> Result<()> {
+        let path = self.dir_for(name).join("logs").join(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let hex = |oid: Option<&Oid>| oid.map_or_else(|| NULL_OID.to_string(), Oid::hex);
+        let mut line = format!("{} {} ", hex(old), hex(new)).into_bytes();
+        line.extend_from_slice(&committer.to_bytes());
+        line.push(b'\t');
+        line.extend_from_slice(message.replace('\n', " ").trim_end().as_bytes());
+        line.push(b'\n');
+        let mut log = OpenOptions::new().append(true).create(true).open(path)?;
+        log.write_all(&line)?;
+        Ok(())
+    }
+
+    pub fn packed(&self) -> Result<Vec<(String, Oid)>> {
+        let content = match fs::read_to_string(self.commondir.join("packed-refs")) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut refs = Vec::new();
+        for line in content.lines() {
+            // Comments carry the header traits and `^` lines peel the
+            // preceding annotated tag.
+            if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(2, ' ');
+            let oid = fields.next().and_then(parse_oid);
+            let name = fields.next();
+            match (oid, name) {
+                (Some(oid), Some(name)) => refs.push((name.to_string(), oid)),
+                _ => {
+                    return Err(Error::InvalidRef(format!(
+                        "packed-refs: malformed line {:?}",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    pub fn list(&self, prefix: &str) -> Result<Vec<(String, Oid)>> {
+        let mut refs = Vec::new();
+        self.list_loose(&self.commondir.join("refs"), "refs", &mut refs)?;
+        if self.gitdir != self.commondir {
+            // Only this worktree's own refs count from either side.
+            let mut own = Vec::new();
+            self.list_loose(&self.gitdir.join("refs"), "refs", &mut own)?;
+            refs.retain(|(name, _)| !Self::is_per_worktree(name));
+            refs.extend(
+                own.into_iter()
+                    .filter(|(name, _)| Self::is_per_worktree(name)),
+            );
+        }
+        for (name, oid) in self.packed()? {
+            if !refs.iter().any(|(loose, _)| *loose == name) {
+                refs.push((name, oid));
+            }
+        }
+        refs.retain(|(name, _)| name.starts_with(prefix));
+        refs.sort();
+        Ok(refs)
+    }
+
+    fn list_loose(&self, dir: &Path, name: &str, refs: &mut Vec<(String, Oid)>) -> Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let child = format!("{}/{}", name, file_name.to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.list_loose(&entry.path(), &child, refs)?;
+            } else if let Some(oid) = self.resolve(&child)? {
+                refs.push((child, oid));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_oid(hex: &str) -> Option<Oid> {
+    if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(Oid::from_hex(hex))
+}
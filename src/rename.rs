@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use crate::oid::Oid;
+
+pub const MAX_SCORE: u32 = 100;
+pub const DEFAULT_RENAME_SCORE: u32 = 50;
+
+const MAX_CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub source: usize,
+    pub destination: usize,
+    pub score: u32,
+}
+
+pub struct Candidate<'a> {
+    pub id: Oid,
+    pub content: &'a [u8],
+}
+
+// Splits content into lines, cutting overly long lines into fixed-size
+// chunks, and counts how many bytes each distinct chunk accounts for.
+fn chunk_counts(content: &[u8]) -> HashMap<&[u8], usize> {
+    let mut counts = HashMap::new();
+    let mut start = 0;
+    for (i, &b) in content.iter().enumerate() {
+        if b == b'\n' || i + 1 - start == MAX_CHUNK_SIZE {
+            *counts.entry(&content[start..=i]).or_insert(0) += i + 1 - start;
+            start = i + 1;
+        }
+    }
+    if start < content.len() {
+        *counts.entry(&content[start..]).or_insert(0) += content.len() - start;
+    }
+    counts
+}
+
+pub fn similarity(source: &[u8], destination: &[u8]) -> u32 {
+    let max_size = std::cmp::max(source.len(), destination.len());
+    if max_size == 0 {
+        return MAX_SCORE;
+    }
+    let source_counts = chunk_counts(source);
+    let destination_counts = chunk_counts(destination);
+    let copied: usize = source_counts
+        .iter()
+        .map(|(chunk, count)| {
+            destination_counts
+                .get(chunk)
+                .map_or(0, |other| std::cmp::min(*count, *other))
+        })
+        .sum();
+    (copied as u64 * u64::from(MAX_SCORE) / max_size as u64) as u32
+}
+
+// Pairs deleted sources with added destinations. Exact matches by object id
+// are taken first, then the most similar remaining pairs above `min_score`.
+pub fn detect_renames(
+    sources: &[Candidate<'_>],
+    destinations: &[Candidate<'_>],
+    min_score: u32,
+) -> Vec<Rename> {
+    let mut renames = Vec::new();
+    let mut used_sources = vec![false; sources.len()];
+    let mut used_destinations = vec![false; destinations.len()];
+
+    for (d, destination) in destinations.iter().enumerate() {
+        if let Some(s) =
+            (0..sources.len()).find(|&s| !used_sources[s] && sources[s].id == destination.id)
+        {
+            used_sources[s] = true;
+            used_destinations[d] = true;
+            renames.push(Rename {
+                source: s,
+                destination: d,
+                score: MAX_SCORE,
+            });
+        }
+    }
+
+    let mut scored = Vec::new();
+    for (d, destination) in destinations.iter().enumerate() {
+        if used_destinations[d] {
+            continue;
+        }
+        for (s, source) in sources.iter().enumerate() {
+            if used_sources[s] {
+                continue;
+            }
+            let score = similarity(source.content, destination.content);
+            if score >= min_score {
+                scored.push(Rename {
+                    source: s,
+                    destination: d,
+                    score,
+                });
+            }
+        }
+    }
+    scored.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.destination.cmp(&b.destination))
+            .then(a.source.cmp(&b.source))
+    });
+    for rename in scored {
+        if used_sources[rename.source] || used_destinations[rename.destination] {
+            continue;
+        }
+        used_sources[rename.source] = true;
+        used_destinations[rename.destination] = true;
+        renames.push(rename);
+    }
+    renames.sort_by_key(|rename| rename.destination);
+    renames
+}
@@ -1,25 +1,561 @@
+use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::odb::Odb;
-use crate::error::Result;
+use chrono::Local;
 
-pub struct Repository {}
+use crate::attributes::AttrSource;
+use crate::commit::Identity;
+use crate::config::parse_bool_text;
+use crate::convert::{AutoCrlf, ConvertOptions, Converter, Eol, SafeCrlf};
+use crate::date::parse_date;
+use crate::error::{Error, Result};
+use crate::filter::FilterDriver;
+use crate::index::Index;
+use crate::odb::{Odb, StandardOdb};
+use crate::oid::Oid;
+use crate::refs::{parse_oid, Reference, Refs};
+use crate::stat::{CheckStat, StatOptions};
+use crate::tree::{read_tree, Tree, TreeEntry};
+
+const DEFAULT_ABBREV: usize = 7;
+const MINIMUM_ABBREV: usize = 4;
+const HEX_LEN: usize = 40;
+const DEFAULT_GC_AUTO: i64 = 6700;
+
+pub struct Repository {
+    gitdir: PathBuf,
+    commondir: PathBuf,
+    workdir: Option<PathBuf>,
+    odb: StandardOdb,
+    refs: Refs,
+}
 
 impl Repository {
     pub fn init<P: AsRef<Path>>(path: P) -> Result<Self> {
-        // ".git/objects"
-        // ".git/objects/pack"
-        // ".git/objects/info"
-        // ".git/HEAD"
-        // ".git/fooks"
-        // ".git/refs"
-        // ".git/refs/heads/"
-        // ".git/refs/tags"
-        unimplemented!()
+        let workdir = path.as_ref();
+        let gitdir = workdir.join(".git");
+        for dir in &[
+            "objects/pack",
+            "objects/info",
+            "hooks",
+            "info",
+            "refs/heads",
+            "refs/tags",
+        ] {
+            fs::create_dir_all(gitdir.join(dir))?;
+        }
+        let head = gitdir.join("HEAD");
+        if !head.exists() {
+            fs::write(head, "ref: refs/heads/master\n")?;
+        }
+        let config = gitdir.join("config");
+        if !config.exists() {
+            fs::write(
+                config,
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n\tlogallrefupdates = true\n",
+            )?;
+        }
+        Self::open(workdir)
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        unimplemented!()
+        let path = path.as_ref();
+        let dot_git = path.join(".git");
+        if dot_git.is_dir() {
+            return Self::from_parts(dot_git, Some(path.to_path_buf()));
+        }
+        if dot_git.is_file() {
+            // Worktrees and submodules point at their git directory.
+            let content = fs::read_to_string(&dot_git)?;
+            let gitdir = content
+                .trim_end()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| Error::NotARepository(path.to_path_buf()))?;
+            return Self::from_parts(path.join(gitdir), Some(path.to_path_buf()));
+        }
+        if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            return Self::from_parts(path.to_path_buf(), None);
+        }
+        Err(Error::NotARepository(path.to_path_buf()))
+    }
+
+    pub fn discover<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let start = path.as_ref().canonicalize()?;
+        let mut current = Some(start.as_path());
+        while let Some(dir) = current {
+            if let Ok(repo) = Self::open(dir) {
+                return Ok(repo);
+            }
+            current = dir.parent();
+        }
+        Err(Error::NotARepository(start.clone()))
+    }
+
+    fn from_parts(gitdir: PathBuf, workdir: Option<PathBuf>) -> Result<Self> {
+        // A linked worktree keeps its own HEAD and index, and names the
+        // repository it shares objects, refs and config with in
+        // `commondir`, relative to itself.
+        let commondir = match fs::read_to_string(gitdir.join("commondir")) {
+            Ok(content) => gitdir.join(content.trim_end_matches(&['\n', '\r'][..])),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => gitdir.clone(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            odb: StandardOdb::from_path(commondir.join("objects")),
+            refs: Refs::new(&gitdir, &commondir),
+            gitdir,
+            commondir,
+            workdir,
+        })
+    }
+
+    pub fn gitdir(&self) -> &Path {
+        &self.gitdir
+    }
+
+    // The directory shared by all worktrees, which is `gitdir` unless this
+    // is a linked worktree.
+    pub fn commondir(&self) -> &Path {
+        &self.commondir
+    }
+
+    pub fn workdir(&self) -> Option<&Path> {
+        self.workdir.as_deref()
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.workdir.is_none()
+    }
+
+    pub(crate) fn require_workdir(&self) -> Result<&Path> {
+        self.workdir()
+            .ok_or_else(|| Error::NotARepository(self.gitdir.clone()))
+    }
+
+    pub fn odb(&self) -> &StandardOdb {
+        &self.odb
+    }
+
+    pub fn refs(&self) -> &Refs {
+        &self.refs
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.gitdir.join("index")
+    }
+
+    pub fn index(&self) -> Result<Index> {
+        Index::open(self.index_path())
+    }
+
+    pub fn write_index(&self, index: &mut Index) -> Result<()> {
+        let workdir = self.require_workdir()?;
+        let converter = self.converter_for(AttrSource::CheckIn, index)?;
+        index.write_file(
+            self.index_path(),
+            workdir,
+            &self.stat_options()?,
+            &converter,
+        )
+    }
+
+    // core.trustctime, core.checkStat, core.fileMode and core.symlinks.
+    pub fn stat_options(&self) -> Result<StatOptions> {
+        let config = self.config()?;
+        let mut options = StatOptions::new();
+        if let Some(trust_ctime) = config.get_bool("core.trustctime")? {
+            options.trust_ctime = trust_ctime;
+        }
+        match config.get("core.checkstat") {
+            Some(value) if value.eq_ignore_ascii_case("default") => {
+                options.check_stat = CheckStat::Default;
+            }
+            Some(value) if value.eq_ignore_ascii_case("minimal") => {
+                options.check_stat = CheckStat::Minimal;
+            }
+            _ => {}
+        }
+        if let Some(trust_filemode) = config.get_bool("core.filemode")? {
+            options.trust_filemode = trust_filemode;
+        }
+        if let Some(has_symlinks) = config.get_bool("core.symlinks")? {
+            options.has_symlinks = has_symlinks;
+        }
+        Ok(options)
+    }
+
+    // core.autocrlf, core.eol and core.safecrlf.
+    pub fn convert_options(&self) -> Result<ConvertOptions> {
+        let config = self.config()?;
+        let mut options = ConvertOptions::new();
+        match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => {
+                options.autocrlf = AutoCrlf::Input;
+            }
+            _ => {
+                if let Some(autocrlf) = config.get_bool("core.autocrlf")? {
+                    options.autocrlf = if autocrlf {
+                        AutoCrlf::True
+                    } else {
+                        AutoCrlf::False
+                    };
+                }
+            }
+        }
+        // Values git does not know leave the default.
+        options.eol = match config.get("core.eol").map(str::to_ascii_lowercase) {
+            Some(ref value) if value == "lf" => Eol::Lf,
+            Some(ref value) if value == "crlf" => Eol::Crlf,
+            _ => Eol::Native,
+        };
+        if options.eol == Eol::Crlf && options.autocrlf == AutoCrlf::Input {
+            return Err(Error::InvalidConfig(
+                "core.autocrlf=input conflicts with core.eol=crlf".to_string(),
+            ));
+        }
+        match config.get("core.safecrlf") {
+            Some(value) if value.eq_ignore_ascii_case("warn") => {
+                options.safecrlf = SafeCrlf::Warn;
+            }
+            _ => {
+                if let Some(safecrlf) = config.get_bool("core.safecrlf")? {
+                    options.safecrlf = if safecrlf {
+                        SafeCrlf::Fail
+                    } else {
+                        SafeCrlf::False
+                    };
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    // The `filter.<driver>.*` drivers that are configured.
+    pub fn filter_drivers(&self) -> Result<Vec<FilterDriver>> {
+        let config = self.config()?;
+        let mut drivers = Vec::new();
+        for name in config.subsections("filter") {
+            let mut driver = FilterDriver::new(name);
+            let key = |variable: &str| format!("filter.{}.{}", name, variable);
+            driver.clean = config.get(&key("clean")).map(str::to_string);
+            driver.smudge = config.get(&key("smudge")).map(str::to_string);
+            driver.process = config.get(&key("process")).map(str::to_string);
+            driver.required = config.get_bool(&key("required"))?.unwrap_or(false);
+            drivers.push(driver);
+        }
+        Ok(drivers)
+    }
+
+    pub fn converter(&self, source: AttrSource) -> Result<Converter> {
+        self.converter_for(source, &self.index()?)
+    }
+
+    pub fn converter_for(&self, source: AttrSource, index: &Index) -> Result<Converter> {
+        let mut converter = Converter::with_attributes(
+            self.attributes_for(source, index)?,
+            self.convert_options()?,
+            Some(self.odb.clone()),
+        );
+        for driver in self.filter_drivers()? {
+            converter.add_driver(driver);
+        }
+        Ok(converter)
+    }
+
+    // core.abbrev: how many hex digits abbreviated object names have at
+    // least, or None when that depends on how many objects there are.
+    pub fn abbrev(&self) -> Result<Option<usize>> {
+        let config = self.config()?;
+        let entry = match config.get_entry("core.abbrev") {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let value = entry
+            .value
+            .as_deref()
+            .ok_or_else(|| entry.invalid("abbrev", Some("missing value")))?;
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(None);
+        }
+        if parse_bool_text(value) == Some(false) {
+            return Ok(Some(HEX_LEN));
+        }
+        match config.get_int("core.abbrev")? {
+            Some(len) if (MINIMUM_ABBREV as i64..=HEX_LEN as i64).contains(&len) => {
+                Ok(Some(len as usize))
+            }
+            _ => Err(entry.invalid("abbrev", Some("abbrev length out of range"))),
+        }
+    }
+
+    // The shortest name for `oid` that is at least core.abbrev digits long
+    // and that no other object starts with.
+    pub fn short_id(&self, oid: &Oid) -> Result<String> {
+        self.abbreviate(oid, self.abbrev_len()?)
+    }
+
+    // How many digits abbreviated names have at least: core.abbrev, or
+    // enough for names to rarely collide, as git does.
+    pub fn abbrev_len(&self) -> Result<usize> {
+        if let Some(len) = self.abbrev()? {
+            return Ok(len);
+        }
+        let mut count = 0;
+        for fanout in 0..=0xff {
+            count += self.odb.loose_objects(&format!("{:02x}", fanout))?.len();
+        }
+        let bits = (usize::BITS - count.leading_zeros()) as usize;
+        Ok(bits.div_ceil(2).max(DEFAULT_ABBREV))
+    }
+
+    // The shortest name for `oid` that is at least `len` digits long and
+    // that no other object starts with.
+    pub fn abbreviate(&self, oid: &Oid, len: usize) -> Result<String> {
+        let hex = oid.hex();
+        let mut len = len.max(MINIMUM_ABBREV);
+        for other in self.odb.loose_objects(&hex[..2])? {
+            if other != hex {
+                let common = hex
+                    .bytes()
+                    .zip(other.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                len = len.max(common + 1);
+            }
+        }
+        Ok(hex[..len.min(HEX_LEN)].to_string())
+    }
+
+    // Whether there are more loose objects than gc.auto allows, judging
+    // from the objects/17 directory like git does. A gc.auto of 0 or less
+    // turns this off.
+    pub fn needs_gc(&self) -> Result<bool> {
+        let threshold = self
+            .config()?
+            .get_int("gc.auto")?
+            .unwrap_or(DEFAULT_GC_AUTO);
+        if threshold <= 0 {
+            return Ok(false);
+        }
+        let loose = self.odb.loose_objects("17")?.len() as i64;
+        Ok(loose > (threshold + 255) / 256)
+    }
+
+    // Who authors commits: GIT_AUTHOR_NAME, GIT_AUTHOR_EMAIL and
+    // GIT_AUTHOR_DATE, or else author.* and user.* settings.
+    pub fn author_identity(&self) -> Result<Identity> {
+        self.identity("author")
+    }
+
+    // Who gets recorded in commits and reflogs, from the GIT_COMMITTER_*
+    // variables or committer.* and user.* settings.
+    pub fn committer_identity(&self) -> Result<Identity> {
+        self.identity("committer")
+    }
+
+    // What is not set is guessed from the user and host names, and the date
+    // is the current time. Like git, an empty name is refused.
+    fn identity(&self, role: &str) -> Result<Identity> {
+        let config = self.config()?;
+        let variable =
+            |field: &str| env::var(format!("GIT_{}_{}", role.to_ascii_uppercase(), field)).ok();
+        let setting = |field: &str| {
+            config
+                .get(&format!("{}.{}", role, field))
+                .or_else(|| config.get(&format!("user.{}", field)))
+                .map(str::to_string)
+        };
+        let user = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+        let name = variable("NAME")
+            .or_else(|| setting("name"))
+            .unwrap_or_else(|| user.clone());
+        let email = variable("EMAIL")
+            .or_else(|| setting("email"))
+            .or_else(|| env::var("EMAIL").ok())
+            .unwrap_or_else(|| {
+                let host = fs::read_to_string("/proc/sys/kernel/hostname")
+                    .or_else(|_| fs::read_to_string("/etc/hostname"))
+                    .unwrap_or_default();
+                match host.trim() {
+                    "" => format!("{}@localhost", user),
+                    host => format!("{}@{}", user, host),
+                }
+            });
+        if name.is_empty() {
+            return Err(Error::InvalidConfig(format!(
+                "empty ident name (for <{}>) not allowed",
+                email
+            )));
+        }
+        let datetime = match variable("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => {
+                let now = Local::now();
+                now.with_timezone(now.offset())
+            }
+        };
+        let identity = Identity::new(name.clone().into_bytes(), email.into_bytes(), datetime);
+        if identity.name().is_empty() {
+            return Err(Error::InvalidConfig(format!(
+                "name consists only of disallowed characters: {}",
+                name
+            )));
+        }
+        Ok(identity)
+    }
+
+    pub fn head(&self) -> Result<Option<Oid>> {
+        self.refs.resolve("HEAD")
+    }
+
+    // The branch HEAD points at, or None when HEAD is detached.
+    pub fn head_branch(&self) -> Result<Option<String>> {
+        match self.refs.read("HEAD")? {
+            Some(Reference::Symbolic(_)) => Ok(Some(self.refs.resolve_name("HEAD")?)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn head_tree(&self) -> Result<Option<Oid>> {
+        match self.head()? {
+            Some(oid) => Ok(Some(self.commit_tree(&oid)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn commit_tree(&self, oid: &Oid) -> Result<Oid> {
+        let commit = self
+            .odb
+            .get(oid)?
+            .into_commit()
+            .ok_or_else(|| Error::InvalidObject(format!("{} is not a commit", oid)))?;
+        Ok(*commit.tree())
+    }
+
+    pub fn tree(&self, oid: &Oid) -> Result<Tree> {
+        read_tree(&self.odb, oid)
+    }
+
+    // The object a revision names: a full object id, or a ref name found
+    // the way git finds it, so `main` is `refs/heads/main`. Annotated tags
+    // are peeled to the commit they tag.
+    pub fn resolve_revision(&self, rev: &str) -> Result<Option<Oid>> {
+        if let Some(oid) = parse_oid(rev) {
+            return Ok(Some(self.peel(&oid)?));
+        }
+        let names = [
+            rev.to_string(),
+            format!("refs/{}", rev),
+            format!("refs/tags/{}", rev),
+            format!("refs/heads/{}", rev),
+            format!("refs/remotes/{}", rev),
+            format!("refs/remotes/{}/HEAD", rev),
+        ];
+        for name in &names {
+            if let Some(oid) = self.refs.resolve(name)? {
+                return Ok(Some(self.peel(&oid)?));
+            }
+        }
+        Ok(None)
+    }
+
+    // The entry at `path` in the tree `oid`, looking through subtrees.
+    pub fn tree_entry(&self, oid: &Oid, path: &[u8]) -> Result<Option<TreeEntry>> {
+        let mut entry: Option<TreeEntry> = None;
+        for name in path.split(|&c| c == b'/').filter(|name| !name.is_empty()) {
+            let tree = match &entry {
+                None => self.tree(oid)?,
+                Some(entry) if entry.mode().is_tree() => self.tree(entry.oid())?,
+                Some(_) => return Ok(None),
+            };
+            match tree.get(name) {
+                Some(found) => entry = Some(found.clone()),
+                None => return Ok(None),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigScope;
+    use crate::odb::ObjectType;
+    use crate::testutil::{identity, TempDir};
+
+    // Lays out what `git worktree add --detach <dir> master` leaves behind.
+    fn add_worktree(main: &Repository, dir: &Path, head: &Oid) {
+        let gitdir = main.gitdir().join("worktrees/wt");
+        fs::create_dir_all(&gitdir).unwrap();
+        fs::write(gitdir.join("commondir"), "../..\n").unwrap();
+        fs::write(gitdir.join("HEAD"), format!("{}\n", head)).unwrap();
+        fs::write(
+            gitdir.join("gitdir"),
+            format!("{}\n", dir.join(".git").display()),
+        )
+        .unwrap();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(".git"), format!("gitdir: {}\n", gitdir.display())).unwrap();
+    }
+
+    #[test]
+    fn opens_linked_worktrees() {
+        let tmp = TempDir::new();
+        let main = Repository::init(tmp.path().join("main")).unwrap();
+        let tree = main.odb().write(ObjectType::Tree, b"").unwrap();
+        let first = main
+            .commit(
+                Some("HEAD"),
+                &identity(),
+                &identity(),
+                "first\n",
+                &tree,
+                &[],
+            )
+            .unwrap();
+        main.config_file(ConfigScope::Local)
+            .and_then(|mut file| {
+                file.set("user.name", "Shared")?;
+                file.save()
+            })
+            .unwrap();
+
+        let dir = tmp.path().join("wt");
+        add_worktree(&main, &dir, &first);
+        let wt = Repository::open(&dir).unwrap();
+        assert_eq!(wt.commondir(), main.gitdir().join("worktrees/wt/../.."));
+        assert_eq!(wt.head().unwrap(), Some(first));
+        assert!(wt.odb().get(&first).is_ok());
+        assert_eq!(wt.resolve_revision("master").unwrap(), Some(first));
+        assert_eq!(wt.config().unwrap().get("user.name"), Some("Shared"));
+        assert_eq!(wt.index_path(), main.gitdir().join("worktrees/wt/index"));
+
+        // Commits on the worktree's HEAD stay there; branches are shared.
+        let second = wt
+            .commit(
+                Some("HEAD"),
+                &identity(),
+                &identity(),
+                "second\n",
+                &tree,
+                &[first],
+            )
+            .unwrap();
+        assert_eq!(wt.head().unwrap(), Some(second));
+        assert_eq!(main.head().unwrap(), Some(first));
+        wt.refs()
+            .write("refs/heads/topic", &Reference::Direct(second))
+            .unwrap();
+        assert_eq!(main.resolve_revision("topic").unwrap(), Some(second));
+        let names: Vec<String> = wt
+            .refs()
+            .list("refs/")
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["refs/heads/master", "refs/heads/topic"]);
     }
 }
@@ -0,0 +1,128 @@
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::oid::Oid;
+
+#[derive(Debug, Clone)]
+pub struct ResolveUndoEntry {
+    pub path: Vec<u8>,
+    pub modes: [u32; 3],
+    pub ids: [Option<Oid>; 3],
+}
+
+#[derive(Debug)]
+pub struct ResolveUndo {
+    entries: BTreeMap<Vec<u8>, ResolveUndoEntry>,
+}
+
+impl ResolveUndoEntry {
+    pub fn new(path: Vec<u8>) -> Self {
+        Self {
+            path,
+            modes: [0; 3],
+            ids: [None; 3],
+        }
+    }
+
+    pub fn set_stage(&mut self, stage: u16, mode: u32, id: Oid) {
+        let i = stage as usize - 1;
+        self.modes[i] = mode;
+        self.ids[i] = Some(id);
+    }
+
+    fn read(reader: &mut dyn BufRead) -> Result<Self> {
+        let mut path = read_nul_terminated(reader)?;
+        path.pop();
+        let mut entry = Self::new(path);
+        for i in 0..3 {
+            let mut mode = read_nul_terminated(reader)?;
+            mode.pop();
+            entry.modes[i] = u32::from_str_radix(&String::from_utf8(mode)?, 8)?;
+        }
+        for i in 0..3 {
+            if entry.modes[i] != 0 {
+                entry.ids[i] = Some(Oid::from_reader(&mut *reader)?);
+            }
+        }
+        Ok(entry)
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_all(&self.path)?;
+        writer.write_all(&[0u8])?;
+        for mode in &self.modes {
+            write!(writer, "{:o}\0", mode)?;
+        }
+        for id in self.ids.iter().flatten() {
+            writer.write_all(id.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl ResolveUndo {
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, path: &[u8]) -> Option<&ResolveUndoEntry> {
+        self.entries.get(path)
+    }
+
+    pub fn insert(&mut self, entry: ResolveUndoEntry) {
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    pub fn remove(&mut self, path: &[u8]) -> Option<ResolveUndoEntry> {
+        self.entries.remove(path)
+    }
+
+    pub fn from_reader<B: BufRead>(mut reader: B) -> Result<Self> {
+        let mut resolve_undo = Self::new();
+        while !reader.fill_buf()?.is_empty() {
+            resolve_undo.insert(ResolveUndoEntry::read(&mut reader)?);
+        }
+        Ok(resolve_undo)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        for entry in self.entries.values() {
+            entry.write(&mut writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ResolveUndo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> IntoIterator for &'a ResolveUndo {
+    type Item = (&'a Vec<u8>, &'a ResolveUndoEntry);
+    type IntoIter = btree_map::Iter<'a, Vec<u8>, ResolveUndoEntry>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+fn read_nul_terminated(reader: &mut dyn BufRead) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.read_until(0x00, &mut buf)?;
+    if buf.last() != Some(&0x00) {
+        return Err(Error::InvalidIndex(
+            "corrupt resolve-undo extension".to_string(),
+        ));
+    }
+    Ok(buf)
+}
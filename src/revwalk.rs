@@ -0,0 +1,1089 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::error::{Error, Result};
+use crate::odb::Odb;
+use crate::oid::Oid;
+use crate::pathspec::{Magic, Pathspec};
+use crate::rename::{detect_renames, Candidate, DEFAULT_RENAME_SCORE};
+use crate::repo::Repository;
+use crate::tree::{flatten_tree, Tree, TreeEntry, TreeItem};
+
+const SEEN: u32 = 1;
+const ADDED: u32 = 1 << 1;
+const UNINTERESTING: u32 = 1 << 2;
+const BOTTOM: u32 = 1 << 3;
+const TREESAME: u32 = 1 << 4;
+const SHOWN: u32 = 1 << 5;
+const TMP_MARK: u32 = 1 << 6;
+
+// How many more commits limiting looks at once only uninteresting ones
+// are left, in case of clock skew.
+const SLOP: usize = 5;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+
+// Which commits a walk shows and how. With a pathspec, only commits that
+// change what it matches are shown, and history is simplified the way git
+// log does by default: at a merge that is TREESAME to a parent, having the
+// same content at the paths, only that parent is followed.
+#[derive(Debug, Clone, Default)]
+pub struct RevWalkOptions {
+    pub pathspec: Pathspec,
+    // Follows every parent of merges, showing the merges that are not
+    // TREESAME to all of them.
+    pub full_history: bool,
+    // Full history with parents rewritten, and merges that bring nothing
+    // in over their other parents left out.
+    pub simplify_merges: bool,
+    // Only commits that are both descendants of the hidden commits and
+    // ancestors of the tips.
+    pub ancestry_path: bool,
+    // Follows the one file in the pathspec back through renames and copies.
+    pub follow: bool,
+    // Shows no parent before all of its children.
+    pub topo_order: bool,
+    // Gives the nearest shown ancestors as parents, like --parents.
+    pub rewrite_parents: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkEntry {
+    pub oid: Oid,
+    pub parents: Vec<Oid>,
+}
+
+// A path whose content differs between two trees, None where a tree has
+// nothing there.
+#[derive(Debug, Clone)]
+struct PathChange {
+    path: Vec<u8>,
+    old: Option<TreeItem>,
+    new: Option<TreeItem>,
+}
+
+// A commit the walk has read. Its parents are rewritten as history is
+// simplified.
+struct Node {
+    tree: Oid,
+    time: i64,
+    parents: Vec<Oid>,
+    original_parents: Vec<Oid>,
+    // Whether a merge is TREESAME to each of its parents, kept when
+    // history is not simplified.
+    treesame: Option<Vec<bool>>,
+}
+
+struct Walker<'a> {
+    repo: &'a Repository,
+    pathspec: Pathspec,
+    follow: Option<Vec<u8>>,
+    prune: bool,
+    limited: bool,
+    topo_order: bool,
+    simplify_history: bool,
+    simplify_merges: bool,
+    ancestry_path: bool,
+    rewrite_parents: bool,
+    nodes: HashMap<Oid, Node>,
+    flags: HashMap<Oid, u32>,
+    commits: VecDeque<Oid>,
+}
+
+fn tree_item(entry: &TreeEntry) -> TreeItem {
+    TreeItem {
+        mode: entry.mode().as_u32(),
+        id: *entry.oid(),
+    }
+}
+
+// The files `pathspec` matches that differ between the trees `old` and `new`,
+// only the first of them if `quick`. Subtrees that are the same are not
+// read.
+fn diff_trees(
+    repo: &Repository,
+    old: Option<&Oid>,
+    new: Option<&Oid>,
+    pathspec: &Pathspec,
+    quick: bool,
+) -> Result<Vec<PathChange>> {
+    let mut changes = Vec::new();
+    diff_subtrees(
+        repo,
+        old,
+        new,
+        &mut Vec::new(),
+        pathspec,
+        quick,
+        &mut changes,
+    )?;
+    Ok(changes)
+}
+
+fn diff_subtrees(
+    repo: &Repository,
+    old: Option<&Oid>,
+    new: Option<&Oid>,
+    base: &mut Vec<u8>,
+    pathspec: &Pathspec,
+    quick: bool,
+    changes: &mut Vec<PathChange>,
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let read = |oid: Option<&Oid>| match oid {
+        Some(oid) => repo.tree(oid),
+        None => Ok(Tree::new()),
+    };
+    let (old_tree, new_tree) = (read(old)?, read(new)?);
+    let mut old_entries = old_tree.into_iter().peekable();
+    let mut new_entries = new_tree.into_iter().peekable();
+    loop {
+        let (name, old_entry, new_entry) = match (old_entries.peek(), new_entries.peek()) {
+            (None, None) => break,
+            (Some((old_name, _)), Some((new_name, _))) if old_name == new_name => {
+                let (name, old_entry) = old_entries.next().unwrap();
+                let (_, new_entry) = new_entries.next().unwrap();
+                (name, Some(old_entry), Some(new_entry))
+            }
+            (Some((old_name, _)), Some((new_name, _))) if old_name > new_name => {
+                let (name, new_entry) = new_entries.next().unwrap();
+                (name, None, Some(new_entry))
+            }
+            (Some(_), _) => {
+                let (name, old_entry) = old_entries.next().unwrap();
+                (name, Some(old_entry), None)
+            }
+            (None, Some(_)) => {
+                let (name, new_entry) = new_entries.next().unwrap();
+                (name, None, Some(new_entry))
+            }
+        };
+        if let (Some(old_entry), Some(new_entry)) = (old_entry, new_entry) {
+            if old_entry.oid() == new_entry.oid() && old_entry.mode().0 == new_entry.mode().0 {
+                continue;
+            }
+        }
+
+        let len = base.len();
+        if !base.is_empty() {
+            base.push(b'/');
+        }
+        base.extend_from_slice(&name.0);
+        let subtree = |entry: Option<&TreeEntry>| {
+            entry
+                .filter(|entry| entry.mode().is_tree())
+                .map(|entry| *entry.oid())
+        };
+        let (old_subtree, new_subtree) = (subtree(old_entry), subtree(new_entry));
+        if (old_subtree.is_some() || new_subtree.is_some()) && pathspec.may_match_dir(base) {
+            diff_subtrees(
+                repo,
+                old_subtree.as_ref(),
+                new_subtree.as_ref(),
+                base,
+                pathspec,
+                quick,
+                changes,
+            )?;
+        }
+        let file = |entry: Option<&TreeEntry>| {
+            entry.filter(|entry| !entry.mode().is_tree()).map(tree_item)
+        };
+        let (old_file, new_file) = (file(old_entry), file(new_entry));
+        if (old_file.is_some() || new_file.is_some()) && pathspec.matches(base, false)? {
+            changes.push(PathChange {
+                path: base.clone(),
+                old: old_file,
+                new: new_file,
+            });
+        }
+        base.truncate(len);
+        if quick && !changes.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Walker<'a> {
+    fn new(repo: &'a Repository, options: &'a RevWalkOptions) -> Result<Self> {
+        let follow = if options.follow {
+            match options.pathspec.items() {
+                [item]
+                    if !item.has_wildcard()
+                        && !item.magic().contains(Magic::EXCLUDE)
+                        && !item.magic().contains(Magic::ICASE)
+                        && item.attrs().is_empty() =>
+                {
+                    Some(
+                        item.pattern()
+                            .strip_suffix(b"/")
+                            .unwrap_or(item.pattern())
+                            .to_vec(),
+                    )
+                }
+                _ => {
+                    return Err(Error::InvalidRevWalk(
+                        "--follow requires exactly one pathspec".to_string(),
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        let simplify_merges = options.simplify_merges;
+        Ok(Self {
+            repo,
+            pathspec: options.pathspec.clone(),
+            // Commits cannot be pruned by paths that change along the way.
+            prune: !options.pathspec.is_empty() && follow.is_none(),
+            follow,
+            limited: options.topo_order || simplify_merges || options.ancestry_path,
+            topo_order: options.topo_order || simplify_merges,
+            simplify_history: !(options.full_history || simplify_merges || options.ancestry_path),
+            simplify_merges,
+            ancestry_path: options.ancestry_path,
+            rewrite_parents: options.rewrite_parents || simplify_merges,
+            nodes: HashMap::new(),
+            flags: HashMap::new(),
+            commits: VecDeque::new(),
+        })
+    }
+
+    fn flags(&self, oid: &Oid) -> u32 {
+        self.flags.get(oid).copied().unwrap_or(0)
+    }
+
+    fn has(&self, oid: &Oid, flag: u32) -> bool {
+        self.flags(oid) & flag != 0
+    }
+
+    fn set(&mut self, oid: &Oid, flag: u32) {
+        *self.flags.entry(*oid).or_insert(0) |= flag;
+    }
+
+    fn clear(&mut self, oid: &Oid, flag: u32) {
+        if let Some(flags) = self.flags.get_mut(oid) {
+            *flags &= !flag;
+        }
+    }
+
+    // Hidden commits given to the walk count along with the interesting
+    // ones when telling TREESAME and which parents to keep.
+    fn relevant(&self, oid: &Oid) -> bool {
+        self.flags(oid) & (UNINTERESTING | BOTTOM) != UNINTERESTING
+    }
+
+    fn load(&mut self, oid: &Oid) -> Result<&mut Node> {
+        match self.nodes.entry(*oid) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let commit = self
+                    .repo
+                    .odb()
+                    .get(oid)?
+                    .into_commit()
+                    .ok_or_else(|| Error::InvalidObject(format!("{} is not a commit", oid)))?;
+                Ok(entry.insert(Node {
+                    tree: *commit.tree(),
+                    time: commit.committer().time().unwrap_or(0),
+                    parents: commit.parents().to_vec(),
+                    original_parents: commit.parents().to_vec(),
+                    treesame: None,
+                }))
+            }
+        }
+    }
+
+    fn node(&self, oid: &Oid) -> &Node {
+        &self.nodes[oid]
+    }
+
+    fn node_mut(&mut self, oid: &Oid) -> &mut Node {
+        self.nodes.get_mut(oid).unwrap()
+    }
+
+    fn time(&self, oid: &Oid) -> i64 {
+        self.node(oid).time
+    }
+
+    // Whether the trees have the same content at the paths.
+    fn tree_same(&self, old: Option<&Oid>, new: &Oid) -> Result<bool> {
+        Ok(diff_trees(self.repo, old, Some(new), &self.pathspec, true)?.is_empty())
+    }
+
+    // Adds `oid` to the commits to walk after the ones that are as new.
+    fn insert_by_date(&mut self, oid: Oid) {
+        let time = self.time(&oid);
+        let at = self
+            .commits
+            .iter()
+            .position(|commit| self.time(commit) < time)
+            .unwrap_or(self.commits.len());
+        self.commits.insert(at, oid);
+    }
+
+    // Adds the commits in `queue` to those to walk, newest first.
+    fn merge_queue(&mut self, mut queue: Vec<Oid>) {
+        queue.sort_by_key(|commit| std::cmp::Reverse(self.time(commit)));
+        let mut at = 0;
+        for oid in queue {
+            let time = self.time(&oid);
+            while at < self.commits.len() && self.time(&self.commits[at]) >= time {
+                at += 1;
+            }
+            self.commits.insert(at, oid);
+            at += 1;
+        }
+    }
+
+    fn mark_parents_uninteresting(&mut self, oid: &Oid) {
+        let mut pending = self
+            .nodes
+            .get(oid)
+            .map_or_else(Vec::new, |node| node.parents.clone());
+        while let Some(oid) = pending.pop() {
+            if self.has(&oid, UNINTERESTING) {
+                continue;
+            }
+            self.set(&oid, UNINTERESTING);
+            // Only as far as commits already read; the rest are marked as
+            // they are reached.
+            if let Some(node) = self.nodes.get(&oid) {
+                pending.extend_from_slice(&node.parents);
+            }
+        }
+    }
+
+    // Simplifies the commit and adds its parents to the walk, or to
+    // `queue` when rewriting parents ahead of the walk.
+    fn process_parents(&mut self, oid: &Oid, mut queue: Option<&mut Vec<Oid>>) -> Result<()> {
+        if self.has(oid, ADDED) {
+            return Ok(());
+        }
+        self.set(oid, ADDED);
+        if self.has(oid, UNINTERESTING) {
+            for parent in self.load(oid)?.parents.clone() {
+                self.set(&parent, UNINTERESTING);
+                self.load(&parent)?;
+                self.mark_parents_uninteresting(&parent);
+                if !self.has(&parent, SEEN) {
+                    self.set(&parent, SEEN);
+                    match queue {
+                        Some(ref mut queue) => queue.push(parent),
+                        None => self.insert_by_date(parent),
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        self.try_to_simplify(oid)?;
+        for parent in self.node(oid).parents.clone() {
+            self.load(&parent)?;
+            if !self.has(&parent, SEEN) {
+                self.set(&parent, SEEN);
+                match queue {
+                    Some(ref mut queue) => queue.push(parent),
+                    None => self.insert_by_date(parent),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Marks the commit TREESAME if it changes nothing at the paths. When
+    // simplifying history, a commit TREESAME to one of its parents keeps
+    // only that one.
+    fn try_to_simplify(&mut self, oid: &Oid) -> Result<()> {
+        if !self.prune {
+            return Ok(());
+        }
+        let tree = self.load(oid)?.tree;
+        let parents = self.node(oid).parents.clone();
+        if parents.is_empty() {
+            if self.tree_same(None, &tree)? {
+                self.set(oid, TREESAME);
+            }
+            return Ok(());
+        }
+
+        let mut treesame = None;
+        let mut relevant_parents = 0;
+        let (mut relevant_change, mut irrelevant_change) = (false, false);
+        for (nth, parent) in parents.iter().enumerate() {
+            if self.relevant(parent) {
+                relevant_parents += 1;
+            }
+            if nth == 1 && !self.simplify_history && !self.has(oid, UNINTERESTING) {
+                let mut same = vec![false; parents.len()];
+                same[0] = !(relevant_change || irrelevant_change);
+                treesame = Some(same);
+            }
+            let parent_tree = self.load(parent)?.tree;
+            if self.tree_same(Some(&parent_tree), &tree)? {
+                if !self.simplify_history || !self.relevant(parent) {
+                    // A side branch bringing in everything is no reason to
+                    // lose the others.
+                    if let Some(same) = &mut treesame {
+                        same[nth] = true;
+                    }
+                    continue;
+                }
+                self.node_mut(oid).parents = vec![*parent];
+                self.set(oid, TREESAME);
+                return Ok(());
+            }
+            if self.relevant(parent) {
+                relevant_change = true;
+            } else {
+                irrelevant_change = true;
+            }
+        }
+        self.node_mut(oid).treesame = treesame;
+        // A merge with relevant parents is TREESAME to them alone, so
+        // merges from uninteresting branches do not make it a change.
+        let changed = if relevant_parents > 0 {
+            relevant_change
+        } else {
+            irrelevant_change
+        };
+        if !changed {
+            self.set(oid, TREESAME);
+        }
+        Ok(())
+    }
+
+    // Recomputes whether a merge is TREESAME after its parents changed.
+    fn update_treesame(&mut self, oid: &Oid) -> bool {
+        let node = self.node(oid);
+        if let (true, Some(same)) = (node.parents.len() > 1, &node.treesame) {
+            let mut relevant_parents = 0;
+            let (mut relevant_change, mut irrelevant_change) = (false, false);
+            for (parent, &same) in node.parents.iter().zip(same) {
+                if self.relevant(parent) {
+                    relevant_change |= !same;
+                    relevant_parents += 1;
+                } else {
+                    irrelevant_change |= !same;
+                }
+            }
+            let changed = if relevant_parents > 0 {
+                relevant_change
+            } else {
+                irrelevant_change
+            };
+            if changed {
+                self.clear(oid, TREESAME);
+            } else {
+                self.set(oid, TREESAME);
+            }
+        }
+        self.has(oid, TREESAME)
+    }
+
+    // Forgets whether the commit was TREESAME to its `nth` parent, which
+    // was just removed, returning what it was.
+    fn compact_treesame(&mut self, oid: &Oid, nth: usize) -> Result<bool> {
+        let node = self.node(oid);
+        if node.parents.is_empty() {
+            let old_same = self.has(oid, TREESAME);
+            let tree = node.tree;
+            if self.tree_same(None, &tree)? {
+                self.set(oid, TREESAME);
+            } else {
+                self.clear(oid, TREESAME);
+            }
+            return Ok(old_same);
+        }
+        let node = self.node_mut(oid);
+        let same = match node.treesame.as_mut() {
+            Some(same) if nth < same.len() => same,
+            _ => return Ok(false),
+        };
+        let old_same = same.remove(nth);
+        // A commit that is no longer a merge is TREESAME as its one
+        // parent says.
+        if same.len() == 1 {
+            let now_same = same[0];
+            node.treesame = None;
+            if now_same {
+                self.set(oid, TREESAME);
+            } else {
+                self.clear(oid, TREESAME);
+            }
+        }
+        Ok(old_same)
+    }
+
+    fn remove_duplicate_parents(&mut self, oid: &Oid) -> Result<usize> {
+        let has_treesame = self.node(oid).treesame.is_some();
+        let mut nth = 0;
+        while nth < self.node(oid).parents.len() {
+            let parents = &self.node(oid).parents;
+            if parents[..nth].contains(&parents[nth]) {
+                self.node_mut(oid).parents.remove(nth);
+                if has_treesame {
+                    self.compact_treesame(oid, nth)?;
+                }
+            } else {
+                nth += 1;
+            }
+        }
+        Ok(nth)
+    }
+
+    // The one parent to go through when the commit is TREESAME: its only
+    // parent, or the only relevant one of a merge.
+    fn one_relevant_parent(&self, oid: &Oid) -> Option<Oid> {
+        let parents = &self.node(oid).parents;
+        if parents.len() < 2 {
+            return parents.first().copied();
+        }
+        let mut relevant = None;
+        for parent in parents.iter().filter(|parent| self.relevant(parent)) {
+            if relevant.is_some() {
+                return None;
+            }
+            relevant = Some(*parent);
+        }
+        relevant
+    }
+
+    // The nearest ancestor through `oid` that is shown, or None when the
+    // line ends at a root that is not.
+    fn rewrite_one(&mut self, oid: Oid) -> Result<Option<Oid>> {
+        let mut queue = Vec::new();
+        let rewritten = self.rewrite_one_in(oid, &mut queue);
+        self.merge_queue(queue);
+        rewritten
+    }
+
+    fn rewrite_one_in(&mut self, mut oid: Oid, queue: &mut Vec<Oid>) -> Result<Option<Oid>> {
+        loop {
+            if !self.limited {
+                self.process_parents(&oid, Some(queue))?;
+            }
+            if self.has(&oid, UNINTERESTING) || !self.has(&oid, TREESAME) {
+                return Ok(Some(oid));
+            }
+            if self.load(&oid)?.parents.is_empty() {
+                return Ok(None);
+            }
+            match self.one_relevant_parent(&oid) {
+                Some(parent) => oid = parent,
+                None => return Ok(Some(oid)),
+            }
+        }
+    }
+
+    fn rewrite_parents(&mut self, oid: &Oid) -> Result<()> {
+        let mut nth = 0;
+        while nth < self.node(oid).parents.len() {
+            let parent = self.node(oid).parents[nth];
+            match self.rewrite_one(parent)? {
+                Some(rewritten) => {
+                    self.node_mut(oid).parents[nth] = rewritten;
+                    nth += 1;
+                }
+                None => {
+                    self.node_mut(oid).parents.remove(nth);
+                }
+            }
+        }
+        self.remove_duplicate_parents(oid)?;
+        Ok(())
+    }
+
+    // Whether the walk shows the commit. TREESAME commits are left out,
+    // but for merges that tie relevant lines together when parents are
+    // rewritten.
+    fn shows(&self, oid: &Oid) -> bool {
+        if self.has(oid, SHOWN | UNINTERESTING) {
+            return false;
+        }
+        if self.prune && self.has(oid, TREESAME) {
+            if !self.rewrite_parents {
+                return false;
+            }
+            let relevant = self
+                .node(oid)
+                .parents
+                .iter()
+                .filter(|parent| self.relevant(parent))
+                .count();
+            return relevant >= 2;
+        }
+        true
+    }
+
+    fn simplify_commit(&mut self, oid: &Oid) -> Result<bool> {
+        let shows = self.shows(oid);
+        if shows && self.prune && self.rewrite_parents {
+            self.rewrite_parents(oid)?;
+        }
+        Ok(shows)
+    }
+
+    // Whether limiting has to go on, counting down the slop once every
+    // commit left is uninteresting.
+    fn still_interesting(&self, date: i64, slop: usize) -> usize {
+        let first = match self.commits.front() {
+            Some(first) => first,
+            None => return 0,
+        };
+        if date <= self.time(first)
+            || self
+                .commits
+                .iter()
+                .any(|commit| !self.has(commit, UNINTERESTING))
+        {
+            return SLOP;
+        }
+        slop - 1
+    }
+
+    // Walks everything up front, for what needs the whole set of commits
+    // to show before showing any.
+    fn limit_list(&mut self) -> Result<()> {
+        let bottoms: Vec<Oid> = if self.ancestry_path {
+            self.commits
+                .iter()
+                .filter(|commit| self.has(commit, BOTTOM))
+                .copied()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if self.ancestry_path && bottoms.is_empty() {
+            return Err(Error::InvalidRevWalk(
+                "--ancestry-path given but there are no bottom commits".to_string(),
+            ));
+        }
+
+        let mut list = Vec::new();
+        let mut date = i64::MAX;
+        let mut slop = SLOP;
+        while let Some(oid) = self.commits.pop_front() {
+            self.process_parents(&oid, None)?;
+            if self.has(&oid, UNINTERESTING) {
+                self.mark_parents_uninteresting(&oid);
+                slop = self.still_interesting(date, slop);
+                if slop > 0 {
+                    continue;
+                }
+                break;
+            }
+            date = self.time(&oid);
+            list.push(oid);
+        }
+        if !bottoms.is_empty() {
+            self.limit_to_ancestry(&bottoms, &list);
+        }
+        // Parents that turned out uninteresting can make merges TREESAME.
+        if self.prune && !self.simplify_history {
+            for oid in &list {
+                if self.flags(oid) & (UNINTERESTING | TREESAME) == 0 {
+                    self.update_treesame(oid);
+                }
+            }
+        }
+        self.commits = list.into();
+        Ok(())
+    }
+
+    // Makes the commits in `list` that cannot reach any of `bottoms`
+    // uninteresting.
+    fn limit_to_ancestry(&mut self, bottoms: &[Oid], list: &[Oid]) {
+        for bottom in bottoms {
+            self.set(bottom, TMP_MARK);
+        }
+        // Oldest first, so parents are mostly marked before children.
+        loop {
+            let mut made_progress = false;
+            for oid in list.iter().rev() {
+                if self.has(oid, TMP_MARK | UNINTERESTING) {
+                    continue;
+                }
+                if self
+                    .node(oid)
+                    .parents
+                    .iter()
+                    .any(|parent| self.has(parent, TMP_MARK))
+                {
+                    self.set(oid, TMP_MARK);
+                    made_progress = true;
+                }
+            }
+            if !made_progress {
+                break;
+            }
+        }
+        for oid in list {
+            if !self.has(oid, TMP_MARK) {
+                self.set(oid, UNINTERESTING);
+            }
+        }
+        for oid in list.iter().chain(bottoms) {
+            self.clear(oid, TMP_MARK);
+        }
+    }
+
+    // Orders the commits so that none comes after its parents, keeping
+    // lines of history together.
+    fn sort_in_topological_order(&mut self) {
+        let list: Vec<Oid> = self.commits.drain(..).collect();
+        let mut indegree: HashMap<Oid, usize> = list.iter().map(|oid| (*oid, 1)).collect();
+        for oid in &list {
+            for parent in &self.node(oid).parents {
+                if let Some(count) = indegree.get_mut(parent).filter(|count| **count > 0) {
+                    *count += 1;
+                }
+            }
+        }
+        // The tips go out in the order the walk found them.
+        let mut stack: Vec<Oid> = list
+            .iter()
+            .rev()
+            .filter(|oid| indegree[oid] == 1)
+            .copied()
+            .collect();
+        while let Some(oid) = stack.pop() {
+            for parent in &self.node(&oid).parents {
+                if let Some(count) = indegree.get_mut(parent).filter(|count| **count > 0) {
+                    *count -= 1;
+                    // A parent goes out once all its children have.
+                    if *count == 1 {
+                        stack.push(*parent);
+                    }
+                }
+            }
+            indegree.insert(oid, 0);
+            self.commits.push_back(oid);
+        }
+    }
+
+    // Rewrites each commit's parents to what they simplify to, then
+    // drops the commits that simplify to another: those TREESAME to the
+    // one relevant parent left once parents that are ancestors of others,
+    // or TREESAME roots, are removed.
+    fn simplify_merges(&mut self) -> Result<()> {
+        if !self.prune {
+            return Ok(());
+        }
+        let mut simplified = HashMap::new();
+        let mut pending: Vec<Oid> = self.commits.iter().rev().copied().collect();
+        while !pending.is_empty() {
+            for oid in std::mem::take(&mut pending) {
+                self.simplify_one(&oid, &mut simplified, &mut pending)?;
+            }
+        }
+        self.commits.retain(|oid| simplified.get(oid) == Some(oid));
+        Ok(())
+    }
+
+    fn simplify_one(
+        &mut self,
+        oid: &Oid,
+        simplified: &mut HashMap<Oid, Oid>,
+        pending: &mut Vec<Oid>,
+    ) -> Result<()> {
+        if simplified.contains_key(oid) {
+            return Ok(());
+        }
+        if self.has(oid, UNINTERESTING) || self.load(oid)?.parents.is_empty() {
+            simplified.insert(*oid, *oid);
+            return Ok(());
+        }
+        // Parents go first.
+        let unsimplified: Vec<Oid> = self
+            .node(oid)
+            .parents
+            .iter()
+            .filter(|parent| !simplified.contains_key(parent))
+            .copied()
+            .collect();
+        if !unsimplified.is_empty() {
+            pending.extend(unsimplified);
+            pending.push(*oid);
+            return Ok(());
+        }
+
+        let parents = self
+            .node(oid)
+            .parents
+            .iter()
+            .map(|parent| simplified[parent])
+            .collect();
+        self.node_mut(oid).parents = parents;
+        let mut count = self.remove_duplicate_parents(oid)?;
+        if count > 1 {
+            let mut marked =
+                self.mark_redundant_parents(oid)? + self.mark_treesame_root_parents(oid)?;
+            if marked > 0 {
+                marked -= self.leave_one_treesame_to_parent(oid);
+            }
+            if marked > 0 {
+                count = self.remove_marked_parents(oid)?;
+            }
+        }
+
+        let parent = if count == 0 || self.has(oid, UNINTERESTING) || !self.has(oid, TREESAME) {
+            None
+        } else {
+            self.one_relevant_parent(oid)
+        };
+        let to = match parent {
+            Some(parent) => simplified.get(&parent).copied().unwrap_or(parent),
+            None => *oid,
+        };
+        simplified.insert(*oid, to);
+        Ok(())
+    }
+
+    // Marks the parents that are ancestors of other parents.
+    fn mark_redundant_parents(&mut self, oid: &Oid) -> Result<usize> {
+        let parents = self.node(oid).parents.clone();
+        let mut marked = 0;
+        for (nth, parent) in parents.iter().enumerate() {
+            let others: Vec<Oid> = parents
+                .iter()
+                .enumerate()
+                .filter(|&(other, head)| other != nth && head != parent)
+                .map(|(_, head)| *head)
+                .collect();
+            if self.reaches(&others, parent)? {
+                self.set(parent, TMP_MARK);
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    // Whether `target` is an ancestor of any of `from`, through parents as
+    // the walk has rewritten them.
+    fn reaches(&mut self, from: &[Oid], target: &Oid) -> Result<bool> {
+        let mut seen = HashSet::new();
+        let mut pending = from.to_vec();
+        while let Some(oid) = pending.pop() {
+            if !seen.insert(oid) {
+                continue;
+            }
+            for parent in &self.load(&oid)?.parents {
+                if parent == target {
+                    return Ok(true);
+                }
+                pending.push(*parent);
+            }
+        }
+        Ok(false)
+    }
+
+    // Marks the root parents that have nothing at the paths, merged in
+    // from independent histories.
+    fn mark_treesame_root_parents(&mut self, oid: &Oid) -> Result<usize> {
+        let mut marked = 0;
+        for parent in self.node(oid).parents.clone() {
+            if self.load(&parent)?.parents.is_empty() && self.has(&parent, TREESAME) {
+                self.set(&parent, TMP_MARK);
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    // Keeps the first parent the merge is TREESAME to, the one default
+    // simplification would follow, unless one that stays is.
+    fn leave_one_treesame_to_parent(&mut self, oid: &Oid) -> usize {
+        let node = self.node(oid);
+        let same = node.treesame.as_deref().unwrap_or(&[]);
+        let mut marked = None;
+        for (parent, _) in node.parents.iter().zip(same).filter(|(_, &same)| same) {
+            if !self.has(parent, TMP_MARK) {
+                return 0;
+            }
+            marked = marked.or(Some(*parent));
+        }
+        match marked {
+            Some(parent) => {
+                self.clear(&parent, TMP_MARK);
+                1
+            }
+            None => 0,
+        }
+    }
+
+    fn remove_marked_parents(&mut self, oid: &Oid) -> Result<usize> {
+        let mut nth = 0;
+        let mut removed = false;
+        while nth < self.node(oid).parents.len() {
+            let parent = self.node(oid).parents[nth];
+            if self.has(&parent, TMP_MARK) {
+                self.clear(&parent, TMP_MARK);
+                self.node_mut(oid).parents.remove(nth);
+                self.compact_treesame(oid, nth)?;
+                removed = true;
+            } else {
+                nth += 1;
+            }
+        }
+        // Removing parents can only make the commit TREESAME.
+        if removed && !self.has(oid, TREESAME) {
+            self.update_treesame(oid);
+        }
+        Ok(nth)
+    }
+
+    // Whether the commit changes the followed file, which becomes the
+    // file it was renamed or copied from when it is added here. Merges
+    // are not shown.
+    fn follows(&mut self, oid: &Oid) -> Result<bool> {
+        let path = match &self.follow {
+            Some(path) => path.clone(),
+            None => return Ok(true),
+        };
+        let node = self.node(oid);
+        let tree = node.tree;
+        let parent = match node.original_parents.as_slice() {
+            [] => None,
+            [parent] => Some(*parent),
+            _ => return Ok(false),
+        };
+        let parent_tree = match parent {
+            Some(parent) => Some(self.load(&parent)?.tree),
+            None => None,
+        };
+        let changes = diff_trees(
+            self.repo,
+            parent_tree.as_ref(),
+            Some(&tree),
+            &Pathspec::literal(&path),
+            false,
+        )?;
+        if changes.is_empty() {
+            return Ok(false);
+        }
+        let added = changes
+            .iter()
+            .find(|change| change.path == path && change.old.is_none());
+        if let (Some(added), Some(parent_tree)) = (added, parent_tree) {
+            if let Some(source) = self.rename_source(&parent_tree, added.new.unwrap())? {
+                self.follow = Some(source);
+            }
+        }
+        Ok(true)
+    }
+
+    // The file in the tree `parent` that `item` was renamed or copied
+    // from: one with the same content, preferably the same name, or else
+    // the most similar one.
+    fn rename_source(&self, parent: &Oid, item: TreeItem) -> Result<Option<Vec<u8>>> {
+        let path = self.follow.as_deref().unwrap_or_default();
+        let basename = |path: &[u8]| path.rsplit(|&c| c == b'/').next().map(<[u8]>::to_vec);
+        let files = flatten_tree(self.repo.odb(), parent)?;
+        let mut identical = files
+            .iter()
+            .filter(|(_, file)| file.id == item.id && file.mode & S_IFMT == item.mode & S_IFMT);
+        if let Some((source, _)) = identical
+            .clone()
+            .find(|(source, _)| basename(source) == basename(path))
+            .or_else(|| identical.next())
+        {
+            return Ok(Some(source.clone()));
+        }
+        if item.mode & S_IFMT != S_IFREG {
+            return Ok(None);
+        }
+
+        let sources: Vec<(&Vec<u8>, Oid, Vec<u8>)> = files
+            .iter()
+            .filter(|(_, file)| file.mode & S_IFMT == S_IFREG)
+            .map(|(source, file)| Ok((source, file.id, self.repo.read_blob_content(&file.id)?)))
+            .collect::<Result<_>>()?;
+        let content = self.repo.read_blob_content(&item.id)?;
+        let candidates: Vec<Candidate<'_>> = sources
+            .iter()
+            .map(|(_, id, content)| Candidate { id: *id, content })
+            .collect();
+        let destination = Candidate {
+            id: item.id,
+            content: &content,
+        };
+        let renames = detect_renames(&candidates, &[destination], DEFAULT_RENAME_SCORE);
+        Ok(renames
+            .first()
+            .map(|rename| sources[rename.source].0.clone()))
+    }
+
+    fn walk(&mut self, tips: &[Oid], hidden: &[Oid]) -> Result<Vec<WalkEntry>> {
+        for oid in hidden {
+            self.set(oid, UNINTERESTING | BOTTOM);
+        }
+        for oid in tips.iter().chain(hidden) {
+            self.load(oid)?;
+            if self.has(oid, UNINTERESTING) {
+                self.mark_parents_uninteresting(oid);
+                self.limited = true;
+            }
+            if !self.has(oid, SEEN) {
+                self.set(oid, SEEN);
+                self.commits.push_back(*oid);
+            }
+        }
+        let mut commits: Vec<Oid> = self.commits.drain(..).collect();
+        commits.sort_by_key(|oid| std::cmp::Reverse(self.time(oid)));
+        self.commits = commits.into();
+
+        if self.limited {
+            self.limit_list()?;
+            if self.topo_order {
+                self.sort_in_topological_order();
+            }
+        }
+        if self.simplify_merges {
+            self.simplify_merges()?;
+        }
+
+        let mut entries = Vec::new();
+        while let Some(oid) = self.commits.pop_front() {
+            if !self.limited {
+                self.process_parents(&oid, None)?;
+            }
+            if !self.simplify_commit(&oid)? {
+                continue;
+            }
+            self.set(&oid, SHOWN);
+            let follows = self.follows(&oid)?;
+            // Like git log, forget the parents of commits once shown, so
+            // rewriting that reaches one that is TREESAME stops there.
+            let rewrite_parents = self.rewrite_parents;
+            let node = self.node_mut(&oid);
+            let parents = std::mem::take(&mut node.parents);
+            if follows {
+                let parents = if rewrite_parents {
+                    parents
+                } else {
+                    node.original_parents.clone()
+                };
+                entries.push(WalkEntry { oid, parents });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Repository {
+    // The commits reachable from `tips` but not from `hidden`, newest
+    // first, as git log lists them with `options`.
+    pub fn rev_walk(
+        &self,
+        tips: &[Oid],
+        hidden: &[Oid],
+        options: &RevWalkOptions,
+    ) -> Result<Vec<WalkEntry>> {
+        Walker::new(self, options)?.walk(tips, hidden)
+    }
+}
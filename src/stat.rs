@@ -0,0 +1,156 @@
+use std::fs::Metadata;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::fs::MetadataExt;
+
+use crate::index::{IndexEntry, IndexTime};
+use crate::odb::{hash_object, ObjectType};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFGITLINK: u32 = 0o160000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStat {
+    Default,
+    Minimal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StatOptions {
+    pub trust_ctime: bool,
+    pub check_stat: CheckStat,
+    pub trust_filemode: bool,
+    pub has_symlinks: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatChange(u32);
+
+impl StatChange {
+    pub const NONE: StatChange = StatChange(0);
+    pub const MTIME: StatChange = StatChange(0x0001);
+    pub const CTIME: StatChange = StatChange(0x0002);
+    pub const OWNER: StatChange = StatChange(0x0004);
+    pub const MODE: StatChange = StatChange(0x0008);
+    pub const INODE: StatChange = StatChange(0x0010);
+    pub const DATA: StatChange = StatChange(0x0020);
+    pub const TYPE: StatChange = StatChange(0x0040);
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, other: StatChange) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for StatChange {
+    type Output = StatChange;
+    fn bitor(self, rhs: StatChange) -> StatChange {
+        StatChange(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for StatChange {
+    fn bitor_assign(&mut self, rhs: StatChange) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl StatOptions {
+    pub fn new() -> Self {
+        Self {
+            trust_ctime: true,
+            check_stat: CheckStat::Default,
+            trust_filemode: true,
+            has_symlinks: true,
+        }
+    }
+
+    // Compares only what `stat` can tell us; whether an unchanged result can
+    // be trusted is decided by the racy-git check in `Index::match_stat`.
+    pub fn match_stat(&self, entry: &IndexEntry, metadata: &Metadata) -> StatChange {
+        let mut changed = StatChange::NONE;
+        let file_type = metadata.file_type();
+        match entry.mode & S_IFMT {
+            S_IFREG => {
+                if !file_type.is_file() {
+                    changed |= StatChange::TYPE;
+                }
+                if self.trust_filemode && (entry.mode ^ metadata.mode()) & 0o100 != 0 {
+                    changed |= StatChange::MODE;
+                }
+            }
+            S_IFLNK => {
+                if !file_type.is_symlink() && (self.has_symlinks || !file_type.is_file()) {
+                    changed |= StatChange::TYPE;
+                }
+            }
+            S_IFGITLINK => {
+                if !file_type.is_dir() {
+                    changed |= StatChange::TYPE;
+                }
+                return changed;
+            }
+            _ => changed |= StatChange::TYPE,
+        }
+        changed |= self.match_stat_data(entry, metadata);
+
+        // A racily clean entry that was smudged on write has its size zeroed.
+        if entry.size == 0 && entry.id != hash_object(ObjectType::Blob, b"") {
+            changed |= StatChange::DATA;
+        }
+        changed
+    }
+
+    fn match_stat_data(&self, entry: &IndexEntry, metadata: &Metadata) -> StatChange {
+        let mut changed = StatChange::NONE;
+        let check_stat = self.check_stat == CheckStat::Default;
+        if entry.mtime.seconds as u32 != metadata.mtime() as u32 {
+            changed |= StatChange::MTIME;
+        }
+        if self.trust_ctime && check_stat && entry.ctime.seconds as u32 != metadata.ctime() as u32 {
+            changed |= StatChange::CTIME;
+        }
+        if check_stat && entry.mtime.nanoseconds != metadata.mtime_nsec() as u32 {
+            changed |= StatChange::MTIME;
+        }
+        if self.trust_ctime && check_stat && entry.ctime.nanoseconds != metadata.ctime_nsec() as u32
+        {
+            changed |= StatChange::CTIME;
+        }
+        if check_stat {
+            if entry.uid != metadata.uid() || entry.gid != metadata.gid() {
+                changed |= StatChange::OWNER;
+            }
+            if entry.ino != metadata.ino() as u32 {
+                changed |= StatChange::INODE;
+            }
+        }
+        if entry.size != metadata.size() as u32 {
+            changed |= StatChange::DATA;
+        }
+        changed
+    }
+}
+
+impl Default for StatOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn is_racy_timestamp(index_timestamp: Option<IndexTime>, entry: &IndexEntry) -> bool {
+    match index_timestamp {
+        Some(timestamp) if timestamp.seconds != 0 => {
+            let (index_seconds, entry_seconds) =
+                (timestamp.seconds as u32, entry.mtime.seconds as u32);
+            index_seconds < entry_seconds
+                || (index_seconds == entry_seconds
+                    && timestamp.nanoseconds <= entry.mtime.nanoseconds)
+        }
+        _ => false,
+    }
+}
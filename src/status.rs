@@ -0,0 +1,756 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::attributes::AttrSource;
+use crate::convert::Converter;
+use crate::error::Result;
+use crate::ignore::Excludes;
+use crate::index::{Index, IndexEntry};
+use crate::odb::Odb;
+use crate::oid::Oid;
+use crate::pathspec::Pathspec;
+use crate::rename::{detect_renames, Candidate, DEFAULT_RENAME_SCORE};
+use crate::repo::Repository;
+use crate::stat::{StatChange, StatOptions};
+use crate::tree::{flatten_matching, TreeItem};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFGITLINK: u32 = 0o160000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    Unmodified,
+    Modified,
+    TypeChanged,
+    Added,
+    Deleted,
+    Renamed,
+    Copied,
+    Unmerged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusKind {
+    Changed,
+    Unmerged,
+    Untracked,
+    Ignored,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    No,
+    Normal,
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PorcelainVersion {
+    V1,
+    V2,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusOptions {
+    pub untracked: UntrackedFiles,
+    pub ignored: bool,
+    pub renames: bool,
+    pub rename_score: u32,
+    pub pathspec: Pathspec,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PorcelainOptions {
+    pub nul_terminated: bool,
+    pub branch: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub path: Vec<u8>,
+    pub kind: StatusKind,
+    pub index: StatusCode,
+    pub worktree: StatusCode,
+    pub head_item: Option<TreeItem>,
+    pub index_item: Option<TreeItem>,
+    pub worktree_mode: u32,
+    pub original_path: Option<Vec<u8>>,
+    pub score: u32,
+    pub stages: [Option<TreeItem>; 3],
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub branch: Option<String>,
+    pub head: Option<Oid>,
+    entries: Vec<StatusEntry>,
+}
+
+impl StatusCode {
+    pub fn as_char(self) -> char {
+        match self {
+            StatusCode::Unmodified => ' ',
+            StatusCode::Modified => 'M',
+            StatusCode::TypeChanged => 'T',
+            StatusCode::Added => 'A',
+            StatusCode::Deleted => 'D',
+            StatusCode::Renamed => 'R',
+            StatusCode::Copied => 'C',
+            StatusCode::Unmerged => 'U',
+        }
+    }
+
+    fn as_v2_char(self) -> char {
+        match self {
+            StatusCode::Unmodified => '.',
+            code => code.as_char(),
+        }
+    }
+}
+
+impl StatusOptions {
+    pub fn new() -> Self {
+        Self {
+            untracked: UntrackedFiles::Normal,
+            ignored: false,
+            renames: true,
+            rename_score: DEFAULT_RENAME_SCORE,
+            pathspec: Pathspec::default(),
+        }
+    }
+}
+
+impl Default for StatusOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusEntry {
+    fn new(path: Vec<u8>, kind: StatusKind) -> Self {
+        Self {
+            path,
+            kind,
+            index: StatusCode::Unmodified,
+            worktree: StatusCode::Unmodified,
+            head_item: None,
+            index_item: None,
+            worktree_mode: 0,
+            original_path: None,
+            score: 0,
+            stages: [None; 3],
+        }
+    }
+
+    pub fn is_staged(&self) -> bool {
+        self.kind == StatusKind::Changed && self.index != StatusCode::Unmodified
+    }
+
+    pub fn is_unstaged(&self) -> bool {
+        self.kind == StatusKind::Changed && self.worktree != StatusCode::Unmodified
+    }
+
+    // The two-letter code used by `git status --short`.
+    pub fn short_code(&self) -> String {
+        match self.kind {
+            StatusKind::Untracked => "??".to_string(),
+            StatusKind::Ignored => "!!".to_string(),
+            _ => format!("{}{}", self.index.as_char(), self.worktree.as_char()),
+        }
+    }
+
+    fn set_unmerged_codes(&mut self) {
+        let present = (
+            self.stages[0].is_some(),
+            self.stages[1].is_some(),
+            self.stages[2].is_some(),
+        );
+        let (x, y) = match present {
+            (true, false, false) => (StatusCode::Deleted, StatusCode::Deleted),
+            (false, true, false) => (StatusCode::Added, StatusCode::Unmerged),
+            (true, true, false) => (StatusCode::Unmerged, StatusCode::Deleted),
+            (false, false, true) => (StatusCode::Unmerged, StatusCode::Added),
+            (true, false, true) => (StatusCode::Deleted, StatusCode::Unmerged),
+            (false, true, true) => (StatusCode::Added, StatusCode::Added),
+            _ => (StatusCode::Unmerged, StatusCode::Unmerged),
+        };
+        self.index = x;
+        self.worktree = y;
+    }
+}
+
+impl Status {
+    pub fn entries(&self) -> &[StatusEntry] {
+        &self.entries
+    }
+
+    pub fn staged(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.entries.iter().filter(|entry| entry.is_staged())
+    }
+
+    pub fn unstaged(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.entries.iter().filter(|entry| entry.is_unstaged())
+    }
+
+    pub fn untracked(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.of_kind(StatusKind::Untracked)
+    }
+
+    pub fn ignored(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.of_kind(StatusKind::Ignored)
+    }
+
+    pub fn conflicted(&self) -> impl Iterator<Item = &StatusEntry> {
+        self.of_kind(StatusKind::Unmerged)
+    }
+
+    fn of_kind(&self, kind: StatusKind) -> impl Iterator<Item = &StatusEntry> {
+        self.entries.iter().filter(move |entry| entry.kind == kind)
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|entry| entry.kind == StatusKind::Ignored)
+    }
+
+    pub fn porcelain(&self, version: PorcelainVersion, options: &PorcelainOptions) -> Vec<u8> {
+        match version {
+            PorcelainVersion::V1 => self.porcelain_v1(options),
+            PorcelainVersion::V2 => self.porcelain_v2(options),
+        }
+    }
+
+    fn porcelain_v1(&self, options: &PorcelainOptions) -> Vec<u8> {
+        let terminator = if options.nul_terminated { b'\0' } else { b'\n' };
+        let mut out = Vec::new();
+        if options.branch {
+            out.extend_from_slice(b"## ");
+            match (&self.branch, &self.head) {
+                (Some(branch), Some(_)) => out.extend_from_slice(short_branch(branch).as_bytes()),
+                (Some(branch), None) => {
+                    out.extend_from_slice(b"No commits yet on ");
+                    out.extend_from_slice(short_branch(branch).as_bytes());
+                }
+                (None, _) => out.extend_from_slice(b"HEAD (no branch)"),
+            }
+            out.push(terminator);
+        }
+        for entry in &self.entries {
+            out.extend_from_slice(entry.short_code().as_bytes());
+            out.push(b' ');
+            match (&entry.original_path, options.nul_terminated) {
+                (Some(original), false) => {
+                    push_path(&mut out, original, false);
+                    out.extend_from_slice(b" -> ");
+                    push_path(&mut out, &entry.path, false);
+                }
+                (Some(original), true) => {
+                    out.extend_from_slice(&entry.path);
+                    out.push(b'\0');
+                    out.extend_from_slice(original);
+                }
+                (None, nul_terminated) => push_path(&mut out, &entry.path, nul_terminated),
+            }
+            out.push(terminator);
+        }
+        out
+    }
+
+    fn porcelain_v2(&self, options: &PorcelainOptions) -> Vec<u8> {
+        let terminator = if options.nul_terminated { b'\0' } else { b'\n' };
+        let mut out = Vec::new();
+        if options.branch {
+            let oid = self
+                .head
+                .map_or_else(|| "(initial)".to_string(), |oid| oid.hex());
+            out.extend_from_slice(format!("# branch.oid {}", oid).as_bytes());
+            out.push(terminator);
+            let head = self
+                .branch
+                .as_ref()
+                .map_or("(detached)", |branch| short_branch(branch));
+            out.extend_from_slice(format!("# branch.head {}", head).as_bytes());
+            out.push(terminator);
+        }
+        // Unlike v1, v2 lists unmerged entries after all other tracked ones.
+        let (unmerged, others): (Vec<_>, Vec<_>) = self
+            .entries
+            .iter()
+            .partition(|entry| entry.kind == StatusKind::Unmerged);
+        let tracked = others.len() - self.untracked().count() - self.ignored().count();
+        let ordered = others[..tracked]
+            .iter()
+            .chain(unmerged.iter())
+            .chain(others[tracked..].iter());
+        for entry in ordered {
+            match entry.kind {
+                StatusKind::Untracked | StatusKind::Ignored => {
+                    let marker = if entry.kind == StatusKind::Untracked {
+                        b"? "
+                    } else {
+                        b"! "
+                    };
+                    out.extend_from_slice(marker);
+                }
+                StatusKind::Unmerged => {
+                    let line = format!(
+                        "u {}{} N... {} {} {} {:06o} {} {} {} ",
+                        entry.index.as_v2_char(),
+                        entry.worktree.as_v2_char(),
+                        format_mode(&entry.stages[0]),
+                        format_mode(&entry.stages[1]),
+                        format_mode(&entry.stages[2]),
+                        entry.worktree_mode,
+                        format_id(&entry.stages[0]),
+                        format_id(&entry.stages[1]),
+                        format_id(&entry.stages[2]),
+                    );
+                    out.extend_from_slice(line.as_bytes());
+                }
+                StatusKind::Changed => {
+                    let kind = if entry.original_path.is_some() {
+                        '2'
+                    } else {
+                        '1'
+                    };
+                    let mut line = format!(
+                        "{} {}{} N... {} {} {:06o} {} {} ",
+                        kind,
+                        entry.index.as_v2_char(),
+                        entry.worktree.as_v2_char(),
+                        format_mode(&entry.head_item),
+                        format_mode(&entry.index_item),
+                        entry.worktree_mode,
+                        format_id(&entry.head_item),
+                        format_id(&entry.index_item),
+                    );
+                    if entry.original_path.is_some() {
+                        line.push_str(&format!("{}{} ", entry.index.as_char(), entry.score));
+                    }
+                    out.extend_from_slice(line.as_bytes());
+                }
+            }
+            push_path(&mut out, &entry.path, options.nul_terminated);
+            if let Some(original) = &entry.original_path {
+                out.push(if options.nul_terminated { b'\0' } else { b'\t' });
+                push_path(&mut out, original, options.nul_terminated);
+            }
+            out.push(terminator);
+        }
+        out
+    }
+}
+
+fn short_branch(branch: &str) -> &str {
+    branch.strip_prefix("refs/heads/").unwrap_or(branch)
+}
+
+fn format_mode(item: &Option<TreeItem>) -> String {
+    format!("{:06o}", item.map_or(0, |item| item.mode))
+}
+
+fn format_id(item: &Option<TreeItem>) -> String {
+    item.map_or_else(|| "0".repeat(40), |item| item.id.hex())
+}
+
+fn push_path(out: &mut Vec<u8>, path: &[u8], nul_terminated: bool) {
+    if nul_terminated {
+        out.extend_from_slice(path);
+    } else {
+        out.extend_from_slice(&quote_path(path));
+    }
+}
+
+// Quotes a path the way git does with core.quotePath enabled.
+pub fn quote_path(path: &[u8]) -> Vec<u8> {
+    let needs_quoting = path
+        .iter()
+        .any(|&b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f);
+    if !needs_quoting {
+        return path.to_vec();
+    }
+    let mut quoted = vec![b'"'];
+    for &b in path {
+        match b {
+            0x07 => quoted.extend_from_slice(b"\\a"),
+            0x08 => quoted.extend_from_slice(b"\\b"),
+            b'\t' => quoted.extend_from_slice(b"\\t"),
+            b'\n' => quoted.extend_from_slice(b"\\n"),
+            0x0b => quoted.extend_from_slice(b"\\v"),
+            0x0c => quoted.extend_from_slice(b"\\f"),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            b'"' => quoted.extend_from_slice(b"\\\""),
+            b'\\' => quoted.extend_from_slice(b"\\\\"),
+            b if !(0x20..0x7f).contains(&b) => {
+                quoted.extend_from_slice(format!("\\{:03o}", b).as_bytes())
+            }
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+fn tree_item(entry: &IndexEntry) -> TreeItem {
+    TreeItem {
+        mode: entry.mode,
+        id: entry.id,
+    }
+}
+
+fn compare_items(old: &TreeItem, new: &TreeItem) -> StatusCode {
+    if old.mode & S_IFMT != new.mode & S_IFMT {
+        StatusCode::TypeChanged
+    } else if old.id != new.id || old.mode != new.mode {
+        StatusCode::Modified
+    } else {
+        StatusCode::Unmodified
+    }
+}
+
+struct Walker<'a> {
+    workdir: &'a Path,
+    index: &'a Index,
+    excludes: Excludes,
+    options: &'a StatusOptions,
+}
+
+impl<'a> Walker<'a> {
+    fn read_dir(&self, dir: &[u8]) -> Result<Vec<(Vec<u8>, bool, bool)>> {
+        let mut children = Vec::new();
+        let full_path = self.workdir.join(std::ffi::OsStr::from_bytes(dir));
+        for entry in fs::read_dir(full_path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name.as_bytes() == b".git" {
+                continue;
+            }
+            let mut path = dir.to_vec();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(name.as_bytes());
+            let is_dir = entry.file_type()?.is_dir();
+            let is_repository = is_dir && entry.path().join(".git").exists();
+            children.push((path, is_dir, is_repository));
+        }
+        children.sort();
+        Ok(children)
+    }
+
+    fn push(&self, entries: &mut Vec<StatusEntry>, mut path: Vec<u8>, is_dir: bool, ignored: bool) {
+        if ignored && !self.options.ignored {
+            return;
+        }
+        if is_dir {
+            path.push(b'/');
+        }
+        let kind = if ignored {
+            StatusKind::Ignored
+        } else {
+            StatusKind::Untracked
+        };
+        entries.push(StatusEntry::new(path, kind));
+    }
+
+    // Everything below an ignored directory is ignored as well. Directories
+    // the pathspec does not match as a whole are walked into for the files
+    // it does.
+    fn walk(&self, dir: &[u8], ignored_dir: bool, entries: &mut Vec<StatusEntry>) -> Result<()> {
+        let pathspec = &self.options.pathspec;
+        for (path, is_dir, is_repository) in self.read_dir(dir)? {
+            if self.index.contains(&path) || (is_dir && !pathspec.may_match_dir(&path)) {
+                continue;
+            }
+            let ignored = ignored_dir || self.excludes.is_ignored(&path, is_dir)?;
+            if ignored && !self.options.ignored {
+                continue;
+            }
+            let matched = pathspec.matches(&path, is_dir)?;
+            if !is_dir {
+                if matched {
+                    self.push(entries, path, false, ignored);
+                }
+                continue;
+            }
+            if !matched && is_repository {
+                continue;
+            }
+            let all = self.options.untracked == UntrackedFiles::All;
+            if self.index.contains_directory(&path) || (all && !is_repository) || !matched {
+                self.walk(&path, ignored, entries)?;
+                continue;
+            }
+            if ignored {
+                self.push(entries, path, true, true);
+                continue;
+            }
+            // A directory without tracked files is shown as a whole: as
+            // untracked if anything in it is, otherwise as ignored.
+            let (untracked, ignored) = if is_repository {
+                (true, false)
+            } else {
+                self.scan(&path)?
+            };
+            if untracked {
+                if self.options.ignored && !is_repository {
+                    let mut inside = Vec::new();
+                    self.walk(&path, false, &mut inside)?;
+                    entries.extend(
+                        inside
+                            .into_iter()
+                            .filter(|entry| entry.kind == StatusKind::Ignored),
+                    );
+                }
+                self.push(entries, path, true, false);
+            } else if ignored {
+                self.push(entries, path, true, true);
+            }
+        }
+        Ok(())
+    }
+
+    // Reports whether an untracked directory holds untracked and ignored
+    // files respectively.
+    fn scan(&self, dir: &[u8]) -> Result<(bool, bool)> {
+        let (mut untracked, mut ignored) = (false, false);
+        for (path, is_dir, is_repository) in self.read_dir(dir)? {
+            if self.excludes.is_ignored(&path, is_dir)? {
+                ignored = true;
+            } else if !is_dir || is_repository {
+                untracked = true;
+            } else {
+                let (sub_untracked, sub_ignored) = self.scan(&path)?;
+                untracked |= sub_untracked;
+                ignored |= sub_ignored;
+            }
+            if untracked && (ignored || !self.options.ignored) {
+                break;
+            }
+        }
+        Ok((untracked, ignored))
+    }
+}
+
+impl Repository {
+    pub fn status(&self, options: &StatusOptions) -> Result<Status> {
+        let workdir = self.require_workdir()?;
+        let index = self.index()?;
+        let head = self.head()?;
+        let pathspec = &options.pathspec;
+        let head_items = match head {
+            Some(oid) => flatten_matching(self.odb(), &self.commit_tree(&oid)?, pathspec)?,
+            None => BTreeMap::new(),
+        };
+
+        let converter = self.converter(AttrSource::CheckIn)?;
+        let stat_options = self.stat_options()?;
+        let mut tracked: BTreeMap<Vec<u8>, StatusEntry> = BTreeMap::new();
+        for conflict in index.conflicts() {
+            if !pathspec.matches(conflict.path, false)? {
+                continue;
+            }
+            let mut entry = StatusEntry::new(conflict.path.to_vec(), StatusKind::Unmerged);
+            entry.stages = [
+                conflict.ancestor.map(tree_item),
+                conflict.ours.map(tree_item),
+                conflict.theirs.map(tree_item),
+            ];
+            entry.head_item = head_items.get(conflict.path).copied();
+            entry.worktree_mode = self.worktree_mode(workdir, conflict.path)?;
+            entry.set_unmerged_codes();
+            tracked.insert(entry.path.clone(), entry);
+        }
+
+        for index_entry in index.iter().filter(|entry| !entry.is_conflicted()) {
+            if !pathspec.matches(&index_entry.path, false)? {
+                continue;
+            }
+            let mut entry = StatusEntry::new(index_entry.path.clone(), StatusKind::Changed);
+            entry.head_item = head_items.get(&index_entry.path).copied();
+            if index_entry.intent_to_add() {
+                entry.worktree = StatusCode::Added;
+            } else {
+                entry.index_item = Some(tree_item(index_entry));
+                entry.index = match &entry.head_item {
+                    Some(head_item) => compare_items(head_item, &tree_item(index_entry)),
+                    None => StatusCode::Added,
+                };
+            }
+            let (worktree, worktree_mode) =
+                self.worktree_status(workdir, &index, index_entry, &stat_options, &converter)?;
+            if !index_entry.intent_to_add() || worktree == StatusCode::Deleted {
+                entry.worktree = worktree;
+            }
+            entry.worktree_mode = worktree_mode;
+            if entry.index != StatusCode::Unmodified || entry.worktree != StatusCode::Unmodified {
+                tracked.insert(entry.path.clone(), entry);
+            }
+        }
+
+        for (path, head_item) in &head_items {
+            if index.contains(path) {
+                continue;
+            }
+            let mut entry = StatusEntry::new(path.clone(), StatusKind::Changed);
+            entry.head_item = Some(*head_item);
+            entry.index = StatusCode::Deleted;
+            tracked.insert(path.clone(), entry);
+        }
+
+        if options.renames {
+            self.detect_staged_renames(&mut tracked, options.rename_score)?;
+        }
+
+        let mut entries: Vec<StatusEntry> = tracked.into_values().collect();
+        if options.untracked != UntrackedFiles::No || options.ignored {
+            let walker = Walker {
+                workdir,
+                index: &index,
+                excludes: self.excludes()?,
+                options,
+            };
+            let mut untracked = Vec::new();
+            walker.walk(&[], false, &mut untracked)?;
+            if options.untracked == UntrackedFiles::No {
+                untracked.retain(|entry| entry.kind != StatusKind::Untracked);
+            }
+            untracked.sort_by(|a, b| {
+                let ignored = |entry: &StatusEntry| entry.kind == StatusKind::Ignored;
+                ignored(a)
+                    .cmp(&ignored(b))
+                    .then_with(|| a.path.cmp(&b.path))
+            });
+            entries.extend(untracked);
+        }
+
+        Ok(Status {
+            branch: self.head_branch()?,
+            head,
+            entries,
+        })
+    }
+
+    fn worktree_mode(&self, workdir: &Path, path: &[u8]) -> Result<u32> {
+        match fs::symlink_metadata(workdir.join(std::ffi::OsStr::from_bytes(path))) {
+            Ok(ref metadata) if metadata.is_dir() => Ok(0),
+            Ok(metadata) => Ok(IndexEntry::mode_from_metadata(&metadata)),
+            Err(ref e) if is_missing(e) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) fn worktree_status(
+        &self,
+        workdir: &Path,
+        index: &Index,
+        entry: &IndexEntry,
+        stat_options: &StatOptions,
+        converter: &Converter,
+    ) -> Result<(StatusCode, u32)> {
+        if entry.skip_worktree() {
+            return Ok((StatusCode::Unmodified, entry.mode));
+        }
+        let metadata = match fs::symlink_metadata(entry.workdir_path(workdir)) {
+            Ok(metadata) => metadata,
+            Err(ref e) if is_missing(e) => return Ok((StatusCode::Deleted, 0)),
+            Err(e) => return Err(e.into()),
+        };
+        // A directory where a file used to be only holds untracked content.
+        if metadata.is_dir() && entry.mode & S_IFMT != S_IFGITLINK {
+            return Ok((StatusCode::Deleted, 0));
+        }
+        let mode = IndexEntry::mode_from_metadata(&metadata);
+        let changed = index.match_stat(workdir, entry, &metadata, stat_options, converter)?;
+        let status = if changed.contains(StatChange::TYPE) {
+            StatusCode::TypeChanged
+        } else if changed.contains(StatChange::MODE)
+            || (changed.contains(StatChange::DATA) && entry.size != 0)
+        {
+            StatusCode::Modified
+        } else if changed.is_empty() {
+            StatusCode::Unmodified
+        } else if Index::content_changed(workdir, entry, &metadata, converter)? {
+            StatusCode::Modified
+        } else {
+            StatusCode::Unmodified
+        };
+        Ok((status, mode))
+    }
+
+    fn detect_staged_renames(
+        &self,
+        tracked: &mut BTreeMap<Vec<u8>, StatusEntry>,
+        min_score: u32,
+    ) -> Result<()> {
+        let deleted: Vec<(Vec<u8>, TreeItem)> = tracked
+            .values()
+            .filter(|entry| entry.kind == StatusKind::Changed && entry.index == StatusCode::Deleted)
+            .map(|entry| (entry.path.clone(), entry.head_item.unwrap()))
+            .collect();
+        let added: Vec<(Vec<u8>, TreeItem)> = tracked
+            .values()
+            .filter(|entry| entry.kind == StatusKind::Changed && entry.index == StatusCode::Added)
+            .map(|entry| (entry.path.clone(), entry.index_item.unwrap()))
+            .collect();
+        if deleted.is_empty() || added.is_empty() {
+            return Ok(());
+        }
+
+        let read_blobs = |items: &[(Vec<u8>, TreeItem)]| -> Result<Vec<Vec<u8>>> {
+            items
+                .iter()
+                .map(|(_, item)| self.read_blob_content(&item.id))
+                .collect()
+        };
+        let deleted_content = read_blobs(&deleted)?;
+        let added_content = read_blobs(&added)?;
+        let sources: Vec<Candidate<'_>> = deleted
+            .iter()
+            .zip(deleted_content.iter())
+            .map(|((_, item), content)| Candidate {
+                id: item.id,
+                content,
+            })
+            .collect();
+        let destinations: Vec<Candidate<'_>> = added
+            .iter()
+            .zip(added_content.iter())
+            .map(|((_, item), content)| Candidate {
+                id: item.id,
+                content,
+            })
+            .collect();
+
+        for rename in detect_renames(&sources, &destinations, min_score) {
+            let (source_path, source_item) = &deleted[rename.source];
+            let (destination_path, _) = &added[rename.destination];
+            tracked.remove(source_path);
+            if let Some(entry) = tracked.get_mut(destination_path) {
+                entry.index = StatusCode::Renamed;
+                entry.head_item = Some(*source_item);
+                entry.original_path = Some(source_path.clone());
+                entry.score = rename.score;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_blob_content(&self, oid: &Oid) -> Result<Vec<u8>> {
+        match self.odb().get(oid)?.into_blob() {
+            Some(blob) => Ok(blob.as_bytes().to_vec()),
+            None => Err(crate::error::Error::InvalidObject(format!(
+                "{} is not a blob",
+                oid
+            ))),
+        }
+    }
+}
+
+pub(crate) fn is_missing(error: &std::io::Error) -> bool {
+    error.kind() == ErrorKind::NotFound || error.raw_os_error() == Some(20)
+}
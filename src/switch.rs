@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::attributes::AttrSource;
+use crate::checkout::{CheckoutOptions, CheckoutProgress, Update};
+use crate::error::{Error, Result};
+use crate::filter::FilterMeta;
+use crate::index::{Index, IndexEntry};
+use crate::merge::{merge, MergeOptions};
+use crate::odb::{ObjectType, Odb};
+use crate::oid::Oid;
+use crate::refs::Reference;
+use crate::repo::Repository;
+use crate::status::StatusCode;
+use crate::tree::{flatten_tree, TreeItem};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFGITLINK: u32 = 0o160000;
+
+type Items = BTreeMap<Vec<u8>, TreeItem>;
+
+// The outcome of git's two-way merge of the index from the tree being left
+// to the one being switched to.
+#[derive(Default)]
+struct TwoWay {
+    updates: Vec<Update>,
+    // Paths whose staged changes would be lost.
+    staged: Vec<Vec<u8>>,
+    // Paths that go away without being in the index. An untracked file
+    // there would be taken for them.
+    removed: Vec<Vec<u8>>,
+}
+
+// A path after carrying local changes over: what the working tree gets,
+// and the stages for the index when it conflicts.
+struct Carried {
+    worktree: Option<TreeItem>,
+    conflict: Option<[Option<TreeItem>; 3]>,
+}
+
+fn item(entry: &IndexEntry) -> TreeItem {
+    TreeItem {
+        mode: entry.mode,
+        id: entry.id,
+    }
+}
+
+fn is_file(item: &TreeItem) -> bool {
+    item.mode & S_IFMT == S_IFREG
+}
+
+// Follows git's twoway_merge: the index entry of a path is kept when the
+// switch does not change the path or when it already matches the new
+// tree, and it is updated only when it still matches the old tree.
+fn two_way_merge(index: &Index, old: &Items, new: &Items) -> TwoWay {
+    let mut paths: BTreeSet<&[u8]> = old.keys().chain(new.keys()).map(|p| &p[..]).collect();
+    paths.extend(index.iter().map(|entry| &entry.path[..]));
+
+    let mut two_way = TwoWay::default();
+    for path in paths {
+        let current = index.get(path, 0).map(item);
+        let (old, new) = (old.get(path), new.get(path));
+        match (current, old, new) {
+            (Some(_), None, None) => {}
+            (Some(current), _, Some(new)) if current == *new || old == Some(new) => {}
+            (Some(current), Some(old), None) if current == *old => {
+                two_way.updates.push(Update::Remove(path.to_vec()));
+            }
+            (Some(current), Some(old), Some(new)) if current == *old => {
+                let entry = IndexEntry::with_id(path.to_vec(), new.mode, new.id, 0);
+                two_way.updates.push(Update::Write(entry));
+            }
+            (Some(_), _, _) => two_way.staged.push(path.to_vec()),
+            // The path's removal is staged.
+            (None, Some(old), Some(new)) if old == new => {}
+            (None, Some(_), Some(_)) => two_way.staged.push(path.to_vec()),
+            (None, None, Some(new)) => {
+                let entry = IndexEntry::with_id(path.to_vec(), new.mode, new.id, 0);
+                two_way.updates.push(Update::Write(entry));
+            }
+            (None, _, None) => two_way.removed.push(path.to_vec()),
+        }
+    }
+    two_way
+}
+
+impl Repository {
+    // Switches to the branch `name`, or detaches HEAD at another ref or a
+    // commit id, like `git checkout`. `HEAD` itself stays where it is, on
+    // its branch if it has one. Local changes to paths that are the
+    // same in both commits are kept; other local changes make it fail
+    // without touching anything, unless `force` throws them away or
+    // `merge` merges them into the new commit, leaving conflicts in the
+    // index and the working tree.
+    pub fn switch_branch(
+        &self,
+        name: &str,
+        options: &CheckoutOptions,
+        mut progress: impl FnMut(&CheckoutProgress),
+    ) -> Result<()> {
+        let workdir = self.require_workdir()?;
+        let (branch, commit) = self.switch_target(name)?;
+        let tree = self.commit_tree(&commit)?;
+        let head = self.head()?;
+        let from = match self.head_branch()? {
+            Some(branch) => branch
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&branch)
+                .to_string(),
+            None => head.map(|oid| oid.hex()).unwrap_or_default(),
+        };
+
+        if options.force {
+            self.checkout_tree(&tree, options, &mut progress)?;
+        } else {
+            let old_tree = match head {
+                Some(oid) => Some(self.commit_tree(&oid)?),
+                None => None,
+            };
+            let meta = FilterMeta {
+                ref_name: branch.clone(),
+                treeish: Some(commit),
+                blob: None,
+            };
+            self.switch_trees(
+                workdir,
+                old_tree.as_ref(),
+                &tree,
+                name,
+                options,
+                &meta,
+                &mut progress,
+            )?;
+        }
+
+        // Like git, checking out HEAD does not even log the move.
+        if name == "HEAD" {
+            return Ok(());
+        }
+        let reference = match &branch {
+            Some(branch) => Reference::Symbolic(branch.clone()),
+            None => Reference::Direct(commit),
+        };
+        self.refs().write("HEAD", &reference)?;
+        self.refs().append_reflog(
+            "HEAD",
+            head.as_ref(),
+            Some(&commit),
+            &self.committer_identity()?,
+            &format!("checkout: moving from {} to {}", from, name),
+        )
+    }
+
+    // A local branch, which HEAD is then to point at, or else any other
+    // revision to detach HEAD at.
+    fn switch_target(&self, name: &str) -> Result<(Option<String>, Oid)> {
+        if name == "HEAD" {
+            if let Some(oid) = self.head()? {
+                return Ok((self.head_branch()?, oid));
+            }
+        }
+        let branch = format!("refs/heads/{}", name);
+        if let Some(oid) = self.refs().resolve(&branch)? {
+            return Ok((Some(branch), oid));
+        }
+        match self.resolve_revision(name)? {
+            Some(oid) => Ok((None, oid)),
+            None => Err(Error::InvalidRef(format!("invalid reference: {}", name))),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn switch_trees(
+        &self,
+        workdir: &Path,
+        old_tree: Option<&Oid>,
+        new_tree: &Oid,
+        label: &str,
+        options: &CheckoutOptions,
+        meta: &FilterMeta,
+        progress: &mut dyn FnMut(&CheckoutProgress),
+    ) -> Result<()> {
+        let mut index = self.index()?;
+        if let Some(conflict) = index.conflicts().first() {
+            return Err(Error::UnmergedIndex(conflict.path.to_vec()));
+        }
+        let old = match old_tree {
+            Some(tree) => flatten_tree(self.odb(), tree)?,
+            None => Items::new(),
+        };
+        let new = flatten_tree(self.odb(), new_tree)?;
+        let two_way = two_way_merge(&index, &old, &new);
+
+        let converter = self.converter_for(AttrSource::CheckIn, &index)?;
+        let stat_options = self.stat_options()?;
+        let mut local_changes = two_way.staged;
+        if let Err(error) =
+            self.check_updates(workdir, &index, &two_way.updates, &stat_options, &converter)
+        {
+            match error {
+                Error::LocalChanges(paths) => local_changes.extend(paths),
+                error if local_changes.is_empty() => return Err(error),
+                _ => {}
+            }
+        }
+        if !local_changes.is_empty() {
+            local_changes.sort();
+            if options.merge && old_tree.is_some() {
+                return self.carry_over_changes(workdir, index, &old, &new, label, meta, progress);
+            }
+            return Err(Error::LocalChanges(local_changes));
+        }
+        let excludes = self.excludes()?;
+        let mut untracked = Vec::new();
+        for path in &two_way.removed {
+            self.check_absent(workdir, &index, &excludes, path, &mut untracked)?;
+        }
+        if !untracked.is_empty() {
+            return Err(Error::UntrackedRemoved(untracked));
+        }
+
+        self.apply_updates(workdir, &mut index, two_way.updates, meta, progress)?;
+        self.write_index(&mut index)
+    }
+
+    // `git checkout --merge`: the local changes are merged into the new
+    // tree, with the old one as the base. The index gets the new tree,
+    // except for conflicting paths, which get all three stages, and the
+    // working tree gets the merged content.
+    #[allow(clippy::too_many_arguments)]
+    fn carry_over_changes(
+        &self,
+        workdir: &Path,
+        mut index: Index,
+        old: &Items,
+        new: &Items,
+        label: &str,
+        meta: &FilterMeta,
+        progress: &mut dyn FnMut(&CheckoutProgress),
+    ) -> Result<()> {
+        // Staged changes would be taken for part of the old tree.
+        let mut staged: Vec<Vec<u8>> = old
+            .keys()
+            .filter(|path| !index.contains(path))
+            .cloned()
+            .collect();
+        for entry in index.iter() {
+            if old.get(&entry.path) != Some(&item(entry)) {
+                staged.push(entry.path.clone());
+            }
+        }
+        if !staged.is_empty() {
+            staged.sort();
+            return Err(Error::StagedChanges(staged));
+        }
+
+        // The working tree as a tree, as if all local changes were added.
+        let converter = self.converter_for(AttrSource::CheckIn, &index)?;
+        let stat_options = self.stat_options()?;
+        let mut work = old.clone();
+        for entry in index.iter() {
+            if entry.mode & S_IFMT == S_IFGITLINK {
+                continue;
+            }
+            match self
+                .worktree_status(workdir, &index, entry, &stat_options, &converter)?
+                .0
+            {
+                StatusCode::Deleted => {
+                    work.remove(&entry.path);
+                }
+                StatusCode::Modified | StatusCode::TypeChanged => {
+                    let path = Path::new(OsStr::from_bytes(&entry.path));
+                    let local = IndexEntry::new(workdir, path, self.odb(), &converter)?;
+                    work.insert(entry.path.clone(), item(&local));
+                }
+                _ => {}
+            }
+        }
+
+        let options = MergeOptions {
+            ours_label: Some(label.to_string()),
+            theirs_label: Some("local".to_string()),
+            ..MergeOptions::new()
+        };
+        let paths: BTreeSet<&Vec<u8>> = old.keys().chain(new.keys()).chain(work.keys()).collect();
+        let mut updates = Vec::new();
+        let mut conflicts = Vec::new();
+        for path in paths {
+            let carried = self.carry_over_entry(
+                old.get(path).copied(),
+                new.get(path).copied(),
+                work.get(path).copied(),
+                &options,
+            )?;
+            if carried.worktree.as_ref() != work.get(path) {
+                updates.push(match carried.worktree {
+                    Some(item) => {
+                        Update::Write(IndexEntry::with_id(path.clone(), item.mode, item.id, 0))
+                    }
+                    None => Update::Remove(path.clone()),
+                });
+            }
+            if let Some(stages) = carried.conflict {
+                conflicts.push((path.clone(), stages));
+            }
+        }
+
+        // The working tree is brought from the local state to the merged
+        // one. What gets written there ends up with fresh stat data.
+        let written: BTreeSet<Vec<u8>> = updates
+            .iter()
+            .filter_map(|update| match update {
+                Update::Write(entry) => Some(entry.path.clone()),
+                Update::Remove(_) => None,
+            })
+            .collect();
+        let mut worktree = Index::new();
+        for (path, item) in &work {
+            worktree.replace_entry(IndexEntry::with_id(path.clone(), item.mode, item.id, 0));
+        }
+        self.apply_updates(workdir, &mut worktree, updates, meta, progress)?;
+
+        let stale: Vec<Vec<u8>> = index
+            .iter()
+            .filter(|entry| !new.contains_key(&entry.path))
+            .map(|entry| entry.path.clone())
+            .collect();
+        for path in stale {
+            index.remove_entry(&path);
+        }
+        for (path, new_item) in new {
+            let entry = match (worktree.get(path, 0), index.get(path, 0)) {
+                (Some(entry), _) if item(entry) == *new_item && written.contains(path) => {
+                    entry.clone()
+                }
+                (_, Some(entry)) if item(entry) == *new_item => continue,
+                _ => IndexEntry::with_id(path.clone(), new_item.mode, new_item.id, 0),
+            };
+            index.replace_entry(entry);
+        }
+        for (path, stages) in conflicts {
+            index.remove_entry(&path);
+            for (stage, item) in stages.iter().enumerate() {
+                if let Some(item) = item {
+                    index.replace_entry(IndexEntry::with_id(
+                        path.clone(),
+                        item.mode,
+                        item.id,
+                        stage as u16 + 1,
+                    ));
+                }
+            }
+        }
+        self.write_index(&mut index)
+    }
+
+    // The three-way merge of a path, with the tree being switched to as
+    // ours and the local version as theirs. Files changed on both sides are
+    // merged line by line; anything else changed on both sides conflicts,
+    // leaving whichever side is there in the working tree.
+    fn carry_over_entry(
+        &self,
+        base: Option<TreeItem>,
+        ours: Option<TreeItem>,
+        theirs: Option<TreeItem>,
+        options: &MergeOptions,
+    ) -> Result<Carried> {
+        if ours == theirs || base == theirs {
+            return Ok(Carried {
+                worktree: ours,
+                conflict: None,
+            });
+        }
+        if base == ours {
+            return Ok(Carried {
+                worktree: theirs,
+                conflict: None,
+            });
+        }
+        let (our_item, their_item) = match (ours, theirs) {
+            (Some(our_item), Some(their_item)) if is_file(&our_item) && is_file(&their_item) => {
+                (our_item, their_item)
+            }
+            _ => {
+                return Ok(Carried {
+                    worktree: ours.or(theirs),
+                    conflict: Some([base, ours, theirs]),
+                })
+            }
+        };
+        let base_content = match base {
+            Some(base) if is_file(&base) => self.read_blob_content(&base.id)?,
+            _ => Vec::new(),
+        };
+        let result = merge(
+            &base_content,
+            &self.read_blob_content(&our_item.id)?,
+            &self.read_blob_content(&their_item.id)?,
+            options,
+        );
+        // A mode changed by one side only is kept.
+        let mode = if base.map(|base| base.mode) == Some(our_item.mode) {
+            their_item.mode
+        } else {
+            our_item.mode
+        };
+        let merged = TreeItem {
+            mode,
+            id: self.odb().write(ObjectType::Blob, &result.content)?,
+        };
+        Ok(Carried {
+            worktree: Some(merged),
+            conflict: if result.is_clean() {
+                None
+            } else {
+                Some([base, ours, theirs])
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{identity, TempDir};
+
+    fn switch(repo: &Repository, name: &str) {
+        repo.switch_branch(name, &CheckoutOptions::new(), |_| {})
+            .unwrap();
+    }
+
+    fn reflog_length(repo: &Repository) -> usize {
+        std::fs::read_to_string(repo.gitdir().join("logs/HEAD"))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn head_stays_where_it_is() {
+        let tmp = TempDir::new();
+        let repo = Repository::init(tmp.path()).unwrap();
+        let tree = repo.odb().write(ObjectType::Tree, b"").unwrap();
+        let commit = repo
+            .commit(
+                Some("HEAD"),
+                &identity(),
+                &identity(),
+                "first\n",
+                &tree,
+                &[],
+            )
+            .unwrap();
+
+        switch(&repo, "HEAD");
+        assert_eq!(
+            repo.head_branch().unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+        assert_eq!(reflog_length(&repo), 1);
+
+        switch(&repo, &commit.hex());
+        assert_eq!(repo.head_branch().unwrap(), None);
+        switch(&repo, "HEAD");
+        assert_eq!(repo.head_branch().unwrap(), None);
+        assert_eq!(repo.head().unwrap(), Some(commit));
+        assert_eq!(reflog_length(&repo), 2);
+
+        switch(&repo, "master");
+        assert_eq!(
+            repo.head_branch().unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use crate::commit::Identity;
+use crate::error::{Error, Result};
+use crate::odb::{ObjectType, Odb};
+use crate::oid::Oid;
+use crate::repo::Repository;
+
+// An annotated tag: a name and message for another object, usually a
+// commit.
+#[derive(Debug)]
+pub struct Tag {
+    object: Oid,
+    object_type: ObjectType,
+    name: Vec<u8>,
+    tagger: Option<Identity>,
+    message: Vec<u8>,
+}
+
+impl Tag {
+    pub fn new(
+        object: Oid,
+        object_type: ObjectType,
+        name: Vec<u8>,
+        tagger: Option<Identity>,
+        message: Vec<u8>,
+    ) -> Self {
+        Self {
+            object,
+            object_type,
+            name,
+            tagger,
+            message,
+        }
+    }
+
+    pub fn object(&self) -> &Oid {
+        &self.object
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    // Very old tags have no tagger.
+    pub fn tagger(&self) -> Option<&Identity> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    // The tag object as git stores it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("object {}\ntype ", self.object).into_bytes();
+        bytes.extend_from_slice(self.object_type.as_bytes());
+        bytes.extend_from_slice(b"\ntag ");
+        bytes.extend_from_slice(&self.name);
+        if let Some(tagger) = &self.tagger {
+            bytes.extend_from_slice(b"\ntagger ");
+            bytes.extend_from_slice(&tagger.to_bytes());
+        }
+        bytes.extend_from_slice(b"\n\n");
+        bytes.extend_from_slice(&self.message);
+        bytes
+    }
+}
+
+impl Repository {
+    // The object `oid` names once annotated tags are followed to what they
+    // tag, which is `oid` itself for anything but a tag.
+    pub fn peel(&self, oid: &Oid) -> Result<Oid> {
+        let mut oid = *oid;
+        // Tags of tags are allowed, but not loops of them.
+        for _ in 0..64 {
+            match self.odb().get(&oid)?.into_tag() {
+                Some(tag) => oid = *tag.object(),
+                None => return Ok(oid),
+            }
+        }
+        Err(Error::InvalidObject(format!(
+            "tag chain too long at {}",
+            oid
+        )))
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::DateTime;
+
+use crate::commit::Identity;
+
+// Runs tests/fixtures/filter.sh, a `filter.<name>.process` command.
+pub const FILTER_PROCESS: &str = concat!(
+    "sh '",
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/filter.sh'"
+);
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// A scratch directory that is removed again when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "hit-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn identity() -> Identity {
+    Identity::new(
+        b"A U Thor".to_vec(),
+        b"author@example.com".to_vec(),
+        DateTime::parse_from_rfc3339("2017-07-14T02:40:00+00:00").unwrap(),
+    )
+}
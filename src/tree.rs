@@ -1,9 +1,14 @@
+use crate::error::{Error, Result};
+use crate::odb::Odb;
 use crate::oid::Oid;
+use crate::pathspec::Pathspec;
+use std::cmp::Ordering;
 use std::collections::btree_map;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io::Write;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Mode(pub Vec<u8>);
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -15,7 +20,7 @@ pub enum EntryKind {
     Blob,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TreeEntry {
     oid: Oid,
     mode: Mode,
@@ -26,11 +31,42 @@ pub struct Tree {
     entries: BTreeMap<Name, TreeEntry>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeItem {
+    pub mode: u32,
+    pub id: Oid,
+}
+
+impl Mode {
+    pub fn from_u32(mode: u32) -> Self {
+        Mode(format!("{:o}", mode).into_bytes())
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.0 == b"40000" || self.0 == b"040000"
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        std::str::from_utf8(&self.0)
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .unwrap_or(0)
+    }
+}
+
 impl TreeEntry {
     pub fn new(oid: Oid, mode: Mode) -> Self {
         Self { oid, mode }
     }
 
+    pub fn oid(&self) -> &Oid {
+        &self.oid
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
     pub fn kind(&self) -> EntryKind {
         if self.mode.0[0] == b'1' {
             EntryKind::Blob
@@ -50,6 +86,112 @@ impl Tree {
     pub fn append_entry(&mut self, name: Name, entry: TreeEntry) {
         self.entries.insert(name, entry);
     }
+
+    pub fn get(&self, name: &[u8]) -> Option<&TreeEntry> {
+        self.entries.get(&Name(name.to_vec()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Trees are sorted as if directory names had a trailing slash.
+    fn sorted_entries(&self) -> Vec<(&Name, &TreeEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            compare_names(&a_name.0, a.mode.is_tree(), &b_name.0, b.mode.is_tree())
+        });
+        entries
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        for (name, entry) in self.sorted_entries() {
+            writer.write_all(&entry.mode.0)?;
+            writer.write_all(b" ")?;
+            writer.write_all(&name.0)?;
+            writer.write_all(&[0u8])?;
+            writer.write_all(entry.oid.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_tree<O: Odb>(odb: &O, oid: &Oid) -> Result<Tree> {
+    odb.get(oid)?
+        .into_tree()
+        .ok_or_else(|| Error::InvalidObject(format!("{} is not a tree", oid)))
+}
+
+pub fn flatten_tree<O: Odb>(odb: &O, oid: &Oid) -> Result<BTreeMap<Vec<u8>, TreeItem>> {
+    flatten_matching(odb, oid, &Pathspec::default())
+}
+
+// Like `flatten_tree`, only the files `pathspec` matches. Subtrees that
+// cannot hold any are not read.
+pub fn flatten_matching<O: Odb>(
+    odb: &O,
+    oid: &Oid,
+    pathspec: &Pathspec,
+) -> Result<BTreeMap<Vec<u8>, TreeItem>> {
+    let mut items = BTreeMap::new();
+    flatten_into(odb, oid, &[], pathspec, &mut items)?;
+    Ok(items)
+}
+
+fn flatten_into<O: Odb>(
+    odb: &O,
+    oid: &Oid,
+    prefix: &[u8],
+    pathspec: &Pathspec,
+    items: &mut BTreeMap<Vec<u8>, TreeItem>,
+) -> Result<()> {
+    let tree = read_tree(odb, oid)?;
+    for (name, entry) in &tree {
+        let mut path = prefix.to_vec();
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(&name.0);
+        if entry.mode.is_tree() {
+            if pathspec.may_match_dir(&path) {
+                flatten_into(odb, &entry.oid, &path, pathspec, items)?;
+            }
+        } else if pathspec.matches(&path, false)? {
+            let item = TreeItem {
+                mode: entry.mode.as_u32(),
+                id: entry.oid,
+            };
+            items.insert(path, item);
+        }
+    }
+    Ok(())
+}
+
+pub fn compare_names(a: &[u8], a_is_tree: bool, b: &[u8], b_is_tree: bool) -> Ordering {
+    let len = std::cmp::min(a.len(), b.len());
+    match a[..len].cmp(&b[..len]) {
+        Ordering::Equal => {}
+        ordering => return ordering,
+    }
+    let a_next = a
+        .get(len)
+        .copied()
+        .or(if a_is_tree { Some(b'/') } else { None });
+    let b_next = b
+        .get(len)
+        .copied()
+        .or(if b_is_tree { Some(b'/') } else { None });
+    a_next.cmp(&b_next)
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> IntoIterator for &'a Tree {
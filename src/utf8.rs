@@ -0,0 +1,313 @@
+// Characters that take no column of their own, mostly combining marks.
+const ZERO_WIDTH: [(u32, u32); 30] = [
+    (0x00ad, 0x00ad),
+    (0x0300, 0x036f),
+    (0x0483, 0x0489),
+    (0x0591, 0x05bd),
+    (0x05bf, 0x05bf),
+    (0x05c1, 0x05c2),
+    (0x05c4, 0x05c5),
+    (0x05c7, 0x05c7),
+    (0x0610, 0x061a),
+    (0x064b, 0x065f),
+    (0x0670, 0x0670),
+    (0x06d6, 0x06dc),
+    (0x06df, 0x06e4),
+    (0x06e7, 0x06e8),
+    (0x06ea, 0x06ed),
+    (0x0e31, 0x0e31),
+    (0x0e34, 0x0e3a),
+    (0x0e47, 0x0e4e),
+    (0x1160, 0x11ff),
+    (0x1ab0, 0x1aff),
+    (0x1dc0, 0x1dff),
+    (0x200b, 0x200f),
+    (0x202a, 0x202e),
+    (0x2060, 0x2064),
+    (0x20d0, 0x20ff),
+    (0xfe00, 0xfe0f),
+    (0xfe20, 0xfe2f),
+    (0xfeff, 0xfeff),
+    (0x1d167, 0x1d169),
+    (0xe0100, 0xe01ef),
+];
+
+// Characters that take two columns, East Asian scripts and most emoji.
+const DOUBLE_WIDTH: [(u32, u32); 36] = [
+    (0x1100, 0x115f),
+    (0x231a, 0x231b),
+    (0x2329, 0x232a),
+    (0x23e9, 0x23ec),
+    (0x23f0, 0x23f0),
+    (0x23f3, 0x23f3),
+    (0x25fd, 0x25fe),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x26aa, 0x26ab),
+    (0x26bd, 0x26be),
+    (0x26c4, 0x26c5),
+    (0x2705, 0x2705),
+    (0x270a, 0x270b),
+    (0x2728, 0x2728),
+    (0x274c, 0x274c),
+    (0x2b1b, 0x2b1c),
+    (0x2e80, 0x303e),
+    (0x3041, 0x33ff),
+    (0x3400, 0x4dbf),
+    (0x4e00, 0x9fff),
+    (0xa000, 0xa4cf),
+    (0xa960, 0xa97f),
+    (0xac00, 0xd7a3),
+    (0xf900, 0xfaff),
+    (0xfe10, 0xfe19),
+    (0xfe30, 0xfe6f),
+    (0xff00, 0xff60),
+    (0xffe0, 0xffe6),
+    (0x17000, 0x18cff),
+    (0x1b000, 0x1b2ff),
+    (0x1f300, 0x1f64f),
+    (0x1f680, 0x1f6ff),
+    (0x1f900, 0x1f9ff),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+];
+
+// The columns `c` takes on a terminal: -1 for control characters, which
+// have none that make sense, like git's wcwidth.
+pub fn char_width(c: char) -> isize {
+    let c = u32::from(c);
+    let within = |ranges: &[(u32, u32)]| ranges.iter().any(|&(low, high)| low <= c && c <= high);
+    if c == 0 {
+        0
+    } else if c < 0x20 || (0x7f..0xa0).contains(&c) {
+        -1
+    } else if within(&ZERO_WIDTH) {
+        0
+    } else if within(&DOUBLE_WIDTH) {
+        2
+    } else {
+        1
+    }
+}
+
+// The character `bytes` starts with and its length, unless that is not
+// UTF-8.
+pub fn next_char(bytes: &[u8]) -> Option<(char, usize)> {
+    let len = match bytes.first()? {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return None,
+    };
+    let c = std::str::from_utf8(bytes.get(..len)?)
+        .ok()?
+        .chars()
+        .next()?;
+    Some((c, len))
+}
+
+// The length of the color escape `text` starts with, like `\e[1;31m`, or
+// 0 if it does not start with one.
+pub fn escape_len(text: &[u8]) -> usize {
+    if !text.starts_with(b"\x1b[") {
+        return 0;
+    }
+    let params = text[2..]
+        .iter()
+        .take_while(|&&c| c.is_ascii_digit() || c == b';')
+        .count();
+    match text.get(2 + params) {
+        Some(b'm') => 3 + params,
+        _ => 0,
+    }
+}
+
+// The columns `text` takes, not counting color escapes or control
+// characters. Text that is not UTF-8 is taken to be a column a byte.
+pub fn display_width(text: &[u8]) -> usize {
+    let mut width = 0;
+    let mut i = 0;
+    while i < text.len() {
+        let skip = escape_len(&text[i..]);
+        if skip > 0 {
+            i += skip;
+            continue;
+        }
+        match next_char(&text[i..]) {
+            Some((c, len)) => {
+                width += char_width(c).max(0) as usize;
+                i += len;
+            }
+            None => return text.len(),
+        }
+    }
+    width
+}
+
+// git's isspace(), which leaves out vertical tabs and form feeds.
+pub fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+// Adds `text` with `indent1` spaces before its first line and `indent2`
+// before the others.
+pub fn add_indented_text(out: &mut Vec<u8>, text: &[u8], indent1: isize, indent2: isize) {
+    let mut indent = indent1.max(0);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let eol = rest
+            .iter()
+            .position(|&c| c == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        out.resize(out.len() + indent as usize, b' ');
+        out.extend_from_slice(&rest[..eol]);
+        rest = &rest[eol..];
+        indent = indent2.max(0);
+    }
+}
+
+// Adds `text` with its words filled into lines of at most `width`
+// columns, the first indented by `indent1` and the rest by `indent2`. A
+// negative `indent1` counts columns already taken on the first line. A
+// single newline joins lines, unless the next one does not start with a
+// letter or digit; blank lines stay. With a width of 0, lines are only
+// indented. This is git's strbuf_add_wrapped_text().
+pub fn add_wrapped_text(
+    out: &mut Vec<u8>,
+    text: &[u8],
+    indent1: isize,
+    indent2: isize,
+    width: isize,
+) {
+    if width <= 0 {
+        add_indented_text(out, text, indent1, indent2);
+        return;
+    }
+    let orig_len = out.len();
+    let mut assume_utf8 = true;
+    'retry: loop {
+        let mut pos = 0;
+        let mut bol = 0;
+        let mut indent = indent1;
+        let mut w = indent1;
+        let mut space = None;
+        if indent < 0 {
+            w = -indent;
+            space = Some(0);
+        }
+        loop {
+            loop {
+                let skip = escape_len(&text[pos..]);
+                if skip == 0 {
+                    break;
+                }
+                pos += skip;
+            }
+            let c = text.get(pos).copied();
+            if c.is_none_or(is_space) {
+                let mut new_line = false;
+                if w <= width || space.is_none() {
+                    if c.is_none() && pos == bol {
+                        return;
+                    }
+                    let start = match space {
+                        Some(space) => space,
+                        None => {
+                            out.resize(out.len() + indent.max(0) as usize, b' ');
+                            bol
+                        }
+                    };
+                    out.extend_from_slice(&text[start..pos]);
+                    let c = match c {
+                        Some(c) => c,
+                        None => return,
+                    };
+                    space = Some(pos);
+                    if c == b'\t' {
+                        w |= 0x07;
+                    } else if c == b'\n' {
+                        space = Some(pos + 1);
+                        match text.get(pos + 1) {
+                            Some(b'\n') => {
+                                out.push(b'\n');
+                                new_line = true;
+                            }
+                            Some(c) if c.is_ascii_alphanumeric() => out.push(b' '),
+                            _ => new_line = true,
+                        }
+                    }
+                    if !new_line {
+                        w += 1;
+                        pos += 1;
+                    }
+                } else {
+                    new_line = true;
+                }
+                if new_line {
+                    out.push(b'\n');
+                    let after = space.take().unwrap();
+                    pos = after + usize::from(text.get(after).is_some_and(|&c| is_space(c)));
+                    bol = pos;
+                    w = indent2;
+                    indent = indent2;
+                }
+                continue;
+            }
+            if assume_utf8 {
+                match next_char(&text[pos..]) {
+                    Some((c, len)) => {
+                        w += char_width(c);
+                        pos += len;
+                    }
+                    None => {
+                        assume_utf8 = false;
+                        out.truncate(orig_len);
+                        continue 'retry;
+                    }
+                }
+            } else {
+                w += 1;
+                pos += 1;
+            }
+        }
+    }
+}
+
+// Replaces the characters of `text` from column `pos` that take up
+// `width` columns with `subst`, keeping color escapes. Text that is not
+// UTF-8 is left alone.
+pub fn replace_columns(text: &mut Vec<u8>, pos: isize, width: isize, subst: &[u8]) {
+    let mut out = Vec::with_capacity(text.len() + subst.len());
+    let mut subst = Some(subst);
+    let mut w = 0;
+    let mut i = 0;
+    while i < text.len() {
+        loop {
+            let skip = escape_len(&text[i..]);
+            if skip == 0 {
+                break;
+            }
+            out.extend_from_slice(&text[i..i + skip]);
+            i += skip;
+        }
+        if i >= text.len() {
+            break;
+        }
+        let (c, len) = match next_char(&text[i..]) {
+            Some(next) => next,
+            None => return,
+        };
+        let n = char_width(c);
+        if n != 0 && w >= pos && w < pos + width {
+            if let Some(subst) = subst.take() {
+                out.extend_from_slice(subst);
+            }
+        } else {
+            out.extend_from_slice(&text[i..i + len]);
+        }
+        w += n;
+        i += len;
+    }
+    *text = out;
+}
@@ -0,0 +1,249 @@
+// A port of git's wildmatch, which gives gitignore and pathspec globs their
+// meaning: with `PATHNAME`, `*`, `?` and brackets never match a slash and
+// only `**` between slashes crosses directories.
+
+pub const PATHNAME: u32 = 1;
+pub const CASEFOLD: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar,
+}
+
+pub fn wildmatch(pattern: &[u8], text: &[u8], flags: u32) -> bool {
+    dowild(pattern, 0, text, 0, flags) == Outcome::Match
+}
+
+// Reads like a NUL-terminated C string so the port stays close to git's.
+fn at(s: &[u8], i: usize) -> u8 {
+    s.get(i).copied().unwrap_or(0)
+}
+
+fn fold(c: u8, flags: u32) -> u8 {
+    if flags & CASEFOLD != 0 {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+fn is_glob_special(c: u8) -> bool {
+    matches!(c, b'*' | b'?' | b'[' | b'\\')
+}
+
+fn dowild(pattern: &[u8], mut p: usize, text: &[u8], mut t: usize, flags: u32) -> Outcome {
+    while p < pattern.len() {
+        let mut p_ch = fold(pattern[p], flags);
+        let mut t_ch = at(text, t);
+        if t_ch == 0 && p_ch != b'*' {
+            return Outcome::AbortAll;
+        }
+        t_ch = fold(t_ch, flags);
+        match p_ch {
+            b'?' => {
+                if flags & PATHNAME != 0 && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => {
+                let match_slash;
+                p += 1;
+                if at(pattern, p) == b'*' {
+                    let starts_segment = p < 2 || pattern[p - 2] == b'/';
+                    while at(pattern, p + 1) == b'*' {
+                        p += 1;
+                    }
+                    p += 1;
+                    let ends_segment = at(pattern, p) == 0
+                        || at(pattern, p) == b'/'
+                        || (at(pattern, p) == b'\\' && at(pattern, p + 1) == b'/');
+                    if starts_segment && ends_segment {
+                        // "**/" may match no directory at all, so that
+                        // "foo/**/bar" also matches "foo/bar".
+                        if at(pattern, p) == b'/'
+                            && dowild(pattern, p + 1, text, t, flags) == Outcome::Match
+                        {
+                            return Outcome::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        match_slash = false;
+                    }
+                } else {
+                    match_slash = flags & PATHNAME == 0;
+                }
+                if p >= pattern.len() {
+                    // A trailing "*" only matches within the last component.
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Outcome::NoMatch;
+                    }
+                    return Outcome::Match;
+                } else if !match_slash && pattern[p] == b'/' {
+                    // A single "*" before a slash matches the next directory
+                    // name; the slash itself is consumed below.
+                    match text[t..].iter().position(|&c| c == b'/') {
+                        Some(offset) => {
+                            t += offset + 1;
+                            p += 1;
+                            continue;
+                        }
+                        None => return Outcome::NoMatch,
+                    }
+                }
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+                    // Skip ahead to the next occurrence of a literal that
+                    // follows the star, as nothing before it can match.
+                    if !is_glob_special(pattern[p]) {
+                        p_ch = fold(pattern[p], flags);
+                        loop {
+                            t_ch = fold(at(text, t), flags);
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') || t_ch == p_ch {
+                                break;
+                            }
+                            t += 1;
+                        }
+                        if t_ch != p_ch {
+                            return Outcome::NoMatch;
+                        }
+                    }
+                    let matched = dowild(pattern, p, text, t, flags);
+                    if matched != Outcome::NoMatch {
+                        if !match_slash || matched != Outcome::AbortToStarStar {
+                            return matched;
+                        }
+                    } else if !match_slash && t_ch == b'/' {
+                        return Outcome::AbortToStarStar;
+                    }
+                    t += 1;
+                    t_ch = fold(at(text, t), flags);
+                }
+                return Outcome::AbortAll;
+            }
+            b'[' => {
+                p += 1;
+                p_ch = at(pattern, p);
+                let negated = p_ch == b'!' || p_ch == b'^';
+                if negated {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                let mut prev_ch = 0;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Outcome::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == 0 {
+                            return Outcome::AbortAll;
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && at(pattern, p + 1) != 0
+                        && at(pattern, p + 1) != b']'
+                    {
+                        p += 1;
+                        p_ch = at(pattern, p);
+                        if p_ch == b'\\' {
+                            p += 1;
+                            p_ch = at(pattern, p);
+                            if p_ch == 0 {
+                                return Outcome::AbortAll;
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        } else if flags & CASEFOLD != 0 && t_ch.is_ascii_lowercase() {
+                            let upper = t_ch.to_ascii_uppercase();
+                            if upper <= p_ch && upper >= prev_ch {
+                                matched = true;
+                            }
+                        }
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(pattern, p + 1) == b':' {
+                        let start = p + 2;
+                        let mut end = start;
+                        while at(pattern, end) != 0 && at(pattern, end) != b']' {
+                            end += 1;
+                        }
+                        if at(pattern, end) == 0 {
+                            return Outcome::AbortAll;
+                        }
+                        if end == start || pattern[end - 1] != b':' {
+                            // Not a "[:class:]", so the '[' is an ordinary
+                            // member of the set.
+                            if t_ch == b'[' {
+                                matched = true;
+                            }
+                        } else {
+                            p = end;
+                            match match_class(&pattern[start..end - 1], t_ch, flags) {
+                                Some(true) => matched = true,
+                                Some(false) => {}
+                                None => return Outcome::AbortAll,
+                            }
+                            p_ch = 0;
+                        }
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+                    prev_ch = p_ch;
+                    p += 1;
+                    p_ch = at(pattern, p);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || (flags & PATHNAME != 0 && t_ch == b'/') {
+                    return Outcome::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    p += 1;
+                    p_ch = at(pattern, p);
+                }
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    if t < text.len() {
+        Outcome::NoMatch
+    } else {
+        Outcome::Match
+    }
+}
+
+fn match_class(class: &[u8], c: u8, flags: u32) -> Option<bool> {
+    let matched = match class {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == 0x0b,
+        b"upper" => c.is_ascii_uppercase() || (flags & CASEFOLD != 0 && c.is_ascii_lowercase()),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    };
+    Some(matched)
+}
@@ -0,0 +1,54 @@
+# A process filter that upper-cases content, and otherwise does what the
+# path asks for: `error*` and `abort*` answer with that status, `fail*`
+# sends content before reporting an error and `delay*` is delayed when it
+# can be. Its state is kept in the current directory.
+read_pkt() {
+    len=$(dd bs=1 count=4 2>/dev/null)
+    [ -n "$len" ] || exit 0
+    [ "$len" != 0000 ] || return 1
+    pkt=$(dd bs=1 count=$((0x$len - 4)) 2>/dev/null)
+}
+read_stream() {
+    : > "$1"
+    while len=$(dd bs=1 count=4 2>/dev/null) && [ "$len" != 0000 ]; do
+        dd bs=1 count=$((0x$len - 4)) 2>/dev/null >> "$1"
+    done
+}
+pkt() { printf '%04x%s\n' $((${#1} + 5)) "$1"; }
+flush() { printf 0000; }
+send_stream() { printf '%04x' $(($(wc -c < "$1") + 4)); cat "$1"; flush; }
+
+while read_pkt; do :; done
+pkt git-filter-server; pkt version=2; flush
+while read_pkt; do :; done
+pkt capability=clean; pkt capability=smudge; pkt capability=delay; flush
+while :; do
+    command= pathname= can_delay=
+    while read_pkt; do
+        case $pkt in
+        command=*) command=${pkt#command=} ;;
+        pathname=*) pathname=${pkt#pathname=} ;;
+        can-delay=1) can_delay=1 ;;
+        esac
+    done
+    if [ "$command" = list_available_blobs ]; then
+        if [ -f delayed ]; then pkt "pathname=$(cat delayed)"; rm delayed; fi
+        flush; pkt status=success; flush
+        continue
+    fi
+    read_stream input
+    case $pathname in
+    error*) pkt status=error; flush ;;
+    abort*) pkt status=abort; flush ;;
+    fail*) tr a-z A-Z < input > output
+        pkt status=success; flush; send_stream output; pkt status=error; flush ;;
+    delay*) if [ -n "$can_delay" ]; then
+            tr a-z A-Z < input > ready; echo "$pathname" > delayed
+            pkt status=delayed; flush
+        else
+            pkt status=success; flush; send_stream ready; flush
+        fi ;;
+    *) tr a-z A-Z < input > output
+        pkt status=success; flush; send_stream output; flush ;;
+    esac
+done